@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
-use little_a_map::{level::Level, render, search};
+use little_a_map::{config::Config, level::Level, render, search};
 use std::env;
 use std::path::PathBuf;
 
@@ -7,7 +7,7 @@ pub fn bench_render(c: &mut Criterion) {
     let world_path = PathBuf::from(env!("BENCH_WORLD_PATH"));
     let output_path = PathBuf::from(env!("BENCH_OUTPUT_PATH"));
     let level_info = Level::from_world_path(&world_path).unwrap();
-    let map_ids = search(&world_path, &output_path, false, false, None).unwrap();
+    let map_ids = search(&world_path, &output_path, false, false, None, 0).unwrap();
     println!("Found {} maps", map_ids.len());
 
     let mut group = c.benchmark_group("little-a-map");
@@ -23,6 +23,7 @@ pub fn bench_render(c: &mut Criterion) {
                     black_box(true),
                     black_box(&level_info),
                     &ids,
+                    &Config::default(),
                 )
             },
             BatchSize::SmallInput,
@@ -55,6 +56,7 @@ pub fn bench_search(c: &mut Criterion) {
                 true,
                 black_box(true),
                 Some(&bounds),
+                0,
             )
         });
     });
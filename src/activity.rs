@@ -0,0 +1,150 @@
+use crate::dimension::Dimension;
+use crate::paths::glob_in;
+use crate::utilities::read_modified;
+use anyhow::Result;
+use log::{debug, warn};
+use std::fs::File;
+use std::io::{self, ErrorKind::NotFound};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// level.dat modified this recently is taken for a save in progress
+const SAVING: Duration = Duration::from_secs(5);
+
+// A busy server may never pause for long, so waiting for it eventually gives up
+const MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+
+// The game rewrites level.dat with each save and holds session.lock while the world is open, so
+// a world is taken to be saving when both are seen. Where the lock can't be checked, the write
+// alone is taken for it.
+pub fn is_saving(world_path: &Path) -> Result<bool> {
+    let recent = modified(&world_path.join("level.dat"))
+        .map_or(false, |m| m.elapsed().unwrap_or_default() < SAVING);
+
+    Ok(recent && is_locked(&world_path.join("session.lock"))?.unwrap_or(true))
+}
+
+/// Wait until no file of a world has been modified for some time, e.g. for the game to finish
+/// saving, returning how long was waited
+pub fn wait_for_quiesce(world_path: &Path, stable: Duration) -> Result<Duration> {
+    let start = Instant::now();
+    loop {
+        let quiet =
+            latest_modification(world_path)?.map_or(stable, |m| m.elapsed().unwrap_or_default());
+        if quiet >= stable {
+            return Ok(start.elapsed());
+        }
+        if start.elapsed() >= MAX_WAIT {
+            warn!(
+                "World is still being modified after {}; searching anyway",
+                humantime::format_duration(MAX_WAIT)
+            );
+            return Ok(start.elapsed());
+        }
+
+        debug!("Wait for the world to be unmodified for {stable:?}");
+        thread::sleep(stable.saturating_sub(quiet));
+    }
+}
+
+fn latest_modification(world_path: &Path) -> Result<Option<SystemTime>> {
+    let mut paths = vec![world_path.join("level.dat")];
+    for pattern in ["playerdata/*.dat", "data/*.dat"] {
+        for entry in glob_in(world_path, pattern)? {
+            paths.push(entry?);
+        }
+    }
+    for dimension_path in Dimension::ALL.iter().filter_map(|d| d.path(world_path)) {
+        for pattern in ["region/r.*.mca", "entities/r.*.mca"] {
+            for entry in glob_in(&dimension_path, pattern)? {
+                paths.push(entry?);
+            }
+        }
+    }
+
+    // Files removed meanwhile are disregarded
+    Ok(paths
+        .iter()
+        .map(PathBuf::as_path)
+        .filter_map(modified)
+        .max())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    read_modified(path).ok()
+}
+
+// The game locks the whole of session.lock as a POSIX record lock, which is tested for without
+// taking it
+#[cfg(unix)]
+#[allow(clippy::cast_possible_truncation)] // Small constants
+fn is_locked(path: &Path) -> io::Result<Option<bool>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == NotFound => return Ok(Some(false)),
+        Err(e) => return Err(e),
+    };
+
+    // SAFETY: flock is plain data, for which zeros are valid, i.e. the whole file from its start
+    let mut lock = unsafe { std::mem::zeroed::<libc::flock>() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+
+    // SAFETY: The descriptor is open, and F_GETLK only describes a conflicting lock in the struct.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } != 0 {
+        // Some FUSE and network mounts, e.g. of backups, support no record locks
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::Unsupported {
+            debug!("Unable to check lock of {}: {error}", path.display());
+            return Ok(None);
+        }
+        return Err(error);
+    }
+
+    Ok(Some(lock.l_type != libc::F_UNLCK as libc::c_short))
+}
+
+#[cfg(not(unix))]
+const fn is_locked(_path: &Path) -> io::Result<Option<bool>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn quiesce() {
+        let world = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let world_path = world.path();
+        fs::create_dir_all(world_path.join("region")).unwrap();
+        fs::write(world_path.join("level.dat"), "").unwrap();
+        fs::write(world_path.join("session.lock"), "☃").unwrap();
+        fs::write(world_path.join("region/r.0.0.mca"), "").unwrap();
+
+        // Unlocked, as by a copy of the world
+        #[cfg(unix)]
+        assert_eq!(
+            is_locked(&world_path.join("session.lock")).unwrap(),
+            Some(false)
+        );
+        #[cfg(unix)]
+        assert!(!is_saving(world_path).unwrap());
+
+        let stable = Duration::from_millis(200);
+        assert!(wait_for_quiesce(world_path, stable).unwrap() > Duration::ZERO);
+        assert!(
+            latest_modification(world_path)
+                .unwrap()
+                .unwrap()
+                .elapsed()
+                .unwrap()
+                >= stable
+        );
+        assert!(wait_for_quiesce(world_path, stable).unwrap() < stable);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::open_files::OpenFiles;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::io::Read;
+use std::path::Path;
+
+const SECTOR: usize = 4096;
+const EXTERNAL: u8 = 0x80;
+
+pub struct Chunk {
+    pub x: usize,
+    pub z: usize,
+    pub data: Vec<u8>,
+}
+
+// Unlike fastanvil, this supports every scheme of chunk compression, including LZ4 as written
+// since 1.20.5 with region-file-compression=lz4, and chunks too large for the region file which
+// are kept separately in c.X.Z.mcc files.
+pub fn chunks(path: &Path, (rx, rz): (i32, i32), files: &OpenFiles) -> Result<Vec<Chunk>> {
+    let region = files.read(path)?;
+    #[cfg(feature = "faults")]
+    let region = crate::fault::truncate(path, region);
+    if region.is_empty() {
+        return Ok(Vec::new());
+    }
+    ensure!(region.len() >= 2 * SECTOR, "Truncated header");
+
+    (0..1024)
+        .filter_map(|i| {
+            let location = u32::from_be_bytes(region[i * 4..i * 4 + 4].try_into().unwrap());
+            (location != 0).then_some((i, location))
+        })
+        .map(|(i, location)| {
+            let (x, z) = (i % 32, i / 32);
+            let start = (location >> 8) as usize * SECTOR;
+            let header = region
+                .get(start..start + 5)
+                .ok_or_else(|| anyhow!("Chunk ({x}, {z}) is beyond the end of the file"))?;
+            let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+            let scheme = header[4];
+
+            let data = if scheme & EXTERNAL == 0 {
+                let payload = region
+                    .get(start + 5..start + 4 + length)
+                    .ok_or_else(|| anyhow!("Chunk ({x}, {z}) is truncated"))?;
+                decompress(scheme, payload)
+            } else {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)] // 0..32
+                let external_path = path.with_file_name(format!(
+                    "c.{}.{}.mcc",
+                    rx * 32 + x as i32,
+                    rz * 32 + z as i32
+                ));
+                decompress(scheme & !EXTERNAL, &files.read(&external_path)?)
+            }
+            .with_context(|| format!("Failed to decompress chunk ({x}, {z})"))?;
+
+            Ok(Chunk { x, z, data })
+        })
+        .collect()
+}
+
+fn decompress(scheme: u8, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    match scheme {
+        1 => {
+            GzDecoder::new(payload).read_to_end(&mut data)?;
+        }
+        2 => {
+            ZlibDecoder::new(payload).read_to_end(&mut data)?;
+        }
+        3 => data.extend_from_slice(payload),
+        4 => lz4_stream(payload, &mut data)?,
+        127 => bail!("Custom compression is unsupported"),
+        _ => bail!("Unknown compression scheme {scheme}"),
+    }
+
+    Ok(data)
+}
+
+// Blocks as written by LZ4Java's LZ4BlockOutputStream. Checksums are not verified.
+fn lz4_stream(mut input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    const HEADER: usize = 21;
+
+    while !input.is_empty() {
+        ensure!(
+            input.len() >= HEADER && input.starts_with(b"LZ4Block"),
+            "Invalid LZ4 block header"
+        );
+        let method = input[8] & 0xF0;
+        let compressed = u32::from_le_bytes(input[9..13].try_into().unwrap()) as usize;
+        let decompressed = u32::from_le_bytes(input[13..17].try_into().unwrap()) as usize;
+        let block = input
+            .get(HEADER..HEADER + compressed)
+            .ok_or_else(|| anyhow!("Truncated LZ4 block"))?;
+        input = &input[HEADER + compressed..];
+
+        if decompressed == 0 {
+            break;
+        }
+
+        let start = output.len();
+        match method {
+            0x10 => output.extend_from_slice(block),
+            0x20 => lz4_block(block, output)?,
+            _ => bail!("Unknown LZ4 block method {method:#x}"),
+        }
+        ensure!(
+            output.len() - start == decompressed,
+            "LZ4 block decompressed to {} rather than {decompressed} bytes",
+            output.len() - start
+        );
+    }
+
+    Ok(())
+}
+
+fn lz4_block(input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    let start = output.len();
+    let mut i = 0;
+    let mut next = || -> Result<u8> {
+        let byte = *input
+            .get(i)
+            .ok_or_else(|| anyhow!("Truncated LZ4 sequence"))?;
+        i += 1;
+        Ok(byte)
+    };
+    let length = |nibble: u8, next: &mut dyn FnMut() -> Result<u8>| -> Result<usize> {
+        let mut length = usize::from(nibble);
+        if nibble == 15 {
+            loop {
+                let byte = next()?;
+                length += usize::from(byte);
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+
+    loop {
+        let token = next()?;
+
+        let literals = length(token >> 4, &mut next)?;
+        for _ in 0..literals {
+            output.push(next()?);
+        }
+
+        // The last sequence has only literals
+        let Ok(low) = next() else { break };
+        let offset = usize::from(u16::from_le_bytes([low, next()?]));
+        ensure!(
+            offset != 0 && offset <= output.len() - start,
+            "Invalid LZ4 match offset"
+        );
+        let matched = length(token & 0x0F, &mut next)? + 4;
+        let from = output.len() - offset;
+        for k in 0..matched {
+            output.push(output[from + k]);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn lz4_stream_of(block: &[u8], decompressed: u32) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for (method, block, decompressed) in [(0x20, block, decompressed), (0x10, &[][..], 0)] {
+            stream.extend_from_slice(b"LZ4Block");
+            stream.push(method);
+            stream.extend_from_slice(&u32::try_from(block.len()).unwrap().to_le_bytes());
+            stream.extend_from_slice(&decompressed.to_le_bytes());
+            stream.extend_from_slice(&[0; 4]);
+            stream.extend_from_slice(block);
+        }
+        stream
+    }
+
+    #[test]
+    fn lz4() {
+        // Literals "abc", then a match of 9 at offset 3, then the literal "!"
+        let block = [0x35, b'a', b'b', b'c', 3, 0, 0x10, b'!'];
+        let mut output = Vec::new();
+        lz4_stream(&lz4_stream_of(&block, 13), &mut output).unwrap();
+        assert_eq!(output, b"abcabcabcabc!");
+
+        let mut output = Vec::new();
+        assert!(lz4_stream(&lz4_stream_of(&block, 12), &mut output).is_err());
+
+        let invalid_offset = [0x30, b'a', b'b', b'c', 4, 0];
+        assert!(lz4_block(&invalid_offset, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn region() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let path = dir.path().join("r.1.-1.mca");
+        let nbt = b"chunk data";
+        let mut region = vec![0; 4 * SECTOR];
+        let mut put = |at: usize, bytes: &[u8]| region[at..at + bytes.len()].copy_from_slice(bytes);
+
+        // Chunk (1, 0) in sector 2, compressed with LZ4 as only literals
+        let payload = lz4_stream_of(&[&[0xA0][..], nbt].concat(), 10);
+        put(4, &(2 << 8 | 1_u32).to_be_bytes());
+        put(
+            2 * SECTOR,
+            &u32::try_from(payload.len() + 1).unwrap().to_be_bytes(),
+        );
+        put(2 * SECTOR + 4, &[4]);
+        put(2 * SECTOR + 5, &payload);
+
+        // Chunk (0, 1) in sector 3, uncompressed in its own file
+        put(4 * 32, &(3 << 8 | 1_u32).to_be_bytes());
+        put(3 * SECTOR, &1_u32.to_be_bytes());
+        put(3 * SECTOR + 4, &[EXTERNAL | 3]);
+
+        fs::write(&path, &region).unwrap();
+        fs::write(dir.path().join("c.32.-31.mcc"), nbt).unwrap();
+        let chunks = chunks(&path, (1, -1), &OpenFiles::new(None)).unwrap();
+        assert_eq!(
+            chunks.iter().map(|c| (c.x, c.z)).collect::<Vec<_>>(),
+            [(1, 0), (0, 1)]
+        );
+        assert!(chunks.iter().all(|c| c.data == nbt));
+    }
+}
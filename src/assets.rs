@@ -0,0 +1,61 @@
+use crate::paths::glob_in;
+use anyhow::{bail, Result};
+use log::debug;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::Path;
+
+// Leaflet isn't vendored, so serving the viewer without a CDN relies on a copy of its dist dir
+const REQUIRED: [&str; 2] = ["leaflet.css", "leaflet.js"];
+
+// Copied into assets/ of the output, preserving modification times so that unchanged files are
+// skipped on later runs. Files no longer in the source are removed.
+pub fn install(source_path: &Path, output_path: &Path, force: bool) -> Result<usize> {
+    for name in REQUIRED {
+        if !source_path.join(name).is_file() {
+            bail!("Missing {name} in {}", source_path.display());
+        }
+    }
+
+    let target_path = output_path.join("assets");
+    let mut installed = HashSet::new();
+    let mut copied = 0;
+    for entry in glob_in(source_path, "**/*")? {
+        let path = entry?;
+        if !path.is_file() {
+            continue;
+        }
+        let target = target_path.join(path.strip_prefix(source_path)?);
+        let modified = fs::metadata(&path)?.modified()?;
+
+        if force
+            || fs::metadata(&target)
+                .and_then(|m| m.modified())
+                .map_or(true, |target_modified| target_modified != modified)
+        {
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::copy(&path, &target)?;
+            File::options()
+                .write(true)
+                .open(&target)?
+                .set_modified(modified)?;
+            copied += 1;
+        }
+        installed.insert(target);
+    }
+
+    for entry in glob_in(&target_path, "**/*")? {
+        let path = entry?;
+        let original = if path.extension().map_or(false, |e| e == "gz") {
+            path.with_extension("")
+        } else {
+            path.clone()
+        };
+        if path.is_file() && !installed.contains(&original) {
+            debug!("Prune: {}", path.display());
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(copied)
+}
@@ -1,8 +1,98 @@
+use crate::config::{GeoJson, MarkerPalette};
+use crate::i18n::Lang;
+use crate::map::{data_path, MapScan};
+use crate::open_files::OpenFiles;
+use crate::utilities::read_modified;
+use crate::Error;
+use anyhow::Result;
 use derivative::Derivative;
 use fastnbt::IntArray;
-use serde::{Deserialize, Deserializer};
+use itertools::Itertools;
+use log::debug;
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
 use serde_with::{json::JsonString, serde_as};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::ErrorKind::NotFound;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+/// Dye colors of banners and of items dyed alike, e.g. bundles and shulker boxes
+pub const COLORS: [&str; 16] = [
+    "black",
+    "blue",
+    "brown",
+    "cyan",
+    "gray",
+    "green",
+    "light_blue",
+    "light_gray",
+    "lime",
+    "magenta",
+    "orange",
+    "pink",
+    "purple",
+    "red",
+    "white",
+    "yellow",
+];
+
+// Of the markers as the game draws banners on maps
+const GAME: [&str; 16] = [
+    "#1f2024", "#42439d", "#735235", "#298796", "#4b4e52", "#5e6f3a", "#3998cf", "#8e8e85",
+    "#70b32c", "#b447ac", "#e5731e", "#db779d", "#7734a9", "#9c3434", "#ffffff", "#f2b929",
+];
+
+// Led by the Okabe–Ito palette, which stays distinct under the common color vision deficiencies,
+// for the colors likeliest to be told apart, with the rest filled in from Paul Tol's palettes
+const COLORBLIND: [&str; 16] = [
+    "#000000", "#0072b2", "#8c510a", "#44aa99", "#777777", "#009e73", "#56b4e9", "#cccccc",
+    "#999933", "#cc79a7", "#e69f00", "#ffaabb", "#882255", "#d55e00", "#ffffff", "#f0e442",
+];
+
+/// Color of banner markers as written into the viewer and named in its text
+#[derive(Serialize)]
+pub struct MarkerColor {
+    pub hex: String,
+    pub label: &'static str,
+}
+
+// Grays are evenly spaced in order of the luminance of the colors of the game, so that they're
+// as distinct as 16 grays can be while dark colors stay dark
+pub fn palette(palette: MarkerPalette, lang: Lang) -> BTreeMap<&'static str, MarkerColor> {
+    let hexes = match palette {
+        MarkerPalette::Game => GAME.map(str::to_owned),
+        MarkerPalette::Colorblind => COLORBLIND.map(str::to_owned),
+        MarkerPalette::Grayscale => {
+            // Rec. 709 luma in ten-thousandths
+            let luminance = |hex: &str| {
+                let channel = |i| u32::from_str_radix(&hex[i..i + 2], 16).unwrap();
+                2126 * channel(1) + 7152 * channel(3) + 722 * channel(5)
+            };
+            let mut ranked = (0..GAME.len()).collect::<Vec<_>>();
+            ranked.sort_by_key(|&i| luminance(GAME[i]));
+
+            let mut grays = [(); 16].map(|()| String::new());
+            for (rank, i) in (0_u8..).zip(ranked) {
+                let level = rank * 0x11;
+                grays[i] = format!("#{level:02x}{level:02x}{level:02x}");
+            }
+            grays
+        }
+    };
+
+    COLORS
+        .into_iter()
+        .zip(hexes)
+        .zip(lang.strings().colors)
+        .map(|((color, hex), label)| (color, MarkerColor { hex, label }))
+        .collect()
+}
+
+/// Banner marker of a map, identified by position
 #[derive(Debug, Derivative, Eq, Ord, PartialOrd)]
 #[derivative(PartialEq)]
 pub struct Banner {
@@ -87,3 +177,422 @@ impl<'de> Deserialize<'de> for Banner {
         })
     }
 }
+
+// Of banners distinct by label, color, and position, the labels of only one
+pub fn unique_labels<'a>(
+    banners: impl IntoIterator<Item = (&'a str, &'a str, i32, i32)>,
+) -> HashSet<&'a str> {
+    let mut counts = HashMap::<&str, usize>::new();
+    for (label, ..) in banners.into_iter().unique() {
+        *counts.entry(label).or_default() += 1;
+    }
+
+    counts
+        .into_iter()
+        .filter_map(|(label, count)| (count == 1).then_some(label))
+        .collect()
+}
+
+// By label, color, and position
+type LabelledBanners = BTreeSet<(String, String, i32, i32)>;
+
+/// Labelled banners of each map of the world as of when it was last read, by which labels are
+/// judged unique across the world rather than among the maps of one output
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Labels {
+    by_map: BTreeMap<u32, (SystemTime, LabelledBanners)>,
+
+    /// When a label last became unique or ceased to be
+    pub modified: Option<SystemTime>,
+}
+
+impl Labels {
+    // Only maps modified since they were last read are read again. Those that can't be read are
+    // forgotten until they can.
+    pub fn update(&mut self, world_path: &Path, ids: &HashSet<u32>, files: &OpenFiles) {
+        span!("labels");
+        let unique = |labels: &Self| {
+            let unique = labels.unique().into_iter().map(str::to_owned);
+            unique.collect::<BTreeSet<_>>()
+        };
+        let before = unique(self);
+
+        self.by_map.retain(|id, _| ids.contains(id));
+        let read = |id: u32| -> Result<_, Error> {
+            let path = data_path(world_path, id);
+            let modified = read_modified(&path)?;
+            if self.by_map.get(&id).is_some_and(|(m, _)| *m == modified) {
+                return Ok(None);
+            }
+
+            let scan = MapScan::from_nbt(id, &files.read_gz(&path)?, modified)?;
+            let banners = scan
+                .banners
+                .into_keys()
+                .filter_map(|b| Some((b.label?, b.color, b.x, b.z)))
+                .collect();
+
+            Ok(Some((modified, banners)))
+        };
+        let read = ids.par_iter().map(|&id| (id, read(id))).collect::<Vec<_>>();
+        for (id, read) in read {
+            match read {
+                Ok(Some(entry)) => {
+                    self.by_map.insert(id, entry);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    debug!("Failed to read the banners of map {id}: {e}");
+                    self.by_map.remove(&id);
+                }
+            }
+        }
+
+        if unique(self) != before {
+            self.modified = Some(SystemTime::now());
+        }
+    }
+
+    pub fn unique(&self) -> HashSet<&str> {
+        unique_labels(
+            self.by_map
+                .values()
+                .flat_map(|(_, banners)| banners)
+                .map(|(label, color, x, z)| (label.as_str(), color.as_str(), *x, *z)),
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct Cluster {
+    pub coordinates: [i32; 2],
+    pub features: Vec<usize>,
+}
+
+// Banners are grouped by grid cell and each group is placed at its members' mean position
+pub fn cluster(positions: &[(i32, i32)], cell_size: i32) -> Vec<Cluster> {
+    let mut cells = BTreeMap::<(i32, i32), Vec<usize>>::new();
+    for (index, &(x, z)) in positions.iter().enumerate() {
+        cells
+            .entry((x.div_euclid(cell_size), z.div_euclid(cell_size)))
+            .or_default()
+            .push(index);
+    }
+
+    cells
+        .into_values()
+        .map(|features| {
+            let count = i64::try_from(features.len()).unwrap();
+            let mean = |axis: fn(&(i32, i32)) -> i32| {
+                let sum = features
+                    .iter()
+                    .map(|&i| i64::from(axis(&positions[i])))
+                    .sum::<i64>();
+                i32::try_from(sum.div_euclid(count)).unwrap()
+            };
+
+            Cluster {
+                coordinates: [mean(|p| p.0), mean(|p| p.1)],
+                features,
+            }
+        })
+        .collect()
+}
+
+/// Features of banners as in `banners.json`, one per line, in order of the root tile containing
+/// them and then of position
+///
+/// Each output listing banners is streamed from it in turn rather than from features held in
+/// memory. Its contents depend only on the scan, so it's rewritten identically until that changes.
+pub struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    pub fn write(path: &Path, features: impl IntoIterator<Item = Result<Value>>) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for feature in features {
+            serde_json::to_writer(&mut writer, &feature?)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner()?.sync_all()?;
+
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Each feature as serialized
+    pub fn lines(&self) -> Result<impl Iterator<Item = Result<String>>> {
+        Ok(BufReader::new(File::open(&self.path)?)
+            .lines()
+            .map(|line| Ok(line?)))
+    }
+
+    pub fn features(&self) -> Result<impl Iterator<Item = Result<Value>>> {
+        Ok(self.lines()?.map(|line| Ok(serde_json::from_str(&line?)?)))
+    }
+
+    /// `FeatureCollection` of the features, copied without parsing them
+    pub fn write_collection(&self, writer: impl Write) -> Result<()> {
+        write_collection(writer, self.lines()?)
+    }
+}
+
+/// Banner as listed among changes, distinct by label and color as well as by position
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Listed {
+    pub x: i32,
+    pub z: i32,
+    pub color: String,
+    pub name: Option<String>,
+}
+
+impl From<&Banner> for Listed {
+    fn from(banner: &Banner) -> Self {
+        Self {
+            x: banner.x,
+            z: banner.z,
+            color: banner.color.clone(),
+            name: banner.label.clone(),
+        }
+    }
+}
+
+/// Banners of a `FeatureCollection` as written to `banners.json`, or None if there's none
+pub fn read_collection(path: &Path) -> Result<Option<BTreeSet<Listed>>> {
+    let collection: Value = match File::open(path) {
+        Ok(f) => serde_json::from_reader(BufReader::new(f))?,
+        Err(e) if e.kind() == NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(Some(
+        collection["features"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|feature| {
+                let coordinates = &feature["geometry"]["coordinates"];
+                let properties = &feature["properties"];
+
+                Some(Listed {
+                    x: i32::try_from(coordinates[0].as_i64()?).ok()?,
+                    z: i32::try_from(coordinates[1].as_i64()?).ok()?,
+                    color: properties["color"].as_str()?.to_owned(),
+                    name: properties["name"].as_str().map(str::to_owned),
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// Banners placed and removed between one set of banners and the next, e.g. to notify a community
+/// of them. A banner relabelled or recolored is both.
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Changes {
+    pub added: Vec<Listed>,
+    pub removed: Vec<Listed>,
+}
+
+impl Changes {
+    #[must_use]
+    pub fn between(old: &BTreeSet<Listed>, new: &BTreeSet<Listed>) -> Self {
+        Self {
+            added: new.difference(old).cloned().collect(),
+            removed: old.difference(new).cloned().collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// `FeatureCollection` of features already serialized
+pub fn write_collection(
+    mut writer: impl Write,
+    lines: impl IntoIterator<Item = Result<String>>,
+) -> Result<()> {
+    writer.write_all(br#"{"type":"FeatureCollection","features":["#)?;
+    for (i, line) in lines.into_iter().enumerate() {
+        if i != 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(line?.as_bytes())?;
+    }
+    writer.write_all(b"]}")?;
+
+    Ok(writer.flush()?)
+}
+
+// Coordinates are scaled, and with `flip` north is up, i.e. along negative z. The bounding box is
+// written after the features since it's known only once they've been read.
+pub fn export(
+    features: impl IntoIterator<Item = Result<Value>>,
+    options: &GeoJson,
+    mut writer: impl Write,
+) -> Result<()> {
+    let transform = |x: f64, z: f64| {
+        let y = if options.flip { -z } else { z };
+        [x * options.scale, y * options.scale]
+    };
+    let crs = json!({ "type": "name", "properties": { "name": options.crs } });
+    write!(
+        writer,
+        r#"{{"type":"FeatureCollection","crs":{crs},"features":["#
+    )?;
+
+    let mut bbox: Option<[f64; 4]> = None;
+    for (i, feature) in features.into_iter().enumerate() {
+        let mut feature = feature?;
+        let coordinates = &mut feature["geometry"]["coordinates"];
+        let [x, y] = transform(
+            coordinates[0].as_f64().unwrap_or_default(),
+            coordinates[1].as_f64().unwrap_or_default(),
+        );
+        *coordinates = json!([x, y]);
+        bbox = Some(bbox.map_or([x, y, x, y], |[x0, y0, x1, y1]| {
+            [x0.min(x), y0.min(y), x1.max(x), y1.max(y)]
+        }));
+
+        if i != 0 {
+            writer.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut writer, &feature)?;
+    }
+
+    writer.write_all(b"]")?;
+    if let Some(bbox) = bbox {
+        write!(writer, r#","bbox":{}"#, json!(bbox))?;
+    }
+    writer.write_all(b"}")?;
+
+    Ok(writer.flush()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn palettes() {
+        for marker_palette in [
+            MarkerPalette::Game,
+            MarkerPalette::Colorblind,
+            MarkerPalette::Grayscale,
+        ] {
+            let colors = palette(marker_palette, Lang::En);
+            assert_eq!(colors.keys().copied().collect::<Vec<_>>(), COLORS);
+
+            let hexes = colors.values().map(|c| &c.hex).collect::<HashSet<_>>();
+            assert_eq!(hexes.len(), COLORS.len());
+        }
+
+        let grays = palette(MarkerPalette::Grayscale, Lang::Fr);
+        assert_eq!(grays["black"].hex, "#000000");
+        assert_eq!(grays["white"].hex, "#ffffff");
+        assert_eq!(grays["light_blue"].label, "Bleu clair");
+    }
+
+    #[test]
+    fn unique() {
+        let banners = [
+            ("Home", "red", 0, 0),
+            ("Home", "red", 0, 0),
+            ("Mine", "red", 0, 0),
+            ("Mine", "blue", 0, 0),
+        ];
+        assert_eq!(unique_labels(banners), HashSet::from(["Home"]));
+
+        // A banner on several maps is one banner
+        let home = ("Home".to_owned(), "red".to_owned(), 0, 0);
+        let mine = ("Mine".to_owned(), "red".to_owned(), 5, 5);
+        let labels = Labels {
+            by_map: BTreeMap::from([
+                (1, (SystemTime::UNIX_EPOCH, BTreeSet::from([home.clone()]))),
+                (2, (SystemTime::UNIX_EPOCH, BTreeSet::from([home, mine]))),
+            ]),
+            modified: None,
+        };
+        assert_eq!(labels.unique(), HashSet::from(["Home", "Mine"]));
+    }
+
+    #[test]
+    fn changes() {
+        let listed = |x, color: &str, name: Option<&str>| Listed {
+            x,
+            z: 0,
+            color: color.to_owned(),
+            name: name.map(str::to_owned),
+        };
+        let old = BTreeSet::from([
+            listed(0, "red", Some("Home")),
+            listed(1, "blue", None),
+            listed(2, "white", None),
+        ]);
+        let new = BTreeSet::from([
+            listed(0, "red", Some("Home")),
+            listed(1, "blue", Some("Mine")),
+            listed(3, "lime", None),
+        ]);
+
+        let changes = Changes::between(&old, &new);
+        assert_eq!(
+            changes.added,
+            [listed(1, "blue", Some("Mine")), listed(3, "lime", None)]
+        );
+        assert_eq!(
+            changes.removed,
+            [listed(1, "blue", None), listed(2, "white", None)]
+        );
+        assert!(Changes::between(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn clusters() {
+        let positions = [(0, 0), (10, 20), (-5, 0), (100, 100)];
+
+        let clusters = cluster(&positions, 64);
+
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(clusters[0].features, [2]);
+        assert_eq!(clusters[1].coordinates, [5, 10]);
+        assert_eq!(clusters[1].features, [0, 1]);
+        assert_eq!(clusters[2].features, [3]);
+    }
+
+    #[test]
+    fn exported() {
+        let point = |x: i32, z: i32| json!({ "type": "Feature", "geometry": { "type": "Point", "coordinates": [x, z] } });
+        let features = [point(100, 64), point(-28, -448)];
+        let export = |features: &[Value], options: &GeoJson| {
+            let mut json = Vec::new();
+            export(features.iter().cloned().map(Ok), options, &mut json).unwrap();
+            serde_json::from_slice::<Value>(&json).unwrap()
+        };
+
+        let exported = export(&features, &GeoJson::default());
+        assert_eq!(exported["type"], "FeatureCollection");
+        assert_eq!(
+            exported["crs"]["properties"]["name"],
+            "urn:ogc:def:crs:EPSG::404000"
+        );
+        assert_eq!(exported["bbox"], json!([-28.0, -448.0, 100.0, 64.0]));
+
+        let options = GeoJson {
+            flip: true,
+            scale: 1.0 / 128.0,
+            ..GeoJson::default()
+        };
+        let exported = export(&features, &options);
+        assert_eq!(
+            exported["features"][1]["geometry"]["coordinates"],
+            json!([-0.21875, 3.5])
+        );
+        assert_eq!(exported["bbox"], json!([-0.21875, -0.5, 0.78125, 3.5]));
+
+        assert!(export(&[], &options).get("bbox").is_none());
+    }
+}
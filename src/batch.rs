@@ -0,0 +1,80 @@
+use crate::map::{Map, MapData};
+use crate::tile::{Composite, Tile};
+use crate::warning::WarningCollector;
+use crate::Error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Tile and the ids of the maps drawn onto it from the top of the stack down
+type Drawing = (Tile, Vec<u32>);
+
+/// Map data and composited tiles shared by the renders of several outputs from one search, e.g. of
+/// profiles, so that each is read or drawn only once. Everything is held until dropped, so a batch
+/// spans one run.
+#[derive(Default)]
+pub struct Batch {
+    data: Mutex<HashMap<u32, MapData>>,
+    composites: Mutex<HashMap<Drawing, Arc<Composite>>>,
+}
+
+impl Batch {
+    // Warnings of a map are collected only where it's first read
+    pub fn map_data(
+        &self,
+        world_path: &Path,
+        id: u32,
+        warnings: &WarningCollector,
+    ) -> Result<MapData, Error> {
+        if let Some(data) = self.data.lock().unwrap().get(&id) {
+            return Ok(data.clone());
+        }
+
+        let data = MapData::from_world_path(world_path, id, warnings)?;
+        self.data.lock().unwrap().insert(id, data.clone());
+
+        Ok(data)
+    }
+
+    pub fn composite(&self, tile: &Tile, maps: &[&(&Map, MapData)]) -> Arc<Composite> {
+        let key = (tile.clone(), maps.iter().map(|(m, _)| m.id).collect());
+        if let Some(composite) = self.composites.lock().unwrap().get(&key) {
+            return Arc::clone(composite);
+        }
+
+        let composite = Arc::new(Composite::draw(tile, maps.iter().copied()));
+        self.composites
+            .lock()
+            .unwrap()
+            .insert(key, Arc::clone(&composite));
+
+        composite
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn reuse() {
+        let batch = Batch::default();
+        let tile = Tile::new(4, 0, 0);
+        let map = |id| Map {
+            modified: SystemTime::UNIX_EPOCH,
+            id,
+            tile: tile.clone(),
+            locked: false,
+            tracking: true,
+        };
+        let (one, two) = (map(1), map(2));
+        let a = (&one, MapData([6; 128 * 128]));
+        let b = (&two, MapData([0; 128 * 128]));
+
+        let first = batch.composite(&tile, &[&a, &b]);
+        assert!(Arc::ptr_eq(&first, &batch.composite(&tile, &[&a, &b])));
+        assert!(!Arc::ptr_eq(&first, &batch.composite(&tile, &[&b, &a])));
+        assert!(!Arc::ptr_eq(&first, &batch.composite(&tile, &[&a])));
+    }
+}
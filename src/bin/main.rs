@@ -1,22 +1,914 @@
-use anyhow::Result;
-use little_a_map::{level::Level, render, search};
-use std::path::PathBuf;
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use little_a_map::config::{Config, ImageFormat, MapImages, MarkerPalette, Prune, Shading};
+use little_a_map::corpus;
+use little_a_map::daemon::{self, Schedule};
+use little_a_map::diff::diff;
+use little_a_map::dimension::Dimension;
+use little_a_map::i18n::Lang;
+use little_a_map::level::{resolve_world_path, Level};
+use little_a_map::merge::{merge, Source};
+use little_a_map::message::{Format, Message};
+use little_a_map::pattern::TilePattern;
+use little_a_map::{
+    cache_size, cache_status, prune_cache, region_statistics, verify_cache, Bounds, CacheEntries,
+    CacheStatus, Force, Generator, Survey, COMPATIBLE_VERSIONS,
+};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+use structopt::clap::{AppSettings, Error, ErrorKind};
 use structopt::StructOpt;
 
+#[allow(clippy::struct_excessive_bools)] // Flags of the command line
 #[derive(StructOpt)]
+#[structopt(setting = AppSettings::ArgsNegateSubcommands)]
 struct Args {
     #[structopt(name = "world dir", parse(from_os_str))]
-    world: PathBuf,
+    world: Option<PathBuf>,
 
     #[structopt(name = "output dir", parse(from_os_str))]
-    output: PathBuf,
+    output: Option<PathBuf>,
+
+    /// Search only regions within X0,Z0,X1,Z1 in region coordinates
+    #[structopt(long, parse(try_from_str = parse_bounds))]
+    bounds: Option<Bounds>,
+
+    /// Serve Leaflet from the output rather than a CDN, copying its dist dir from here, as with
+    /// `assets` in the config
+    #[structopt(long, parse(from_os_str))]
+    assets: Option<PathBuf>,
+
+    /// Also split banners by root tile for the viewer to load only those in view, as with
+    /// `banner_shards = true` in the config
+    #[structopt(long)]
+    banner_shards: bool,
+
+    /// Compress the search cache at this zstd level, e.g. 19 for a smaller cache written more
+    /// slowly, overriding `cache_compression` in the config
+    #[structopt(long)]
+    cache_compression: Option<i32>,
+
+    /// Keep the search cache in this dir rather than in the output dir, overriding `cache_dir` in
+    /// the config
+    #[structopt(long, parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// Write the paths of the output files written by each run to this file, e.g. for
+    /// `rsync --files-from`, overriding `changed_list` in the config
+    #[structopt(long, parse(from_os_str))]
+    changed_list: Option<PathBuf>,
+
+    /// Also search command storage of datapacks for maps, as with `command_storage = true` in the
+    /// config
+    #[structopt(long)]
+    command_storage: bool,
+
+    /// Read settings such as map ids to include or exclude from this TOML file
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Hard-link identical tiles to one file, as with `dedupe_tiles = true` in the config
+    #[structopt(long)]
+    dedupe_tiles: bool,
+
+    /// Have the viewer refresh tiles as announced by /events at this URL, overriding `events_url`
+    /// in the config
+    #[structopt(long)]
+    events_url: Option<String>,
+
+    /// Disregard the search cache and modification times of outputs, regenerating everything on
+    /// the first run
+    #[structopt(long)]
+    force: bool,
+
+    /// Render these maps by comma-separated id, and the tiles they appear in, on the first run
+    #[structopt(long, require_delimiter = true)]
+    force_maps: Vec<u32>,
+
+    /// Render every output on the first run without disregarding the search cache
+    #[structopt(long)]
+    force_render: bool,
+
+    /// Search the whole world on the first run rather than only regions modified since the cache
+    #[structopt(long)]
+    force_search: bool,
+
+    /// Render the tiles within these, by comma-separated ZOOM/X/Y as in the tiles dir, on the
+    /// first run
+    #[structopt(long, require_delimiter = true, parse(try_from_str = parse_tile))]
+    force_tiles: Vec<(u8, i32, i32)>,
+
+    /// Also write an overlay colored by how recently each area was mapped, as with
+    /// `freshness = true` in the config
+    #[structopt(long)]
+    freshness: bool,
+
+    /// Also write a gallery.html page of the maps likely to be map art, as with `gallery = true`
+    /// in the config
+    #[structopt(long)]
+    gallery: bool,
+
+    /// Also write banners, maps, and tiles to `world.gpkg` for GIS tools, as with
+    /// `geopackage = true` in the config
+    #[cfg(feature = "gis")]
+    #[structopt(long)]
+    geopackage: bool,
+
+    /// Encode images as webp, png, avif if built with that feature, or indexed (raw palette
+    /// indices, not for the viewer), overriding `image_format` in the config
+    #[structopt(long)]
+    image_format: Option<ImageFormat>,
+
+    /// Keep running, regenerating the output after each interval, e.g. "5m"
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    interval: Option<Duration>,
+
+    /// Delay each run by up to this much additional random time
+    #[structopt(long, default_value = "0s", parse(try_from_str = humantime::parse_duration))]
+    jitter: Duration,
+
+    /// Language of the viewer: de, en, es, fr, or ja, overriding `lang` in the config
+    #[structopt(long)]
+    lang: Option<Lang>,
+
+    /// Shade map colors in linear light rather than as the game does, as with
+    /// `colors.linear = true` in the config
+    #[structopt(long)]
+    linear_colors: bool,
+
+    /// Serve /health, /metrics, and /events at this address while running with --interval
+    #[structopt(long)]
+    listen: Option<SocketAddr>,
+
+    /// Write a MANIFEST listing each output file with its size and checksum, as with
+    /// `manifest = true` in the config
+    #[structopt(long)]
+    manifest: bool,
+
+    /// Fail rather than render more than this many maps, overriding `max_maps` in the config
+    #[structopt(long)]
+    max_maps: Option<usize>,
+
+    /// Open at most this many files of the world at once, overriding `max_open_files` in the
+    /// config
+    #[structopt(long)]
+    max_open_files: Option<usize>,
+
+    /// Fail rather than render tiles estimated to total more than this many bytes, overriding
+    /// `max_output_size` in the config
+    #[structopt(long)]
+    max_output_size: Option<u64>,
+
+    /// Write individual map images for all maps, only those bearing banners (popup), or none
+    /// (off), overriding `map_images` in the config
+    #[structopt(long)]
+    map_images: Option<MapImages>,
+
+    /// Color banner markers as in the game, with a colorblind-friendly palette (colorblind), or
+    /// in grays (grayscale), overriding `marker_palette` in the config
+    #[structopt(long)]
+    marker_palette: Option<MarkerPalette>,
+
+    /// Also write outlines of the maps for the viewer to show by scale, as with `outlines = true`
+    /// in the config
+    #[structopt(long)]
+    outlines: bool,
+
+    /// Search the inventories of only this player by UUID, which may be repeated, overriding
+    /// `players.include` in the config
+    #[structopt(long, number_of_values = 1)]
+    player_filter: Vec<String>,
+
+    /// Also write gzip-compressed copies of text outputs for static hosting, as with
+    /// `precompress = true` in the config
+    #[structopt(long)]
+    precompress: bool,
+
+    /// Write a Chrome trace of the phases of each run, e.g. for Perfetto, to a file of its own in
+    /// this dir
+    #[cfg(feature = "profiling")]
+    #[structopt(long, parse(from_os_str))]
+    profile: Option<PathBuf>,
+
+    /// Prune outputs of maps no longer found: never, safe (only after a complete search), or
+    /// aggressive, overriding `prune` in the config
+    #[structopt(long)]
+    prune: Option<Prune>,
+
+    /// Refuse to write anything within the world, as with `read_only = true` in the config
+    #[structopt(long)]
+    read_only: bool,
+
+    /// Highlight in the viewer the banners added by the latest change of banners, as with
+    /// `recent_banners = true` in the config
+    #[structopt(long)]
+    recent_banners: bool,
+
+    /// Write each run to its own release within the output, publishing it as `current` once
+    /// complete and keeping this many, overriding `releases` in the config
+    #[structopt(long)]
+    releases: Option<usize>,
+
+    /// Print messages as text, or as JSON objects one per line including warnings and errors
+    #[structopt(long, default_value = "text")]
+    output_format: Format,
+
+    /// Search again, up to this many times, any files modified by the game during the search
+    #[structopt(long, default_value = "0")]
+    rescan: usize,
+
+    /// Shade map colors by relief as the game does, or flat in the middle shade, overriding
+    /// `colors.shading` in the config
+    #[structopt(long)]
+    shading: Option<Shading>,
+
+    /// Also write statistics of the world to run.json and stats.html, as with
+    /// `statistics = true` in the config
+    #[structopt(long)]
+    statistics: bool,
+
+    /// Fail rather than discard a search cache that can't be read, as with `strict_cache = true`
+    /// in the config
+    #[structopt(long)]
+    strict_cache: bool,
+
+    /// Search and scan the maps, then report how many maps, banners, and tiles would be rendered
+    /// and where, without rendering anything
+    #[structopt(long, conflicts_with = "interval")]
+    stats_only: bool,
+
+    /// Number the tiles from the root tile containing the block at X,Z, e.g. "20000,-8000",
+    /// overriding `tile_origin` in the config
+    #[structopt(long, parse(try_from_str = parse_position))]
+    tile_origin: Option<(i32, i32)>,
+
+    /// Name the tiles by this pattern of {z}, {x}, {y}, and {hash}, e.g. "{hash}/{z}_{x}_{y}",
+    /// overriding `tile_pattern` in the config
+    #[structopt(long)]
+    tile_pattern: Option<TilePattern>,
+
+    /// Before each search, wait until no file of the world has been modified for this long,
+    /// e.g. "10s", so that a save in progress is read once finished
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    wait_for_quiesce: Option<Duration>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Check that the NBT snippets of a corpus dir read as their snapshots record, optionally first
+    /// capturing a chunk of a world into the corpus, e.g. one that fails to be searched
+    #[structopt(setting = AppSettings::Hidden)]
+    Corpus {
+        /// Replace the snapshots with what's read rather than comparing
+        #[structopt(long)]
+        bless: bool,
+
+        /// Capture the chunk at X,Z in chunk coordinates of the world given by --world
+        #[structopt(long, requires = "world", parse(try_from_str = parse_position))]
+        chunk: Option<(i32, i32)>,
+
+        /// Dimension of the chunk to capture: `overworld`, `the_nether`, or `the_end`
+        #[structopt(long, default_value = "overworld", parse(try_from_str = parse_dimension))]
+        dimension: Dimension,
+
+        /// World dir from which to capture the chunk given by --chunk
+        #[structopt(long, requires = "chunk", parse(from_os_str))]
+        world: Option<PathBuf>,
+
+        #[structopt(name = "corpus dir", parse(from_os_str))]
+        corpus: PathBuf,
+    },
+
+    /// Report tiles, maps, and banners added, removed, or changed between two output dirs
+    Diff {
+        /// Print the report as JSON
+        #[structopt(long)]
+        json: bool,
+
+        #[structopt(name = "old output dir", parse(from_os_str))]
+        old: PathBuf,
+
+        #[structopt(name = "new output dir", parse(from_os_str))]
+        new: PathBuf,
+    },
+
+    /// Describe the state of an output directory
+    Info {
+        /// Read the search cache from this dir rather than from the output dir
+        #[structopt(long, parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
+
+        /// List map counts and modification times of each searched region
+        #[structopt(long)]
+        regions: bool,
+
+        /// Also describe the game version of this world and whether it's compatible
+        #[structopt(long, parse(from_os_str))]
+        world: Option<PathBuf>,
+
+        #[structopt(name = "output dir", parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Composite the outputs of several runs into one site
+    Merge {
+        /// Block coordinates X,Z at which to center the view
+        #[structopt(long, default_value = "0,0", parse(try_from_str = parse_position))]
+        center: (i32, i32),
+
+        /// Re-merge all tiles and maps regardless of modification times
+        #[structopt(long)]
+        force: bool,
+
+        #[structopt(name = "output dir", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Source output directories as [NAME=]PATH[@X,Z], where the optional
+        /// offset X,Z is in blocks and must be a multiple of 2048
+        #[structopt(name = "source", required = true)]
+        sources: Vec<Source>,
+    },
+
+    /// Drop entries of the search cache for files gone from the world, as each search does too
+    PruneCache {
+        /// Read the search cache from this dir rather than from the output dir
+        #[structopt(long, parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
+
+        #[structopt(name = "world dir", parse(from_os_str))]
+        world: PathBuf,
+
+        #[structopt(name = "output dir", parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Search the world afresh and report where the cache disagrees
+    VerifyCache {
+        /// Search only regions within X0,Z0,X1,Z1 in region coordinates
+        #[structopt(long, parse(try_from_str = parse_bounds))]
+        bounds: Option<Bounds>,
+
+        /// Read the search cache from this dir rather than from the output dir
+        #[structopt(long, parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
+
+        #[structopt(name = "world dir", parse(from_os_str))]
+        world: PathBuf,
+
+        #[structopt(name = "output dir", parse(from_os_str))]
+        output: PathBuf,
+    },
+}
+
+fn parse_bounds(text: &str) -> Result<Bounds> {
+    let parts = text
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<i32>, _>>()?;
+    let [x0, z0, x1, z1] = parts[..] else {
+        anyhow::bail!("Expected X0,Z0,X1,Z1: {text}");
+    };
+
+    Ok(((x0.min(x1), z0.min(z1)), (x0.max(x1), z0.max(z1))))
+}
+
+fn parse_tile(text: &str) -> Result<(u8, i32, i32)> {
+    let parts = text.split('/').collect::<Vec<_>>();
+    let [zoom, x, y] = parts[..] else {
+        bail!("Expected ZOOM/X/Y: {text}");
+    };
+    let zoom = zoom.parse()?;
+    if zoom > 4 {
+        bail!("Expected a zoom of 0 through 4: {text}");
+    }
+
+    Ok((zoom, x.parse()?, y.parse()?))
+}
+
+fn parse_dimension(text: &str) -> Result<Dimension> {
+    Dimension::ALL
+        .into_iter()
+        .find(|d| d.name() == text)
+        .ok_or_else(|| anyhow::anyhow!("Expected overworld, the_nether, or the_end: {text}"))
+}
+
+fn parse_position(text: &str) -> Result<(i32, i32)> {
+    let (x, z) = text
+        .split_once(',')
+        .ok_or_else(|| anyhow::anyhow!("Expected X,Z: {text}"))?;
+
+    Ok((x.parse()?, z.parse()?))
+}
+
+fn info(
+    output: &Path,
+    cache_dir: Option<&Path>,
+    world: Option<&Path>,
+    regions: bool,
+) -> Result<()> {
+    let statistics = region_statistics(output, cache_dir)?;
+    let cache = cache_status(output, cache_dir)?;
+    let size = cache_size(output, cache_dir)?;
+    let level = world
+        .map(|w| Level::read(&resolve_world_path(w)?))
+        .transpose()?;
+    let now = SystemTime::now();
+    let with_maps = statistics.iter().filter(|s| s.maps > 0);
+    let bounds = with_maps.clone().fold(None, |bounds, s| {
+        let (x, z) = s.position;
+        Some(
+            bounds.map_or(((x, z), (x, z)), |((x0, z0), (x1, z1)): Bounds| {
+                ((x0.min(x), z0.min(z)), (x1.max(x), z1.max(z)))
+            }),
+        )
+    });
+
+    if Format::get() == Format::Json {
+        let mut message = json!({
+            "event": "info",
+            "regions": statistics.len(),
+            "regions_with_maps": with_maps.count(),
+            "bounds": bounds.map(|((x0, z0), (x1, z1))| [x0, z0, x1, z1]),
+            "cache": cache,
+            "cache_size": size,
+            "compatible_versions": COMPATIBLE_VERSIONS,
+        });
+        if let Some(level) = &level {
+            message["world"] = json!({
+                "version": level.version.to_string(),
+                "data_version": level.data_version,
+                "compatible": level.is_compatible(),
+            });
+        }
+        if regions {
+            message["region_statistics"] = statistics
+                .iter()
+                .map(|s| -> Result<_> {
+                    Ok(json!({
+                        "dimension": s.dimension.name(),
+                        "kind": s.kind,
+                        "position": [s.position.0, s.position.1],
+                        "maps": s.maps,
+                        "chunks_with_maps": s.chunks_with_maps,
+                        "chunks": s.chunks,
+                        "modified": s.modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+                    }))
+                })
+                .collect::<Result<_>>()?;
+        }
+        println!("{message}");
+        return Ok(());
+    }
+
+    if regions {
+        for s in &statistics {
+            let (x, z) = s.position;
+            let age = now.duration_since(s.modified).unwrap_or_default();
+            println!(
+                "{} {} region ({x}, {z}): {} maps in {} of {} chunks, modified {} ago",
+                s.dimension.name(),
+                s.kind,
+                s.maps,
+                s.chunks_with_maps,
+                s.chunks,
+                humantime::format_duration(Duration::from_secs(age.as_secs()))
+            );
+        }
+    }
+
+    println!(
+        "Searched {} regions, of which {} contain maps",
+        statistics.len(),
+        with_maps.count()
+    );
+    if let Some(((x0, z0), (x1, z1))) = bounds {
+        println!("Regions containing maps are within --bounds {x0},{z0},{x1},{z1}");
+    }
+    println!(
+        "Search cache is {}",
+        match cache {
+            CacheStatus::Absent => "absent",
+            CacheStatus::Current => "current",
+            CacheStatus::Outdated => "outdated and will be rebuilt",
+            CacheStatus::Corrupt => "corrupt and will be rebuilt",
+        }
+    );
+    if cache == CacheStatus::Current {
+        println!(
+            "Search cache holds {} in {} bytes",
+            describe_entries(&size.entries),
+            size.bytes
+        );
+    }
+    println!("Compatible with game versions {COMPATIBLE_VERSIONS}");
+    if let Some(level) = &level {
+        println!(
+            "World is of game version {} (DataVersion {}), {}",
+            level.version,
+            level.data_version,
+            if level.is_compatible() {
+                "compatible"
+            } else {
+                "incompatible"
+            }
+        );
+    }
+
+    Ok(())
+}
+
+fn describe_entries(entries: &CacheEntries) -> String {
+    format!(
+        "{} block regions, {} entity regions, {} players, {} command storage, and {} quarantined files",
+        entries.block_regions,
+        entries.entity_regions,
+        entries.players,
+        entries.storage,
+        entries.quarantined
+    )
+}
+
+fn prune(world: &Path, output: &Path, cache_dir: Option<&Path>) -> Result<()> {
+    let dropped = prune_cache(&resolve_world_path(world)?, output, cache_dir)?;
+    let size = cache_size(output, cache_dir)?;
+
+    if Format::get() == Format::Json {
+        println!(
+            "{}",
+            json!({ "event": "cache_pruned", "dropped": dropped, "cache_size": size })
+        );
+    } else {
+        println!(
+            "Dropped from the cache {} of files gone from the world",
+            describe_entries(&dropped)
+        );
+        println!(
+            "Search cache holds {} in {} bytes",
+            describe_entries(&size.entries),
+            size.bytes
+        );
+    }
+
+    Ok(())
+}
+
+fn report_survey(survey: &Survey) {
+    if Format::get() == Format::Json {
+        println!("{}", json!({ "event": "survey", "survey": survey }));
+        return;
+    }
+
+    println!(
+        "Would render {} maps with {} banners onto {} tiles of about {} bytes",
+        survey.maps, survey.banners, survey.tiles, survey.estimated_bytes
+    );
+    if let Some(((x0, z0), (x1, z1))) = survey.bounds {
+        println!("Tiles span blocks {x0},{z0} through {x1},{z1}");
+    }
+    for (dimension, count) in &survey.statistics.maps_by_dimension {
+        println!("Found {count} maps of {dimension}");
+    }
+}
+
+fn compare(old: &Path, new: &Path, as_json: bool) -> Result<()> {
+    let diff = diff(old, new)?;
+
+    if as_json || Format::get() == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        for (kind, changes) in [
+            ("tiles", &diff.tiles),
+            ("maps", &diff.maps),
+            ("banners", &diff.banners),
+        ] {
+            println!(
+                "{} {kind} added, {} removed, {} changed",
+                changes.added.len(),
+                changes.removed.len(),
+                changes.changed.len()
+            );
+            for (sign, keys) in [
+                ('+', &changes.added),
+                ('-', &changes.removed),
+                ('~', &changes.changed),
+            ] {
+                for key in keys {
+                    println!("  {sign} {key}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_corpus(
+    corpus_path: &Path,
+    bless: bool,
+    capture: Option<(&Path, Dimension, (i32, i32))>,
+) -> Result<()> {
+    if let Some((world, dimension, chunk)) = capture {
+        for path in corpus::capture(&resolve_world_path(world)?, dimension, chunk, corpus_path)? {
+            if Format::get() == Format::Text {
+                println!("Captured {}", path.display());
+            }
+        }
+    }
+
+    let outcomes = corpus::run(corpus_path, bless)?;
+    let failed = outcomes.iter().filter(|o| o.diff.is_some()).count();
+
+    if Format::get() == Format::Json {
+        println!(
+            "{}",
+            json!({ "event": "corpus_checked", "outcomes": outcomes })
+        );
+    } else {
+        for outcome in &outcomes {
+            match &outcome.diff {
+                Some(diff) => println!("{}: differs from its snapshot\n{diff}", outcome.snippet),
+                None => println!("{}: ok", outcome.snippet),
+            }
+        }
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        bail!(
+            "{failed} of {} snippets differ from their snapshots",
+            outcomes.len()
+        )
+    }
+}
+
+fn verify(
+    world: &Path,
+    output: &Path,
+    cache_dir: Option<&Path>,
+    bounds: Option<&Bounds>,
+) -> Result<()> {
+    let discrepancies = verify_cache(
+        &resolve_world_path(world)?,
+        output,
+        cache_dir,
+        false,
+        bounds,
+    )?;
+    let list = |ids: &BTreeSet<u32>| ids.iter().map(ToString::to_string).join(", ");
+
+    if Format::get() == Format::Json {
+        println!(
+            "{}",
+            json!({ "event": "cache_verified", "discrepancies": discrepancies })
+        );
+    } else {
+        for d in &discrepancies {
+            if !d.missing.is_empty() {
+                println!("{}: cache lacks maps {}", d.source, list(&d.missing));
+            }
+            if !d.unexpected.is_empty() {
+                println!("{}: cache has extra maps {}", d.source, list(&d.unexpected));
+            }
+        }
+    }
+
+    if discrepancies.is_empty() {
+        if Format::get() == Format::Text {
+            println!("Cache agrees with world");
+        }
+        Ok(())
+    } else {
+        bail!(
+            "Cache disagrees with world in {} places, which is expected only where the world has changed since the last search",
+            discrepancies.len()
+        )
+    }
+}
+
+// In JSON, log records are messages too and so are written to stdout
+fn init_logger(format: Format) {
+    match format {
+        Format::Text => env_logger::init(),
+        Format::Json => {
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+                .target(env_logger::Target::Stdout)
+                .format(|buf, record| {
+                    let message = Message::Log {
+                        level: &record.level().as_str().to_ascii_lowercase(),
+                        target: record.target(),
+                        message: record.args().to_string(),
+                    };
+                    writeln!(buf, "{}", serde_json::to_string(&message)?)
+                })
+                .init();
+        }
+    }
 }
 
 #[paw::main]
-fn main(Args { output, world }: Args) -> Result<()> {
-    env_logger::init();
+fn main(args: Args) -> Result<()> {
+    args.output_format.set();
+    init_logger(args.output_format);
+
+    let result = run(args);
+    if let (Err(e), Format::Json) = (&result, Format::get()) {
+        Message::Error {
+            message: format!("{e:#}"),
+        }
+        .emit();
+        process::exit(1);
+    }
+
+    result
+}
+
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Some(Command::Corpus {
+            bless,
+            chunk,
+            dimension,
+            world,
+            corpus,
+        }) => check_corpus(
+            &corpus,
+            bless,
+            world.as_deref().zip(chunk).map(|(w, c)| (w, dimension, c)),
+        ),
+        Some(Command::Diff { json, old, new }) => compare(&old, &new, json),
+        Some(Command::Info {
+            cache_dir,
+            regions,
+            world,
+            output,
+        }) => info(&output, cache_dir.as_deref(), world.as_deref(), regions),
+        Some(Command::Merge {
+            center,
+            force,
+            output,
+            sources,
+        }) => Ok(merge(&output, &sources, center, false, force)?),
+        Some(Command::PruneCache {
+            cache_dir,
+            world,
+            output,
+        }) => prune(&world, &output, cache_dir.as_deref()),
+        Some(Command::VerifyCache {
+            bounds,
+            cache_dir,
+            world,
+            output,
+        }) => verify(&world, &output, cache_dir.as_deref(), bounds.as_ref()),
+        None => {
+            let (Some(world), Some(output)) = (args.world, args.output) else {
+                Error::with_description(
+                    "The world dir and output dir arguments are required",
+                    ErrorKind::MissingRequiredArgument,
+                )
+                .exit()
+            };
+
+            let mut config = args
+                .config
+                .as_deref()
+                .map(Config::from_path)
+                .transpose()?
+                .unwrap_or_default();
+            config.banner_shards |= args.banner_shards;
+            config.colors.linear |= args.linear_colors;
+            config.command_storage |= args.command_storage;
+            config.dedupe_tiles |= args.dedupe_tiles;
+            config.freshness |= args.freshness;
+            config.gallery |= args.gallery;
+            #[cfg(feature = "gis")]
+            {
+                config.geopackage |= args.geopackage;
+            }
+            config.manifest |= args.manifest;
+            config.outlines |= args.outlines;
+            config.precompress |= args.precompress;
+            config.read_only |= args.read_only;
+            config.recent_banners |= args.recent_banners;
+            config.statistics |= args.statistics;
+            config.strict_cache |= args.strict_cache;
+            if let Some(assets) = args.assets {
+                config.assets = Some(assets);
+            }
+            if let Some(cache_compression) = args.cache_compression {
+                config.cache_compression = Some(cache_compression);
+            }
+            if let Some(cache_dir) = args.cache_dir {
+                config.cache_dir = Some(cache_dir);
+            }
+            if let Some(changed_list) = args.changed_list {
+                config.changed_list = Some(changed_list);
+            }
+            if let Some(events_url) = args.events_url {
+                config.events_url = Some(events_url);
+            }
+            if let Some(image_format) = args.image_format {
+                config.image_format = image_format;
+            }
+            if let Some(lang) = args.lang {
+                config.lang = lang;
+            }
+            if let Some(map_images) = args.map_images {
+                config.map_images = map_images;
+            }
+            if let Some(marker_palette) = args.marker_palette {
+                config.marker_palette = marker_palette;
+            }
+            if let Some(max_maps) = args.max_maps {
+                config.max_maps = Some(max_maps);
+            }
+            if let Some(max_open_files) = args.max_open_files {
+                config.max_open_files = Some(max_open_files);
+            }
+            if let Some(max_output_size) = args.max_output_size {
+                config.max_output_size = Some(max_output_size);
+            }
+            if !args.player_filter.is_empty() {
+                config.players.include = args.player_filter;
+            }
+            if let Some(prune) = args.prune {
+                config.prune = prune;
+            }
+            if let Some(releases) = args.releases {
+                config.releases = Some(releases);
+            }
+            if let Some(shading) = args.shading {
+                config.colors.shading = shading;
+            }
+            if let Some(tile_origin) = args.tile_origin {
+                config.tile_origin = tile_origin;
+            }
+            if let Some(tile_pattern) = args.tile_pattern {
+                config.tile_pattern = tile_pattern;
+            }
+            let build = |force: &Force| {
+                let mut builder = Generator::builder()
+                    .world(&world)
+                    .output(&output)
+                    .force_search(force.search)
+                    .force_render(force.render)
+                    .force_maps(force.maps.iter().copied())
+                    .force_tiles(force.tiles.iter().copied())
+                    .rescans(args.rescan)
+                    .config(config.clone());
+                if let Some(bounds) = args.bounds {
+                    builder = builder.bounds(bounds);
+                }
+                if let Some(stable) = args.wait_for_quiesce {
+                    builder = builder.wait_for_quiesce(stable);
+                }
+                #[cfg(feature = "profiling")]
+                if let Some(dir) = &args.profile {
+                    builder = builder.profile(dir);
+                }
+                builder.build()
+            };
+            let force = Force {
+                search: args.force || args.force_search,
+                render: args.force || args.force_render,
+                maps: args.force_maps.into_iter().collect(),
+                tiles: args.force_tiles.into_iter().collect(),
+            };
+            if args.stats_only {
+                report_survey(&build(&force)?.survey()?);
+                return Ok(());
+            }
+            let generator = build(&Force::default())?;
+
+            // Forced only once rather than on every interval
+            let mut forced = (!force.is_empty()).then(|| build(&force)).transpose()?;
+            let mut generate = || -> Result<_> {
+                let report = match forced.take() {
+                    Some(forced) => forced.run()?,
+                    None => generator.run()?,
+                };
+                Ok(report.tiles_changed)
+            };
 
-    let level = Level::from_world_path(&world)?;
-    let map_ids = search(&world, &output, false, false, None)?;
-    render(&world, &output, false, false, &level, &map_ids)
+            match args.interval {
+                Some(interval) => {
+                    let jitter = args.jitter;
+                    Ok(daemon::run(
+                        &Schedule { interval, jitter },
+                        args.listen,
+                        generate,
+                    )?)
+                }
+                None => generate().map(drop),
+            }
+        }
+    }
 }
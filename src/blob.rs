@@ -0,0 +1,61 @@
+use anyhow::Result;
+use log::debug;
+use std::fs;
+use std::io::ErrorKind::AlreadyExists;
+use std::path::{Path, PathBuf};
+
+// Identical files are hard links to one blob, found by checksum and length and then confirmed by
+// content so that a collision merely goes undeduplicated.
+fn blob_path(output_path: &Path, data: &[u8]) -> PathBuf {
+    output_path.join(format!(
+        ".blobs/{:08x}-{:x}",
+        crc32fast::hash(data),
+        data.len()
+    ))
+}
+
+// Blobs are published only by linking a complete file, so a blob is never read partially written.
+pub fn write(output_path: &Path, data: &[u8], path: &Path) -> Result<()> {
+    let blob_path = blob_path(output_path, data);
+
+    if fs::read(&blob_path).map_or(false, |blob| blob == data) {
+        fs::hard_link(&blob_path, path)?;
+    } else {
+        fs::write(path, data)?;
+        fs::create_dir_all(blob_path.parent().unwrap())?;
+        match fs::hard_link(path, &blob_path) {
+            Err(e) if e.kind() != AlreadyExists => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// A blob linked from nowhere else is no longer used
+#[cfg(unix)]
+pub fn prune(output_path: &Path) -> Result<usize> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir_path = output_path.join(".blobs");
+    if !dir_path.is_dir() {
+        return Ok(0);
+    }
+
+    let mut pruned = 0;
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        if entry.metadata()?.nlink() == 1 {
+            debug!("Prune: {}", entry.path().display());
+            fs::remove_file(entry.path())?;
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+#[cfg(not(unix))]
+pub fn prune(_output_path: &Path) -> Result<usize> {
+    Ok(0)
+}
@@ -1,53 +1,477 @@
-use anyhow::Result;
+use crate::banner::Labels;
+use crate::config::PlayerSelection;
+use crate::dimension::Dimension;
+use crate::utilities::read_modified;
+use anyhow::{ensure, Result};
 use serde::de::{self, Unexpected, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
 use std::fs::{self, File};
+#[cfg(feature = "native")]
+use std::io::BufReader;
 use std::io::ErrorKind::NotFound;
-use std::path::Path;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
+#[cfg(feature = "native")]
 use zstd::stream::{read::Decoder as ZstdDecoder, write::Encoder as ZstdEncoder};
 
-pub type IdsBy<K> = HashMap<K, HashSet<u32>>;
+pub type IdsBy<K> = HashMap<K, MapIds>;
+pub type SummaryBy<K> = HashMap<K, RegionSummary>;
+pub type Region = (Dimension, i32, i32);
+
+// Leads the file, uncompressed and followed by the schema, so that a cache of a layout other than
+// this build's is told apart from a corrupt one even where the version is the same. The schema is
+// bumped by any change to the layout of `Cache` or of anything within it.
+const MAGIC: &[u8; 4] = b"LAMC";
+const SCHEMA: u32 = 1;
+
+// Trained by `zstd --train` on caches of varied worlds, to which the repetitive bincode layout of
+// region keys, summaries, and player UUIDs is common, so that small caches compress better
+#[cfg(feature = "native")]
+const DICTIONARY: &[u8] = include_bytes!("cache.dict");
+
+// The cache is compressed by zstd, or where built without C libraries by gzip, which has neither
+// the dictionary nor levels beyond 9. Level 0 is the default of either.
+#[cfg(feature = "native")]
+pub fn compression_levels() -> RangeInclusive<i32> {
+    zstd::compression_level_range()
+}
+
+#[cfg(not(feature = "native"))]
+pub const fn compression_levels() -> RangeInclusive<i32> {
+    0..=9
+}
+
+// Each codec's stream leads with its own magic number, by which a build with zstd reads caches of
+// either codec, and a build without it tells a cache compressed by zstd from a corrupt one.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+fn peek_magic(f: &mut File) -> io::Result<[u8; 4]> {
+    let mut magic = [0; 4];
+    f.read_exact(&mut magic)?;
+    f.seek(SeekFrom::Current(-4))?;
+    Ok(magic)
+}
+
+fn decompress(mut f: File, primed: bool) -> io::Result<Box<dyn Read>> {
+    if peek_magic(&mut f)?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(flate2::read::GzDecoder::new(f)))
+    } else {
+        unzstd(f, primed)
+    }
+}
+
+#[cfg(feature = "native")]
+fn unzstd(f: File, primed: bool) -> io::Result<Box<dyn Read>> {
+    Ok(Box::new(ZstdDecoder::with_dictionary(
+        BufReader::new(f),
+        if primed { DICTIONARY } else { &[] },
+    )?))
+}
+
+#[cfg(not(feature = "native"))]
+fn unzstd(_f: File, _primed: bool) -> io::Result<Box<dyn Read>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Compressed by zstd, which this build lacks",
+    ))
+}
+
+#[cfg(feature = "native")]
+fn compress(f: File, level: i32, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<File> {
+    let mut z = ZstdEncoder::with_dictionary(f, level, DICTIONARY)?;
+    write(&mut z)?;
+    Ok(z.finish()?)
+}
+
+#[cfg(not(feature = "native"))]
+fn compress(f: File, level: i32, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<File> {
+    let level = match u32::try_from(level) {
+        Ok(0) | Err(_) => flate2::Compression::default(),
+        Ok(level) => flate2::Compression::new(level),
+    };
+    let mut z = flate2::write::GzEncoder::new(f, level);
+    write(&mut z)?;
+    Ok(z.finish()?)
+}
+
+/// Map IDs found in a file, each with the number of copies of it, e.g. 3 of a stack of three
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MapIds(HashMap<u32, u32>);
+
+impl MapIds {
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.keys().copied()
+    }
+
+    pub fn copies(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.0.iter().map(|(&id, &copies)| (id, copies))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Each copied as many times, e.g. the contents of a stack
+    #[must_use]
+    pub fn times(mut self, count: u32) -> Self {
+        for copies in self.0.values_mut() {
+            *copies = copies.saturating_mul(count);
+        }
+        self
+    }
+}
+
+// Copies of the same ID are summed
+impl Extend<(u32, u32)> for MapIds {
+    fn extend<I: IntoIterator<Item = (u32, u32)>>(&mut self, iter: I) {
+        for (id, copies) in iter {
+            let total = self.0.entry(id).or_default();
+            *total = total.saturating_add(copies);
+        }
+    }
+}
+
+impl FromIterator<(u32, u32)> for MapIds {
+    fn from_iter<I: IntoIterator<Item = (u32, u32)>>(iter: I) -> Self {
+        let mut ids = Self::default();
+        ids.extend(iter);
+        ids
+    }
+}
+
+impl IntoIterator for MapIds {
+    type Item = (u32, u32);
+    type IntoIter = std::collections::hash_map::IntoIter<u32, u32>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+// One copy of each
+impl<const N: usize> From<[u32; N]> for MapIds {
+    fn from(ids: [u32; N]) -> Self {
+        ids.into_iter().map(|id| (id, 1)).collect()
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RegionSummary {
+    pub modified: SystemTime,
+    pub chunks: usize,
+    pub chunks_with_maps: usize,
+}
+
+impl Default for RegionSummary {
+    fn default() -> Self {
+        Self {
+            modified: SystemTime::UNIX_EPOCH,
+            chunks: usize::default(),
+            chunks_with_maps: usize::default(),
+        }
+    }
+}
+
+/// Counts of the entries of a search cache by the kind of file they're of
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct CacheEntries {
+    pub block_regions: usize,
+    pub entity_regions: usize,
+    pub players: usize,
+    pub storage: usize,
+    pub quarantined: usize,
+}
+
+impl CacheEntries {
+    #[must_use]
+    pub const fn total(&self) -> usize {
+        self.block_regions + self.entity_regions + self.players + self.storage + self.quarantined
+    }
+}
+
+/// Why files were searched rather than taken from the cache
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMiss {
+    /// No cache was found, e.g. on the first run
+    Absent,
+    /// The cache was disregarded on request
+    Forced,
+    /// The cache was written by another version
+    Outdated,
+    /// The cache failed to be read, e.g. having been truncated
+    Corrupt,
+    /// New or modified since the cache was written
+    Modified,
+}
+
+/// Why a cache was discarded and replaced by a search of the whole world
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Unreadable {
+    /// Written by another version or schema, e.g. "version 0.13.2"
+    Outdated(String),
+    Corrupt(String),
+}
+
+impl fmt::Display for Unreadable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Outdated(writer) => write!(f, "written by {writer}"),
+            Self::Corrupt(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl From<&Unreadable> for CacheMiss {
+    fn from(unreadable: &Unreadable) -> Self {
+        match unreadable {
+            Unreadable::Outdated(_) => Self::Outdated,
+            Unreadable::Corrupt(_) => Self::Corrupt,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct QuarantinedFile {
+    pub modified: u64,
+    pub error: String,
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct Cache {
     #[serde(skip)]
     pub modified: Option<SystemTime>,
 
+    // Kept alongside rather than within so that it can be inspected
+    #[serde(skip)]
+    pub quarantine: BTreeMap<PathBuf, QuarantinedFile>,
+
+    // Of a cache that was found but discarded
+    #[serde(skip)]
+    pub unreadable: Option<Unreadable>,
+
     #[serde(deserialize_with = "validate_version")]
     version: String,
 
-    pub map_ids_by_entities_region: IdsBy<(i32, i32)>,
-    pub map_ids_by_block_region: IdsBy<(i32, i32)>,
-    pub map_ids_by_player: IdsBy<usize>,
+    pub map_ids_by_entities_region: IdsBy<Region>,
+    pub map_ids_by_block_region: IdsBy<Region>,
+    pub map_ids_by_player: IdsBy<String>,
+    pub map_ids_by_storage: IdsBy<String>,
+    pub summary_by_entities_region: SummaryBy<Region>,
+    pub summary_by_block_region: SummaryBy<Region>,
+    pub players: PlayerSelection,
+
+    // Mean bytes of a tile image as of the previous run, by which the growth of the output is
+    // estimated before rendering
+    pub tile_size: Option<u64>,
+
+    // Banner labels of every map of the world, by which uniqueness isn't judged only among the
+    // maps of one output
+    pub labels: Labels,
+}
+
+fn quarantine_path(path: &Path) -> PathBuf {
+    path.with_file_name("quarantine.json")
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    path.with_extension("dat.partial")
+}
+
+// None of a cache written before the schema was recorded
+fn read_schema(f: &mut File) -> io::Result<Option<u32>> {
+    let mut header = [0; MAGIC.len() + 4];
+    f.read_exact(&mut header)?;
+
+    Ok(header
+        .strip_prefix(MAGIC)
+        .map(|schema| u32::from_le_bytes(schema.try_into().unwrap())))
 }
 
 impl Cache {
+    fn read(mut f: File) -> Result<Self> {
+        let schema = read_schema(&mut f)?;
+        ensure!(schema == Some(SCHEMA), "Unexpected cache schema {schema:?}");
+
+        Ok(bincode::deserialize_from(decompress(f, true)?)?)
+    }
+
+    // The schema and then the version lead the layout, so they're read apart from the rest to tell
+    // a cache of another version or build from a corrupt one. Caches written before the schema was
+    // recorded are of another layout even where of the same version, and some of them were
+    // compressed without a dictionary.
+    fn diagnose(path: &Path, error: &anyhow::Error) -> Unreadable {
+        let version = |f: File, primed| -> Result<String> {
+            Ok(bincode::deserialize_from(decompress(f, primed)?)?)
+        };
+        let writer = || -> Result<Option<String>> {
+            let mut f = File::open(path)?;
+            let schema = read_schema(&mut f)?;
+            if schema.is_none() {
+                f.rewind()?;
+            }
+            if !cfg!(feature = "native") && peek_magic(&mut f)? == ZSTD_MAGIC {
+                return Ok(Some("a build with zstd".to_owned()));
+            }
+
+            Ok(match schema {
+                Some(schema) if schema != SCHEMA => Some(format!("cache schema {schema}")),
+                Some(_) => Some(version(f, true)?)
+                    .filter(|v| v != env!("CARGO_PKG_VERSION"))
+                    .map(|v| format!("version {v}")),
+                None => {
+                    let legacy = version(File::open(path)?, true)
+                        .or_else(|_| version(File::open(path)?, false))?;
+                    Some(format!("version {legacy} of an earlier cache schema"))
+                }
+            })
+        };
+
+        match writer() {
+            Ok(Some(writer)) => Unreadable::Outdated(writer),
+            _ => Unreadable::Corrupt(format!("{error:#}")),
+        }
+    }
+
+    // A cache that fails to be read is replaced by a default one noting why
     pub fn from_path(path: &Path) -> Result<Self> {
-        match File::open(path) {
-            Ok(f) => {
-                let mut cache =
-                    bincode::deserialize_from::<_, Self>(ZstdDecoder::new(f)?).unwrap_or_default();
-                cache.modified = Some(fs::metadata(path)?.modified()?);
+        let mut cache = match File::open(path) {
+            Ok(f) => match Self::read(f) {
+                Ok(mut cache) => {
+                    cache.modified = Some(fs::metadata(path)?.modified()?);
+                    cache
+                }
+                Err(e) => Self {
+                    unreadable: Some(Self::diagnose(path, &e)),
+                    ..Self::default()
+                },
+            },
+            Err(e) if e.kind() == NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        match File::open(quarantine_path(path)) {
+            Ok(f) => cache.quarantine = serde_json::from_reader(f).unwrap_or_default(),
+            Err(e) if e.kind() == NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(cache)
+    }
 
-                Ok(cache)
+    // Of the search that this cache spares, or None if it's current
+    pub fn miss(&self) -> Option<CacheMiss> {
+        match (&self.unreadable, self.modified) {
+            (Some(unreadable), _) => Some(unreadable.into()),
+            (None, None) => Some(CacheMiss::Absent),
+            (None, Some(_)) => None,
+        }
+    }
+
+    // Of blocks and entities only, since players and storage are of no one dimension
+    pub fn dimensions_by_map_id(&self) -> HashMap<u32, BTreeSet<Dimension>> {
+        let mut dimensions = HashMap::<_, BTreeSet<_>>::new();
+        let regions = self.map_ids_by_block_region.iter();
+        for (&(dimension, ..), ids) in regions.chain(&self.map_ids_by_entities_region) {
+            for id in ids.ids() {
+                dimensions.entry(id).or_default().insert(dimension);
             }
-            Err(e) if e.kind() == NotFound => Ok(Self::default()),
-            Err(e) => Err(e.into()),
+        }
+
+        dimensions
+    }
+
+    pub fn entries(&self) -> CacheEntries {
+        CacheEntries {
+            block_regions: self.summary_by_block_region.len(),
+            entity_regions: self.summary_by_entities_region.len(),
+            players: self.map_ids_by_player.len(),
+            storage: self.map_ids_by_storage.len(),
+            quarantined: self.quarantine.len(),
+        }
+    }
+
+    // Entries of files gone from the world, e.g. regions deleted to reset them or players pruned,
+    // are dropped so that their maps are no longer found and the cache doesn't grow without bound.
+    // Returned are the counts of those dropped.
+    pub fn collect_garbage(&mut self, world_path: &Path) -> CacheEntries {
+        let before = self.entries();
+        let region_exists = |kind: &str, &(dimension, x, z): &Region| {
+            dimension.path(world_path).map_or(false, |dimension_path| {
+                dimension_path
+                    .join(kind)
+                    .join(format!("r.{x}.{z}.mca"))
+                    .exists()
+            })
+        };
+
+        self.map_ids_by_block_region
+            .retain(|r, _| region_exists("region", r));
+        self.summary_by_block_region
+            .retain(|r, _| region_exists("region", r));
+        self.map_ids_by_entities_region
+            .retain(|r, _| region_exists("entities", r));
+        self.summary_by_entities_region
+            .retain(|r, _| region_exists("entities", r));
+        self.map_ids_by_player
+            .retain(|uuid, _| world_path.join(format!("playerdata/{uuid}.dat")).exists());
+        self.map_ids_by_storage
+            .retain(|name, _| world_path.join(format!("data/{name}.dat")).exists());
+        self.quarantine.retain(|p, _| p.exists());
+
+        let after = self.entries();
+        CacheEntries {
+            block_regions: before.block_regions - after.block_regions,
+            entity_regions: before.entity_regions - after.entity_regions,
+            players: before.players - after.players,
+            storage: before.storage - after.storage,
+            quarantined: before.quarantined - after.quarantined,
         }
     }
 
     pub fn is_expired_for(&self, path: &Path) -> Result<bool> {
-        let modified = fs::metadata(path)?.modified()?;
+        let modified = read_modified(path)?;
         Ok(self.modified.map_or(true, |m| m < modified))
     }
 
-    pub fn write_to(&self, path: &Path) -> Result<()> {
+    // Written aside and renamed into place so that a crash mid-write leaves the previous cache
+    // rather than a truncated one, which would be replaced by a full search
+    pub fn write_to(&self, path: &Path, modified: SystemTime, level: i32) -> Result<()> {
         fs::create_dir_all(path.parent().unwrap())?;
-        let z = ZstdEncoder::new(File::create(path)?, 0)?.auto_finish();
-        Ok(bincode::serialize_into(z, self)?)
+        let partial_path = partial_path(path);
+        let mut f = File::create(&partial_path)?;
+        f.write_all(MAGIC)?;
+        f.write_all(&SCHEMA.to_le_bytes())?;
+        let f = compress(f, level, |w| Ok(bincode::serialize_into(w, self)?))?;
+        f.set_modified(modified)?;
+        f.sync_all()?;
+        fs::rename(&partial_path, path)?;
+
+        let quarantine_path = quarantine_path(path);
+        let quarantine = self
+            .quarantine
+            .iter()
+            .filter(|(p, _)| p.exists())
+            .collect::<BTreeMap<_, _>>();
+        if quarantine.is_empty() {
+            match fs::remove_file(quarantine_path) {
+                Err(e) if e.kind() != NotFound => return Err(e.into()),
+                _ => {}
+            }
+        } else {
+            serde_json::to_writer_pretty(File::create(quarantine_path)?, &quarantine)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -57,6 +481,14 @@ impl Default for Cache {
             map_ids_by_entities_region: HashMap::default(),
             map_ids_by_block_region: HashMap::default(),
             map_ids_by_player: HashMap::default(),
+            map_ids_by_storage: HashMap::default(),
+            quarantine: BTreeMap::default(),
+            unreadable: Option::default(),
+            summary_by_entities_region: HashMap::default(),
+            summary_by_block_region: HashMap::default(),
+            tile_size: Option::default(),
+            labels: Labels::default(),
+            players: PlayerSelection::default(),
             modified: Option::default(),
             version: env!("CARGO_PKG_VERSION").to_owned(),
         }
@@ -112,7 +544,12 @@ mod test {
             "version": version.as_ref(),
             "map_ids_by_entities_region": {},
             "map_ids_by_block_region": {},
-            "map_ids_by_player": {}
+            "map_ids_by_player": {},
+            "map_ids_by_storage": {},
+            "summary_by_entities_region": {},
+            "summary_by_block_region": {},
+            "labels": { "by_map": {} },
+            "players": {}
         }))?)
     }
 
@@ -124,4 +561,171 @@ mod test {
         assert!(with_version(next_version(current)).is_err());
         assert!(with_version(previous_version(current)).is_err());
     }
+
+    #[test]
+    fn write_to() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let path = dir.path().join("cache.dat");
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        let mut cache = Cache::default();
+        cache
+            .map_ids_by_block_region
+            .insert((Dimension::Overworld, 0, 0), [1, 2].into());
+        for level in [0, *compression_levels().end()] {
+            cache.write_to(&path, modified, level).unwrap();
+
+            let read = Cache::from_path(&path).unwrap();
+            assert_eq!(read.modified, Some(modified));
+            assert_eq!(read.map_ids_by_block_region, cache.map_ids_by_block_region);
+            assert!(!partial_path(&path).exists());
+        }
+
+        // Left behind by a crash mid-write
+        fs::write(partial_path(&path), "").unwrap();
+        let read = Cache::from_path(&path).unwrap();
+        assert_eq!(read.entries().block_regions, 1);
+        assert_eq!(read.miss(), None);
+        cache.write_to(&path, modified, 0).unwrap();
+        assert!(!partial_path(&path).exists());
+    }
+
+    #[test]
+    fn diagnose() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let path = dir.path().join("cache.dat");
+        assert_eq!(
+            Cache::from_path(&path).unwrap().miss(),
+            Some(CacheMiss::Absent)
+        );
+
+        let mut cache = Cache {
+            version: next_version(env!("CARGO_PKG_VERSION")),
+            ..Cache::default()
+        };
+        cache.write_to(&path, SystemTime::now(), 0).unwrap();
+        let read = Cache::from_path(&path).unwrap();
+        assert_eq!(read.miss(), Some(CacheMiss::Outdated));
+        assert_eq!(
+            read.unreadable.unwrap().to_string(),
+            format!("written by version {}", cache.version)
+        );
+
+        // Written before the schema was recorded, even by the same version, and compressed without
+        // a dictionary
+        #[cfg(feature = "native")]
+        for version in [cache.version.clone(), env!("CARGO_PKG_VERSION").to_owned()] {
+            let legacy = Cache {
+                version: version.clone(),
+                ..Cache::default()
+            };
+            let mut z = ZstdEncoder::new(File::create(&path).unwrap(), 0).unwrap();
+            bincode::serialize_into(&mut z, &legacy).unwrap();
+            z.finish().unwrap();
+            let read = Cache::from_path(&path).unwrap();
+            assert_eq!(read.miss(), Some(CacheMiss::Outdated));
+            assert_eq!(
+                read.unreadable.unwrap().to_string(),
+                format!("written by version {version} of an earlier cache schema")
+            );
+        }
+
+        cache.version = env!("CARGO_PKG_VERSION").to_owned();
+        cache.write_to(&path, SystemTime::now(), 0).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let mut other_schema = bytes.clone();
+        other_schema[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(SCHEMA + 1).to_le_bytes());
+        fs::write(&path, other_schema).unwrap();
+        let read = Cache::from_path(&path).unwrap();
+        assert_eq!(read.miss(), Some(CacheMiss::Outdated));
+        assert_eq!(
+            read.unreadable.unwrap().to_string(),
+            format!("written by cache schema {}", SCHEMA + 1)
+        );
+
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert_eq!(
+            Cache::from_path(&path).unwrap().miss(),
+            Some(CacheMiss::Corrupt)
+        );
+
+        fs::write(&path, "stale").unwrap();
+        assert_eq!(
+            Cache::from_path(&path).unwrap().miss(),
+            Some(CacheMiss::Corrupt)
+        );
+    }
+
+    #[test]
+    fn codecs() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let path = dir.path().join("cache.dat");
+        let mut f = File::create(&path).unwrap();
+        f.write_all(MAGIC).unwrap();
+        f.write_all(&SCHEMA.to_le_bytes()).unwrap();
+
+        // Written by a build of the other kind
+        #[cfg(feature = "native")]
+        {
+            let mut z = flate2::write::GzEncoder::new(f, flate2::Compression::default());
+            bincode::serialize_into(&mut z, &Cache::default()).unwrap();
+            z.finish().unwrap();
+            assert_eq!(Cache::from_path(&path).unwrap().miss(), None);
+        }
+        #[cfg(not(feature = "native"))]
+        {
+            f.write_all(&ZSTD_MAGIC).unwrap();
+            let read = Cache::from_path(&path).unwrap();
+            assert_eq!(read.miss(), Some(CacheMiss::Outdated));
+            assert_eq!(
+                read.unreadable.unwrap().to_string(),
+                "written by a build with zstd"
+            );
+        }
+    }
+
+    #[test]
+    fn collect_garbage() {
+        let world = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let world_path = world.path();
+        let player = "65836968-3bd4-4a3b-a8b8-5b5a2e9e8a1e";
+        fs::create_dir_all(world_path.join("region")).unwrap();
+        fs::create_dir_all(world_path.join("playerdata")).unwrap();
+        fs::write(world_path.join("region/r.0.0.mca"), "").unwrap();
+        fs::write(world_path.join(format!("playerdata/{player}.dat")), "").unwrap();
+
+        let mut cache = Cache::default();
+        for region in [(Dimension::Overworld, 0, 0), (Dimension::Overworld, 1, 0)] {
+            cache.map_ids_by_block_region.insert(region, [1].into());
+            cache
+                .summary_by_block_region
+                .insert(region, RegionSummary::default());
+        }
+        cache
+            .summary_by_entities_region
+            .insert((Dimension::Nether, 0, 0), RegionSummary::default());
+        cache
+            .map_ids_by_player
+            .insert(player.to_owned(), [2].into());
+        cache
+            .map_ids_by_player
+            .insert("gone".to_owned(), [3].into());
+
+        let dropped = cache.collect_garbage(world_path);
+        assert_eq!(
+            dropped,
+            CacheEntries {
+                block_regions: 1,
+                entity_regions: 1,
+                players: 1,
+                ..CacheEntries::default()
+            }
+        );
+        assert_eq!(cache.entries().total(), 2);
+        assert!(cache
+            .map_ids_by_block_region
+            .contains_key(&(Dimension::Overworld, 0, 0)));
+        assert_eq!(cache.collect_garbage(world_path).total(), 0);
+    }
 }
@@ -0,0 +1,501 @@
+use crate::i18n::Lang;
+use crate::layer::Layers;
+use crate::map;
+use crate::pattern::TilePattern;
+use crate::Error;
+use itertools::Itertools;
+use log::debug;
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[allow(clippy::struct_excessive_bools)] // Toggles of the output
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub assets: Option<PathBuf>,
+    pub banner_shards: bool,
+    pub cache_compression: Option<i32>,
+    pub cache_dir: Option<PathBuf>,
+    pub changed_list: Option<PathBuf>,
+    pub colors: Colors,
+    pub command_storage: bool,
+    pub dedupe_tiles: bool,
+    pub events_url: Option<String>,
+    pub freshness: bool,
+    pub gallery: bool,
+    pub geojson: Option<GeoJson>,
+    #[cfg(feature = "gis")]
+    pub geopackage: bool,
+    pub hide_banners: bool,
+    pub history: Option<History>,
+    pub icons: BTreeMap<String, String>,
+    pub image_format: ImageFormat,
+    pub lang: Lang,
+    // Provided by a user of the library rather than the config file
+    #[serde(skip)]
+    pub layers: Layers,
+    pub manifest: bool,
+    pub map_images: MapImages,
+    pub maps: MapSelection,
+    pub marker_palette: MarkerPalette,
+    pub max_maps: Option<usize>,
+    pub max_open_files: Option<usize>,
+    pub max_output_size: Option<u64>,
+    pub outlines: bool,
+    pub players: PlayerSelection,
+    pub precompress: bool,
+    pub profiles: Vec<Profile>,
+    pub prune: Prune,
+    pub read_only: bool,
+    pub recent_banners: bool,
+    pub releases: Option<usize>,
+    pub statistics: bool,
+    pub strict_cache: bool,
+    pub tile_origin: (i32, i32),
+    pub tile_pattern: TilePattern,
+}
+
+impl Config {
+    // Settings of the primary output apply also to each profile except where overridden, while
+    // history, the list of changed files, and releases are kept only for the primary output.
+    #[must_use]
+    pub fn for_profile(&self, profile: &Profile) -> Self {
+        Self {
+            changed_list: None,
+            hide_banners: profile.hide_banners,
+            history: None,
+            maps: profile.maps.clone(),
+            profiles: Vec::new(),
+            releases: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        toml::from_str(&fs::read_to_string(path).map_err(|e| Error::io(path, e))?)
+            .map_err(|e| Error::Config(format!("Failed to parse {}: {e}", path.display())))
+    }
+}
+
+// Additional output rendered from the same search
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub output: PathBuf,
+    #[serde(default)]
+    pub hide_banners: bool,
+    #[serde(default)]
+    pub maps: MapSelection,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct History {
+    pub keep: usize,
+    pub period: Period,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            keep: 24,
+            period: Period::Month,
+        }
+    }
+}
+
+// Banners are exported for GIS tools apart from banners.json, whose block coordinates the viewer
+// relies on. By default the CRS is the wildcard Cartesian plane in meters, i.e. one per block.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoJson {
+    pub crs: String,
+    pub flip: bool,
+    pub scale: f64,
+}
+
+impl Default for GeoJson {
+    fn default() -> Self {
+        Self {
+            crs: "urn:ogc:def:crs:EPSG::404000".to_owned(),
+            flip: false,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Month,
+}
+
+// Individual map images are shown only when inspecting the map, so they can be limited to maps
+// that bear banners, which are the likeliest to be inspected.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MapImages {
+    Off,
+    Popup,
+    #[default]
+    All,
+}
+
+impl FromStr for MapImages {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "off" => Ok(Self::Off),
+            "popup" => Ok(Self::Popup),
+            "all" => Ok(Self::All),
+            _ => Err(Error::Config(format!("Expected off, popup, or all: {s}"))),
+        }
+    }
+}
+
+// Banner markers are drawn in the colors of the game, or for colorblind viewers in colors more
+// distinct under common color vision deficiencies, or in distinct shades of gray, e.g. for print
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkerPalette {
+    #[default]
+    Game,
+    Colorblind,
+    Grayscale,
+}
+
+impl FromStr for MarkerPalette {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "game" => Ok(Self::Game),
+            "colorblind" => Ok(Self::Colorblind),
+            "grayscale" => Ok(Self::Grayscale),
+            _ => Err(Error::Config(format!(
+                "Expected game, colorblind, or grayscale: {s}"
+            ))),
+        }
+    }
+}
+
+// Shades of each base color are derived as the game does by scaling its channels, which darkens
+// them more than the same scale would in linear light. Flat shading uses the middle shade for all
+// four, e.g. to compare colors without the relief of the terrain.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub linear: bool,
+    pub shading: Shading,
+}
+
+impl fmt::Display for Colors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} shading in {}",
+            self.shading.name(),
+            if self.linear { "linear light" } else { "sRGB" }
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Shading {
+    #[default]
+    Relief,
+    Flat,
+}
+
+impl Shading {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Relief => "relief",
+            Self::Flat => "flat",
+        }
+    }
+}
+
+impl FromStr for Shading {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "relief" => Ok(Self::Relief),
+            "flat" => Ok(Self::Flat),
+            _ => Err(Error::Config(format!("Expected relief or flat: {s}"))),
+        }
+    }
+}
+
+// Outputs of maps no longer found are pruned only when every map may have been found, unless
+// aggressive. A search is incomplete when bounded or when any file failed to be read.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Prune {
+    Never,
+    #[default]
+    Safe,
+    Aggressive,
+}
+
+// Images of maps and tiles are WebP unless traded for the wider support of PNG or the smaller size
+// of AVIF. Raw palette indices are meant for further processing rather than for the viewer.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[cfg(feature = "avif")]
+    Avif,
+    Indexed,
+    Png,
+    #[default]
+    WebP,
+}
+
+impl FromStr for ImageFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            #[cfg(feature = "avif")]
+            "avif" => Ok(Self::Avif),
+            "indexed" => Ok(Self::Indexed),
+            "png" => Ok(Self::Png),
+            "webp" => Ok(Self::WebP),
+            _ => Err(Error::Config(format!(
+                "Expected {}: {s}",
+                Self::ALL.iter().map(|f| f.extension()).join(", ")
+            ))),
+        }
+    }
+}
+
+impl Prune {
+    #[must_use]
+    pub const fn permits(self, complete: bool) -> bool {
+        match self {
+            Self::Never => false,
+            Self::Safe => complete,
+            Self::Aggressive => true,
+        }
+    }
+}
+
+impl FromStr for Prune {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "never" => Ok(Self::Never),
+            "safe" => Ok(Self::Safe),
+            "aggressive" => Ok(Self::Aggressive),
+            _ => Err(Error::Config(format!(
+                "Expected never, safe, or aggressive: {s}"
+            ))),
+        }
+    }
+}
+
+// Applied to search results rather than during search so that the cache remains complete
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MapSelection {
+    pub include: Vec<IdRange>,
+    pub exclude: Vec<IdRange>,
+}
+
+impl MapSelection {
+    #[must_use]
+    pub fn apply(&self, world_path: &Path, ids: &HashSet<u32>) -> HashSet<u32> {
+        let is_excluded = |id: &u32| self.exclude.iter().any(|r| r.0.contains(id));
+
+        let included = self
+            .include
+            .iter()
+            .flat_map(|r| r.0.clone())
+            .filter(|id| !ids.contains(id))
+            .filter(|id| {
+                let exists = map::data_path(world_path, *id).exists();
+                if !exists {
+                    debug!("Map {id} is included but does not exist");
+                }
+                exists
+            });
+
+        ids.iter()
+            .copied()
+            .chain(included)
+            .filter(|id| !is_excluded(id))
+            .collect()
+    }
+}
+
+// Unlike the selection of maps, applied during search so that the inventories of players left out
+// are neither read nor kept in the cache, which records the selection under which it was made
+#[derive(Clone, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerSelection {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl PlayerSelection {
+    #[must_use]
+    pub fn permits(&self, uuid: &str) -> bool {
+        let lists = |list: &[String]| list.iter().any(|u| u.eq_ignore_ascii_case(uuid));
+
+        (self.include.is_empty() || lists(&self.include)) && !lists(&self.exclude)
+    }
+}
+
+#[derive(Clone)]
+pub struct IdRange(RangeInclusive<u32>);
+
+impl<'de> Deserialize<'de> for IdRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IdRangeVisitor;
+
+        impl Visitor<'_> for IdRangeVisitor {
+            type Value = IdRange;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("map id or range of map ids such as \"10-20\"")
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                let id = u32::try_from(value)
+                    .map_err(|_| E::invalid_value(Unexpected::Signed(value), &self))?;
+
+                Ok(IdRange(id..=id))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                let invalid = || E::invalid_value(Unexpected::Str(value), &self);
+                let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+                let start = start.trim().parse().map_err(|_| invalid())?;
+                let end = end.trim().parse().map_err(|_| invalid())?;
+
+                Ok(IdRange(start..=end))
+            }
+        }
+
+        deserializer.deserialize_any(IdRangeVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use itertools::{assert_equal, Itertools};
+
+    #[test]
+    fn ranges() {
+        let config: Config = toml::from_str(
+            r#"
+            [maps]
+            exclude = [3, "10-12"]
+            "#,
+        )
+        .unwrap();
+        let ids = (0..15).collect();
+
+        let selected = config.maps.apply(Path::new("/nonexistent"), &ids);
+
+        assert_equal(
+            selected.into_iter().sorted(),
+            [0, 1, 2, 4, 5, 6, 7, 8, 9, 13, 14],
+        );
+    }
+
+    #[test]
+    fn history() {
+        let config: Config = toml::from_str("[history]\nperiod = \"day\"").unwrap();
+        let history = config.history.unwrap();
+
+        assert_eq!(history.keep, 24);
+        assert!(matches!(history.period, Period::Day));
+        assert!(toml::from_str::<Config>("").unwrap().history.is_none());
+    }
+
+    #[test]
+    fn profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            precompress = true
+
+            [history]
+
+            [[profiles]]
+            output = "/srv/public"
+            hide_banners = true
+            maps.exclude = [3]
+            "#,
+        )
+        .unwrap();
+        let profile = config.for_profile(&config.profiles[0]);
+        let ids = (0..5).collect();
+
+        assert!(profile.precompress);
+        assert!(profile.hide_banners);
+        assert!(profile.history.is_none());
+        assert!(profile.profiles.is_empty());
+        assert_equal(
+            profile
+                .maps
+                .apply(Path::new("/nonexistent"), &ids)
+                .into_iter()
+                .sorted(),
+            [0, 1, 2, 4],
+        );
+        assert!(toml::from_str::<Config>("[[profiles]]\nhide_banners = true").is_err());
+    }
+
+    #[test]
+    fn prune() {
+        let config: Config = toml::from_str("prune = \"never\"").unwrap();
+
+        assert_eq!(config.prune, Prune::Never);
+        assert_eq!(Config::default().prune, Prune::Safe);
+        assert!(!Prune::Safe.permits(false));
+        assert!(Prune::Aggressive.permits(false));
+        assert!("sometimes".parse::<Prune>().is_err());
+    }
+
+    #[test]
+    fn players() {
+        let (a, b) = (
+            "65836968-df64-4ca6-af3c-12f547c7f765",
+            "0d3e4f2a-7c1b-4e8e-9b7a-2f6c1d5e8a90",
+        );
+        let config: Config =
+            toml::from_str(&format!("[players]\nexclude = [\"{}\"]", a.to_uppercase())).unwrap();
+
+        assert!(!config.players.permits(a));
+        assert!(config.players.permits(b));
+        assert!(PlayerSelection::default().permits(a));
+
+        let included = PlayerSelection {
+            include: vec![b.to_owned()],
+            exclude: Vec::new(),
+        };
+        assert!(!included.permits(a));
+        assert!(included.permits(b));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(toml::from_str::<Config>("[maps]\nexclude = [-1]").is_err());
+        assert!(toml::from_str::<Config>("[maps]\nexclude = [\"ten\"]").is_err());
+        assert!(toml::from_str::<Config>("[maps]\nhide = [1]").is_err());
+    }
+}
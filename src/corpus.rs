@@ -0,0 +1,261 @@
+use crate::anvil;
+use crate::cache::MapIds;
+use crate::dimension::Dimension;
+use crate::geometry::{region_of_chunk, REGION_CHUNKS};
+use crate::level::Level;
+use crate::map::MapScan;
+use crate::open_files::OpenFiles;
+use crate::paths::{glob_in, slashed};
+use crate::search::{MapIdsOfEntitiesChunk, MapIdsOfLevelChunk, MapIdsOfPlayer, MapIdsOfStorage};
+use crate::Error;
+use anyhow::{anyhow, Result};
+use fastnbt::from_bytes;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind::NotFound;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// A corpus holds snippets of uncompressed NBT by game version and kind, e.g.
+// `1.21.4/entities/c.-4.-3.nbt`, each beside a snapshot `.snap` of what was read from it as sorted
+// lines of text so that a change of format by the game shows as a readable diff.
+
+/// Kind of NBT in a corpus, named as the dir of the world that it's captured from
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Entities,
+    Maps,
+    Players,
+    Region,
+    Storage,
+}
+
+impl Kind {
+    pub const ALL: [Self; 5] = [
+        Self::Entities,
+        Self::Maps,
+        Self::Players,
+        Self::Region,
+        Self::Storage,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Entities => "entities",
+            Self::Maps => "maps",
+            Self::Players => "players",
+            Self::Region => "region",
+            Self::Storage => "storage",
+        }
+    }
+}
+
+/// Result of one snippet of a corpus
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    /// Relative to the corpus, e.g. `1.21.4/entities/c.-4.-3.nbt`
+    pub snippet: String,
+    /// Game version of the snippet
+    pub version: String,
+    /// Lines of the snapshot that weren't read (`-`) and lines read that aren't in the snapshot
+    /// (`+`), if any
+    pub diff: Option<String>,
+}
+
+/// Read each snippet of a corpus as the search does and compare with its snapshot, or with `bless`
+/// replace the snapshot instead
+pub fn run(corpus_path: &Path, bless: bool) -> Result<Vec<Outcome>, Error> {
+    Ok(glob_in(corpus_path, "*/*/*.nbt")?
+        .map(|entry| -> Result<_> {
+            let path = entry?;
+            let relative = path.strip_prefix(corpus_path)?;
+            let version = relative.iter().next().unwrap().to_string_lossy();
+            let kind_name = path.parent().and_then(Path::file_name).unwrap();
+            let kind = Kind::ALL
+                .into_iter()
+                .find(|k| kind_name == k.name())
+                .ok_or_else(|| anyhow!("Unknown kind of snippet: {}", path.display()))?;
+            let name = path.file_stem().unwrap().to_string_lossy();
+
+            let actual = describe(kind, &name, &fs::read(&path)?)
+                .into_iter()
+                .map(|line| line + "\n")
+                .collect::<String>();
+            let snapshot_path = path.with_extension("snap");
+            let expected = match fs::read_to_string(&snapshot_path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+
+            let diff = if bless {
+                if actual != expected {
+                    fs::write(&snapshot_path, &actual)?;
+                }
+                None
+            } else {
+                diff(&expected, &actual)
+            };
+
+            Ok(Outcome {
+                snippet: slashed(relative),
+                version: version.into_owned(),
+                diff,
+            })
+        })
+        .collect::<Result<_>>()?)
+}
+
+/// Copy the block and entity data of one chunk of a world into a corpus, e.g. one that fails to be
+/// searched, as snippets to submit for diagnosis
+pub fn capture(
+    world_path: &Path,
+    dimension: Dimension,
+    (x, z): (i32, i32),
+    corpus_path: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    let version = Level::read(world_path)?.version.to_string();
+    let dimension_path = dimension.path(world_path).ok_or_else(|| {
+        Error::Other(format!(
+            "Dimension {} not found in {}",
+            dimension.name(),
+            world_path.display()
+        ))
+    })?;
+    let name = match dimension {
+        Dimension::Overworld => format!("c.{x}.{z}"),
+        _ => format!("{}.c.{x}.{z}", dimension.name()),
+    };
+
+    let (rx, rz) = region_of_chunk((x, z));
+    let position = (x.rem_euclid(REGION_CHUNKS), z.rem_euclid(REGION_CHUNKS));
+    let mut captured = Vec::new();
+    for kind in [Kind::Region, Kind::Entities] {
+        let region_path = dimension_path
+            .join(kind.name())
+            .join(format!("r.{rx}.{rz}.mca"));
+        if !region_path.exists() {
+            continue;
+        }
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)] // 0..32
+        let chunk = anvil::chunks(&region_path, (rx, rz), &OpenFiles::new(None))?
+            .into_iter()
+            .find(|c| (c.x as i32, c.z as i32) == position);
+        if let Some(chunk) = chunk {
+            let path = corpus_path
+                .join(&version)
+                .join(kind.name())
+                .join(format!("{name}.nbt"));
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, chunk.data)?;
+            captured.push(path);
+        }
+    }
+
+    if captured.is_empty() {
+        return Err(Error::Other(format!(
+            "Chunk ({x}, {z}) not found in {}",
+            dimension_path.display()
+        )));
+    }
+
+    Ok(captured)
+}
+
+// Maps are described by what's rendered of them, and anything else by the map ids found in it
+fn describe(kind: Kind, name: &str, nbt: &[u8]) -> Vec<String> {
+    let ids = |ids: MapIds| ids.ids().sorted().map(|id| format!("map {id}")).collect();
+    let described = || -> Result<Vec<String>> {
+        Ok(match kind {
+            Kind::Entities => ids(from_bytes::<MapIdsOfEntitiesChunk>(nbt)?.0),
+            Kind::Maps => {
+                let id = name
+                    .strip_prefix("map_")
+                    .and_then(|id| id.parse().ok())
+                    .unwrap_or_default();
+                describe_map(&MapScan::from_nbt(id, nbt, SystemTime::UNIX_EPOCH)?)
+            }
+            Kind::Players => ids(from_bytes::<MapIdsOfPlayer>(nbt)?.0),
+            Kind::Region => ids(from_bytes::<MapIdsOfLevelChunk>(nbt)?.0),
+            Kind::Storage => ids(from_bytes::<MapIdsOfStorage>(nbt)?.0),
+        })
+    };
+
+    described().unwrap_or_else(|e| vec![format!("error: {e:#}")])
+}
+
+fn describe_map(scan: &MapScan) -> Vec<String> {
+    let dimensions = scan
+        .maps_by_dimension
+        .keys()
+        .map(|d| format!("dimension {}", d.name()));
+    let tiles = scan
+        .maps_by_tile
+        .keys()
+        .map(|t| format!("tile {}/{}/{}", t.zoom, t.x, t.y));
+    let explorer = (!scan.explorer_map_ids.is_empty()).then(|| "explorer".to_owned());
+    let banners = scan.banners.keys().map(|b| {
+        let line = format!("banner {} {} {}", b.x, b.z, b.color);
+        match &b.label {
+            Some(label) => format!("{line} {label:?}"),
+            None => line,
+        }
+    });
+    let frames = scan
+        .frames
+        .keys()
+        .map(|f| format!("frame {} {} {}", f.x, f.z, f.rotation));
+
+    dimensions
+        .chain(tiles)
+        .chain(explorer)
+        .chain(banners)
+        .chain(frames)
+        .sorted()
+        .collect()
+}
+
+fn diff(expected: &str, actual: &str) -> Option<String> {
+    let expected_lines = expected.lines().collect::<HashSet<_>>();
+    let actual_lines = actual.lines().collect::<HashSet<_>>();
+
+    let lines = expected
+        .lines()
+        .filter(|l| !actual_lines.contains(l))
+        .map(|l| format!("- {l}"))
+        .chain(
+            actual
+                .lines()
+                .filter(|l| !expected_lines.contains(l))
+                .map(|l| format!("+ {l}")),
+        )
+        .collect_vec();
+
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diffs() {
+        assert_eq!(diff("map 1\nmap 2\n", "map 1\nmap 2\n"), None);
+        assert_eq!(
+            diff("map 1\nmap 2\n", "map 2\nmap 3\n").as_deref(),
+            Some("- map 1\n+ map 3")
+        );
+        assert_eq!(diff("", "map 1\n").as_deref(), Some("+ map 1"));
+    }
+
+    #[test]
+    fn errors() {
+        let described = describe(Kind::Players, "player", &[0]);
+        assert_eq!(described.len(), 1);
+        assert!(described[0].starts_with("error: "));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::Error;
+use anyhow::Result;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use serde_with::{serde_as, TimestampSeconds};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// Of reading a request and of writing to a subscriber, after which the client is dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Schedule {
+    pub interval: Duration,
+    pub jitter: Duration,
+}
+
+impl Schedule {
+    fn delay(&self) -> Duration {
+        let jitter = u64::try_from(self.jitter.as_millis()).unwrap_or(u64::MAX);
+        let random = RandomState::new().build_hasher().finish();
+
+        self.interval + Duration::from_millis(random % jitter.saturating_add(1))
+    }
+}
+
+// Times are kept at full precision so that runs within the same second are still told apart, and
+// are shown as Unix seconds.
+#[serde_as]
+#[derive(Default, Serialize)]
+pub struct Status {
+    pub runs: usize,
+    pub failures: usize,
+    #[serde_as(as = "Option<TimestampSeconds<i64>>")]
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<String>,
+    #[serde_as(as = "Option<TimestampSeconds<i64>>")]
+    pub last_error_at: Option<SystemTime>,
+    #[serde_as(as = "Option<TimestampSeconds<i64>>")]
+    pub next_run: Option<SystemTime>,
+}
+
+impl Status {
+    fn is_healthy(&self) -> bool {
+        match (self.last_success, self.last_error_at) {
+            (Some(success), Some(error)) => success > error,
+            (_, None) => true,
+            (None, Some(_)) => false,
+        }
+    }
+
+    fn metrics(&self) -> String {
+        let name = env!("CARGO_PKG_NAME").replace('-', "_");
+
+        format!(
+            "{name}_runs_total {}\n{name}_failures_total {}\n{name}_last_success_timestamp_seconds {}\n{name}_up {}\n",
+            self.runs,
+            self.failures,
+            self.last_success.map_or(0, unix_time),
+            u8::from(self.is_healthy())
+        )
+    }
+}
+
+// Failures of the job are counted and logged with their causes, e.g. as an anyhow chain. The tiles
+// changed by each successful run are announced to subscribers of /events.
+pub fn run<E: fmt::Display>(
+    schedule: &Schedule,
+    listen: Option<SocketAddr>,
+    mut job: impl FnMut() -> Result<BTreeSet<(u8, i32, i32)>, E>,
+) -> Result<(), Error> {
+    let status = Arc::new(Mutex::new(Status::default()));
+    let subscribers = Arc::new(Mutex::new(Vec::<TcpStream>::new()));
+
+    if let Some(address) = listen {
+        let listener = TcpListener::bind(address)?;
+        let status = Arc::clone(&status);
+        let subscribers = Arc::clone(&subscribers);
+        info!("Listening on http://{address}");
+
+        // Each connection is served on a thread of its own so that a slow client holds up no other
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let status = Arc::clone(&status);
+                let subscribers = Arc::clone(&subscribers);
+                thread::spawn(move || {
+                    if let Err(e) = respond(&stream, &status, &subscribers) {
+                        warn!("Failed to respond: {e}");
+                    }
+                });
+            }
+        });
+    }
+
+    install_signal_handlers();
+
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        let result = job();
+        let now = SystemTime::now();
+        let delay = schedule.delay();
+
+        // Subscribers are notified once the status is released, since writing to them may block
+        let event = {
+            let mut status = status.lock().unwrap();
+            status.runs += 1;
+            status.next_run = Some(now + delay);
+            match result {
+                Ok(tiles) => {
+                    status.last_success = Some(now);
+                    Some(tiles_event(&tiles))
+                }
+                Err(e) => {
+                    error!("{e:#}");
+                    status.failures += 1;
+                    status.last_error = Some(format!("{e:#}"));
+                    status.last_error_at = Some(now);
+                    None
+                }
+            }
+        };
+        if let Some(event) = event {
+            notify(&subscribers, &event);
+        }
+
+        let resume = SystemTime::now() + delay;
+        while !SHUTDOWN.load(Ordering::SeqCst) && SystemTime::now() < resume {
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+
+    info!("Shutting down");
+    Ok(())
+}
+
+fn respond(
+    stream: &TcpStream,
+    status: &Mutex<Status>,
+    subscribers: &Mutex<Vec<TcpStream>>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    // Kept open for server-sent events, which viewers served from elsewhere may also subscribe to
+    if path == "/events" {
+        write!(
+            &mut &*stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\n\r\n"
+        )?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        subscribers.lock().unwrap().push(stream.try_clone()?);
+
+        return Ok(());
+    }
+
+    let (code, content_type, body) = {
+        let status = status.lock().unwrap();
+        match path {
+            "/health" => (
+                if status.is_healthy() {
+                    "200 OK"
+                } else {
+                    "503 Service Unavailable"
+                },
+                "application/json",
+                json!({ "healthy": status.is_healthy(), "status": *status }).to_string(),
+            ),
+            "/metrics" => ("200 OK", "text/plain; version=0.0.4", status.metrics()),
+            _ => ("404 Not Found", "text/plain", "Not Found\n".to_owned()),
+        }
+    };
+
+    write!(
+        &mut &*stream,
+        "HTTP/1.1 {code}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+// Tiles are named as in their paths, e.g. "4/-1/2", or else the event is only a comment that serves
+// to drop subscribers who have gone away.
+fn tiles_event(tiles: &BTreeSet<(u8, i32, i32)>) -> String {
+    if tiles.is_empty() {
+        return ":\n\n".to_owned();
+    }
+
+    let keys = tiles
+        .iter()
+        .map(|(zoom, x, y)| format!("{zoom}/{x}/{y}"))
+        .collect::<Vec<_>>();
+
+    format!("event: tiles\ndata: {}\n\n", json!(keys))
+}
+
+fn notify(subscribers: &Mutex<Vec<TcpStream>>, event: &str) {
+    subscribers
+        .lock()
+        .unwrap()
+        .retain(|stream| (&mut &*stream).write_all(event.as_bytes()).is_ok());
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(unix)]
+fn install_signal_handlers() {
+    extern "C" fn handle(_: libc::c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+
+    // SAFETY: The handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+const fn install_signal_handlers() {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let schedule = Schedule {
+            interval: Duration::from_secs(300),
+            jitter: Duration::from_secs(30),
+        };
+        for _ in 0..100 {
+            let delay = schedule.delay();
+            assert!(delay >= Duration::from_secs(300));
+            assert!(delay <= Duration::from_secs(330));
+        }
+
+        let schedule = Schedule {
+            interval: Duration::from_secs(60),
+            jitter: Duration::ZERO,
+        };
+        assert_eq!(schedule.delay(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(tiles_event(&BTreeSet::new()), ":\n\n");
+        assert_eq!(
+            tiles_event(&[(4, -1, 2), (4, 0, 0)].into()),
+            "event: tiles\ndata: [\"4/-1/2\",\"4/0/0\"]\n\n"
+        );
+    }
+
+    #[test]
+    fn healthy() {
+        let at = |ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+        let mut status = Status::default();
+        assert!(status.is_healthy());
+
+        status.last_error_at = Some(at(1_000));
+        assert!(!status.is_healthy());
+
+        // Within the same second
+        status.last_success = Some(at(1_500));
+        assert!(status.is_healthy());
+        status.last_error_at = Some(at(1_900));
+        assert!(!status.is_healthy());
+    }
+}
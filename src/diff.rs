@@ -0,0 +1,153 @@
+use crate::encoding;
+use crate::paths::{glob_in, slashed};
+use crate::Error;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::ErrorKind::NotFound;
+use std::path::Path;
+
+type Contents<T> = BTreeMap<String, T>;
+
+#[derive(Default, Serialize)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Changes {
+    fn between<T: PartialEq>(old: &Contents<T>, new: &Contents<T>) -> Self {
+        let mut changes = Self::default();
+
+        for (key, value) in new {
+            match old.get(key) {
+                None => changes.added.push(key.clone()),
+                Some(old_value) if old_value != value => changes.changed.push(key.clone()),
+                Some(_) => {}
+            }
+        }
+        changes.removed = old
+            .keys()
+            .filter(|k| !new.contains_key(*k))
+            .cloned()
+            .collect();
+
+        changes
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Diff {
+    pub tiles: Changes,
+    pub maps: Changes,
+    pub banners: Changes,
+}
+
+impl Diff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty() && self.maps.is_empty() && self.banners.is_empty()
+    }
+}
+
+pub fn diff(old_path: &Path, new_path: &Path) -> Result<Diff, Error> {
+    Ok(Diff {
+        tiles: Changes::between(&tiles(old_path)?, &tiles(new_path)?),
+        maps: Changes::between(
+            &files(old_path, "maps/**/*")?,
+            &files(new_path, "maps/**/*")?,
+        ),
+        banners: Changes::between(&banners(old_path)?, &banners(new_path)?),
+    })
+}
+
+// Images in whichever format the output records, so that switching formats changes every image
+fn files(output_path: &Path, pattern: &str) -> Result<Contents<Vec<u8>>> {
+    let extension = encoding::recorded(output_path)?;
+    glob_in(output_path, &format!("{pattern}.{extension}"))?
+        .map(|entry| {
+            let path = entry?;
+            let key = slashed(&path.strip_prefix(output_path)?.with_extension(""));
+
+            Ok((key, fs::read(path)?))
+        })
+        .collect()
+}
+
+// A tile also changes when the maps composing it do, even if the image happens not to
+fn tiles(output_path: &Path) -> Result<Contents<(Vec<u8>, Option<Value>)>> {
+    files(output_path, "tiles/**/*")?
+        .into_iter()
+        .map(|(key, image)| {
+            let meta = match File::open(output_path.join(format!("{key}.meta.json"))) {
+                Ok(f) => Some(serde_json::from_reader(f)?),
+                Err(e) if e.kind() == NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+
+            Ok((key, (image, meta)))
+        })
+        .collect()
+}
+
+fn banners(output_path: &Path) -> Result<Contents<(Value, Value)>> {
+    let path = output_path.join("banners.json");
+    let collection: Value = match File::open(&path) {
+        Ok(f) => serde_json::from_reader(f)
+            .with_context(|| format!("Failed to deserialize {}", path.display()))?,
+        Err(e) if e.kind() == NotFound => return Ok(Contents::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(collection["features"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|feature| {
+            let coordinates = &feature["geometry"]["coordinates"];
+            let properties = &feature["properties"];
+            let mut key = format!("({}, {})", coordinates[0], coordinates[1]);
+            if let Some(source) = properties["source"].as_str() {
+                key = format!("{source} {key}");
+            }
+
+            (
+                key,
+                (properties["color"].clone(), properties["name"].clone()),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn between() {
+        let old = BTreeMap::from([
+            ("a".to_owned(), 1),
+            ("b".to_owned(), 2),
+            ("c".to_owned(), 3),
+        ]);
+        let new = BTreeMap::from([
+            ("b".to_owned(), 2),
+            ("c".to_owned(), 4),
+            ("d".to_owned(), 5),
+        ]);
+
+        let changes = Changes::between(&old, &new);
+
+        assert_eq!(changes.added, ["d"]);
+        assert_eq!(changes.removed, ["a"]);
+        assert_eq!(changes.changed, ["c"]);
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Dimension {
+    Overworld,
+    Nether,
+    End,
+}
+
+impl Dimension {
+    pub const ALL: [Self; 3] = [Self::Overworld, Self::Nether, Self::End];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Overworld => "overworld",
+            Self::Nether => "the_nether",
+            Self::End => "the_end",
+        }
+    }
+
+    const fn subdirectory(self) -> Option<&'static str> {
+        match self {
+            Self::Overworld => None,
+            Self::Nether => Some("DIM-1"),
+            Self::End => Some("DIM1"),
+        }
+    }
+
+    #[must_use]
+    pub fn vanilla_path(self, world_path: &Path) -> PathBuf {
+        self.subdirectory()
+            .map_or_else(|| world_path.to_owned(), |s| world_path.join(s))
+    }
+
+    // Paper and Spigot keep each dimension in a sibling world, e.g. world_nether/DIM-1
+    #[must_use]
+    pub fn split_path(self, world_path: &Path) -> PathBuf {
+        match (self.subdirectory(), world_path.file_name()) {
+            (Some(subdirectory), Some(world_name)) => {
+                let suffix = match self {
+                    Self::End => "_the_end",
+                    _ => "_nether",
+                };
+                let mut name = world_name.to_owned();
+                name.push(suffix);
+                world_path.with_file_name(name).join(subdirectory)
+            }
+            _ => world_path.to_owned(),
+        }
+    }
+
+    #[must_use]
+    pub fn path(self, world_path: &Path) -> Option<PathBuf> {
+        if self == Self::Overworld {
+            return Some(world_path.to_owned());
+        }
+
+        [self.vanilla_path(world_path), self.split_path(world_path)]
+            .into_iter()
+            .find(|p| p.join("region").is_dir() || p.join("entities").is_dir())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let world_path = Path::new("/srv/minecraft/survival");
+
+        assert_eq!(
+            Dimension::Nether.vanilla_path(world_path),
+            Path::new("/srv/minecraft/survival/DIM-1")
+        );
+        assert_eq!(
+            Dimension::Nether.split_path(world_path),
+            Path::new("/srv/minecraft/survival_nether/DIM-1")
+        );
+        assert_eq!(
+            Dimension::End.split_path(world_path),
+            Path::new("/srv/minecraft/survival_the_end/DIM1")
+        );
+        assert_eq!(Dimension::Overworld.split_path(world_path), world_path);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::config::{Colors, ImageFormat};
+use crate::layout;
+use crate::palette::{self, Palette};
+use crate::paths::glob_in;
+use crate::utilities::write_webp_rgb;
+use crate::Error;
+use anyhow::Result;
+use log::{debug, info};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Encoder of the 128 × 128 indexed images of maps and tiles as written to the output
+pub trait ImageSink: Sync {
+    fn extension(&self) -> &'static str;
+    fn write(&self, w: &mut dyn Write, indexed: &[u8; 128 * 128]) -> Result<(), Error>;
+}
+
+impl ImageFormat {
+    pub const ALL: &'static [Self] = &[
+        #[cfg(feature = "avif")]
+        Self::Avif,
+        Self::Indexed,
+        Self::Png,
+        Self::WebP,
+    ];
+
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "avif")]
+            Self::Avif => "avif",
+            Self::Indexed => "indexed",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    /// Encoder of images in this format, in the palette of some colors unless indexed
+    #[must_use]
+    pub fn sink(self, colors: Colors) -> Box<dyn ImageSink> {
+        let palette = palette::get(colors);
+        match self {
+            #[cfg(feature = "avif")]
+            Self::Avif => Box::new(Avif(palette)),
+            Self::Indexed => Box::new(Indexed),
+            Self::Png => Box::new(Png(palette)),
+            Self::WebP => Box::new(WebP(palette)),
+        }
+    }
+}
+
+/// Extension of the images in an output, which predate the choice of format if unrecorded
+pub fn recorded(output_path: &Path) -> Result<String, Error> {
+    Ok(layout::recorded(output_path, layout::IMAGE_FORMAT)?
+        .unwrap_or_else(|| ImageFormat::WebP.extension().to_owned()))
+}
+
+// Images of a previous format are removed rather than left for the viewer to miss, and the output
+// is rendered again in full since tiles are otherwise skipped by the age of their metadata, as it
+// also is when colors change.
+pub fn switch(output_path: &Path, sink: &dyn ImageSink, colors: Colors) -> Result<bool, Error> {
+    Ok(switch_layout(output_path, sink, colors)?)
+}
+
+fn switch_layout(output_path: &Path, sink: &dyn ImageSink, colors: Colors) -> Result<bool> {
+    let format_switched = layout::switch(
+        output_path,
+        layout::IMAGE_FORMAT,
+        sink.extension(),
+        ImageFormat::WebP.extension(),
+        |previous| {
+            info!(
+                "Switch image format from {previous} to {}",
+                sink.extension()
+            );
+            for pattern in ["maps/*", "tiles/**/*", "freshness/**/*"] {
+                for entry in glob_in(output_path, &format!("{pattern}.{previous}"))? {
+                    let path = entry?;
+                    debug!("Prune: {}", path.display());
+                    fs::remove_file(path)?;
+                }
+            }
+
+            Ok(())
+        },
+    )?;
+    let colors_switched = layout::switch(
+        output_path,
+        layout::COLORS,
+        &colors.to_string(),
+        &Colors::default().to_string(),
+        |_| {
+            info!("Switch colors to {colors}");
+            Ok(())
+        },
+    )?;
+
+    Ok(format_switched | colors_switched)
+}
+
+struct WebP(&'static Palette);
+impl ImageSink for WebP {
+    fn extension(&self) -> &'static str {
+        "webp"
+    }
+
+    fn write(&self, mut w: &mut dyn Write, indexed: &[u8; 128 * 128]) -> Result<(), Error> {
+        Ok(write_webp_rgb(&mut w, &palette::rgb_in(self.0, indexed))?)
+    }
+}
+
+struct Png(&'static Palette);
+impl ImageSink for Png {
+    fn extension(&self) -> &'static str {
+        "png"
+    }
+
+    fn write(&self, w: &mut dyn Write, indexed: &[u8; 128 * 128]) -> Result<(), Error> {
+        Ok(write_png_rgb(w, &palette::rgb_in(self.0, indexed))?)
+    }
+}
+
+fn write_png_rgb(w: &mut dyn Write, rgb: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(w, 128, 128);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_compression(png::Compression::Best);
+    encoder.write_header()?.write_image_data(rgb)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "avif")]
+struct Avif(&'static Palette);
+#[cfg(feature = "avif")]
+impl ImageSink for Avif {
+    fn extension(&self) -> &'static str {
+        "avif"
+    }
+
+    fn write(&self, w: &mut dyn Write, indexed: &[u8; 128 * 128]) -> Result<(), Error> {
+        Ok(write_avif_rgb(w, &palette::rgb_in(self.0, indexed))?)
+    }
+}
+
+#[cfg(feature = "avif")]
+fn write_avif_rgb(w: &mut dyn Write, rgb: &[u8]) -> Result<()> {
+    let pixels = rgb
+        .chunks_exact(3)
+        .map(|p| ravif::RGB8::new(p[0], p[1], p[2]))
+        .collect::<Vec<_>>();
+    let encoded = ravif::Encoder::new()
+        .with_quality(100.0)
+        .encode_rgb(ravif::Img::new(&pixels[..], 128, 128))?;
+    w.write_all(&encoded.avif_file)?;
+
+    Ok(())
+}
+
+struct Indexed;
+impl ImageSink for Indexed {
+    fn extension(&self) -> &'static str {
+        "indexed"
+    }
+
+    fn write(&self, w: &mut dyn Write, indexed: &[u8; 128 * 128]) -> Result<(), Error> {
+        w.write_all(indexed)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn switch_format() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = dir.path();
+        let colors = Colors::default();
+        let flat = Colors {
+            shading: crate::config::Shading::Flat,
+            ..colors
+        };
+        let png = &*ImageFormat::Png.sink(colors);
+        let webp = &*ImageFormat::WebP.sink(colors);
+        fs::create_dir_all(output.join("maps")).unwrap();
+        fs::write(output.join("maps/1.webp"), "").unwrap();
+
+        assert!(!switch(output, webp, colors).unwrap());
+        assert!(output.join("maps/1.webp").exists());
+
+        assert!(switch(output, webp, flat).unwrap());
+        assert!(!switch(output, webp, flat).unwrap());
+        assert!(output.join("maps/1.webp").exists());
+        assert_eq!(recorded(output).unwrap(), "webp");
+
+        assert!(switch(output, png, colors).unwrap());
+        assert!(!switch(output, png, colors).unwrap());
+        assert!(!output.join("maps/1.webp").exists());
+        assert_eq!(recorded(output).unwrap(), "png");
+    }
+
+    #[test]
+    fn signatures() {
+        let indexed = [34; 128 * 128];
+        let encode = |format: ImageFormat| {
+            let mut encoded = Vec::new();
+            format
+                .sink(Colors::default())
+                .write(&mut encoded, &indexed)
+                .unwrap();
+            encoded
+        };
+
+        assert_eq!(encode(ImageFormat::Indexed), indexed);
+        assert_eq!(encode(ImageFormat::Png)[1..4], *b"PNG");
+        assert_eq!(encode(ImageFormat::WebP)[8..12], *b"WEBP");
+    }
+}
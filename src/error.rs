@@ -0,0 +1,113 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Failure of the library by category, for callers that handle some differently, e.g. reporting
+/// a world of an unsupported version apart from a corrupt file
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Options, config, or arguments that were rejected
+    #[error("{0}")]
+    Config(String),
+
+    /// World saved by a game version outside of `COMPATIBLE_VERSIONS`
+    #[error("Incompatible with game version {version}: {}", path.display())]
+    Incompatible { path: PathBuf, version: String },
+
+    #[error("Failed to access {}", describe(path.as_deref()))]
+    Io {
+        path: Option<PathBuf>,
+        #[source]
+        source: io::Error,
+    },
+
+    /// NBT that failed to deserialize, typically of a corrupt or truncated file
+    #[error("Failed to deserialize {}{}", describe(path.as_deref()), map_id.map(|id| format!(" of map {id}")).unwrap_or_default())]
+    Nbt {
+        path: Option<PathBuf>,
+        map_id: Option<u32>,
+        #[source]
+        source: fastnbt::error::Error,
+    },
+
+    #[error("World not found: {}", path.display())]
+    WorldNotFound { path: PathBuf },
+
+    /// Anything else, described together with its causes
+    #[error("{0}")]
+    Other(String),
+}
+
+fn describe(path: Option<&Path>) -> String {
+    path.map_or_else(|| "file".to_owned(), |p| p.display().to_string())
+}
+
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: Some(path.to_owned()),
+            source,
+        }
+    }
+
+    pub(crate) fn nbt(path: &Path, source: fastnbt::error::Error) -> Self {
+        Self::Nbt {
+            path: Some(path.to_owned()),
+            map_id: None,
+            source,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Self::Io { path: None, source }
+    }
+}
+
+impl From<fastnbt::error::Error> for Error {
+    fn from(source: fastnbt::error::Error) -> Self {
+        Self::Nbt {
+            path: None,
+            map_id: None,
+            source,
+        }
+    }
+}
+
+// Internally errors are anyhow chains, of which the categorized error is recovered at the edge of
+// the library, or else the chain is kept as text.
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        let message = format!("{error:#}");
+
+        error
+            .downcast::<Self>()
+            .or_else(|e| e.downcast::<io::Error>().map(Self::from))
+            .or_else(|e| e.downcast::<fastnbt::error::Error>().map(Self::from))
+            .unwrap_or(Self::Other(message))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn categories() {
+        let nbt = Error::nbt(Path::new("level.dat"), serde::de::Error::custom("bad"));
+        let error = Error::from(anyhow::Error::from(nbt).context("Failed to load the level"));
+        assert!(
+            matches!(error, Error::Nbt { ref path, .. } if path.as_deref() == Some(Path::new("level.dat")))
+        );
+        assert_eq!(error.to_string(), "Failed to deserialize level.dat");
+
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let error = Error::from(Err::<(), _>(missing).context("Failed to read").unwrap_err());
+        assert!(matches!(error, Error::Io { path: None, .. }));
+
+        let error = Error::from(anyhow::anyhow!("Invalid").context("Failed"));
+        assert!(matches!(error, Error::Other(ref m) if m == "Failed: Invalid"));
+    }
+}
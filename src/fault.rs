@@ -0,0 +1,102 @@
+//! Failures injected into IO by tests of how a run degrades, e.g. that a damaged region is
+//! quarantined with a warning rather than ending the search
+//!
+//! Each is injected for paths within a scope, typically the temporary dir of one test, so that
+//! tests running in parallel don't interfere. Only with the `faults` feature.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    /// Region files end halfway through, as if cut short by a crash while being saved
+    TruncatedRegion,
+    /// Tile images can't be written, as if denied by the permissions of the output
+    DeniedTileWrite,
+    /// Modification times of tiles can't be set, as on some network filesystems
+    FailedSetModified,
+}
+
+static INJECTED: Mutex<Vec<(Fault, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Injected until dropped
+#[must_use]
+pub struct Injected {
+    fault: Fault,
+    scope: PathBuf,
+}
+
+impl Drop for Injected {
+    fn drop(&mut self) {
+        let mut injected = INJECTED.lock().unwrap();
+        if let Some(i) = injected
+            .iter()
+            .position(|(f, s)| *f == self.fault && *s == self.scope)
+        {
+            injected.remove(i);
+        }
+    }
+}
+
+/// Fail IO of a kind on paths within `scope`
+pub fn inject(fault: Fault, scope: &Path) -> Injected {
+    INJECTED.lock().unwrap().push((fault, scope.to_owned()));
+
+    Injected {
+        fault,
+        scope: scope.to_owned(),
+    }
+}
+
+fn is_injected(fault: Fault, path: &Path) -> bool {
+    INJECTED
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(f, scope)| *f == fault && path.starts_with(scope))
+}
+
+pub(crate) fn check(fault: Fault, path: &Path) -> io::Result<()> {
+    if is_injected(fault, path) {
+        let kind = match fault {
+            Fault::DeniedTileWrite => io::ErrorKind::PermissionDenied,
+            Fault::TruncatedRegion => io::ErrorKind::UnexpectedEof,
+            Fault::FailedSetModified => io::ErrorKind::Unsupported,
+        };
+        return Err(io::Error::new(kind, format!("Injected {fault:?}")));
+    }
+
+    Ok(())
+}
+
+// Cut short at a sector boundary beyond the header, so that only some chunks are lost
+pub(crate) fn truncate(path: &Path, mut region: Vec<u8>) -> Vec<u8> {
+    if is_injected(Fault::TruncatedRegion, path) {
+        region.truncate((region.len() / 2).max(2 * 4096) / 4096 * 4096);
+    }
+
+    region
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scoped() {
+        let scope = Path::new("/faults/a");
+        let injected = inject(Fault::DeniedTileWrite, scope);
+        assert_eq!(
+            check(Fault::DeniedTileWrite, &scope.join("tiles/0/0/0.webp"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(check(Fault::DeniedTileWrite, Path::new("/faults/b/tiles")).is_ok());
+        assert!(check(Fault::FailedSetModified, scope).is_ok());
+
+        drop(injected);
+        assert!(check(Fault::DeniedTileWrite, scope).is_ok());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::geometry::Origin;
+use crate::tile::Tile;
+use std::collections::BTreeSet;
+
+/// Parts of the output to regenerate regardless of the cache and modification times, e.g. to
+/// repair an area without rendering the whole world again
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Force {
+    /// Search the whole world rather than only regions modified since the cache
+    pub search: bool,
+    /// Render every map, tile, and other output
+    pub render: bool,
+    /// Render these maps and the tiles they appear in
+    pub maps: BTreeSet<u32>,
+    /// Render the tiles within these by zoom and position as numbered in the output, i.e. in
+    /// `tiles/{zoom}/{x}/{y}`
+    pub tiles: BTreeSet<(u8, i32, i32)>,
+}
+
+impl Force {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(crate) fn map(&self, id: u32) -> bool {
+        self.render || self.maps.contains(&id)
+    }
+
+    pub(crate) fn tile(
+        &self,
+        tile: &Tile,
+        origin: Origin,
+        ids: impl IntoIterator<Item = u32>,
+    ) -> bool {
+        self.render
+            || tile
+                .ancestry()
+                .any(|t| self.tiles.contains(&origin.number(&t)))
+            || ids.into_iter().any(|id| self.maps.contains(&id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scopes() {
+        let within = Tile {
+            zoom: 4,
+            x: 5,
+            y: -3,
+        };
+        let beside = Tile {
+            zoom: 4,
+            x: 9,
+            y: -3,
+        };
+        let force = Force {
+            maps: [7].into(),
+            tiles: [(3, 2, -2)].into(),
+            ..Force::default()
+        };
+
+        assert!(Force::default().is_empty());
+        assert!(!force.is_empty());
+        assert!(force.map(7));
+        assert!(!force.map(8));
+        let origin = Origin::default();
+        assert!(force.tile(&within, origin, [8]));
+        assert!(force.tile(&beside, origin, [7, 8]));
+        assert!(!force.tile(&beside, origin, [8]));
+        assert!(!force.tile(&within, Origin { x: 1, y: 0 }, [8]));
+    }
+}
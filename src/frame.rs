@@ -0,0 +1,76 @@
+use fastnbt::IntArray;
+use serde::{Deserialize, Deserializer};
+
+/// Item frame decoration of a map, i.e. the green marker of a frame holding the map itself
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Frame {
+    pub x: i32,
+    pub z: i32,
+
+    /// Clockwise degrees from north, as the frame faces
+    pub rotation: i32,
+}
+
+impl<'de> Deserialize<'de> for Frame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Internal {
+            V1204(InternalV1204),
+            V1205(InternalV1205),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct InternalV1204 {
+            pos: Pos,
+            rotation: i32,
+        }
+
+        #[derive(Deserialize)]
+        struct InternalV1205 {
+            pos: IntArray,
+            rotation: i32,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Pos {
+            x: i32,
+            z: i32,
+        }
+
+        Ok(match Internal::deserialize(deserializer)? {
+            Internal::V1204(i) => Self {
+                rotation: i.rotation.rem_euclid(360),
+                x: i.pos.x,
+                z: i.pos.z,
+            },
+            Internal::V1205(i) => Self {
+                rotation: i.rotation.rem_euclid(360),
+                x: i.pos[0],
+                z: i.pos[2],
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fastnbt::nbt;
+
+    #[test]
+    fn versions() {
+        let v1204 = nbt!({ "EntityId": 1, "Pos": { "X": 8, "Y": 64, "Z": -8 }, "Rotation": 270 });
+        let v1205 = nbt!({ "entity_id": 1, "pos": [I; 8, 64, -8], "rotation": -90 });
+        let expected = Frame {
+            x: 8,
+            z: -8,
+            rotation: 270,
+        };
+
+        assert_eq!(fastnbt::from_value::<Frame>(&v1204).unwrap(), expected);
+        assert_eq!(fastnbt::from_value::<Frame>(&v1205).unwrap(), expected);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::geometry::{tile_bounds, MAP_SIZE, MAX_ZOOM};
+use crate::map::{MapData, MapScan};
+use crate::warning::WarningCollector;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::Path;
+
+// Map art is placed block by block and then locked in a cartography table so that it isn't drawn
+// over by the terrain, which it seldom resembles. A map is taken for art when locked at scale 0,
+// opaque throughout, of more shades than terrain typically shows, and not overlapped by any
+// unlocked map, which would have been drawn from the terrain.
+const MIN_SHADES: usize = 24;
+
+/// Map likely to be map art, by its id and the center of what it depicts
+#[derive(Debug, Eq, PartialEq)]
+pub struct Art {
+    pub id: u32,
+    pub x: i32,
+    pub z: i32,
+}
+
+/// Maps of a scan likely to be map art, in order of id
+pub fn detect(world_path: &Path, scan: &MapScan) -> Vec<Art> {
+    let redundant = scan.redundant();
+    let mut art = scan
+        .maps_by_tile
+        .iter()
+        .filter(|(tile, _)| tile.zoom == MAX_ZOOM)
+        .flat_map(|(tile, maps)| maps.iter().map(move |m| (tile, m.id)))
+        .filter(|(tile, id)| {
+            scan.locked_map_ids.contains(id)
+                && !redundant.contains(id)
+                && tile.ancestry().all(|t| {
+                    scan.maps_by_tile.get(&t).map_or(true, |maps| {
+                        maps.iter().all(|m| scan.locked_map_ids.contains(&m.id))
+                    })
+                })
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .filter(|(_, id)| {
+            // Unreadable maps are reported by the render instead
+            MapData::from_world_path(world_path, *id, &WarningCollector::default())
+                .map_or(false, |data| is_art(&data))
+        })
+        .map(|(tile, id)| {
+            let ((x, z), _) = tile_bounds(tile);
+            Art {
+                id,
+                x: x + MAP_SIZE / 2,
+                z: z + MAP_SIZE / 2,
+            }
+        })
+        .collect::<Vec<_>>();
+    art.sort_unstable_by_key(|a| a.id);
+
+    art
+}
+
+fn is_art(data: &MapData) -> bool {
+    let opaque = data.0.iter().all(|&c| c / 4 != 0);
+    let shades = data.0.iter().collect::<HashSet<_>>().len();
+
+    opaque && shades >= MIN_SHADES
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        #[allow(clippy::cast_possible_truncation)] // Below 244
+        let varied = MapData(std::array::from_fn(|i| (i % 61 + 1) as u8 * 4 + 2));
+        let mut partial = MapData(varied.0);
+        partial.0[..128].fill(0);
+        let plain = MapData([7 * 4 + 1; 128 * 128]);
+
+        assert!(is_art(&varied));
+        assert!(!is_art(&partial));
+        assert!(!is_art(&plain));
+    }
+}
@@ -0,0 +1,426 @@
+use crate::activity;
+use crate::batch::Batch;
+use crate::cache::compression_levels;
+use crate::config::Config;
+use crate::layer::LayerProvider;
+use crate::level::{resolve_world_path, Level};
+use crate::manifest;
+use crate::paths::is_within;
+use crate::release;
+use crate::search::Searched;
+use crate::stats::Statistics;
+use crate::warning::{WarningCollector, WarningGroup};
+use crate::{render_counted, search_counted, survey, Bounds, CacheMiss, Error, Force, Survey};
+#[cfg(feature = "async")]
+use futures_channel::oneshot;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub maps_found: usize,
+    pub players_searched: usize,
+    pub entity_regions_searched: usize,
+    pub block_regions_searched: usize,
+    pub maps_rendered: usize,
+    // Maps skipped in compositing for being identical to a later map of the same tile
+    pub maps_deduplicated: usize,
+    pub maps_pruned: usize,
+    pub tiles_rendered: usize,
+    pub tiles_pruned: usize,
+    // Tiles of the primary output whose pixels changed, by zoom and position as numbered in the output
+    pub tiles_changed: BTreeSet<(u8, i32, i32)>,
+    // Files searched by why they weren't taken from the cache
+    pub cache_misses: BTreeMap<CacheMiss, usize>,
+    // Aggregates of the world, if enabled by `statistics` in the config
+    pub statistics: Option<Statistics>,
+    // Files of the primary output written this run, relative to it, if enabled by `changed_list`
+    // in the config
+    pub files_changed: Option<BTreeSet<PathBuf>>,
+    pub warnings: Vec<WarningGroup>,
+}
+
+#[derive(Default)]
+pub struct GeneratorBuilder {
+    world_path: Option<PathBuf>,
+    output_path: Option<PathBuf>,
+    quiet: bool,
+    force: Force,
+    bounds: Option<Bounds>,
+    rescans: usize,
+    wait_for_quiesce: Option<Duration>,
+    layers: Vec<Arc<dyn LayerProvider>>,
+    #[cfg(feature = "profiling")]
+    profile: Option<PathBuf>,
+    config: Config,
+}
+
+impl GeneratorBuilder {
+    /// World dir, or server dir containing server.properties
+    #[must_use]
+    pub fn world(mut self, path: impl Into<PathBuf>) -> Self {
+        self.world_path = Some(path.into());
+        self
+    }
+
+    #[must_use]
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output_path = Some(path.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Disregard the cache and modification times of outputs
+    #[must_use]
+    pub const fn force(mut self, force: bool) -> Self {
+        self.force.search = force;
+        self.force.render = force;
+        self
+    }
+
+    /// Disregard the cache, searching the whole world
+    #[must_use]
+    pub const fn force_search(mut self, force: bool) -> Self {
+        self.force.search = force;
+        self
+    }
+
+    /// Disregard modification times of outputs, rendering them all
+    #[must_use]
+    pub const fn force_render(mut self, force: bool) -> Self {
+        self.force.render = force;
+        self
+    }
+
+    /// Render these maps and the tiles they appear in regardless of modification times
+    #[must_use]
+    pub fn force_maps(mut self, ids: impl IntoIterator<Item = u32>) -> Self {
+        self.force.maps.extend(ids);
+        self
+    }
+
+    /// Render the tiles within these, by zoom and position, regardless of modification times
+    #[must_use]
+    pub fn force_tiles(mut self, tiles: impl IntoIterator<Item = (u8, i32, i32)>) -> Self {
+        self.force.tiles.extend(tiles);
+        self
+    }
+
+    #[must_use]
+    pub const fn bounds(mut self, bounds: Bounds) -> Self {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    #[must_use]
+    pub const fn rescans(mut self, rescans: usize) -> Self {
+        self.rescans = rescans;
+        self
+    }
+
+    /// Before each search, wait until no file of the world has been modified for this long
+    #[must_use]
+    pub const fn wait_for_quiesce(mut self, stable: Duration) -> Self {
+        self.wait_for_quiesce = Some(stable);
+        self
+    }
+
+    /// Add an overlay of the viewer from data beyond the world, fetched on every run
+    #[must_use]
+    pub fn layer(mut self, provider: impl LayerProvider + 'static) -> Self {
+        self.layers.push(Arc::new(provider));
+        self
+    }
+
+    /// Write a Chrome trace of the phases of each run to a file of its own in this dir
+    #[cfg(feature = "profiling")]
+    #[must_use]
+    pub fn profile(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.profile = Some(dir.into());
+        self
+    }
+
+    #[must_use]
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn build(self) -> Result<Generator, Error> {
+        let world_path = self
+            .world_path
+            .ok_or_else(|| Error::Config("Missing world path".to_owned()))?;
+        let output_path = self
+            .output_path
+            .ok_or_else(|| Error::Config("Missing output path".to_owned()))?;
+        let world_path = resolve_world_path(&world_path)?;
+        let mut config = self.config;
+        config.layers.0.extend(self.layers);
+
+        if let Some(level) = config.cache_compression {
+            if !compression_levels().contains(&level) {
+                return Err(Error::Config(format!(
+                    "Compression level {level} of the cache is beyond {:?}",
+                    compression_levels()
+                )));
+            }
+        }
+
+        // Nothing is ever written into the world, but in strict read-only mode neither is anything
+        // written beneath it, e.g. by an output misplaced within a world on a writable mount
+        if config.read_only {
+            let written = [Some(&output_path), config.cache_dir.as_ref()]
+                .into_iter()
+                .flatten()
+                .chain(config.changed_list.as_ref())
+                .chain(config.profiles.iter().map(|p| &p.output));
+            for path in written {
+                if is_within(path, &world_path).map_err(|e| Error::io(path, e))? {
+                    return Err(Error::Config(format!(
+                        "{} is within the read-only world {}",
+                        path.display(),
+                        world_path.display()
+                    )));
+                }
+            }
+        }
+
+        Ok(Generator {
+            world_path,
+            output_path,
+            quiet: self.quiet,
+            force: self.force,
+            bounds: self.bounds,
+            rescans: self.rescans,
+            wait_for_quiesce: self.wait_for_quiesce,
+            #[cfg(feature = "profiling")]
+            profile: self.profile,
+            config,
+        })
+    }
+}
+
+pub struct Generator {
+    world_path: PathBuf,
+    output_path: PathBuf,
+    quiet: bool,
+    force: Force,
+    bounds: Option<Bounds>,
+    rescans: usize,
+    wait_for_quiesce: Option<Duration>,
+    #[cfg(feature = "profiling")]
+    profile: Option<PathBuf>,
+    config: Config,
+}
+
+impl Generator {
+    #[must_use]
+    pub fn builder() -> GeneratorBuilder {
+        GeneratorBuilder::default()
+    }
+
+    pub fn run(&self) -> Result<RunReport, Error> {
+        let warnings = WarningCollector::default();
+        #[cfg(feature = "profiling")]
+        let report = match &self.profile {
+            Some(dir) => crate::profiling::trace(dir, || self.run_collecting(&warnings))?,
+            None => self.run_collecting(&warnings),
+        };
+        #[cfg(not(feature = "profiling"))]
+        let report = self.run_collecting(&warnings);
+        let warnings = warnings.log();
+
+        Ok(RunReport {
+            warnings,
+            ..report?
+        })
+    }
+
+    /// Run on a thread of its own, e.g. awaited from a handler of an async server without blocking
+    /// the workers of its runtime. The run begins immediately rather than when first polled, and
+    /// continues to completion even if the future is dropped.
+    #[cfg(feature = "async")]
+    pub fn run_async(
+        self: &Arc<Self>,
+    ) -> impl Future<Output = Result<RunReport, Error>> + Send + 'static {
+        let (sender, receiver) = oneshot::channel();
+        let generator = Arc::clone(self);
+        thread::spawn(move || {
+            // Nobody awaits the report if the future was dropped
+            let _ = sender.send(generator.run());
+        });
+
+        async move {
+            receiver
+                .await
+                .map_err(|_| Error::Other("Generator panicked".to_owned()))?
+        }
+    }
+
+    /// Search the world and scan its maps, reporting what a run would render without rendering
+    pub fn survey(&self) -> Result<Survey, Error> {
+        let warnings = WarningCollector::default();
+        let output_path = match self.config.releases {
+            Some(_) => release::current(&self.output_path)?,
+            None => None,
+        }
+        .unwrap_or_else(|| self.output_path.clone());
+        let survey = self
+            .search(&output_path, &warnings)
+            .and_then(|(_, ids, searched)| {
+                Ok(survey(
+                    &self.world_path,
+                    &ids,
+                    searched.tile_size,
+                    &self.config,
+                )?)
+            });
+        warnings.log();
+
+        survey
+    }
+
+    // The level is loaded anew on each run since its spawn point may have changed in between
+    fn search(
+        &self,
+        output_path: &Path,
+        warnings: &WarningCollector,
+    ) -> Result<(Level, HashSet<u32>, Searched), Error> {
+        if let Some(stable) = self.wait_for_quiesce {
+            let waited = activity::wait_for_quiesce(&self.world_path, stable)?;
+            if !waited.is_zero() {
+                info!("Waited {waited:.1?} for the world to be saved");
+            }
+        }
+
+        let level = Level::from_world_path(&self.world_path)?;
+        let (ids, searched) = search_counted(
+            &self.world_path,
+            output_path,
+            self.config.cache_dir.as_deref(),
+            self.config.cache_compression.unwrap_or_default(),
+            self.config.strict_cache,
+            self.quiet,
+            self.force.search,
+            self.bounds.as_ref(),
+            self.rescans,
+            self.config.command_storage,
+            &self.config.players,
+            self.config.max_open_files,
+            warnings,
+        )?;
+
+        Ok((level, ids, searched))
+    }
+
+    // Each run is written to a release of its own if enabled, published only once complete
+    fn run_collecting(&self, warnings: &WarningCollector) -> Result<RunReport, Error> {
+        let Some(keep) = self.config.releases else {
+            return self.run_in(&self.output_path, warnings);
+        };
+
+        let release_path = release::prepare(&self.output_path, SystemTime::now())?;
+        let report = self.run_in(&release_path, warnings).and_then(|report| {
+            let pruned = release::publish(&self.output_path, &release_path, keep)?;
+            debug!(
+                "Published {}, pruning {pruned} releases",
+                release_path.display()
+            );
+
+            Ok(report)
+        });
+        if report.is_err() {
+            if let Err(e) = fs::remove_dir_all(&release_path) {
+                warn!("Failed to remove {}: {e}", release_path.display());
+            }
+        }
+
+        report
+    }
+
+    fn run_in(&self, output_path: &Path, warnings: &WarningCollector) -> Result<RunReport, Error> {
+        let (level, ids, searched) = self.search(output_path, warnings)?;
+        let before = self
+            .config
+            .changed_list
+            .as_ref()
+            .map(|_| manifest::read(output_path).unwrap_or_default());
+
+        // Profiles render the same maps, which are read and drawn once for all outputs
+        let batch = (!self.config.profiles.is_empty()).then(Batch::default);
+        let rendered = render_counted(
+            &self.world_path,
+            output_path,
+            self.quiet,
+            &self.force,
+            &level,
+            &ids,
+            Some(&searched),
+            &self.config,
+            warnings,
+            batch.as_ref(),
+        )?;
+
+        for profile in &self.config.profiles {
+            debug!("Render profile {}", profile.output.display());
+            render_counted(
+                &self.world_path,
+                &profile.output,
+                self.quiet,
+                &self.force,
+                &level,
+                &ids,
+                Some(&searched),
+                &self.config.for_profile(profile),
+                warnings,
+                batch.as_ref(),
+            )?;
+        }
+
+        let files_changed = before
+            .map(|before| -> Result<_, Error> {
+                let changed = manifest::changed(&before, &manifest::read(output_path)?);
+                if let Some(list_path) = &self.config.changed_list {
+                    manifest::write_list(list_path, &changed)?;
+                    debug!(
+                        "Listed {} changed files in {}",
+                        changed.len(),
+                        list_path.display()
+                    );
+                }
+
+                Ok(changed)
+            })
+            .transpose()?;
+
+        Ok(RunReport {
+            maps_found: ids.len(),
+            players_searched: searched.players,
+            entity_regions_searched: searched.entity_regions,
+            block_regions_searched: searched.block_regions,
+            maps_rendered: rendered.maps_rendered,
+            maps_deduplicated: rendered.maps_deduplicated,
+            maps_pruned: rendered.maps_pruned,
+            tiles_rendered: rendered.tiles_rendered,
+            tiles_pruned: rendered.tiles_pruned,
+            tiles_changed: rendered.tiles_changed,
+            cache_misses: searched.cache_misses,
+            statistics: rendered.statistics,
+            files_changed,
+            warnings: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,274 @@
+//! Conversions between the coordinates of a world and those of the output, e.g. for a frontend or
+//! bot to link a block position to the tile showing it
+//!
+//! Positions are `(x, z)` in blocks unless named otherwise, and bounds are the inclusive corners
+//! `((x0, z0), (x1, z1))`. Maps of scale 0 are centered on multiples of 128 blocks, so the tiles
+//! and maps of every scale begin 64 blocks before a multiple of their size. Tiles are numbered in
+//! the output relative to an [`Origin`], by default the root tile containing `(0, 0)`.
+
+use crate::tile::Tile;
+
+/// Blocks across a chunk
+pub const CHUNK_SIZE: i32 = 16;
+
+/// Chunks across a region, i.e. one `r.X.Z.mca` file
+pub const REGION_CHUNKS: i32 = 32;
+
+/// Blocks across a map of scale 0, which is also the pixels across any map or tile
+pub const MAP_SIZE: i32 = 128;
+
+/// Most detailed zoom of the tiles, at which a tile spans one map of scale 0
+pub const MAX_ZOOM: u8 = 4;
+
+const OFFSET: i32 = MAP_SIZE / 2;
+
+/// Chunk containing a block
+#[must_use]
+pub const fn chunk_of((x, z): (i32, i32)) -> (i32, i32) {
+    (x.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE))
+}
+
+/// Blocks within a chunk
+#[must_use]
+pub const fn chunk_bounds((cx, cz): (i32, i32)) -> ((i32, i32), (i32, i32)) {
+    span((cx, cz), CHUNK_SIZE, 0)
+}
+
+/// Region containing a chunk
+#[must_use]
+pub const fn region_of_chunk((cx, cz): (i32, i32)) -> (i32, i32) {
+    (cx.div_euclid(REGION_CHUNKS), cz.div_euclid(REGION_CHUNKS))
+}
+
+/// Region containing a block
+#[must_use]
+pub const fn region_of(block: (i32, i32)) -> (i32, i32) {
+    region_of_chunk(chunk_of(block))
+}
+
+/// Blocks within a region
+#[must_use]
+pub const fn region_bounds((rx, rz): (i32, i32)) -> ((i32, i32), (i32, i32)) {
+    span((rx, rz), CHUNK_SIZE * REGION_CHUNKS, 0)
+}
+
+/// Blocks across a map of a scale from 0 to 4
+#[must_use]
+pub const fn map_size(scale: u8) -> i32 {
+    MAP_SIZE << scale
+}
+
+/// Zoom of the tiles at which one tile spans one map of a scale
+#[must_use]
+pub const fn zoom_of_scale(scale: u8) -> u8 {
+    MAX_ZOOM - scale
+}
+
+/// Center of the map of a scale that the game makes when it's first held at a block, i.e. its
+/// `xCenter` and `zCenter`
+#[must_use]
+pub const fn map_center(scale: u8, (x, z): (i32, i32)) -> (i32, i32) {
+    let size = map_size(scale);
+    let ((x0, z0), _) = span(
+        ((x + OFFSET).div_euclid(size), (z + OFFSET).div_euclid(size)),
+        size,
+        OFFSET,
+    );
+
+    (x0 + size / 2, z0 + size / 2)
+}
+
+/// Blocks depicted by the map of a scale centered at a position
+#[must_use]
+pub const fn map_bounds(scale: u8, (x, z): (i32, i32)) -> ((i32, i32), (i32, i32)) {
+    let size = map_size(scale);
+
+    (
+        (x - size / 2, z - size / 2),
+        (x + size / 2 - 1, z + size / 2 - 1),
+    )
+}
+
+/// Tile of a zoom containing a block
+#[must_use]
+pub const fn tile_of(zoom: u8, (x, z): (i32, i32)) -> Tile {
+    let size = map_size(zoom_of_scale(zoom));
+
+    Tile {
+        zoom,
+        x: (x + OFFSET).div_euclid(size),
+        y: (z + OFFSET).div_euclid(size),
+    }
+}
+
+/// Blocks within a tile
+#[must_use]
+pub const fn tile_bounds(tile: &Tile) -> ((i32, i32), (i32, i32)) {
+    span((tile.x, tile.y), map_size(zoom_of_scale(tile.zoom)), OFFSET)
+}
+
+/// Path of a tile relative to the output by the default pattern, e.g. `tiles/4/-3/7.webp` for an
+/// extension of `webp` and the default origin
+#[must_use]
+pub fn tile_path(origin: Origin, tile: &Tile, extension: &str) -> String {
+    let (zoom, x, y) = origin.number(tile);
+
+    format!("tiles/{zoom}/{x}/{y}.{extension}")
+}
+
+/// Root tile numbered `0, 0` in the output, relative to which the tiles of every zoom are numbered
+///
+/// An origin other than the default keeps the numbers small where the world is far from `(0, 0)`,
+/// while the bounds of the tiles are the same by any origin.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Origin {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Origin {
+    /// Origin at the root tile containing a block
+    #[must_use]
+    pub const fn at(block: (i32, i32)) -> Self {
+        let Tile { x, y, .. } = tile_of(0, block);
+
+        Self { x, y }
+    }
+
+    /// Zoom and position of a tile as numbered in the output, i.e. in `tiles/{zoom}/{x}/{y}`
+    #[must_use]
+    pub const fn number(self, tile: &Tile) -> (u8, i32, i32) {
+        (
+            tile.zoom,
+            tile.x - (self.x << tile.zoom),
+            tile.y - (self.y << tile.zoom),
+        )
+    }
+
+    /// Tile by its zoom and position as numbered in the output
+    #[must_use]
+    pub const fn tile(self, (zoom, x, y): (u8, i32, i32)) -> Tile {
+        Tile {
+            zoom,
+            x: x + (self.x << zoom),
+            y: y + (self.y << zoom),
+        }
+    }
+}
+
+const fn span((i, j): (i32, i32), size: i32, offset: i32) -> ((i32, i32), (i32, i32)) {
+    let (x, z) = (i * size - offset, j * size - offset);
+
+    ((x, z), (x + size - 1, z + size - 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::iter;
+
+    // Within the world border, beyond which nothing is mapped
+    const BORDER: i32 = 29_999_984;
+
+    // Properties are checked of a deterministic pseudorandom sample of positions, along with the
+    // extremes and those around the origin where signs change
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)] // Sampled bits
+    fn positions() -> impl Iterator<Item = (i32, i32)> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let random = iter::repeat_with(move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        });
+
+        random
+            .map(|n| ((n as i32) % BORDER, ((n >> 32) as i32) % BORDER))
+            .take(10_000)
+            .chain((-130..130).map(|i| (i, -i)))
+            .chain([(BORDER, BORDER), (-BORDER, -BORDER), (BORDER, -BORDER)])
+    }
+
+    fn contains(((x0, z0), (x1, z1)): ((i32, i32), (i32, i32)), (x, z): (i32, i32)) -> bool {
+        (x0..=x1).contains(&x) && (z0..=z1).contains(&z)
+    }
+
+    #[test]
+    fn chunks_and_regions() {
+        for block in positions() {
+            let chunk = chunk_of(block);
+            assert!(contains(chunk_bounds(chunk), block), "{block:?}");
+            assert!(
+                contains(region_bounds(region_of(block)), block),
+                "{block:?}"
+            );
+            assert_eq!(region_of(block), region_of_chunk(chunk));
+        }
+
+        assert_eq!(chunk_of((-1, 16)), (-1, 1));
+        assert_eq!(region_of((-1, 512)), (-1, 1));
+        assert_eq!(region_bounds((-1, 0)), ((-512, 0), (-1, 511)));
+    }
+
+    #[test]
+    fn maps() {
+        for block in positions() {
+            for scale in 0..=4 {
+                let center = map_center(scale, block);
+                assert!(contains(map_bounds(scale, center), block), "{block:?}");
+                assert_eq!(map_center(scale, center), center);
+
+                // Maps are drawn onto the tile of their zoom that spans exactly them
+                let tile = Tile::from_position(scale, center.0, center.1);
+                assert_eq!(tile, tile_of(zoom_of_scale(scale), block), "{block:?}");
+                assert_eq!(tile_bounds(&tile), map_bounds(scale, center));
+            }
+        }
+
+        assert_eq!(map_center(0, (63, -65)), (0, -128));
+        assert_eq!(map_center(1, (0, 0)), (64, 64));
+        assert_eq!(map_center(4, (-65, 1983)), (-1088, 960));
+        assert_eq!(map_bounds(0, (0, 0)), ((-64, -64), (63, 63)));
+    }
+
+    #[test]
+    fn tiles() {
+        for block in positions() {
+            let leaf = tile_of(MAX_ZOOM, block);
+            for tile in leaf.ancestry() {
+                assert_eq!(tile, tile_of(tile.zoom, block), "{block:?}");
+                assert!(contains(tile_bounds(&tile), block), "{block:?}");
+            }
+        }
+
+        assert_eq!(tile_of(0, (1983, -65)), Tile::new(0, 0, -1));
+        assert_eq!(tile_bounds(&Tile::new(0, 0, 0)), ((-64, -64), (1983, 1983)));
+        assert_eq!(
+            tile_path(Origin::default(), &tile_of(4, (-400, 900)), "webp"),
+            "tiles/4/-3/7.webp"
+        );
+    }
+
+    #[test]
+    fn origins() {
+        let origin = Origin::at((100_000, -5000));
+        assert_eq!(origin, Origin { x: 48, y: -3 });
+        assert_eq!(Origin::at((0, 0)), Origin::default());
+
+        for block in positions() {
+            for tile in tile_of(MAX_ZOOM, block).ancestry() {
+                assert_eq!(origin.tile(origin.number(&tile)), tile, "{block:?}");
+                assert_eq!(Origin::default().number(&tile), (tile.zoom, tile.x, tile.y));
+            }
+        }
+
+        // Tiles within the origin are numbered from 0
+        assert_eq!(origin.number(&tile_of(0, (100_000, -5000))), (0, 0, 0));
+        assert_eq!(origin.number(&tile_of(4, (98_240, -6208))), (4, 0, 0));
+        assert_eq!(origin.number(&tile_of(4, (98_239, -6209))), (4, -1, -1));
+        assert_eq!(
+            tile_path(origin, &tile_of(4, (100_000, -5000)), "png"),
+            "tiles/4/13/9.png"
+        );
+    }
+}
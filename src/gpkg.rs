@@ -0,0 +1,240 @@
+//! `GeoPackage` of banners, of the coverage of maps, and of tiles, for analysis of exploration in
+//! GIS tools such as QGIS. Only with the `gis` feature.
+//!
+//! Coordinates are in blocks with north up, i.e. x and -z, in the undefined Cartesian SRS that
+//! every `GeoPackage` declares.
+
+use crate::geometry::Origin;
+use crate::map::MapScan;
+use crate::pattern::TilePattern;
+use crate::tile::Tile;
+use anyhow::Result;
+use itertools::Itertools;
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::SystemTime;
+
+const SRS_ID: i32 = -1;
+
+// Tables required of a GeoPackage, with the definitions of the SRSs it must declare, followed by
+// those of the features
+const SCHEMA: &str = r#"
+PRAGMA application_id = 1196444487;
+PRAGMA user_version = 10400;
+
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+INSERT INTO gpkg_spatial_ref_sys VALUES
+    ('Undefined Cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined Cartesian coordinate reference system'),
+    ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system'),
+    ('WGS 84 geodetic', 4326, 'EPSG', 4326, 'GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AXIS["Latitude",NORTH],AXIS["Longitude",EAST],AUTHORITY["EPSG","4326"]]', 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid');
+
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    min_x DOUBLE,
+    min_y DOUBLE,
+    max_x DOUBLE,
+    max_y DOUBLE,
+    srs_id INTEGER,
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL,
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL,
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+
+CREATE TABLE banners (
+    fid INTEGER PRIMARY KEY AUTOINCREMENT,
+    geom POINT NOT NULL,
+    name TEXT,
+    color TEXT NOT NULL,
+    maps TEXT NOT NULL
+);
+CREATE TABLE maps (
+    fid INTEGER PRIMARY KEY AUTOINCREMENT,
+    geom POLYGON NOT NULL,
+    map_id INTEGER NOT NULL UNIQUE,
+    scale INTEGER NOT NULL,
+    explorer BOOLEAN NOT NULL,
+    locked BOOLEAN NOT NULL,
+    modified DATETIME NOT NULL
+);
+CREATE TABLE tiles (
+    fid INTEGER PRIMARY KEY AUTOINCREMENT,
+    geom POLYGON NOT NULL,
+    zoom INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    path TEXT NOT NULL UNIQUE
+);
+
+INSERT INTO gpkg_contents (table_name, data_type, identifier, description, srs_id) VALUES
+    ('banners', 'features', 'banners', 'Banner markers', -1),
+    ('maps', 'features', 'maps', 'Areas covered by maps', -1),
+    ('tiles', 'features', 'tiles', 'Tiles of the output, by path relative to it', -1);
+INSERT INTO gpkg_geometry_columns VALUES
+    ('banners', 'geom', 'POINT', -1, 0, 0),
+    ('maps', 'geom', 'POLYGON', -1, 0, 0),
+    ('tiles', 'geom', 'POLYGON', -1, 0, 0);
+"#;
+
+/// Write the `GeoPackage` anew, replacing any previous one only once complete
+pub fn write(
+    path: &Path,
+    results: &MapScan,
+    tiles: &HashSet<(u8, i32, i32)>,
+    origin: Origin,
+    pattern: &TilePattern,
+    extension: &str,
+    modified: SystemTime,
+) -> Result<()> {
+    let partial_path = path.with_extension("gpkg.partial");
+    match fs::remove_file(&partial_path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let mut db = Connection::open(&partial_path)?;
+    db.execute_batch(SCHEMA)?;
+    let transaction = db.transaction()?;
+
+    {
+        let mut insert = transaction
+            .prepare("INSERT INTO banners (geom, name, color, maps) VALUES (?1, ?2, ?3, ?4)")?;
+        for (banner, ids) in &results.banners {
+            insert.execute(params![
+                point(banner.x, banner.z),
+                banner.label,
+                banner.color,
+                ids.iter().join(", "),
+            ])?;
+        }
+
+        let mut insert = transaction.prepare(
+            "INSERT INTO maps (geom, map_id, scale, explorer, locked, modified) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for map in results
+            .maps_by_tile
+            .values()
+            .flatten()
+            .sorted_by_key(|m| m.id)
+        {
+            insert.execute(params![
+                square(&map.tile),
+                map.id,
+                4 - map.tile.zoom,
+                results.explorer_map_ids.contains(&map.id),
+                map.locked,
+                humantime::format_rfc3339_millis(map.modified).to_string(),
+            ])?;
+        }
+
+        let mut insert = transaction
+            .prepare("INSERT INTO tiles (geom, zoom, x, y, path) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for &key in tiles.iter().sorted() {
+            let (zoom, x, y) = key;
+            insert.execute(params![
+                square(&origin.tile(key)),
+                zoom,
+                x,
+                y,
+                format!("tiles/{}.{extension}", pattern.format(key)),
+            ])?;
+        }
+    }
+
+    transaction.commit()?;
+    db.close().map_err(|(_, e)| e)?;
+
+    fs::rename(&partial_path, path)?;
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)?;
+
+    Ok(())
+}
+
+// GeoPackageBinary, i.e. a header of flags, SRS, and envelope followed by little-endian WKB
+fn geometry(envelope: Option<[f64; 4]>, wkb: &[u8]) -> Vec<u8> {
+    // Little-endian, with an envelope of [min x, max x, min y, max y] if any
+    let flags = if envelope.is_some() { 0b11 } else { 0b01 };
+
+    let mut blob = vec![b'G', b'P', 0, flags];
+    blob.extend(SRS_ID.to_le_bytes());
+    for bound in envelope.into_iter().flatten() {
+        blob.extend(bound.to_le_bytes());
+    }
+    blob.extend(wkb);
+
+    blob
+}
+
+fn point(x: i32, z: i32) -> Vec<u8> {
+    let mut wkb = vec![1];
+    wkb.extend(1_u32.to_le_bytes());
+    wkb.extend(f64::from(x).to_le_bytes());
+    wkb.extend(f64::from(-z).to_le_bytes());
+
+    geometry(None, &wkb)
+}
+
+// Exterior ring counterclockwise with north up, as simple features expect
+fn square(tile: &Tile) -> Vec<u8> {
+    let (x, z) = tile.position();
+    let size = 128 * 2_i32.pow(u32::from(4 - tile.zoom));
+    let (x0, x1) = (f64::from(x), f64::from(x + size));
+    let (y0, y1) = (f64::from(-z - size), f64::from(-z));
+
+    let mut wkb = vec![1];
+    wkb.extend(3_u32.to_le_bytes());
+    wkb.extend(1_u32.to_le_bytes());
+    wkb.extend(5_u32.to_le_bytes());
+    for (x, y) in [(x0, y1), (x0, y0), (x1, y0), (x1, y1), (x0, y1)] {
+        wkb.extend(x.to_le_bytes());
+        wkb.extend(y.to_le_bytes());
+    }
+
+    geometry(Some([x0, x1, y0, y1]), &wkb)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let blob = point(3, 5);
+        assert_eq!(&blob[..4], b"GP\x00\x01");
+        assert_eq!(blob.len(), 8 + 21);
+        assert_eq!(blob[13..21], 3.0_f64.to_le_bytes());
+        assert_eq!(blob[21..29], (-5.0_f64).to_le_bytes());
+
+        let blob = square(&Tile::new(4, 0, 0));
+        assert_eq!(&blob[..4], b"GP\x00\x03");
+        assert_eq!(blob.len(), 8 + 32 + 13 + 5 * 16);
+        assert_eq!(blob[16..24], 128.0_f64.to_le_bytes());
+        assert_eq!(blob[24..32], (-128.0_f64).to_le_bytes());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::{History, Period};
+use crate::paths::glob_in;
+use crate::pattern::TilePattern;
+use anyhow::Result;
+use log::debug;
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::SystemTime;
+
+type Manifest = BTreeMap<String, u64>;
+
+// Each version of a tile is archived once, keyed by its modification time, and each snapshot is a
+// manifest of the versions current at the time.
+pub fn snapshot(
+    output_path: &Path,
+    tiles: &HashSet<(u8, i32, i32)>,
+    pattern: &TilePattern,
+    history: &History,
+    now: SystemTime,
+) -> Result<usize> {
+    let history_path = output_path.join("history");
+    let name = snapshot_name(now, history.period);
+
+    let archived = tiles
+        .par_iter()
+        .map(|&(zoom, x, y)| -> Result<(String, u64, bool)> {
+            let key = format!("{zoom}/{x}/{y}");
+            let tile_path =
+                output_path.join(format!("tiles/{}.webp", pattern.format((zoom, x, y))));
+            let modified = fs::metadata(&tile_path)?.modified()?;
+            let version = modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+            let object_path = history_path.join(format!("tiles/{key}/{version}.webp"));
+
+            let is_new = !object_path.exists();
+            if is_new {
+                fs::create_dir_all(object_path.parent().unwrap())?;
+                fs::copy(&tile_path, &object_path)?;
+                File::options()
+                    .write(true)
+                    .open(&object_path)?
+                    .set_modified(modified)?;
+            }
+
+            Ok((key, version, is_new))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let archived_count = archived.iter().filter(|(_, _, is_new)| *is_new).count();
+    let manifest = archived
+        .into_iter()
+        .map(|(key, version, _)| (key, version))
+        .collect::<Manifest>();
+
+    fs::create_dir_all(&history_path)?;
+    serde_json::to_writer(
+        File::create(history_path.join(format!("{name}.json")))?,
+        &manifest,
+    )?;
+
+    let mut names = glob_in(&history_path, "*.json")?
+        .map(|entry| Ok(entry?.file_stem().unwrap().to_string_lossy().into_owned()))
+        .filter(|name| !matches!(name.as_deref(), Ok("index")))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    let expired = names.len().saturating_sub(history.keep.max(1));
+    for name in names.drain(..expired) {
+        debug!("Prune: history snapshot {name}");
+        fs::remove_file(history_path.join(format!("{name}.json")))?;
+    }
+
+    let mut referenced = HashSet::new();
+    for name in &names {
+        let manifest: Manifest =
+            serde_json::from_reader(File::open(history_path.join(format!("{name}.json")))?)?;
+        referenced.extend(
+            manifest
+                .into_iter()
+                .map(|(key, version)| history_path.join(format!("tiles/{key}/{version}.webp"))),
+        );
+    }
+    for entry in glob_in(&history_path, "tiles/*/*/*/*.webp")? {
+        let path = entry?;
+        if !referenced.contains(&path) {
+            debug!("Prune: {}", path.display());
+            fs::remove_file(path)?;
+        }
+    }
+
+    serde_json::to_writer(
+        File::create(history_path.join("index.json"))?,
+        &json!({ "snapshots": names }),
+    )?;
+
+    Ok(archived_count)
+}
+
+fn snapshot_name(time: SystemTime, period: Period) -> String {
+    let date = humantime::format_rfc3339_seconds(time).to_string();
+
+    match period {
+        Period::Day => date[..10].to_owned(),
+        Period::Month => date[..7].to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn snapshot_names() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(snapshot_name(time, Period::Day), "2023-11-14");
+        assert_eq!(snapshot_name(time, Period::Month), "2023-11");
+    }
+}
@@ -0,0 +1,264 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    De,
+    #[default]
+    En,
+    Es,
+    Fr,
+    Ja,
+}
+
+impl Lang {
+    pub const ALL: [Self; 5] = [Self::De, Self::En, Self::Es, Self::Fr, Self::Ja];
+
+    #[must_use]
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::De => "de",
+            Self::En => "en",
+            Self::Es => "es",
+            Self::Fr => "fr",
+            Self::Ja => "ja",
+        }
+    }
+
+    #[must_use]
+    pub const fn strings(self) -> &'static Strings {
+        match self {
+            Self::De => &Strings {
+                banners: "Banner",
+                banners_by_color: "Banner nach Farbe",
+                colors: [
+                    "Schwarz",
+                    "Blau",
+                    "Braun",
+                    "Türkis",
+                    "Grau",
+                    "Grün",
+                    "Hellblau",
+                    "Hellgrau",
+                    "Hellgrün",
+                    "Magenta",
+                    "Orange",
+                    "Rosa",
+                    "Violett",
+                    "Rot",
+                    "Weiß",
+                    "Gelb",
+                ],
+                freshness: "Aktualität",
+                gallery: "Galerie",
+                last_played: "zuletzt gespielt {time}",
+                locked: "Gesperrt, daher nicht mehr aktualisiert",
+                map: "Karte",
+                maps_by_age: "Karten nach Alter",
+                maps_by_dimension: "Karten nach Dimension",
+                maps_by_scale: "Karten nach Maßstab",
+                now: "Jetzt",
+                statistics: "Statistik",
+                uncharted: "Unerforscht",
+                untracked: "Ohne Positionsanzeige",
+                world_day: "Welttag {day}",
+            },
+            Self::En => &Strings {
+                banners: "Banners",
+                banners_by_color: "Banners by color",
+                colors: [
+                    "Black",
+                    "Blue",
+                    "Brown",
+                    "Cyan",
+                    "Gray",
+                    "Green",
+                    "Light Blue",
+                    "Light Gray",
+                    "Lime",
+                    "Magenta",
+                    "Orange",
+                    "Pink",
+                    "Purple",
+                    "Red",
+                    "White",
+                    "Yellow",
+                ],
+                freshness: "Freshness",
+                gallery: "Gallery",
+                last_played: "last played {time}",
+                locked: "Locked, so no longer updated",
+                map: "Map",
+                maps_by_age: "Maps by age",
+                maps_by_dimension: "Maps by dimension",
+                maps_by_scale: "Maps by scale",
+                now: "Now",
+                statistics: "Statistics",
+                uncharted: "Uncharted",
+                untracked: "Not tracking position",
+                world_day: "World day {day}",
+            },
+            Self::Es => &Strings {
+                banners: "Estandartes",
+                banners_by_color: "Estandartes por color",
+                colors: [
+                    "Negro",
+                    "Azul",
+                    "Marrón",
+                    "Cian",
+                    "Gris",
+                    "Verde",
+                    "Azul claro",
+                    "Gris claro",
+                    "Verde lima",
+                    "Magenta",
+                    "Naranja",
+                    "Rosa",
+                    "Morado",
+                    "Rojo",
+                    "Blanco",
+                    "Amarillo",
+                ],
+                freshness: "Actualidad",
+                gallery: "Galería",
+                last_played: "jugado por última vez {time}",
+                locked: "Bloqueado, así que ya no se actualiza",
+                map: "Mapa",
+                maps_by_age: "Mapas por antigüedad",
+                maps_by_dimension: "Mapas por dimensión",
+                maps_by_scale: "Mapas por escala",
+                now: "Ahora",
+                statistics: "Estadísticas",
+                uncharted: "Inexplorado",
+                untracked: "Sin seguimiento de posición",
+                world_day: "Día {day} del mundo",
+            },
+            Self::Fr => &Strings {
+                banners: "Bannières",
+                banners_by_color: "Bannières par couleur",
+                colors: [
+                    "Noir",
+                    "Bleu",
+                    "Marron",
+                    "Cyan",
+                    "Gris",
+                    "Vert",
+                    "Bleu clair",
+                    "Gris clair",
+                    "Vert clair",
+                    "Magenta",
+                    "Orange",
+                    "Rose",
+                    "Violet",
+                    "Rouge",
+                    "Blanc",
+                    "Jaune",
+                ],
+                freshness: "Fraîcheur",
+                gallery: "Galerie",
+                last_played: "dernière partie {time}",
+                locked: "Verrouillée, donc plus mise à jour",
+                map: "Carte",
+                maps_by_age: "Cartes par âge",
+                maps_by_dimension: "Cartes par dimension",
+                maps_by_scale: "Cartes par échelle",
+                now: "Maintenant",
+                statistics: "Statistiques",
+                uncharted: "Inexploré",
+                untracked: "Sans suivi de position",
+                world_day: "Jour {day} du monde",
+            },
+            Self::Ja => &Strings {
+                banners: "旗",
+                banners_by_color: "色別の旗",
+                colors: [
+                    "黒色",
+                    "青色",
+                    "茶色",
+                    "青緑色",
+                    "灰色",
+                    "緑色",
+                    "空色",
+                    "薄灰色",
+                    "黄緑色",
+                    "赤紫色",
+                    "橙色",
+                    "桃色",
+                    "紫色",
+                    "赤色",
+                    "白色",
+                    "黄色",
+                ],
+                freshness: "鮮度",
+                gallery: "ギャラリー",
+                last_played: "最終プレイ {time}",
+                locked: "ロック済みのため更新されない",
+                map: "地図",
+                maps_by_age: "経過日数別の地図",
+                maps_by_dimension: "ディメンション別の地図",
+                maps_by_scale: "縮尺別の地図",
+                now: "現在",
+                statistics: "統計",
+                uncharted: "未踏",
+                untracked: "位置表示なし",
+                world_day: "ワールド {day} 日目",
+            },
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Self::ALL
+            .into_iter()
+            .find(|l| l.code() == s)
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "Expected one of {}: {s}",
+                    Self::ALL.map(Self::code).join(", ")
+                ))
+            })
+    }
+}
+
+// Text of the viewer and its pages, which are otherwise language-neutral
+#[derive(Serialize)]
+pub struct Strings {
+    pub banners: &'static str,
+    pub banners_by_color: &'static str,
+    // Of banners, in the order of `banner::COLORS`
+    #[serde(skip)]
+    pub colors: [&'static str; 16],
+    pub freshness: &'static str,
+    pub gallery: &'static str,
+    // With a placeholder {time}
+    pub last_played: &'static str,
+    pub locked: &'static str,
+    pub map: &'static str,
+    pub maps_by_age: &'static str,
+    pub maps_by_dimension: &'static str,
+    pub maps_by_scale: &'static str,
+    pub now: &'static str,
+    pub statistics: &'static str,
+    pub uncharted: &'static str,
+    pub untracked: &'static str,
+    // With a placeholder {day}
+    pub world_day: &'static str,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        for lang in Lang::ALL {
+            assert_eq!(lang.code().parse::<Lang>().unwrap(), lang);
+        }
+        assert!("xx".parse::<Lang>().is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// Glyphs drawn over banner markers, each a path in a 16 × 16 view box
+const ICONS: [(&str, &str); 8] = [
+    ("anvil", "M2 3h11v2h-2c0 2 1 3 3 3v2H2V8c2 0 3-1 3-3H2zM5 10h6v2h2v2H3v-2h2z"),
+    ("castle", "M2 2h2v2h2V2h4v2h2V2h2v5l-1 1v6H3V8L2 7zM7 10v4h2v-4z"),
+    ("farm", "M8 1c3 2 3 5 0 7-3-2-3-5 0-7zM7 8h2v7H7zM3 7c2 0 4 2 4 4-2 0-4-2-4-4zm10 0c0 2-2 4-4 4 0-2 2-4 4-4z"),
+    ("home", "M8 1l7 7h-2v7H9v-4H7v4H3V8H1z"),
+    ("mine", "M2 3c4-2 8-2 12 0l-1 1c-3-1-7-1-10 0zM7 3h2l1 12H6z"),
+    ("portal", "M4 1h8v14H4zm2 2v10h4V3z"),
+    ("shop", "M1 2h14l-1 5H2zM3 8h10v7h-3v-4H6v4H3z"),
+    ("star", "M8 1l2 5h5l-4 3 2 6-5-4-5 4 2-6-4-3h5z"),
+];
+
+/// Icon of the longest prefix of the label among those configured
+#[must_use]
+pub fn resolve<'a>(label: &str, prefixes: &'a BTreeMap<String, String>) -> Option<&'a str> {
+    prefixes
+        .iter()
+        .filter(|(prefix, _)| label.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, icon)| icon.as_str())
+}
+
+pub fn validate(prefixes: &BTreeMap<String, String>) -> Result<()> {
+    for icon in prefixes.values() {
+        if !ICONS.iter().any(|(name, _)| name == icon) {
+            bail!(
+                "Unknown icon {icon}; expected one of {}",
+                ICONS.map(|(name, _)| name).join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Written only when changed, like world.json
+pub fn write_sprite(output_path: &Path) -> Result<()> {
+    let sprite = sprite();
+    let path = output_path.join("icons.svg");
+
+    if fs::read_to_string(&path).map_or(true, |s| s != sprite) {
+        fs::write(path, sprite)?;
+    }
+
+    Ok(())
+}
+
+fn sprite() -> String {
+    let mut sprite = r#"<svg xmlns="http://www.w3.org/2000/svg">"#.to_owned();
+    for (name, path) in ICONS {
+        let _ = write!(
+            sprite,
+            r#"<symbol id="{name}" viewBox="0 0 16 16"><path d="{path}"/></symbol>"#
+        );
+    }
+    sprite.push_str("</svg>");
+
+    sprite
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolution() {
+        let prefixes = BTreeMap::from([
+            ("⚒".to_owned(), "anvil".to_owned()),
+            ("[shop]".to_owned(), "shop".to_owned()),
+            ("[shop] ⚒".to_owned(), "star".to_owned()),
+        ]);
+
+        assert_eq!(resolve("⚒ Smithy", &prefixes), Some("anvil"));
+        assert_eq!(resolve("[shop] Bakery", &prefixes), Some("shop"));
+        assert_eq!(resolve("[shop] ⚒ Tools", &prefixes), Some("star"));
+        assert_eq!(resolve("Spawn", &prefixes), None);
+        assert!(validate(&prefixes).is_ok());
+
+        let unknown = BTreeMap::from([("$".to_owned(), "bank".to_owned())]);
+        assert!(validate(&unknown).is_err());
+    }
+
+    #[test]
+    fn symbols() {
+        let sprite = sprite();
+
+        for (name, _) in ICONS {
+            assert!(sprite.contains(&format!(r#"<symbol id="{name}""#)));
+        }
+    }
+}
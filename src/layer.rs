@@ -0,0 +1,157 @@
+//! Overlays of the viewer from data beyond the world, provided by a downstream user of the library
+
+use crate::paths::glob_in;
+use crate::Error;
+use log::debug;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Source of an extra overlay of the viewer, e.g. claims from the database of a land claim plugin
+/// or the lines of a rail network
+///
+/// Its features are fetched on every render and written to `layers/{name}.json` as a
+/// `FeatureCollection` in block coordinates `[x, z]`. A feature may set `color` and `name` among
+/// its properties to be drawn in that color and labeled.
+pub trait LayerProvider: Send + Sync {
+    /// Identifier of the layer of letters, digits, `-`, and `_`, distinct among layers
+    fn name(&self) -> &str;
+
+    /// Label of the layer in the viewer's control of overlays
+    fn label(&self) -> &str {
+        self.name()
+    }
+
+    /// Features of the layer, each a `Feature` object
+    fn features(&self) -> Result<Vec<Value>, Error>;
+}
+
+/// Providers of the extra overlays of an output
+#[derive(Clone, Default)]
+pub struct Layers(pub Vec<Arc<dyn LayerProvider>>);
+
+impl fmt::Debug for Layers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|provider| provider.name()))
+            .finish()
+    }
+}
+
+/// Overlay as listed in the viewer
+#[derive(Serialize)]
+pub struct LayerInfo {
+    name: String,
+    label: String,
+    version: String,
+}
+
+// Rewritten only when changed so that a caching proxy or mirror refetches only what did. Layers
+// of providers no longer registered are pruned.
+pub fn write(output_path: &Path, layers: &Layers) -> Result<Vec<LayerInfo>, Error> {
+    let dir_path = output_path.join("layers");
+
+    let mut names = HashSet::new();
+    let mut infos = Vec::new();
+    for provider in &layers.0 {
+        let name = provider.name();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error::Config(format!("Invalid layer name: {name:?}")));
+        }
+        if !names.insert(name) {
+            return Err(Error::Config(format!(
+                "Layer names must be distinct: {name}"
+            )));
+        }
+
+        let features = provider.features()?;
+        let contents = json!({ "type": "FeatureCollection", "features": features }).to_string();
+        let path = dir_path.join(format!("{name}.json"));
+        if fs::read_to_string(&path).map_or(true, |c| c != contents) {
+            fs::create_dir_all(&dir_path)?;
+            fs::write(&path, &contents)?;
+        }
+
+        infos.push(LayerInfo {
+            name: name.to_owned(),
+            label: provider.label().to_owned(),
+            version: format!("{:08x}", crc32fast::hash(contents.as_bytes())),
+        });
+    }
+
+    if dir_path.is_dir() {
+        for entry in glob_in(&dir_path, "*.json")? {
+            let path = entry.map_err(glob::GlobError::into_error)?;
+            let name = path.file_stem().unwrap().to_string_lossy();
+            if !names.contains(&*name) {
+                debug!("Prune: {}", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+        if names.is_empty() {
+            fs::remove_dir_all(&dir_path)?;
+        }
+    }
+
+    Ok(infos)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Fixed(&'static str, Vec<Value>);
+    impl LayerProvider for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn features(&self) -> Result<Vec<Value>, Error> {
+            Ok(self.1.clone())
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = dir.path();
+        let point = json!({
+            "type": "Feature",
+            "geometry": { "type": "Point", "coordinates": [1, 2] },
+            "properties": { "name": "Station" },
+        });
+        let rails = Layers(vec![Arc::new(Fixed("rails", vec![point]))]);
+
+        let infos = write(output, &rails).unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].label, "rails");
+        let collection: Value =
+            serde_json::from_str(&fs::read_to_string(output.join("layers/rails.json")).unwrap())
+                .unwrap();
+        assert_eq!(collection["features"][0]["properties"]["name"], "Station");
+
+        for invalid in ["", "../rails", "rails.json"] {
+            let layers = Layers(vec![Arc::new(Fixed(invalid, Vec::new()))]);
+            assert!(
+                matches!(write(output, &layers), Err(Error::Config(_))),
+                "{invalid}"
+            );
+        }
+        let repeated = Layers(vec![
+            Arc::new(Fixed("rails", Vec::new())),
+            Arc::new(Fixed("rails", Vec::new())),
+        ]);
+        assert!(matches!(write(output, &repeated), Err(Error::Config(_))));
+
+        write(output, &Layers::default()).unwrap();
+        assert!(!output.join("layers").exists());
+    }
+}
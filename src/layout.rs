@@ -0,0 +1,245 @@
+use crate::paths::glob_in;
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind::NotFound;
+use std::path::Path;
+
+pub const LAYOUT_VERSION: u32 = 3;
+
+const MARKER: &str = ".layout_version";
+
+// Settings by which the output is laid out or written, e.g. the format of its images or the icons of
+// its banners, each recorded once changed from its default so that a later change of it is noticed
+// and the output laid out or written again
+const SETTINGS: &str = ".layout_settings.json";
+
+type Settings = BTreeMap<String, String>;
+
+pub const IMAGE_FORMAT: &str = "image_format";
+pub const COLORS: &str = "colors";
+pub const TILE_PATTERN: &str = "tile_pattern";
+pub const TILE_ORIGIN: &str = "tile_origin";
+pub const BANNER_ICONS: &str = "banner_icons";
+pub const GEOJSON: &str = "geojson";
+
+// Each migration upgrades the layout from its index to the next version
+const MIGRATIONS: [fn(&Path) -> Result<()>; LAYOUT_VERSION as usize] = [
+    migrate_png_to_webp,
+    migrate_image_format_marker,
+    migrate_tile_pattern_marker,
+];
+
+fn version(output_path: &Path) -> Result<u32> {
+    let marker_path = output_path.join(MARKER);
+
+    match fs::read_to_string(&marker_path) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .with_context(|| format!("Failed to parse {}", marker_path.display())),
+        Err(e) if e.kind() == NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn migrate(output_path: &Path) -> Result<()> {
+    let version = version(output_path)?;
+
+    if version == LAYOUT_VERSION {
+        return Ok(());
+    }
+    if version > LAYOUT_VERSION {
+        bail!(
+            "Output layout version {version} is newer than supported version {LAYOUT_VERSION}: {}",
+            output_path.display()
+        );
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrate output layout from version {from} to {}", from + 1);
+        migration(output_path)?;
+    }
+
+    fs::create_dir_all(output_path)?;
+    fs::write(output_path.join(MARKER), format!("{LAYOUT_VERSION}\n"))?;
+
+    Ok(())
+}
+
+/// Setting of an output as last laid out, or None if never changed from its default
+///
+/// Outputs yet to be migrated are read as they are, e.g. the sources of a merge.
+pub fn recorded(output_path: &Path, setting: &str) -> Result<Option<String>> {
+    let mut settings = legacy_settings(output_path)?;
+    settings.extend(read_settings(output_path)?.unwrap_or_default());
+
+    Ok(settings.remove(setting))
+}
+
+/// Whether a setting of an output differs from that by which it was last laid out, in which case
+/// `change` is called with the previous value to lay out the output anew before the setting is
+/// recorded
+pub fn switch(
+    output_path: &Path,
+    setting: &str,
+    value: &str,
+    default: &str,
+    change: impl FnOnce(&str) -> Result<()>,
+) -> Result<bool> {
+    let settings = read_settings(output_path)?.unwrap_or_default();
+    let previous = settings.get(setting).map_or(default, String::as_str);
+    if previous == value {
+        return Ok(false);
+    }
+
+    change(previous)?;
+    record(output_path, setting, value, default)?;
+
+    Ok(true)
+}
+
+/// Record a setting of an output once laid out by it
+pub fn record(output_path: &Path, setting: &str, value: &str, default: &str) -> Result<()> {
+    let mut settings = read_settings(output_path)?.unwrap_or_default();
+    if value == default {
+        settings.remove(setting);
+    } else {
+        settings.insert(setting.to_owned(), value.to_owned());
+    }
+
+    write_settings(output_path, &settings)
+}
+
+fn read_settings(output_path: &Path) -> Result<Option<Settings>> {
+    let path = output_path.join(SETTINGS);
+
+    match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", path.display())),
+        Err(e) if e.kind() == NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_settings(output_path: &Path, settings: &Settings) -> Result<()> {
+    let path = output_path.join(SETTINGS);
+
+    if settings.is_empty() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    } else {
+        fs::create_dir_all(output_path)?;
+        Ok(fs::write(path, serde_json::to_string_pretty(settings)?)?)
+    }
+}
+
+fn remove_matching(output_path: &Path, pattern: &str) -> Result<()> {
+    for entry in glob_in(output_path, pattern)? {
+        let path = entry?;
+        debug!("Prune: {}", path.display());
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+// Unversioned layouts may contain PNG images, which pruning of WebP images never matches
+fn migrate_png_to_webp(output_path: &Path) -> Result<()> {
+    remove_matching(output_path, "maps/*.png")?;
+    remove_matching(output_path, "tiles/*/*/*.png")
+}
+
+fn read_marker(output_path: &Path, name: &str) -> Result<Option<String>> {
+    match fs::read_to_string(output_path.join(name)) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Settings were once recorded each in a marker of its own, which is read as a fallback until
+// migrated
+fn legacy_settings(output_path: &Path) -> Result<Settings> {
+    let mut settings = image_format_marker(output_path)?;
+    settings.extend(tile_pattern_marker(output_path)?);
+
+    Ok(settings)
+}
+
+fn migrate_marker(
+    output_path: &Path,
+    name: &str,
+    read: fn(&Path) -> Result<Settings>,
+) -> Result<()> {
+    let mut settings = read_settings(output_path)?.unwrap_or_default();
+    settings.extend(read(output_path)?);
+    write_settings(output_path, &settings)?;
+
+    match fs::remove_file(output_path.join(name)) {
+        Err(e) if e.kind() != NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Layouts before version 2 recorded the image format, and on a second line any colors other than
+// the default, in a marker of their own
+const IMAGE_FORMAT_MARKER: &str = ".image_format";
+
+fn image_format_marker(output_path: &Path) -> Result<Settings> {
+    let text = read_marker(output_path, IMAGE_FORMAT_MARKER)?.unwrap_or_default();
+    let lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    Ok([IMAGE_FORMAT, COLORS]
+        .into_iter()
+        .zip(lines)
+        .map(|(setting, value)| (setting.to_owned(), value.to_owned()))
+        .collect())
+}
+
+fn migrate_image_format_marker(output_path: &Path) -> Result<()> {
+    migrate_marker(output_path, IMAGE_FORMAT_MARKER, image_format_marker)
+}
+
+// Layouts before version 3 recorded any tile pattern other than the default in a marker of its own
+const TILE_PATTERN_MARKER: &str = ".tile_pattern";
+
+fn tile_pattern_marker(output_path: &Path) -> Result<Settings> {
+    Ok(read_marker(output_path, TILE_PATTERN_MARKER)?
+        .map(|text| (TILE_PATTERN.to_owned(), text.trim().to_owned()))
+        .into_iter()
+        .collect())
+}
+
+fn migrate_tile_pattern_marker(output_path: &Path) -> Result<()> {
+    migrate_marker(output_path, TILE_PATTERN_MARKER, tile_pattern_marker)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn markers() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = dir.path();
+        fs::write(output.join(MARKER), "1\n").unwrap();
+        fs::write(output.join(IMAGE_FORMAT_MARKER), "png\nflat\n").unwrap();
+        fs::write(output.join(TILE_PATTERN_MARKER), "{z}_{x}_{y}\n").unwrap();
+        let recorded = |setting| recorded(output, setting).unwrap();
+
+        // Read as they are before migrating, e.g. of the sources of a merge
+        assert_eq!(recorded(TILE_PATTERN).as_deref(), Some("{z}_{x}_{y}"));
+
+        migrate(output).unwrap();
+        assert_eq!(version(output).unwrap(), LAYOUT_VERSION);
+        assert!(!output.join(IMAGE_FORMAT_MARKER).exists());
+        assert!(!output.join(TILE_PATTERN_MARKER).exists());
+        assert_eq!(recorded(IMAGE_FORMAT).as_deref(), Some("png"));
+        assert_eq!(recorded(COLORS).as_deref(), Some("flat"));
+        assert_eq!(recorded(TILE_PATTERN).as_deref(), Some("{z}_{x}_{y}"));
+    }
+}
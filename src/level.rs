@@ -1,32 +1,198 @@
 use super::COMPATIBLE_VERSIONS;
-use crate::utilities::read_gz;
-use anyhow::{Context, Result};
+use crate::utilities::{read_gz, retry_once};
+use crate::Error;
 use fastnbt::from_bytes;
 use forgiving_semver::{Version, VersionReq};
-use std::path::Path;
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind::NotFound;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Save formats changed at these DataVersions, and not all at once across a world, since chunks
+// keep their format until loaded again. Chunks and items are therefore read by their shape.
+
+/// 21w43a, leading up to 1.18, where chunks lose their `Level` compound
+pub const CHUNK_ROOT_DATA_VERSION: i32 = 2844;
+
+/// 24w09a, leading up to 1.20.5, where the `tag` of items gives way to components
+pub const ITEM_COMPONENTS_DATA_VERSION: i32 = 3819;
+
+const TICKS_PER_DAY: i64 = 24_000;
 
 #[derive(serde_query::Deserialize)]
 pub struct Level {
+    #[query(".Data.BorderCenterX")]
+    pub border_center_x: f64,
+    #[query(".Data.BorderCenterZ")]
+    pub border_center_z: f64,
+    #[query(".Data.BorderSize")]
+    pub border_size: f64,
+    #[query(".Data.DataVersion")]
+    pub data_version: i32,
+    #[query(".Data.DayTime")]
+    day_time: i64,
+    #[query(".Data.GameRules")]
+    game_rules: HashMap<String, String>,
+    #[query(".Data.LastPlayed")]
+    last_played: i64,
     #[query(".Data.SpawnX")]
     pub spawn_x: i32,
     #[query(".Data.SpawnZ")]
     pub spawn_z: i32,
+    /// Game ticks since the world was created
+    #[query(".Data.Time")]
+    pub time: i64,
     #[query(".Data.Version.Name")]
     pub version: Version,
 }
 
 impl Level {
-    pub fn from_world_path(world_path: &Path) -> Result<Self> {
+    pub fn from_world_path(world_path: &Path) -> Result<Self, Error> {
+        let level = Self::read(world_path)?;
+
+        if !level.is_compatible() {
+            return Err(Error::Incompatible {
+                path: world_path.to_owned(),
+                version: level.version.to_string(),
+            });
+        }
+
+        Ok(level)
+    }
+
+    /// Read regardless of the game version, e.g. to report it
+    // The backup kept by the game stands in for level.dat while that's unreadable
+    pub fn read(world_path: &Path) -> Result<Self, Error> {
         let path = world_path.join("level.dat");
-        let level: Self = from_bytes(&read_gz(&path)?)
-            .with_context(|| format!("Failed to deserialize {}", path.display()))?;
+        let level = retry_once(&path, || Self::from_path(&path)).or_else(|e| {
+            let backup_path = world_path.join("level.dat_old");
+            if !backup_path.exists() {
+                return Err(e);
+            }
 
-        assert!(
-            VersionReq::parse(COMPATIBLE_VERSIONS)?.matches(&level.version),
-            "Incompatible with game version {}",
-            level.version
-        );
+            warn!("{e}; falling back to {}", backup_path.display());
+            Self::from_path(&backup_path)
+        })?;
 
         Ok(level)
     }
+
+    #[must_use]
+    pub fn is_compatible(&self) -> bool {
+        VersionReq::parse(COMPATIBLE_VERSIONS)
+            .unwrap()
+            .matches(&self.version)
+    }
+
+    fn from_path(path: &Path) -> Result<Self, Error> {
+        from_bytes(&read_gz(path)?).map_err(|e| Error::nbt(path, e))
+    }
+
+    /// Days as counted by the game, which sleeping through the night advances
+    #[must_use]
+    pub const fn day(&self) -> i64 {
+        self.day_time / TICKS_PER_DAY
+    }
+
+    // Zero where the world was never played, e.g. as generated by other tools than the game
+    #[must_use]
+    pub fn last_played(&self) -> Option<SystemTime> {
+        u64::try_from(self.last_played)
+            .ok()
+            .filter(|&millis| millis > 0)
+            .map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    fn game_rule(&self, name: &str) -> Option<u32> {
+        self.game_rules.get(name)?.parse().ok()
+    }
+
+    // Only present since 1.20.5
+    #[must_use]
+    pub fn spawn_chunk_radius(&self) -> Option<u32> {
+        self.game_rule("spawnChunkRadius")
+    }
+
+    #[must_use]
+    pub fn spawn_radius(&self) -> Option<u32> {
+        self.game_rule("spawnRadius")
+    }
+}
+
+// A server directory names its world in server.properties, defaulting to "world"
+pub fn resolve_world_path(path: &Path) -> Result<PathBuf, Error> {
+    if path.join("level.dat").exists() {
+        return Ok(path.to_owned());
+    }
+
+    let properties_path = path.join("server.properties");
+    let properties = match fs::read_to_string(&properties_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == NotFound => return Ok(path.to_owned()),
+        Err(e) => return Err(Error::io(&properties_path, e)),
+    };
+    let world_path = path.join(level_name(&properties).unwrap_or_else(|| "world".to_owned()));
+
+    if !world_path.join("level.dat").exists() {
+        return Err(Error::WorldNotFound { path: world_path });
+    }
+
+    Ok(world_path)
+}
+
+fn level_name(properties: &str) -> Option<String> {
+    properties
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| !l.starts_with('#') && !l.starts_with('!'))
+        .find_map(|l| {
+            let (key, value) = l.split_once(['=', ':'])?;
+            (key.trim_end() == "level-name").then(|| unescape(value.trim_start()))
+        })
+        .filter(|n| !n.is_empty())
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(e) => e,
+                None => break,
+            }
+        } else {
+            c
+        });
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn level_names() {
+        assert_eq!(
+            level_name("level-name=survival\n").as_deref(),
+            Some("survival")
+        );
+        assert_eq!(
+            level_name("#level-name=old\nmotd=Hi\nlevel-name = My\\ World\n").as_deref(),
+            Some("My World")
+        );
+        assert_eq!(
+            level_name("level-name=C\\:\\\\worlds\n").as_deref(),
+            Some("C:\\worlds")
+        );
+        assert_eq!(level_name("level-name=\n"), None);
+        assert_eq!(level_name("motd=Hi\n"), None);
+    }
 }
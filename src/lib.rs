@@ -1,44 +1,428 @@
 #![allow(clippy::implicit_hasher)]
 
+// Span of a phase of a run, recorded to the trace of `--profile` only with the `profiling` feature
+#[cfg(feature = "profiling")]
+macro_rules! span {
+    ($($args:tt)*) => {
+        let _span = tracing::info_span!($($args)*).entered();
+    };
+}
+#[cfg(not(feature = "profiling"))]
+macro_rules! span {
+    ($($args:tt)*) => {};
+}
+
+mod activity;
+mod anvil;
+mod assets;
 mod banner;
+mod batch;
+mod blob;
 mod cache;
+pub mod config;
+pub mod corpus;
+pub mod daemon;
+pub mod diff;
+pub mod dimension;
+pub mod encoding;
+mod error;
+#[cfg(feature = "faults")]
+pub mod fault;
+mod force;
+mod frame;
+mod gallery;
+mod generator;
+pub mod geometry;
+#[cfg(feature = "gis")]
+mod gpkg;
+mod history;
+pub mod i18n;
+mod icons;
+pub mod layer;
+mod layout;
 pub mod level;
+mod manifest;
 mod map;
+pub mod merge;
+pub mod message;
+mod open_files;
 pub mod palette;
+mod paths;
+pub mod pattern;
+#[cfg(feature = "profiling")]
+mod profiling;
+mod release;
 mod search;
+pub mod stats;
+#[cfg(feature = "synthetic")]
+pub mod synthetic;
 mod tile;
 mod utilities;
+pub mod warning;
 
-use anyhow::Result;
+use crate::paths::glob_in;
+use anyhow::{anyhow, bail, Result};
 use askama::Template;
-use banner::Banner;
-use cache::Cache;
-use glob::glob;
+pub use banner::Banner;
+use banner::{Labels, Listed, MarkerColor};
+use batch::Batch;
+use cache::{Cache, MapIds, Region};
+pub use cache::{CacheEntries, CacheMiss};
+use config::{Config, ImageFormat, MapImages, MarkerPalette, PlayerSelection};
+use dimension::Dimension;
+use encoding::ImageSink;
+pub use error::Error;
+pub use force::Force;
+use gallery::Art;
+pub use generator::{Generator, GeneratorBuilder, RunReport};
+use geometry::Origin;
+use i18n::Lang;
 use indicatif::ProgressBar;
+use itertools::Itertools;
+use layer::LayerInfo;
 use level::Level;
-use log::debug;
-use map::{Map, MapData, MapScan};
+use log::{debug, warn};
+use map::MapData;
+pub use map::{Map, MapScan};
+use message::Message;
+use open_files::OpenFiles;
+use pattern::TilePattern;
 use rayon::prelude::*;
-use search::{search_entities, search_level, search_players, Bounds};
+pub use search::Bounds;
+use search::{search_world, Searched};
+use serde::Serialize;
 use serde_json::json;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use stats::{Copies, Statistics};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{self, BufWriter, ErrorKind, Write};
 use std::ops::AddAssign;
-use std::path::Path;
-use std::time::{Instant, SystemTime};
-use tile::Tile;
-use utilities::progress_bar;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tile::Rendered;
+pub use tile::Tile;
+use utilities::{available_space, precompress, progress_bar};
+use warning::{WarningCollector, WarningKind};
 
-pub const COMPATIBLE_VERSIONS: &str = ">=1.20.2, <1.22";
+pub const COMPATIBLE_VERSIONS: &str = ">=1.17, <1.22";
 
-#[derive(Template)]
+// Viewer zoom levels below the maximum, each with a cell size of 64 px in blocks
+const CLUSTER_ZOOMS: [(i8, i32); 4] = [(-1, 128), (0, 64), (1, 32), (2, 16)];
+const CLUSTER_THRESHOLD: usize = 250;
+
+// Intermediate of the outputs listing banners
+pub(crate) const BANNER_SPOOL: &str = ".banners.jsonl";
+
+// Assumed of tiles before any have been rendered, somewhat above that of typical terrain
+const TILE_SIZE_ESTIMATE: u64 = 16 * 1024;
+
+const TEXT_OUTPUTS: [&str; 20] = [
+    "assets/**/*.css",
+    "assets/**/*.js",
+    "banners-changes.json",
+    "banners-clustered.json",
+    "banners/*.json",
+    "banners.geojson",
+    "banners.html",
+    "banners.json",
+    "frames.json",
+    "icons.svg",
+    "gallery.html",
+    "index.html",
+    "layers/*.json",
+    "legend.json",
+    "outlines.json",
+    "run.json",
+    "site-config.json",
+    "stats.html",
+    "world.json",
+    "tiles/**/*.meta.json",
+];
+
+// Also written as site-config.json for other frontends, which thereby need not parse the viewer
+#[derive(Serialize, Template)]
 #[template(path = "index.html.j2")]
+#[allow(clippy::struct_excessive_bools)] // Toggles of the viewer
 struct IndexTemplate<'a> {
+    age: Option<&'a WorldAge>,
+    banner_colors: &'a BTreeMap<&'static str, MarkerColor>,
+    banner_shards: bool,
+    // Blocks spanned by the tiles
+    bounds: Option<Bounds>,
     cache_version: &'a str,
     center: [i32; 2],
+    cluster_banners: bool,
+    events: Option<&'a str>,
+    frames: bool,
+    freshness: Option<u64>,
     generator: &'a str,
+    history: bool,
+    image_extension: &'a str,
+    lang: Lang,
+    layers: &'a [LayerInfo],
+    legend: &'a BTreeMap<String, usize>,
+    map_images: bool,
     maps_stacked: usize,
+    offline: bool,
+    origin: [i32; 2],
+    outlines: bool,
+    recent_banners: bool,
+    tile_pattern: &'a str,
+    world: Option<&'a WorldInfo>,
+}
+
+// Listed apart from the viewer for browsers without scripts and for screen readers
+#[derive(Template)]
+#[template(path = "banners.html.j2")]
+struct BannersTemplate {
+    lang: Lang,
+    banner_colors: BTreeMap<&'static str, MarkerColor>,
+    banners: Vec<ListedBanner>,
+}
+
+// Aggregates of the world for its community, optionally written with the viewer
+#[derive(Template)]
+#[template(path = "stats.html.j2")]
+struct StatsTemplate<'a> {
+    lang: Lang,
+    // Counts of maps by the denominator of their scale, e.g. 4 for 1:4
+    scales: Vec<(u32, usize)>,
+    statistics: &'a Statistics,
+}
+
+// Images of the maps likely to be map art, optionally written with the viewer
+#[derive(Template)]
+#[template(path = "gallery.html.j2")]
+struct GalleryTemplate<'a> {
+    lang: Lang,
+    image_extension: &'a str,
+    art: &'a [Art],
+}
+
+// Ordered by name as banners are
+#[derive(Eq, Ord, PartialEq, PartialOrd)]
+struct ListedBanner {
+    name: Option<String>,
+    color: String,
+    x: i64,
+    z: i64,
+}
+
+fn write_index(output_path: &Path, template: &IndexTemplate) -> Result<()> {
+    File::create(output_path.join("index.html"))?.write_all(template.render()?.as_bytes())?;
+
+    // Rewritten only when changed, as world.json is
+    let site_config_path = output_path.join("site-config.json");
+    let site_config = serde_json::to_string(template)?;
+    if fs::read_to_string(&site_config_path).map_or(true, |c| c != site_config) {
+        fs::write(&site_config_path, site_config)?;
+    }
+
+    Ok(())
+}
+
+fn write_banner_list(
+    output_path: &Path,
+    lang: Lang,
+    marker_palette: MarkerPalette,
+    spool: &banner::Spool,
+    modified: SystemTime,
+) -> Result<()> {
+    let mut banners = Vec::new();
+    for feature in spool.features()? {
+        let feature = feature?;
+        let listed = (|| {
+            let coordinates = feature.pointer("/geometry/coordinates")?;
+            Some(ListedBanner {
+                name: feature
+                    .pointer("/properties/name")
+                    .and_then(|n| n.as_str())
+                    .map(Into::into),
+                color: feature.pointer("/properties/color")?.as_str()?.into(),
+                x: coordinates.get(0)?.as_i64()?,
+                z: coordinates.get(1)?.as_i64()?,
+            })
+        })();
+        banners.extend(listed);
+    }
+    banners.sort();
+
+    let list_file = File::create(output_path.join("banners.html"))?;
+    let template = BannersTemplate {
+        lang,
+        banner_colors: banner::palette(marker_palette, lang),
+        banners,
+    };
+    (&list_file).write_all(template.render()?.as_bytes())?;
+    list_file.set_modified(modified)?;
+
+    Ok(())
+}
+
+// Banners are split by the root tile containing them for the viewer to load only those in view,
+// with an index of the shards and of the banners listed for navigation. A shard is dated to the
+// latest map bearing its banners, or when changed otherwise, e.g. by removal of a banner, to the
+// latest map bearing any banner.
+//
+// The spool is in order of root tile, so each shard is written once its last banner is read.
+fn write_banner_shards(
+    output_path: &Path,
+    spool: &banner::Spool,
+    modified: SystemTime,
+) -> Result<()> {
+    let dir_path = output_path.join("banners");
+    fs::create_dir_all(&dir_path)?;
+
+    let mut shards = BTreeSet::<(i32, i32)>::new();
+    let mut unique = Vec::new();
+    let mut shard: Option<((i32, i32), u64, Vec<String>)> = None;
+    for line in spool.lines()? {
+        let line = line?;
+        let feature = serde_json::from_str::<serde_json::Value>(&line)?;
+        let coordinate = |i| -> Result<i32> {
+            Ok(feature["geometry"]["coordinates"][i]
+                .as_i64()
+                .ok_or_else(|| anyhow!("Banner lacks coordinates"))?
+                .try_into()?)
+        };
+        let root = geometry::tile_of(0, (coordinate(0)?, coordinate(1)?));
+        let key = (root.x, root.y);
+        let feature_modified = feature["properties"]["maps_modified"]
+            .as_object()
+            .into_iter()
+            .flat_map(|m| m.values())
+            .filter_map(serde_json::Value::as_u64)
+            .max()
+            .unwrap_or_default();
+        if feature["properties"]["unique"] == true {
+            unique.push(feature);
+        }
+
+        if shard.as_ref().is_some_and(|s| s.0 != key) {
+            write_banner_shard(&dir_path, shard.take().unwrap(), modified)?;
+        }
+        if shard.is_none() && !shards.insert(key) {
+            return Err(anyhow!("Banners out of order in shard {key:?}"));
+        }
+        let (_, shard_modified, lines) = shard.get_or_insert_with(|| (key, 0, Vec::new()));
+        *shard_modified = (*shard_modified).max(feature_modified);
+        lines.push(line);
+    }
+    if let Some(shard) = shard {
+        write_banner_shard(&dir_path, shard, modified)?;
+    }
+
+    for entry in glob_in(&dir_path, "*_*.json")? {
+        let path = entry?;
+        let is_current = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.split_once('_'))
+            .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+            .map_or(false, |key| shards.contains(&key));
+        if !is_current {
+            debug!("Prune: {}", path.display());
+            fs::remove_file(path)?;
+        }
+    }
+
+    let index_file = File::create(dir_path.join("index.json"))?;
+    serde_json::to_writer(
+        &index_file,
+        &json!({
+            "type": "FeatureCollection",
+            "features": unique,
+            "shards": shards.iter().map(|&key| <[i32; 2]>::from(key)).collect::<Vec<_>>(),
+        }),
+    )?;
+    index_file.set_modified(modified)?;
+
+    Ok(())
+}
+
+fn write_banner_shard(
+    dir_path: &Path,
+    ((x, y), shard_modified, lines): ((i32, i32), u64, Vec<String>),
+    modified: SystemTime,
+) -> Result<()> {
+    let path = dir_path.join(format!("{x}_{y}.json"));
+    let mut json = Vec::new();
+    banner::write_collection(&mut json, lines.into_iter().map(Ok))?;
+
+    let previous = match fs::read(&path) {
+        Ok(previous) => Some(previous),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if previous.as_ref() != Some(&json) {
+        fs::write(&path, &json)?;
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(if previous.is_some() {
+                modified
+            } else {
+                SystemTime::UNIX_EPOCH + Duration::from_secs(shard_modified)
+            })?;
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct WorldInfo {
+    border: Border,
+    spawn: Spawn,
+}
+
+#[derive(Serialize)]
+struct Border {
+    center: [f64; 2],
+    size: f64,
+}
+
+#[derive(Serialize)]
+struct Spawn {
+    position: [i32; 2],
+    chunk_radius: Option<u32>,
+    radius: Option<u32>,
+}
+
+// Unlike `WorldInfo`, changed by every save of the world
+#[derive(Serialize)]
+struct WorldAge {
+    day: i64,
+    last_played: Option<String>,
+    ticks: i64,
+}
+
+impl From<&Level> for WorldAge {
+    fn from(level: &Level) -> Self {
+        Self {
+            day: level.day(),
+            last_played: level
+                .last_played()
+                .map(|t| humantime::format_rfc3339_millis(t).to_string()),
+            ticks: level.time,
+        }
+    }
+}
+
+impl From<&Level> for WorldInfo {
+    fn from(level: &Level) -> Self {
+        Self {
+            border: Border {
+                center: [level.border_center_x, level.border_center_z],
+                size: level.border_size,
+            },
+            spawn: Spawn {
+                position: [level.spawn_x, level.spawn_z],
+                chunk_radius: level.spawn_chunk_radius(),
+                radius: level.spawn_radius(),
+            },
+        }
+    }
 }
 
 #[derive(Default)]
@@ -47,7 +431,9 @@ struct Report {
     pub maps_rendered: usize,
     pub maps_stacked: usize,
     pub tiles_rendered: usize,
+    pub tiles_unchanged: usize,
     pub tiles: HashSet<(u8, i32, i32)>,
+    pub tiles_changed: BTreeSet<(u8, i32, i32)>,
 }
 
 impl AddAssign for Report {
@@ -56,16 +442,29 @@ impl AddAssign for Report {
         self.maps_rendered += other.maps_rendered;
         self.maps_stacked = self.maps_stacked.max(other.maps_stacked);
         self.tiles_rendered += other.tiles_rendered;
+        self.tiles_unchanged += other.tiles_unchanged;
         self.tiles.extend(other.tiles);
+        self.tiles_changed.extend(other.tiles_changed);
     }
 }
 
 struct Quadrant<'a> {
     world_path: &'a Path,
     output_path: &'a Path,
-    force: bool,
+    origin: Origin,
+    pattern: &'a TilePattern,
+    force: &'a Force,
+    dedupe: bool,
+    freshness: Option<SystemTime>,
+    center: (i32, i32),
     bar: &'a ProgressBar,
+    sink: &'a dyn ImageSink,
     maps_by_tile: &'a HashMap<Tile, BTreeSet<Map>>,
+    occupied: &'a HashSet<Tile>,
+    has_image: &'a (dyn Fn(u32) -> bool + Sync),
+    redundant: &'a HashSet<u32>,
+    warnings: &'a WarningCollector,
+    batch: Option<&'a Batch>,
     layers: &'a mut Vec<Option<Vec<(&'a Map, MapData)>>>,
 }
 
@@ -73,12 +472,23 @@ impl Quadrant<'_> {
     fn render(&mut self, tile: &Tile) -> Result<Report> {
         let mut report = Report::default();
 
+        if !self.occupied.contains(tile) && self.layers.iter().flatten().all(Vec::is_empty) {
+            return Ok(report);
+        }
+
         self.layers.push(
             self.maps_by_tile
                 .get(tile)
                 .map(|maps| {
                     maps.iter()
-                        .map(|m| Ok((m, MapData::from_world_path(self.world_path, m.id)?)))
+                        .map(|m| {
+                            let data = self.batch.map_or_else(
+                                || MapData::from_world_path(self.world_path, m.id, self.warnings),
+                                |b| b.map_data(self.world_path, m.id, self.warnings),
+                            )?;
+
+                            Ok((m, data))
+                        })
                         .collect::<Result<_>>()
                 })
                 .transpose()?,
@@ -86,84 +496,463 @@ impl Quadrant<'_> {
 
         if tile.zoom == 4 {
             let maps = || self.layers.iter().flatten().flatten();
+            let drawn = || {
+                maps()
+                    .rev()
+                    .filter(|(m, _)| !self.redundant.contains(&m.id))
+            };
             let count = maps().count();
 
             if count > 0 {
                 report.maps_stacked = report.maps_stacked.max(count);
-                report.tiles.insert((tile.zoom, tile.x, tile.y));
+                report.tiles.insert(self.origin.number(tile));
 
                 if let Some(map_modified) = maps().map(|&(m, _)| m.modified).max() {
-                    if tile.render(self.output_path, maps().rev(), map_modified, self.force)? {
-                        report.tiles_rendered += 1;
+                    let force = self
+                        .force
+                        .tile(tile, self.origin, maps().map(|(m, _)| m.id));
+                    match tile.render(
+                        self.output_path,
+                        self.origin,
+                        self.pattern,
+                        drawn(),
+                        map_modified,
+                        self.sink,
+                        force,
+                        self.dedupe,
+                        self.batch,
+                    )? {
+                        Rendered::UpToDate => {}
+                        Rendered::Unchanged => {
+                            report.tiles_rendered += 1;
+                            report.tiles_unchanged += 1;
+                        }
+                        Rendered::Changed => {
+                            report.tiles_rendered += 1;
+                            report.tiles_changed.insert(self.origin.number(tile));
+                        }
+                    }
+
+                    if let Some(now) = self.freshness {
+                        tile.render_freshness(
+                            self.output_path,
+                            self.origin,
+                            self.pattern,
+                            drawn(),
+                            map_modified,
+                            now,
+                            self.sink,
+                            force,
+                        )?;
                     }
                 }
             }
 
             self.bar.inc(1);
         } else {
-            for quadrant in &tile.quadrants() {
+            let mut quadrants = tile.quadrants();
+            quadrants.sort_by_key(|q| q.distance_from(self.center));
+            for quadrant in &quadrants {
                 report += self.render(quadrant)?;
             }
         }
 
-        report.maps.extend(
-            self.layers
-                .pop()
-                .unwrap()
-                .iter_mut()
-                .flatten()
-                .map(|(map, data)| {
-                    if map.render(self.output_path, data, self.force).unwrap(/* FIXME: Handle result */) {
-                        report.maps_rendered += 1;
-                    }
+        let maps = self
+            .layers
+            .pop()
+            .unwrap()
+            .iter_mut()
+            .flatten()
+            .map(|(map, data)| -> Result<_, Error> {
+                if (self.has_image)(map.id)
+                    && map.render(self.output_path, data, self.sink, self.force.map(map.id))?
+                {
+                    report.maps_rendered += 1;
+                }
 
-                    map.id
-                }),
-        );
+                Ok(map.id)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        report.maps.extend(maps);
 
         Ok(report)
     }
 }
 
+pub struct RegionStatistics {
+    pub kind: &'static str,
+    pub dimension: Dimension,
+    pub position: (i32, i32),
+    pub maps: usize,
+    pub chunks: usize,
+    pub chunks_with_maps: usize,
+    pub modified: SystemTime,
+}
+
+// Kept by default within the output, or elsewhere such as under XDG_CACHE_HOME so that it
+// survives deployments of the output
+fn cache_path(output_path: &Path, cache_dir: Option<&Path>) -> PathBuf {
+    cache_dir
+        .map_or_else(|| output_path.join(".cache"), Path::to_owned)
+        .join(format!("{}.dat", env!("CARGO_PKG_NAME")))
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    Absent,
+    Current,
+    /// Of another version, and to be replaced by a full search
+    Outdated,
+    /// Failed to be read, and to be replaced by a full search
+    Corrupt,
+}
+
+pub fn cache_status(output_path: &Path, cache_dir: Option<&Path>) -> Result<CacheStatus, Error> {
+    Ok(
+        match Cache::from_path(&cache_path(output_path, cache_dir))?.miss() {
+            None => CacheStatus::Current,
+            Some(CacheMiss::Outdated) => CacheStatus::Outdated,
+            Some(CacheMiss::Corrupt) => CacheStatus::Corrupt,
+            Some(_) => CacheStatus::Absent,
+        },
+    )
+}
+
+/// Bytes and entries of the search cache, e.g. to watch its growth
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct CacheSize {
+    pub bytes: u64,
+    pub entries: CacheEntries,
+}
+
+pub fn cache_size(output_path: &Path, cache_dir: Option<&Path>) -> Result<CacheSize, Error> {
+    let path = cache_path(output_path, cache_dir);
+    let bytes = match fs::metadata(&path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => 0,
+        Err(e) => return Err(Error::io(&path, e)),
+    };
+
+    Ok(CacheSize {
+        bytes,
+        entries: Cache::from_path(&path)?.entries(),
+    })
+}
+
+/// Drop entries of the search cache for files gone from the world, as each search does too,
+/// returning the counts of those dropped
+pub fn prune_cache(
+    world_path: &Path,
+    output_path: &Path,
+    cache_dir: Option<&Path>,
+) -> Result<CacheEntries, Error> {
+    let path = cache_path(output_path, cache_dir);
+    let mut cache = Cache::from_path(&path)?;
+    let dropped = cache.collect_garbage(world_path);
+
+    // Dated as before so that files modified since the last search are still searched again, and
+    // compressed at the default level until the next search
+    if let Some(modified) = cache.modified.filter(|_| dropped.total() > 0) {
+        cache.write_to(&path, modified, 0)?;
+    }
+
+    Ok(dropped)
+}
+
+pub fn region_statistics(
+    output_path: &Path,
+    cache_dir: Option<&Path>,
+) -> Result<Vec<RegionStatistics>, Error> {
+    let cache = Cache::from_path(&cache_path(output_path, cache_dir))?;
+
+    let mut statistics = [
+        (
+            "block",
+            &cache.map_ids_by_block_region,
+            &cache.summary_by_block_region,
+        ),
+        (
+            "entities",
+            &cache.map_ids_by_entities_region,
+            &cache.summary_by_entities_region,
+        ),
+    ]
+    .into_iter()
+    .flat_map(|(kind, ids_by_region, summary_by_region)| {
+        summary_by_region
+            .iter()
+            .map(move |(&(dimension, x, z), summary)| RegionStatistics {
+                kind,
+                dimension,
+                position: (x, z),
+                maps: ids_by_region.get(&(dimension, x, z)).map_or(0, MapIds::len),
+                chunks: summary.chunks,
+                chunks_with_maps: summary.chunks_with_maps,
+                modified: summary.modified,
+            })
+    })
+    .collect::<Vec<_>>();
+    statistics.sort_by_key(|s| (s.kind, s.dimension, s.position));
+
+    Ok(statistics)
+}
+
+#[derive(Serialize)]
+pub struct Discrepancy {
+    pub source: String,
+    pub missing: BTreeSet<u32>,
+    pub unexpected: BTreeSet<u32>,
+}
+
+pub fn verify_cache(
+    world_path: &Path,
+    output_path: &Path,
+    cache_dir: Option<&Path>,
+    quiet: bool,
+    bounds: Option<&Bounds>,
+) -> Result<Vec<Discrepancy>, Error> {
+    let cached = Cache::from_path(&cache_path(output_path, cache_dir))?;
+
+    // Command storage is verified only if the cache includes it, and players as selected for it
+    let command_storage = !cached.map_ids_by_storage.is_empty();
+    let mut actual = Cache::default();
+    let warnings = WarningCollector::default();
+    let searched = search_world(
+        world_path,
+        quiet,
+        bounds,
+        command_storage,
+        &cached.players,
+        &mut actual,
+        &OpenFiles::new(None),
+        &warnings,
+    );
+    warnings.log();
+    searched?;
+
+    let describe_region = |kind: &str, (dimension, x, z): &Region| {
+        format!("{} {kind} region ({x}, {z})", dimension.name())
+    };
+    let mut discrepancies = Vec::new();
+    let mut compare = |source: String, cached: Option<&MapIds>, actual: Option<&MapIds>| {
+        let ids = |ids: Option<&MapIds>| ids.map_or_else(HashSet::new, |i| i.ids().collect());
+        let (cached, actual) = (ids(cached), ids(actual));
+        if cached != actual {
+            discrepancies.push(Discrepancy {
+                source,
+                missing: actual.difference(&cached).copied().collect(),
+                unexpected: cached.difference(&actual).copied().collect(),
+            });
+        }
+    };
+
+    for (kind, cached, actual) in [
+        (
+            "block",
+            &cached.map_ids_by_block_region,
+            &actual.map_ids_by_block_region,
+        ),
+        (
+            "entities",
+            &cached.map_ids_by_entities_region,
+            &actual.map_ids_by_entities_region,
+        ),
+    ] {
+        for region in cached.keys().chain(actual.keys()).sorted().dedup() {
+            compare(
+                describe_region(kind, region),
+                cached.get(region),
+                actual.get(region),
+            );
+        }
+    }
+    for name in (cached.map_ids_by_storage.keys())
+        .chain(actual.map_ids_by_storage.keys())
+        .sorted()
+        .dedup()
+    {
+        compare(
+            format!("storage {name}"),
+            cached.map_ids_by_storage.get(name),
+            actual.map_ids_by_storage.get(name),
+        );
+    }
+    let (cached, actual) = (&cached.map_ids_by_player, &actual.map_ids_by_player);
+    for player in cached.keys().chain(actual.keys()).sorted().dedup() {
+        compare(
+            format!("player {player}"),
+            cached.get(player),
+            actual.get(player),
+        );
+    }
+
+    Ok(discrepancies)
+}
+
 pub fn search(
     world_path: &Path,
     output_path: &Path,
     quiet: bool,
     force: bool,
     bounds: Option<&Bounds>,
-) -> Result<HashSet<u32>> {
+    rescans: usize,
+) -> Result<HashSet<u32>, Error> {
+    let warnings = WarningCollector::default();
+    let searched = search_counted(
+        world_path,
+        output_path,
+        None,
+        0,
+        false,
+        quiet,
+        force,
+        bounds,
+        rescans,
+        false,
+        &PlayerSelection::default(),
+        None,
+        &warnings,
+    );
+    warnings.log();
+
+    Ok(searched?.0)
+}
+
+#[allow(clippy::fn_params_excessive_bools, clippy::too_many_arguments)] // Options of the search
+fn search_counted(
+    world_path: &Path,
+    output_path: &Path,
+    cache_dir: Option<&Path>,
+    cache_compression: i32,
+    strict_cache: bool,
+    quiet: bool,
+    force: bool,
+    bounds: Option<&Bounds>,
+    rescans: usize,
+    command_storage: bool,
+    players: &PlayerSelection,
+    max_open_files: Option<usize>,
+    warnings: &WarningCollector,
+) -> Result<(HashSet<u32>, Searched)> {
+    span!("search");
     let start_time = Instant::now();
 
-    let cache_path = output_path.join(format!(".cache/{}.dat", env!("CARGO_PKG_NAME")));
+    if activity::is_saving(world_path)? {
+        warnings.add(WarningKind::Saving, world_path.display().to_string());
+    }
+
+    let cache_path = cache_path(output_path, cache_dir);
     let mut cache = if force {
         Cache::default()
     } else {
         Cache::from_path(&cache_path)?
     };
-    let players_searched = search_players(world_path, quiet, &mut cache)?;
-    let entity_regions_searched = search_entities(world_path, quiet, bounds, &mut cache)?;
-    let block_regions_searched = search_level(world_path, quiet, bounds, &mut cache)?;
-    cache.write_to(&cache_path)?;
+    if let Some(unreadable) = &cache.unreadable {
+        let subject = format!("{}: {unreadable}", cache_path.display());
+        if strict_cache {
+            bail!("Refusing to discard the cache {subject}");
+        }
+        warnings.add(WarningKind::DiscardedCache, subject);
+    }
+    let miss = if force {
+        CacheMiss::Forced
+    } else {
+        cache.miss().unwrap_or(CacheMiss::Modified)
+    };
+
+    let (count, bytes) = tile_usage(output_path, &encoding::recorded(output_path)?)?;
+    if let Some(size) = bytes.checked_div(count) {
+        cache.tile_size = Some(size);
+    }
+
+    // Files modified after the search began may have been read mid-save, so the cache is dated to
+    // the beginning of the search and optionally such files are searched again right away.
+    let files = OpenFiles::new(max_open_files);
+    let mut snapshot = SystemTime::now();
+    let mut searched = search_world(
+        world_path,
+        quiet,
+        bounds,
+        command_storage,
+        players,
+        &mut cache,
+        &files,
+        warnings,
+    )?;
+    let mut cache_misses = BTreeMap::new();
+    if searched.total() > 0 {
+        cache_misses.insert(miss, searched.total());
+    }
+    for _ in 0..rescans {
+        cache.modified = Some(snapshot);
+        snapshot = SystemTime::now();
+
+        let rescanned = search_world(
+            world_path,
+            true,
+            bounds,
+            command_storage,
+            players,
+            &mut cache,
+            &files,
+            warnings,
+        )?
+        .total();
+        if rescanned == 0 {
+            break;
+        }
+
+        warn!("Searched again {rescanned} files that were modified during the search");
+        *cache_misses.entry(CacheMiss::Modified).or_default() += rescanned;
+    }
+    let dropped = cache.collect_garbage(world_path);
+    if dropped.total() > 0 {
+        debug!("Dropped from the cache {dropped:?} of files gone from the world");
+    }
 
     let ids = cache
         .map_ids_by_entities_region
-        .into_values()
-        .chain(cache.map_ids_by_block_region.into_values())
-        .chain(cache.map_ids_by_player.into_values())
-        .flatten()
+        .values()
+        .chain(cache.map_ids_by_block_region.values())
+        .chain(cache.map_ids_by_player.values())
+        .chain(cache.map_ids_by_storage.values())
+        .flat_map(MapIds::ids)
         .collect::<HashSet<_>>();
+    cache.labels.update(world_path, &ids, &files);
+
+    cache.write_to(&cache_path, snapshot, cache_compression)?;
+    searched.copies = Copies::from_cache(&cache);
+    searched.dimensions_by_map_id = cache.dimensions_by_map_id();
+    searched.cache_misses = cache_misses;
+    searched.labels = cache.labels;
 
     if !quiet {
-        println!(
-            "Found {} map items across {block_regions_searched} block regions, {entity_regions_searched} entity regions, and {players_searched} players in {:.2}s",
-            ids.len(),
-            start_time.elapsed().as_secs_f32()
-        );
+        Message::Searched {
+            maps: ids.len(),
+            block_regions: searched.block_regions,
+            entity_regions: searched.entity_regions,
+            players: searched.players,
+            seconds: start_time.elapsed().as_secs_f32(),
+        }
+        .emit();
     }
 
-    Ok(ids)
+    Ok((ids, searched))
+}
+
+/// Image of one map as 128 × 128 RGB pixels by rows, e.g. to serve on demand without rendering
+/// the whole output
+pub fn render_map(world_path: &Path, id: u32) -> Result<[u8; 128 * 128 * 3], Error> {
+    let warnings = WarningCollector::default();
+    let data = MapData::from_world_path(world_path, id, &warnings);
+    warnings.log();
+
+    Ok(palette::rgb(&data?.0))
 }
 
+/// Outputs of maps not among `ids` are pruned only under the `aggressive` pruning policy, as
+/// nothing is known of how complete the search for `ids` was.
 pub fn render(
     world_path: &Path,
     output_path: &Path,
@@ -171,24 +960,164 @@ pub fn render(
     force: bool,
     level: &Level,
     ids: &HashSet<u32>,
-) -> Result<()> {
+    config: &Config,
+) -> Result<(), Error> {
+    let warnings = WarningCollector::default();
+    let rendered = render_counted(
+        world_path,
+        output_path,
+        quiet,
+        &Force {
+            render: force,
+            ..Force::default()
+        },
+        level,
+        ids,
+        None,
+        config,
+        &warnings,
+        None,
+    );
+    warnings.log();
+    rendered?;
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct RenderCounts {
+    statistics: Option<Statistics>,
+    maps_deduplicated: usize,
+    maps_rendered: usize,
+    maps_pruned: usize,
+    tiles_rendered: usize,
+    tiles_pruned: usize,
+    tiles_changed: BTreeSet<(u8, i32, i32)>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_counted(
+    world_path: &Path,
+    output_path: &Path,
+    quiet: bool,
+    force: &Force,
+    level: &Level,
+    ids: &HashSet<u32>,
+    searched: Option<&Searched>,
+    config: &Config,
+    warnings: &WarningCollector,
+    batch: Option<&Batch>,
+) -> Result<RenderCounts> {
+    span!("render", output = %output_path.display());
     let start_time = Instant::now();
 
-    let results = MapScan::run(world_path, ids)?;
+    layout::migrate(output_path)?;
+
+    // History and merging read back tiles as WebP
+    if config.history.is_some() && config.image_format != ImageFormat::WebP {
+        return Err(Error::Config(
+            "History requires image_format = \"webp\"; disable history or change the format in the config"
+                .to_owned(),
+        )
+        .into());
+    }
+    let sink = &*config.image_format.sink(config.colors);
+    let switched = encoding::switch(output_path, sink, config.colors)?
+        | pattern::switch(output_path, &config.tile_pattern)?
+        | pattern::switch_origin(output_path, Origin::at(config.tile_origin))?;
+    let force = &Force {
+        render: force.render || switched,
+        ..force.clone()
+    };
+
+    let ids = config.maps.apply(world_path, ids);
+    check_max_maps(config, &ids)?;
+
+    let mut results = MapScan::run(world_path, &ids, &OpenFiles::new(config.max_open_files))?;
+    // Maps are rendered or not by the dimension their data depicts, wherever their items were
+    // found, but those found only elsewhere may be mistaken for each other
+    for (id, found) in searched.iter().flat_map(|s| &s.dimensions_by_map_id) {
+        if let Some(depicted) = results.dimension_by_map_id.get(id) {
+            if !found.contains(depicted) {
+                let found = found.iter().map(|d| d.name()).join(", ");
+                warnings.add(
+                    WarningKind::CrossDimension,
+                    format!("{id}: {} found in {found}", depicted.name()),
+                );
+            }
+        }
+    }
+    if config.hide_banners {
+        results.banners.clear();
+        results.banners_modified = Some(SystemTime::UNIX_EPOCH);
+    }
+
+    let banner_map_ids = results.banners.values().flatten().collect::<HashSet<_>>();
+    // Map art is listed with its image regardless of which other maps have images
+    let art = if config.gallery {
+        gallery::detect(world_path, &results)
+    } else {
+        Vec::new()
+    };
+    let art_map_ids = art.iter().map(|a| a.id).collect::<HashSet<_>>();
+    let has_image = |id: u32| {
+        art_map_ids.contains(&id)
+            || match config.map_images {
+                MapImages::Off => false,
+                MapImages::Popup => banner_map_ids.contains(&id),
+                MapImages::All => true,
+            }
+    };
+
+    // Copies of a map within a stack are composited only once
+    let redundant = results.redundant();
+    debug!("Deduplicated {} maps of identical colors", redundant.len());
+
+    // Subtrees lacking maps both within and above are skipped
+    let occupied = results
+        .maps_by_tile
+        .keys()
+        .flat_map(Tile::ancestry)
+        .collect::<HashSet<_>>();
+    let leaves = results
+        .maps_by_tile
+        .keys()
+        .flat_map(Tile::leaves)
+        .collect::<HashSet<_>>();
+    let length = leaves.len();
+    let tile_size = searched.and_then(|s| s.tile_size);
+    preflight(output_path, length, tile_size, sink, config)?;
 
-    let length = results.root_tiles.len() * 4_usize.pow(4);
     let bar = progress_bar(quiet, "Render", length, "tiles");
+    let freshness = config.freshness.then(SystemTime::now);
+    let origin = Origin::at(config.tile_origin);
 
+    // Tiles nearest the initial view are rendered first so that a lengthy render refreshes what
+    // viewers see soonest. Workers take root tiles in that order rather than each from a split.
+    let center = (level.spawn_x, level.spawn_z);
     let report = results
         .root_tiles
-        .par_iter()
+        .iter()
+        .sorted_by_key(|t| t.distance_from(center))
+        .par_bridge()
         .map(|tile| {
             Quadrant {
                 world_path,
                 output_path,
+                origin,
+                pattern: &config.tile_pattern,
                 force,
+                dedupe: config.dedupe_tiles,
+                freshness,
+                center,
                 bar: &bar,
+                sink,
                 maps_by_tile: &results.maps_by_tile,
+                occupied: &occupied,
+                has_image: &has_image,
+                redundant: &redundant,
+                warnings,
+                batch,
                 layers: &mut Vec::with_capacity(5),
             }
             .render(tile)
@@ -199,88 +1128,416 @@ pub fn render(
         })?;
 
     bar.finish_and_clear();
+    debug!(
+        "Kept the images of {} tiles of unchanged pixels",
+        report.tiles_unchanged
+    );
 
-    let maps_pruned = glob(output_path.join("maps/*.webp").to_str().unwrap())?
-        .map(|entry| -> Result<usize> {
-            let path = entry?;
-            let id: u32 = path.file_stem().unwrap().to_str().unwrap().parse()?;
+    let complete = searched.is_some_and(|s| s.complete);
+    let (maps_pruned, tiles_pruned) = if config.prune.permits(complete) {
+        let maps_pruned = glob_in(output_path, &format!("maps/*.{}", sink.extension()))?
+            .map(|entry| -> Result<usize> {
+                let path = entry?;
+                let id: u32 = path.file_stem().unwrap().to_string_lossy().parse()?;
 
-            Ok(if report.maps.contains(&id) {
-                0
-            } else {
-                debug!("Prune: {}", path.display());
-                fs::remove_file(path)?;
-                1
-            })
-        })
-        .sum::<Result<usize>>()?;
-
-    let tiles_pruned = glob(output_path.join("tiles/*/*/*.webp").to_str().unwrap())?
-        .map(|entry| -> Result<usize> {
-            let path = entry?;
-            let relative = path.strip_prefix(output_path)?;
-            let mut parts = relative.to_str().unwrap().split('/').skip(1);
-            let zoom: u8 = parts.next().unwrap().parse()?;
-            let x: i32 = parts.next().unwrap().parse()?;
-            let y: i32 = parts.next().unwrap().split('.').next().unwrap().parse()?;
-
-            Ok(if report.tiles.contains(&(zoom, x, y)) {
-                0
-            } else {
-                let base = output_path.join(format!("tiles/{zoom}/{x}/{y}"));
-                debug!("Prune: {}", base.display());
-                fs::remove_file(base.with_extension("webp"))?;
-                fs::remove_file(base.with_extension("meta.json"))?;
-                1
+                Ok(if report.maps.contains(&id) && has_image(id) {
+                    0
+                } else {
+                    debug!("Prune: {}", path.display());
+                    fs::remove_file(path)?;
+                    1
+                })
             })
-        })
-        .sum::<Result<usize>>()?;
+            .sum::<Result<usize>>()?;
+
+        (
+            maps_pruned,
+            prune_tiles(output_path, &report.tiles, &config.tile_pattern, sink)?,
+        )
+    } else {
+        debug!("Skip pruning under policy {:?}", config.prune);
+        (0, 0)
+    };
+    let blobs_pruned = blob::prune(output_path)?;
+    debug!("Pruned {blobs_pruned} blobs");
+
+    if let Some(history) = &config.history {
+        let archived = history::snapshot(
+            output_path,
+            &report.tiles,
+            &config.tile_pattern,
+            history,
+            SystemTime::now(),
+        )?;
+        debug!("Archived {archived} tiles");
+    }
+
+    let mut legend = BTreeMap::<String, usize>::new();
+    for banner in results.banners.keys() {
+        *legend.entry(banner.color.clone()).or_default() += 1;
+    }
+
+    // Icons are resolved into banners.json, which is therefore rewritten when they're configured
+    // otherwise than it was written with, as is the export of banners when its options are
+    icons::validate(&config.icons)?;
+    let sprite_path = output_path.join("icons.svg");
+    let banner_icons = serde_json::to_string(&config.icons)?;
+    let icons_changed = layout::recorded(output_path, layout::BANNER_ICONS)?
+        .unwrap_or_else(|| "{}".to_owned())
+        != banner_icons;
+
+    let geojson_path = output_path.join("banners.geojson");
+    if config.geojson.is_none() {
+        remove_if_exists(&geojson_path)?;
+    }
+    let geojson = serde_json::to_string(&config.geojson)?;
+    let geojson_changed = layout::recorded(output_path, layout::GEOJSON)?
+        .unwrap_or_else(|| "null".to_owned())
+        != geojson
+        || (config.geojson.is_some() && !geojson_path.exists());
+
+    let shards_path = output_path.join("banners");
+    if !config.banner_shards && shards_path.is_dir() {
+        fs::remove_dir_all(&shards_path)?;
+    }
 
     if let Some(modified) = results.banners_modified {
+        // Labels elsewhere in the world becoming unique or ceasing to be also outdate the banners
+        let labels = searched.map(|s| &s.labels);
+        let modified = labels
+            .and_then(|l| l.modified)
+            .map_or(modified, |l| l.max(modified));
         let banners_path = output_path.join("banners.json");
 
-        if force
+        if force.render
+            || config.hide_banners
+            || icons_changed
             || tiles_pruned != 0
+            || geojson_changed
+            || config.banner_shards != shards_path.is_dir()
             || fs::metadata(&banners_path)
                 .and_then(|m| m.modified())
                 .map_or(true, |json_modified| json_modified < modified)
         {
-            let is_unique = {
-                let mut u = HashMap::<&str, bool>::new();
-                results
-                    .banners
+            let unique =
+                labels.map_or_else(
+                    || {
+                        banner::unique_labels(results.banners.keys().filter_map(|b| {
+                            Some((b.label.as_deref()?, b.color.as_str(), b.x, b.z))
+                        }))
+                    },
+                    Labels::unique,
+                );
+            let is_unique = |b: &Banner| b.label.as_deref().map_or(false, |l| unique.contains(l));
+            let unix_time = |id: &u32| -> Result<u64> {
+                Ok(results.modified_by_map_id[id]
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs())
+            };
+
+            let feature = |banner: &Banner, source_ids: &BTreeSet<u32>, ids: &BTreeSet<u32>| {
+                let supplier = source_ids
                     .iter()
-                    .filter_map(|b| b.label.as_ref())
-                    .for_each(|l| {
-                        u.entry(l).and_modify(|v| *v = false).or_insert(true);
-                    });
-                move |b: &Banner| b.label.as_deref().map_or(false, |l| *u.get(l).unwrap())
+                    .max_by_key(|id| (results.modified_by_map_id[id], Reverse(*id)))
+                    .unwrap();
+
+                Ok(json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [banner.x, banner.z]
+                    },
+                    "properties": {
+                        "color": banner.color,
+                        "maps": ids,
+                        "maps_modified": ids
+                            .iter()
+                            .map(|id| Ok((*id, unix_time(id)?)))
+                            .collect::<Result<BTreeMap<_, _>>>()?,
+                        "icon": banner.label.as_deref().and_then(|l| icons::resolve(l, &config.icons)),
+                        "name": banner.label,
+                        "supplied_by": supplier,
+                        "unique": is_unique(banner),
+                    }
+                }))
             };
 
+            // Banners at a position share the maps depicting any of them
+            let mut ordered = results.banners.iter().collect::<Vec<_>>();
+            ordered.sort_by_key(|(banner, _)| {
+                let root = geometry::tile_of(0, (banner.x, banner.z));
+                (root.x, root.y, banner.x, banner.z)
+            });
+            let by_position = ordered.iter().chunk_by(|(banner, _)| (banner.x, banner.z));
+            let features = by_position.into_iter().flat_map(|(_, group)| {
+                let group = group.collect::<Vec<_>>();
+                let ids = group
+                    .iter()
+                    .flat_map(|(_, source_ids)| source_ids.iter().copied())
+                    .collect::<BTreeSet<_>>();
+                let feature = &feature;
+                group
+                    .into_iter()
+                    .map(move |(banner, source_ids)| feature(banner, source_ids, &ids))
+            });
+            let spool = banner::Spool::write(&output_path.join(BANNER_SPOOL), features)?;
+
+            // Read before being replaced, as the set of banners to which the new one is compared
+            let previous = banner::read_collection(&banners_path).unwrap_or_else(|e| {
+                debug!("Failed to read {}: {e:#}", banners_path.display());
+                None
+            });
             let banners_file = File::create(&banners_path)?;
+            spool.write_collection(BufWriter::new(&banners_file))?;
+            banners_file.set_modified(modified)?;
+
+            // Changes are kept until the next, so that a notice of them outlasts runs changing
+            // nothing. There are none before a previous set to compare, nor while banners are hidden.
+            let changes_path = output_path.join("banners-changes.json");
+            if config.hide_banners {
+                remove_if_exists(&changes_path)?;
+            } else if let Some(previous) = previous {
+                let current = results.banners.keys().map(Listed::from).collect();
+                let changes = banner::Changes::between(&previous, &current);
+                if !changes.is_empty() {
+                    debug!(
+                        "Banners changed: {} added and {} removed",
+                        changes.added.len(),
+                        changes.removed.len()
+                    );
+                    let changes_file = File::create(&changes_path)?;
+                    serde_json::to_writer(
+                        &changes_file,
+                        &json!({
+                            "modified": modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+                            "added": changes.added,
+                            "removed": changes.removed,
+                        }),
+                    )?;
+                    changes_file.set_modified(modified)?;
+                }
+            }
+            write_banner_list(
+                output_path,
+                config.lang,
+                config.marker_palette,
+                &spool,
+                modified,
+            )?;
+            if config.banner_shards {
+                write_banner_shards(output_path, &spool, modified)?;
+            }
+
+            if let Some(options) = &config.geojson {
+                let geojson_file = File::create(&geojson_path)?;
+                banner::export(spool.features()?, options, BufWriter::new(&geojson_file))?;
+                geojson_file.set_modified(modified)?;
+            }
+
+            // In order of the features
+            let positions = ordered.iter().map(|(b, _)| (b.x, b.z)).collect::<Vec<_>>();
+            let clustered_file = File::create(output_path.join("banners-clustered.json"))?;
             serde_json::to_writer(
-                &banners_file,
+                &clustered_file,
                 &json!({
-                    "type": "FeatureCollection",
-                    "features": results.banners.iter().map(|banner| json!({
+                    "zooms": CLUSTER_ZOOMS
+                        .iter()
+                        .map(|&(zoom, cell_size)| (zoom.to_string(), banner::cluster(&positions, cell_size)))
+                        .collect::<BTreeMap<_, _>>(),
+                }),
+            )?;
+            clustered_file.set_modified(modified)?;
+
+            let legend_file = File::create(output_path.join("legend.json"))?;
+            serde_json::to_writer(&legend_file, &json!({ "colors": legend }))?;
+            legend_file.set_modified(modified)?;
+
+            layout::record(output_path, layout::BANNER_ICONS, &banner_icons, "{}")?;
+            layout::record(output_path, layout::GEOJSON, &geojson, "null")?;
+        }
+    }
+
+    let outlines_path = output_path.join("outlines.json");
+    match results.maps_modified {
+        Some(modified) if config.outlines => {
+            if force.render
+                || tiles_pruned != 0
+                || fs::metadata(&outlines_path)
+                    .and_then(|m| m.modified())
+                    .map_or(true, |json_modified| json_modified < modified)
+            {
+                let features = results
+                    .maps_by_tile
+                    .values()
+                    .flatten()
+                    .sorted_by_key(|m| m.id)
+                    .map(|map| {
+                        let scale = 4 - map.tile.zoom;
+                        let size = 128 * 2_i32.pow(u32::from(scale));
+                        let (x0, z0) = map.tile.position();
+                        let (x1, z1) = (x0 + size, z0 + size);
+
+                        json!({
+                            "type": "Feature",
+                            "geometry": {
+                                "type": "Polygon",
+                                "coordinates": [[[x0, z0], [x1, z0], [x1, z1], [x0, z1], [x0, z0]]]
+                            },
+                            "properties": {
+                                "explorer": results.explorer_map_ids.contains(&map.id),
+                                "id": map.id,
+                                "scale": scale,
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                let outlines_file = File::create(&outlines_path)?;
+                serde_json::to_writer(
+                    &outlines_file,
+                    &json!({ "type": "FeatureCollection", "features": features }),
+                )?;
+                outlines_file.set_modified(modified)?;
+            }
+        }
+        _ => remove_if_exists(&outlines_path)?,
+    }
+
+    let frames_path = output_path.join("frames.json");
+    if let Some(modified) = results.frames_modified {
+        if force.render
+            || fs::metadata(&frames_path)
+                .and_then(|m| m.modified())
+                .map_or(true, |json_modified| json_modified < modified)
+        {
+            let features = results
+                .frames
+                .iter()
+                .map(|(frame, ids)| {
+                    json!({
                         "type": "Feature",
                         "geometry": {
                             "type": "Point",
-                            "coordinates": [banner.x, banner.z]
+                            "coordinates": [frame.x, frame.z]
                         },
                         "properties": {
-                            "color": banner.color,
-                            "maps": results.map_ids_by_banner_position[&(banner.x, banner.z)],
-                            "name": banner.label,
-                            "unique": is_unique(banner),
+                            "maps": ids,
+                            "rotation": frame.rotation,
                         }
-                    })).collect::<Vec<_>>()
-                }),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let frames_file = File::create(&frames_path)?;
+            serde_json::to_writer(
+                &frames_file,
+                &json!({ "type": "FeatureCollection", "features": features }),
             )?;
-            banners_file.set_modified(modified)?;
+            frames_file.set_modified(modified)?;
+        }
+    } else {
+        remove_if_exists(&frames_path)?;
+    }
+
+    #[cfg(feature = "gis")]
+    {
+        let gpkg_path = output_path.join("world.gpkg");
+        let modified = results.maps_modified.max(results.banners_modified);
+        match modified {
+            Some(modified) if config.geopackage => {
+                if force.render
+                    || report.tiles_rendered != 0
+                    || tiles_pruned != 0
+                    || fs::metadata(&gpkg_path)
+                        .and_then(|m| m.modified())
+                        .map_or(true, |gpkg_modified| gpkg_modified < modified)
+                {
+                    gpkg::write(
+                        &gpkg_path,
+                        &results,
+                        &report.tiles,
+                        origin,
+                        &config.tile_pattern,
+                        sink.extension(),
+                        modified,
+                    )?;
+                }
+            }
+            _ => remove_if_exists(&gpkg_path)?,
         }
     }
 
+    let layers = layer::write(output_path, &config.layers)?;
+
+    if config.icons.is_empty() {
+        remove_if_exists(&sprite_path)?;
+    } else {
+        icons::write_sprite(output_path)?;
+    }
+
+    let freshness_path = output_path.join("freshness");
+    if freshness.is_none() && freshness_path.is_dir() {
+        fs::remove_dir_all(freshness_path)?;
+    }
+
+    let gallery_path = output_path.join("gallery.html");
+    if config.gallery {
+        let gallery_html = GalleryTemplate {
+            lang: config.lang,
+            image_extension: sink.extension(),
+            art: &art,
+        }
+        .render()?;
+        if force.render || fs::read_to_string(&gallery_path).map_or(true, |c| c != gallery_html) {
+            fs::write(&gallery_path, gallery_html)?;
+        }
+    } else {
+        remove_if_exists(&gallery_path)?;
+    }
+
+    let run_path = output_path.join("run.json");
+    let stats_path = output_path.join("stats.html");
+    let age = WorldAge::from(level);
+    let statistics = config.statistics.then(|| Statistics {
+        copies: searched.map(|s| s.copies.clone()),
+        ..Statistics::from_scan(&results, SystemTime::now())
+    });
+    if let Some(statistics) = &statistics {
+        let run_json = serde_json::to_string(&json!({
+            "generator": format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            "maps": ids.len(),
+            "tiles": report.tiles.len(),
+            "world": &age,
+            "statistics": statistics,
+        }))?;
+        let stats_html = StatsTemplate {
+            lang: config.lang,
+            scales: statistics
+                .maps_by_scale
+                .iter()
+                .map(|(&scale, &count)| (2_u32.pow(u32::from(scale)), count))
+                .collect(),
+            statistics,
+        }
+        .render()?;
+        for (path, contents) in [(&run_path, run_json), (&stats_path, stats_html)] {
+            if force.render || fs::read_to_string(path).map_or(true, |c| c != contents) {
+                fs::write(path, contents)?;
+            }
+        }
+    } else {
+        remove_if_exists(&run_path)?;
+        remove_if_exists(&stats_path)?;
+    }
+
+    // Rewritten only when changed since level.dat is saved continually
+    let world = WorldInfo::from(level);
+    let world_path_json = output_path.join("world.json");
+    let world_json = serde_json::to_string(&world)?;
+    if force.render || fs::read_to_string(&world_path_json).map_or(true, |j| j != world_json) {
+        fs::write(&world_path_json, world_json)?;
+    }
+
     let modified = results
         .banners_modified
         .into_iter()
@@ -288,28 +1545,245 @@ pub fn render(
         .max()
         .unwrap_or(SystemTime::UNIX_EPOCH);
     let index_template = IndexTemplate {
+        age: Some(&age),
+        banner_colors: &banner::palette(config.marker_palette, config.lang),
+        banner_shards: config.banner_shards,
+        bounds: span(&leaves),
         cache_version: &format!(
             "{:x}",
             modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs()
         ),
         center: [level.spawn_z, level.spawn_x],
+        cluster_banners: results.banners.len() > CLUSTER_THRESHOLD,
+        events: config.events_url.as_deref(),
+        frames: !results.frames.is_empty(),
+        freshness: freshness
+            .map(|now| {
+                now.duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs() / 86400)
+            })
+            .transpose()?,
         generator: &format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        history: config.history.is_some(),
+        image_extension: sink.extension(),
+        lang: config.lang,
+        layers: &layers,
+        legend: &legend,
+        map_images: config.map_images != MapImages::Off,
         maps_stacked: report.maps_stacked,
+        offline: config.assets.is_some(),
+        origin: [origin.x, origin.y],
+        outlines: config.outlines,
+        recent_banners: config.recent_banners && !config.hide_banners,
+        tile_pattern: config.tile_pattern.as_str(),
+        world: Some(&world),
     };
-    File::create(output_path.join("index.html"))?.write_all(index_template.render()?.as_bytes())?;
+    write_index(output_path, &index_template)?;
+
+    if let Some(assets_path) = &config.assets {
+        let installed = assets::install(assets_path, output_path, force.render)?;
+        debug!("Installed {installed} assets");
+    }
+
+    precompress_text_outputs(output_path, config.precompress, force.render)?;
+
+    // The list of changed files is drawn from the manifests before and after
+    if config.manifest || config.changed_list.is_some() {
+        let listed = manifest::write(output_path)?;
+        debug!("Listed {listed} files in {}", manifest::NAME);
+    } else {
+        remove_if_exists(&output_path.join(manifest::NAME))?;
+    }
 
     if !quiet {
         if report.maps_rendered == 0 && report.tiles_rendered == 0 && tiles_pruned == 0 {
-            println!("Already up-to-date");
+            Message::UpToDate.emit();
         } else {
-            println!(
-                "Rendered {} tiles and {} maps and pruned {tiles_pruned} tiles and {maps_pruned} maps in {:.2}s",
-                report.tiles_rendered,
-                report.maps_rendered,
-                start_time.elapsed().as_secs_f32()
-            );
+            Message::Rendered {
+                tiles: report.tiles_rendered,
+                maps: report.maps_rendered,
+                tiles_pruned,
+                maps_pruned,
+                seconds: start_time.elapsed().as_secs_f32(),
+            }
+            .emit();
+        }
+    }
+
+    Ok(RenderCounts {
+        statistics,
+        maps_deduplicated: redundant.len(),
+        maps_rendered: report.maps_rendered,
+        maps_pruned,
+        tiles_rendered: report.tiles_rendered,
+        tiles_pruned,
+        tiles_changed: report.tiles_changed,
+    })
+}
+
+/// What a run would render, by a search and a scan of the maps without drawing anything, e.g. to
+/// size hosting for a large world
+#[derive(Clone, Debug, Serialize)]
+pub struct Survey {
+    /// Maps drawn onto the tiles, i.e. those of the overworld
+    pub maps: usize,
+    pub banners: usize,
+    /// Tiles of the most detailed zoom, which are the only ones written
+    pub tiles: usize,
+    /// Blocks spanned by the tiles
+    pub bounds: Option<Bounds>,
+    /// Bytes of the tiles, by the mean size of those of previous runs
+    pub estimated_bytes: u64,
+    pub statistics: Statistics,
+}
+
+// Rejected before scanning, whose memory grows with the count of maps
+fn check_max_maps(config: &Config, ids: &HashSet<u32>) -> Result<(), Error> {
+    match config.max_maps {
+        Some(max_maps) if ids.len() > max_maps => Err(Error::Config(format!(
+            "Found {} maps, more than the maximum of {max_maps}; raise max_maps or exclude maps in the config",
+            ids.len()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn survey(
+    world_path: &Path,
+    ids: &HashSet<u32>,
+    tile_size: Option<u64>,
+    config: &Config,
+) -> Result<Survey> {
+    let ids = config.maps.apply(world_path, ids);
+    check_max_maps(config, &ids)?;
+    let mut scan = MapScan::run(world_path, &ids, &OpenFiles::new(config.max_open_files))?;
+    if config.hide_banners {
+        scan.banners.clear();
+    }
+
+    let leaves = scan
+        .maps_by_tile
+        .keys()
+        .flat_map(Tile::leaves)
+        .collect::<HashSet<_>>();
+
+    Ok(Survey {
+        maps: scan.maps_by_tile.values().map(BTreeSet::len).sum(),
+        banners: scan.banners.len(),
+        tiles: leaves.len(),
+        bounds: span(&leaves),
+        estimated_bytes: leaves.len() as u64 * tile_size.unwrap_or(TILE_SIZE_ESTIMATE),
+        statistics: Statistics::from_scan(&scan, SystemTime::now()),
+    })
+}
+
+/// Blocks spanned by tiles
+fn span(tiles: &HashSet<Tile>) -> Option<Bounds> {
+    tiles
+        .iter()
+        .map(geometry::tile_bounds)
+        .reduce(|((x0, z0), (x1, z1)), ((x2, z2), (x3, z3))| {
+            ((x0.min(x2), z0.min(z2)), (x1.max(x3), z1.max(z3)))
+        })
+}
+
+// A render that would fill the disk midway is refused beforehand rather than leaving the output
+// half-updated, by estimate of its tiles at the mean size of those of previous runs
+fn preflight(
+    output_path: &Path,
+    planned: usize,
+    tile_size: Option<u64>,
+    sink: &dyn ImageSink,
+    config: &Config,
+) -> Result<()> {
+    let estimate = planned as u64 * tile_size.unwrap_or(TILE_SIZE_ESTIMATE);
+    debug!("Estimated {estimate} bytes of tiles");
+
+    if let Some(max_output_size) = config.max_output_size {
+        if estimate > max_output_size {
+            return Err(Error::Config(format!(
+                "Estimated {estimate} bytes of tiles, more than the maximum of {max_output_size}; raise max_output_size or exclude maps in the config"
+            ))
+            .into());
+        }
+    }
+
+    // Tiles already present are replaced in place
+    let needed = estimate.saturating_sub(tile_usage(output_path, sink.extension())?.1);
+    if let Some(available) = available_space(output_path).map_err(|e| Error::io(output_path, e))? {
+        if needed > available {
+            return Err(Error::io(
+                output_path,
+                io::Error::other(format!(
+                    "Estimated {needed} more bytes of tiles, but only {available} are available"
+                )),
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+// Count and total bytes of the tile images of the most detailed zoom
+fn tile_usage(output_path: &Path, extension: &str) -> Result<(u64, u64)> {
+    let tiles_path = output_path.join("tiles");
+    pattern::tiles_in(&tiles_path, &pattern::recorded(output_path)?, extension)?
+        .into_iter()
+        .filter(|&((zoom, _, _), _)| zoom == 4)
+        .try_fold((0, 0), |(count, bytes), (_, path)| {
+            let size = fs::metadata(tiles_path.join(format!("{path}.{extension}")))?.len();
+
+            Ok((count + 1, bytes + size))
+        })
+}
+
+fn prune_tiles(
+    output_path: &Path,
+    tiles: &HashSet<(u8, i32, i32)>,
+    pattern: &TilePattern,
+    sink: &dyn ImageSink,
+) -> Result<usize> {
+    let extension = sink.extension();
+    pattern::tiles_in(&output_path.join("tiles"), pattern, extension)?
+        .into_iter()
+        .map(|(key, path)| -> Result<usize> {
+            Ok(if tiles.contains(&key) {
+                0
+            } else {
+                let base = output_path.join(format!("tiles/{path}"));
+                debug!("Prune: {}", base.display());
+                fs::remove_file(output_path.join(format!("tiles/{path}.{extension}")))?;
+                fs::remove_file(output_path.join(format!("tiles/{path}.meta.json")))?;
+                remove_if_exists(&output_path.join(format!("tiles/{path}.meta.json.gz")))?;
+                remove_if_exists(&output_path.join(format!("freshness/{path}.{extension}")))?;
+                1
+            })
+        })
+        .sum()
+}
+
+fn precompress_text_outputs(output_path: &Path, enabled: bool, force: bool) -> Result<()> {
+    for pattern in TEXT_OUTPUTS {
+        if enabled {
+            glob_in(output_path, pattern)?
+                .par_bridge()
+                .try_for_each(|entry| precompress(&entry?, force).map(drop))?;
+        } else {
+            for entry in glob_in(output_path, &format!("{pattern}.gz"))? {
+                let path = entry?;
+                debug!("Prune: {}", path.display());
+                fs::remove_file(path)?;
+            }
         }
     }
 
     Ok(())
 }
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
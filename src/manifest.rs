@@ -0,0 +1,203 @@
+use crate::paths::{glob_in, slashed};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind::NotFound};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+pub const NAME: &str = "MANIFEST";
+
+// One line per file as "CRC32 SIZE MTIME PATH" sorted by path, for mirrors to compare against
+// their own copy before transferring anything.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub checksum: u32,
+    pub size: u64,
+    pub modified: u64,
+}
+
+pub fn read(output_path: &Path) -> Result<HashMap<PathBuf, Entry>> {
+    let path = output_path.join(NAME);
+    let file = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map(|line| {
+            let line = line?;
+            let parse = || -> Option<(PathBuf, Entry)> {
+                let mut parts = line.splitn(4, ' ');
+                let entry = Entry {
+                    checksum: u32::from_str_radix(parts.next()?, 16).ok()?,
+                    size: parts.next()?.parse().ok()?,
+                    modified: parts.next()?.parse().ok()?,
+                };
+                Some((PathBuf::from(parts.next()?), entry))
+            };
+            parse().with_context(|| format!("Invalid line in {}: {line}", path.display()))
+        })
+        .collect()
+}
+
+// Paths relative to the output of everything in it but hidden files such as the cache
+fn published(output_path: &Path) -> Result<Vec<PathBuf>> {
+    glob_in(output_path, "**/*")?
+        .map(|entry| Ok(entry?.strip_prefix(output_path)?.to_owned()))
+        .filter(|relative| {
+            relative.as_ref().map_or(true, |r: &PathBuf| {
+                !r.components().any(|c| match c {
+                    Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+                    _ => false,
+                })
+            })
+        })
+        .collect()
+}
+
+// Checksums are reused for files of unchanged size and modification time. The manifest is
+// replaced atomically so that a mirror never fetches a partial one.
+pub fn write(output_path: &Path) -> Result<usize> {
+    let previous = read(output_path).unwrap_or_default();
+
+    let mut entries = published(output_path)?
+        .into_iter()
+        .filter(|r| !r.starts_with(NAME))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .filter_map(|relative| {
+            let path = output_path.join(&relative);
+            let entry = (|| -> Result<Option<Entry>> {
+                let metadata = fs::metadata(&path)?;
+                if !metadata.is_file() {
+                    return Ok(None);
+                }
+                let size = metadata.len();
+                let modified = metadata
+                    .modified()?
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs();
+
+                Ok(Some(match previous.get(&relative) {
+                    Some(e) if e.size == size && e.modified == modified => e.clone(),
+                    _ => Entry {
+                        checksum: crc32fast::hash(&fs::read(&path)?),
+                        size,
+                        modified,
+                    },
+                }))
+            })();
+
+            entry.transpose().map(|e| e.map(|e| (relative, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut manifest = String::new();
+    for (relative, e) in &entries {
+        let _ = writeln!(
+            manifest,
+            "{:08x} {} {} {}",
+            e.checksum,
+            e.size,
+            e.modified,
+            slashed(relative)
+        );
+    }
+
+    let temporary_path = output_path.join(format!(".{NAME}.tmp"));
+    fs::write(&temporary_path, manifest)?;
+    fs::rename(temporary_path, output_path.join(NAME))?;
+
+    Ok(entries.len())
+}
+
+// Files are taken to have changed as rsync does by default, i.e. if their size or modification
+// time differs, as listed by the manifests before and after a run. Files removed aren't listed.
+pub fn changed(
+    before: &HashMap<PathBuf, Entry>,
+    after: &HashMap<PathBuf, Entry>,
+) -> BTreeSet<PathBuf> {
+    after
+        .iter()
+        .filter(|(relative, a)| before.get(*relative) != Some(a))
+        .map(|(relative, _)| relative.clone())
+        .collect()
+}
+
+/// Write a list of paths relative to the output, one per line, e.g. for `rsync --files-from`
+pub fn write_list(path: &Path, relatives: &BTreeSet<PathBuf>) -> Result<()> {
+    let mut list = String::new();
+    for relative in relatives {
+        let _ = writeln!(list, "{}", slashed(relative));
+    }
+
+    fs::write(path, list).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let temporary = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let dir = temporary.path();
+        fs::create_dir_all(dir.join(".cache")).unwrap();
+        fs::create_dir_all(dir.join("tiles")).unwrap();
+        fs::write(dir.join(".cache/ignored"), "").unwrap();
+        fs::write(dir.join("tiles/b.webp"), "tile").unwrap();
+        fs::write(dir.join("a.json"), "{}").unwrap();
+
+        let listed = write(dir).unwrap();
+        let first = read(dir).unwrap();
+
+        // An unchanged size and modification time is trusted
+        let manifest = fs::read_to_string(dir.join(NAME)).unwrap();
+        let stale = manifest.replacen(&format!("{:08x}", crc32fast::hash(b"{}")), "00000000", 1);
+        fs::write(dir.join(NAME), &stale).unwrap();
+        write(dir).unwrap();
+        let second = fs::read_to_string(dir.join(NAME));
+
+        assert_eq!(listed, 2);
+        assert_eq!(
+            first[Path::new("tiles/b.webp")].checksum,
+            crc32fast::hash(b"tile")
+        );
+        assert!(manifest.lines().next().unwrap().ends_with(" a.json"));
+        assert_eq!(second.unwrap(), stale);
+    }
+
+    #[test]
+    fn changes() {
+        let temporary = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let dir = temporary.path();
+        fs::create_dir_all(dir.join(".cache")).unwrap();
+        fs::create_dir_all(dir.join("tiles/4")).unwrap();
+        fs::write(dir.join("kept.json"), "{}").unwrap();
+        fs::write(dir.join("removed.json"), "{}").unwrap();
+        fs::write(dir.join("tiles/4/grown.webp"), "tile").unwrap();
+        write(dir).unwrap();
+        let before = read(dir).unwrap();
+
+        fs::write(dir.join(".cache/ignored"), "").unwrap();
+        fs::remove_file(dir.join("removed.json")).unwrap();
+        fs::write(dir.join("tiles/4/grown.webp"), "larger tile").unwrap();
+        fs::write(dir.join("added.json"), "{}").unwrap();
+        write(dir).unwrap();
+        let after = read(dir).unwrap();
+
+        let list_path = dir.join(".changed");
+        write_list(&list_path, &changed(&before, &after)).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&list_path).unwrap(),
+            "added.json\ntiles/4/grown.webp\n"
+        );
+    }
+}
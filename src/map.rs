@@ -2,34 +2,54 @@
 #![allow(clippy::non_canonical_partial_ord_impl)] // Pending mcarton/rust-derivative#115
 
 use crate::banner::Banner;
+use crate::dimension::Dimension;
+use crate::encoding::ImageSink;
+use crate::frame::Frame;
+use crate::open_files::OpenFiles;
 use crate::tile::Tile;
-use crate::utilities::{read_gz, write_webp};
-use anyhow::{Context, Result};
+use crate::utilities::{read_gz, read_modified, retry_once};
+use crate::warning::{WarningCollector, WarningKind};
+use crate::Error;
 use derivative::Derivative;
-use fastnbt::from_bytes;
+use fastnbt::{from_bytes, ByteArray};
 use itertools::Itertools;
 use log::{debug, log_enabled, Level::Debug};
 use rayon::prelude::*;
 use serde::de::{self, Unexpected, Visitor};
 use serde::{Deserialize, Deserializer};
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::fs::{self, File};
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind::NotFound;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
-#[derive(PartialEq)]
-enum Dimension {
-    Nether,
-    Overworld,
-    End,
+// Locations of the data of a map, of which the first to exist is read. Should the game move its
+// maps, the new location is added here rather than wherever they're read.
+const LOCATIONS: [fn(&Path, u32) -> PathBuf; 1] = [data_dir];
+
+fn data_dir(world_path: &Path, id: u32) -> PathBuf {
+    world_path.join(format!("data/map_{id}.dat"))
+}
+
+// Where none exists, the first location is given so that errors name where a map is expected
+pub fn data_path(world_path: &Path, id: u32) -> PathBuf {
+    LOCATIONS
+        .iter()
+        .map(|location| location(world_path, id))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| LOCATIONS[0](world_path, id))
 }
-impl<'de> Deserialize<'de> for Dimension {
+
+struct NbtDimension(Dimension);
+impl<'de> Deserialize<'de> for NbtDimension {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         struct DimensionVisitor;
 
         impl Visitor<'_> for DimensionVisitor {
-            type Value = Dimension;
+            type Value = NbtDimension;
 
             fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
                 formatter.write_str("integer or string")
@@ -37,18 +57,18 @@ impl<'de> Deserialize<'de> for Dimension {
 
             fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
                 match value {
-                    -1 => Ok(Dimension::Nether),
-                    0 => Ok(Dimension::Overworld),
-                    1 => Ok(Dimension::End),
+                    -1 => Ok(NbtDimension(Dimension::Nether)),
+                    0 => Ok(NbtDimension(Dimension::Overworld)),
+                    1 => Ok(NbtDimension(Dimension::End)),
                     _ => Err(E::invalid_value(Unexpected::Signed(value), &self)),
                 }
             }
 
             fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
                 match value {
-                    "minecraft:the_nether" => Ok(Dimension::Nether),
-                    "minecraft:overworld" => Ok(Dimension::Overworld),
-                    "minecraft:the_end" => Ok(Dimension::End),
+                    "minecraft:the_nether" => Ok(NbtDimension(Dimension::Nether)),
+                    "minecraft:overworld" => Ok(NbtDimension(Dimension::Overworld)),
+                    "minecraft:the_end" => Ok(NbtDimension(Dimension::End)),
                     _ => Err(E::invalid_value(Unexpected::Str(value), &self)),
                 }
             }
@@ -58,6 +78,7 @@ impl<'de> Deserialize<'de> for Dimension {
     }
 }
 
+/// Map item, ordered by modification so that later maps are drawn over earlier ones
 #[derive(Debug, Derivative, Eq)]
 #[derivative(Ord, PartialEq, PartialOrd)]
 pub struct Map {
@@ -69,33 +90,65 @@ pub struct Map {
     #[derivative(PartialEq = "ignore")]
     #[derivative(PartialOrd = "ignore")]
     pub tile: Tile,
+
+    /// Locked in a cartography table, so it no longer updates
+    #[derivative(Ord = "ignore")]
+    #[derivative(PartialEq = "ignore")]
+    #[derivative(PartialOrd = "ignore")]
+    pub locked: bool,
+
+    /// Showing the positions of players holding it
+    #[derivative(Ord = "ignore")]
+    #[derivative(PartialEq = "ignore")]
+    #[derivative(PartialOrd = "ignore")]
+    pub tracking: bool,
 }
 
 impl Map {
-    pub fn render(&self, output_path: &Path, data: &MapData, force: bool) -> Result<bool> {
+    pub fn render(
+        &self,
+        output_path: &Path,
+        data: &MapData,
+        sink: &dyn ImageSink,
+        force: bool,
+    ) -> Result<bool, Error> {
         let dir_path = output_path.join("maps");
-        let webp_path = dir_path.join(self.id.to_string()).with_extension("webp");
+        let image_path = dir_path
+            .join(self.id.to_string())
+            .with_extension(sink.extension());
 
         if !force
-            && fs::metadata(&webp_path)
+            && fs::metadata(&image_path)
                 .and_then(|m| m.modified())
                 .map_or(false, |meta_modified| meta_modified >= self.modified)
         {
             return Ok(false);
         }
 
-        fs::create_dir_all(&dir_path)?;
-        let mut webp_file = File::create(webp_path)?;
-        write_webp(&mut webp_file, &data.0)?;
-        webp_file.set_modified(self.modified)?;
+        // Replaced rather than overwritten since it may be linked
+        fs::create_dir_all(&dir_path).map_err(|e| Error::io(&dir_path, e))?;
+        match fs::remove_file(&image_path) {
+            Err(e) if e.kind() != NotFound => return Err(Error::io(&image_path, e)),
+            _ => {}
+        }
+        let mut image_file = File::create(&image_path).map_err(|e| Error::io(&image_path, e))?;
+        sink.write(&mut image_file, &data.0)?;
+        image_file
+            .set_modified(self.modified)
+            .map_err(|e| Error::io(&image_path, e))?;
 
         Ok(true)
     }
 }
 
+#[derive(Clone)]
 pub struct MapData(pub [u8; 128 * 128]);
-impl<'de> Deserialize<'de> for MapData {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+impl MapData {
+    pub fn from_world_path(
+        world_path: &Path,
+        id: u32,
+        warnings: &WarningCollector,
+    ) -> Result<Self, Error> {
         #[derive(Deserialize)]
         struct Internal<'a> {
             #[serde(borrow)]
@@ -106,143 +159,298 @@ impl<'de> Deserialize<'de> for MapData {
         struct Data<'a> {
             #[serde(borrow)]
             colors: &'a [u8],
+            width: Option<i32>,
         }
 
-        let internal = Internal::deserialize(deserializer)?;
-        Ok(Self(internal.data.colors.try_into().map_err(|_| {
-            de::Error::invalid_value(
-                Unexpected::Bytes(internal.data.colors),
-                &"array of 128 × 128 indexed-color pixels",
-            )
-        })?))
+        span!("read_map", id);
+        let path = data_path(world_path, id);
+        let (map_data, is_exact, length) = retry_once(&path, || {
+            let bytes = read_gz(&path)?;
+            let data = from_bytes::<Internal>(&bytes)
+                .map_err(|source| Error::Nbt {
+                    path: Some(path.clone()),
+                    map_id: Some(id),
+                    source,
+                })?
+                .data;
+            let (map_data, is_exact) = Self::fit(data.colors, data.width);
+
+            Ok::<_, Error>((map_data, is_exact, data.colors.len()))
+        })?;
+
+        if !is_exact {
+            debug!(
+                "Map {id} has {length} colors rather than 128 × 128, so it is cropped or padded"
+            );
+            warnings.add(WarningKind::Resized, id.to_string());
+        }
+
+        Ok(map_data)
     }
-}
-impl MapData {
-    pub fn from_world_path(world_path: &Path, id: u32) -> Result<Self> {
-        let path = world_path.join(format!("data/map_{id}.dat"));
 
-        from_bytes(&read_gz(&path)?)
-            .with_context(|| format!("Failed to deserialize {}", path.display()))
+    // Datapacks may produce maps of other sizes, which are cropped or padded with transparency
+    fn fit(colors: &[u8], width: Option<i32>) -> (Self, bool) {
+        if let (Ok(exact), None | Some(128)) = (colors.try_into(), width) {
+            return (Self(exact), true);
+        }
+
+        let width = width
+            .and_then(|w| usize::try_from(w).ok())
+            .filter(|&w| w > 0)
+            .unwrap_or(128);
+        let mut fitted = [0; 128 * 128];
+        for (y, row) in colors.chunks(width).take(128).enumerate() {
+            let length = row.len().min(128);
+            fitted[y * 128..y * 128 + length].copy_from_slice(&row[..length]);
+        }
+
+        (Self(fitted), false)
     }
 }
 
+/// Maps of the overworld by the tiles they cover, with their banners
 #[derive(Default)]
 pub struct MapScan {
-    pub banners: BTreeSet<Banner>,
+    /// Map ids depicting each banner
+    pub banners: BTreeMap<Banner, BTreeSet<u32>>,
+    /// Latest modification of any map depicting banners
     pub banners_modified: Option<SystemTime>,
+    /// Hash of the colors of each map
+    pub content_by_map_id: HashMap<u32, u64>,
+    /// Dimension of each map as its data says, by which alone it's rendered or not, wherever its
+    /// items were found
+    pub dimension_by_map_id: HashMap<u32, Dimension>,
+    /// Maps tracking players at any distance, i.e. explorer maps
+    pub explorer_map_ids: HashSet<u32>,
+    /// Map ids decorated by each item frame
+    pub frames: BTreeMap<Frame, BTreeSet<u32>>,
+    /// Latest modification of any map decorated by item frames
+    pub frames_modified: Option<SystemTime>,
+    /// Maps locked in a cartography table, as map art usually is
+    pub locked_map_ids: HashSet<u32>,
+    /// Count of maps of each dimension, including those not rendered
+    pub maps_by_dimension: BTreeMap<Dimension, usize>,
     pub maps_by_tile: HashMap<Tile, BTreeSet<Map>>,
+    /// Latest modification of any map
     pub maps_modified: Option<SystemTime>,
-    pub map_ids_by_banner_position: HashMap<(i32, i32), BTreeSet<u32>>,
+    pub modified_by_map_id: HashMap<u32, SystemTime>,
+    /// Zoom 0 tiles containing the maps
     pub root_tiles: HashSet<Tile>,
 }
 impl MapScan {
-    pub fn run(world_path: &Path, ids: &HashSet<u32>) -> Result<Self> {
+    pub fn run(world_path: &Path, ids: &HashSet<u32>, files: &OpenFiles) -> Result<Self, Error> {
+        span!("scan", maps = ids.len());
+
+        ids.into_par_iter()
+            .map(|&id| -> Result<Self, Error> {
+                span!("scan_map", id);
+                let path = data_path(world_path, id);
+                let modified = read_modified(&path)?;
+
+                retry_once(&path, || {
+                    Self::from_nbt(id, &files.read_gz(&path)?, modified).map_err(|e| match e {
+                        Error::Nbt { map_id, source, .. } => Error::Nbt {
+                            path: Some(path.clone()),
+                            map_id,
+                            source,
+                        },
+                        e => e,
+                    })
+                })
+            })
+            .try_reduce(Self::default, |results, other| Ok(results.merge(other)))
+    }
+
+    /// Scan one map from the uncompressed NBT of its map_#.dat, e.g. as read from elsewhere than
+    /// a world directory
+    pub fn from_nbt(id: u32, nbt: &[u8], modified: SystemTime) -> Result<Self, Error> {
         enum Meta {
-            Normal { banners: Vec<Banner>, tile: Tile },
-            Other,
+            Normal {
+                banners: Vec<Banner>,
+                content: u64,
+                explorer: bool,
+                frames: Vec<Frame>,
+                locked: bool,
+                tile: Tile,
+                tracking: bool,
+            },
+            Other(Dimension),
         }
         impl<'de> Deserialize<'de> for Meta {
             fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-                #[derive(serde_query::Deserialize)]
+                #[derive(Deserialize)]
                 struct Internal {
-                    #[query(".data.banners")]
+                    data: Data,
+                }
+                // Frames are absent from maps made by some tools, and locking from those made before
+                // 1.14, unlike the rest. Absent tracking of position is taken as the game does.
+                const fn tracking_by_default() -> bool {
+                    true
+                }
+                #[derive(Deserialize)]
+                struct Data {
                     banners: Vec<Banner>,
-                    #[query(".data.dimension")]
-                    dimension: Dimension,
-                    #[query(".data.scale")]
+                    colors: Option<ByteArray>,
+                    dimension: NbtDimension,
+                    #[serde(default)]
+                    frames: Vec<Frame>,
+                    #[serde(default)]
+                    locked: bool,
                     scale: u8,
-                    #[query(".data.xCenter")]
+                    #[serde(default = "tracking_by_default", rename = "trackingPosition")]
+                    tracking_position: bool,
+                    #[serde(default, rename = "unlimitedTracking")]
+                    unlimited_tracking: bool,
+                    #[serde(rename = "xCenter")]
                     x: i32,
-                    #[query(".data.zCenter")]
+                    #[serde(rename = "zCenter")]
                     z: i32,
                 }
-                let internal = Internal::deserialize(deserializer)?;
-                if internal.dimension == Dimension::Overworld {
+                let internal = Internal::deserialize(deserializer)?.data;
+                if internal.dimension.0 == Dimension::Overworld {
+                    let mut hasher = DefaultHasher::new();
+                    internal.colors.as_deref().hash(&mut hasher);
+
                     Ok(Self::Normal {
                         banners: internal.banners,
+                        content: hasher.finish(),
+                        explorer: internal.unlimited_tracking,
+                        frames: internal.frames,
+                        locked: internal.locked,
                         tile: Tile::from_position(internal.scale, internal.x, internal.z),
+                        tracking: internal.tracking_position,
                     })
                 } else {
-                    Ok(Self::Other)
+                    Ok(Self::Other(internal.dimension.0))
                 }
             }
         }
 
-        let data_path = world_path.join("data");
-
-        ids.into_par_iter()
-            .map(move |&id| -> Result<Self> {
-                let path = data_path.join(format!("map_{id}.dat"));
-                let mut results = Self::default();
-
-                if let Meta::Normal { banners, tile } = from_bytes(&read_gz(&path)?)
-                    .with_context(|| format!("Failed to deserialize {}", path.display()))?
-                {
-                    let modified = fs::metadata(&path)?.modified()?;
-
-                    results.root_tiles.insert(tile.root());
-                    results.maps_modified.replace(modified);
-                    if !banners.is_empty() {
-                        results.banners_modified.replace(modified);
-
-                        if log_enabled!(Debug) {
-                            let list = banners
-                                .iter()
-                                .sorted()
-                                .map(|Banner { x, z, .. }| format!("({x}, {z})",))
-                                .join(", ");
-                            debug!("Map {id} banners: {list}");
-                        }
-                    }
-                    for banner in &banners {
-                        results
-                            .map_ids_by_banner_position
-                            .entry((banner.x, banner.z))
-                            .or_default()
-                            .insert(id);
-                    }
-                    results.banners.extend(banners);
-                    results
-                        .maps_by_tile
-                        .entry(tile.clone())
-                        .or_default()
-                        .insert(Map { modified, id, tile });
-                } else {
-                    debug!("Ignoring map {id}");
+        let mut results = Self::default();
+
+        let meta = from_bytes(nbt).map_err(|source| Error::Nbt {
+            path: None,
+            map_id: Some(id),
+            source,
+        })?;
+        if let Meta::Normal {
+            banners,
+            content,
+            explorer,
+            frames,
+            locked,
+            tile,
+            tracking,
+        } = meta
+        {
+            results.maps_by_dimension.insert(Dimension::Overworld, 1);
+            results.dimension_by_map_id.insert(id, Dimension::Overworld);
+            results.root_tiles.insert(tile.root());
+            results.maps_modified.replace(modified);
+            results.modified_by_map_id.insert(id, modified);
+            results.content_by_map_id.insert(id, content);
+            if explorer {
+                results.explorer_map_ids.insert(id);
+            }
+            if locked {
+                results.locked_map_ids.insert(id);
+            }
+            if !banners.is_empty() {
+                results.banners_modified.replace(modified);
+
+                if log_enabled!(Debug) {
+                    let list = banners
+                        .iter()
+                        .sorted()
+                        .map(|Banner { x, z, .. }| format!("({x}, {z})",))
+                        .join(", ");
+                    debug!("Map {id} banners: {list}");
                 }
+            }
+            for banner in banners {
+                results.banners.entry(banner).or_default().insert(id);
+            }
+            if !frames.is_empty() {
+                results.frames_modified.replace(modified);
+            }
+            for frame in frames {
+                results.frames.entry(frame).or_default().insert(id);
+            }
+            results
+                .maps_by_tile
+                .entry(tile.clone())
+                .or_default()
+                .insert(Map {
+                    modified,
+                    id,
+                    tile,
+                    locked,
+                    tracking,
+                });
+        } else if let Meta::Other(dimension) = meta {
+            results.maps_by_dimension.insert(dimension, 1);
+            results.dimension_by_map_id.insert(id, dimension);
+            debug!("Ignoring map {id}");
+        }
 
-                Ok(results)
-            })
-            .try_reduce(Self::default, |mut results, other| {
-                if let Some(b) = other.banners_modified {
-                    if results.banners_modified.map_or(true, |a| a < b) {
-                        results.banners_modified.replace(b);
-                    }
-                }
-                if let Some(b) = other.maps_modified {
-                    if results.maps_modified.map_or(true, |a| a < b) {
-                        results.maps_modified.replace(b);
-                    }
-                }
-                results.root_tiles.extend(other.root_tiles);
-                for (tile, other_maps) in other.maps_by_tile {
-                    results
-                        .maps_by_tile
-                        .entry(tile)
-                        .or_default()
-                        .extend(other_maps);
-                }
-                for (position, other_ids) in other.map_ids_by_banner_position {
-                    results
-                        .map_ids_by_banner_position
-                        .entry(position)
-                        .or_default()
-                        .extend(other_ids);
-                }
-                results.banners.extend(other.banners);
+        Ok(results)
+    }
+
+    #[must_use]
+    pub fn merge(mut self, other: Self) -> Self {
+        if let Some(b) = other.banners_modified {
+            if self.banners_modified.map_or(true, |a| a < b) {
+                self.banners_modified.replace(b);
+            }
+        }
+        if let Some(b) = other.frames_modified {
+            if self.frames_modified.map_or(true, |a| a < b) {
+                self.frames_modified.replace(b);
+            }
+        }
+        if let Some(b) = other.maps_modified {
+            if self.maps_modified.map_or(true, |a| a < b) {
+                self.maps_modified.replace(b);
+            }
+        }
+        self.root_tiles.extend(other.root_tiles);
+        for (dimension, count) in other.maps_by_dimension {
+            *self.maps_by_dimension.entry(dimension).or_default() += count;
+        }
+        for (tile, other_maps) in other.maps_by_tile {
+            self.maps_by_tile
+                .entry(tile)
+                .or_default()
+                .extend(other_maps);
+        }
+        for (banner, other_ids) in other.banners {
+            self.banners.entry(banner).or_default().extend(other_ids);
+        }
+        for (frame, other_ids) in other.frames {
+            self.frames.entry(frame).or_default().extend(other_ids);
+        }
+        self.content_by_map_id.extend(other.content_by_map_id);
+        self.dimension_by_map_id.extend(other.dimension_by_map_id);
+        self.explorer_map_ids.extend(other.explorer_map_ids);
+        self.locked_map_ids.extend(other.locked_map_ids);
+        self.modified_by_map_id.extend(other.modified_by_map_id);
+
+        self
+    }
 
-                Ok(results)
+    /// Maps of the same colors as a later map of the same tile, which is drawn over them exactly
+    #[must_use]
+    pub fn redundant(&self) -> HashSet<u32> {
+        self.maps_by_tile
+            .values()
+            .flat_map(|maps| {
+                let mut seen = HashSet::new();
+                maps.iter()
+                    .rev()
+                    .filter(move |m| !seen.insert(self.content_by_map_id[&m.id]))
+                    .map(|m| m.id)
             })
+            .collect()
     }
 }
 
@@ -252,6 +460,141 @@ mod test {
     use std::cmp::Ordering::{Equal, Greater, Less};
     use std::time::Duration;
 
+    #[test]
+    fn locations() {
+        let world = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let world_path = world.path();
+        fs::create_dir_all(world_path.join("data")).unwrap();
+        fs::write(world_path.join("data/map_1.dat"), "").unwrap();
+
+        let (present, absent) = (data_path(world_path, 1), data_path(world_path, 2));
+
+        assert_eq!(present, world_path.join("data/map_1.dat"));
+        assert_eq!(absent, world_path.join("data/map_2.dat"));
+    }
+
+    #[test]
+    fn from_nbt() {
+        let nbt = fastnbt::to_bytes(&fastnbt::nbt!({
+            "data": {
+                "banners": [{ "color": "red", "pos": [I; 100, 64, -100] }],
+                "dimension": "minecraft:overworld",
+                "scale": 0_i8,
+                "xCenter": 64,
+                "zCenter": -64,
+            }
+        }))
+        .unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+
+        let scan = MapScan::from_nbt(7, &nbt, modified).unwrap();
+        let tile = Tile::new(4, 0, -1);
+        assert_eq!(scan.maps_by_tile[&tile].first().unwrap().id, 7);
+        assert!(scan.root_tiles.contains(&tile.root()));
+        assert_eq!(scan.banners_modified, Some(modified));
+        assert_eq!(scan.banners.values().collect::<Vec<_>>(), [&[7].into()]);
+
+        let merged = scan.merge(MapScan::from_nbt(8, &nbt, modified).unwrap());
+        assert_eq!(
+            merged.banners.values().collect::<Vec<_>>(),
+            [&[7, 8].into()]
+        );
+        assert!(merged.frames.is_empty());
+        assert_eq!(merged.frames_modified, None);
+        assert!(merged.explorer_map_ids.is_empty());
+    }
+
+    #[test]
+    fn explorer() {
+        let nbt = fastnbt::to_bytes(&fastnbt::nbt!({
+            "data": {
+                "banners": [],
+                "dimension": "minecraft:overworld",
+                "scale": 2_i8,
+                "unlimitedTracking": 1_i8,
+                "xCenter": 64,
+                "zCenter": -64,
+            }
+        }))
+        .unwrap();
+
+        let scan = MapScan::from_nbt(7, &nbt, SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(scan.explorer_map_ids, [7].into());
+        assert_eq!(scan.maps_by_tile.keys().next().unwrap().zoom, 2);
+    }
+
+    #[test]
+    fn redundant() {
+        let nbt = |color: i8| {
+            fastnbt::to_bytes(&fastnbt::nbt!({
+                "data": {
+                    "banners": [],
+                    "colors": fastnbt::Value::ByteArray(ByteArray::new(vec![color; 128 * 128])),
+                    "dimension": "minecraft:overworld",
+                    "scale": 0_i8,
+                    "xCenter": 64,
+                    "zCenter": -64,
+                }
+            }))
+            .unwrap()
+        };
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+        let scan = [(1, 34, 1), (2, 34, 3), (3, 38, 2)]
+            .into_iter()
+            .map(|(id, color, secs)| MapScan::from_nbt(id, &nbt(color), at(secs)).unwrap())
+            .fold(MapScan::default(), MapScan::merge);
+
+        assert_eq!(scan.redundant(), [1].into());
+    }
+
+    #[test]
+    fn frames() {
+        let nbt = fastnbt::to_bytes(&fastnbt::nbt!({
+            "data": {
+                "banners": [],
+                "dimension": "minecraft:overworld",
+                "frames": [{ "entity_id": 1, "pos": [I; 10, 64, -10], "rotation": 90 }],
+                "scale": 0_i8,
+                "xCenter": 64,
+                "zCenter": -64,
+            }
+        }))
+        .unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+
+        let scan = MapScan::from_nbt(7, &nbt, modified).unwrap();
+        let frame = Frame {
+            x: 10,
+            z: -10,
+            rotation: 90,
+        };
+        assert_eq!(scan.frames[&frame], [7].into());
+        assert_eq!(scan.frames_modified, Some(modified));
+        assert_eq!(scan.banners_modified, None);
+    }
+
+    #[test]
+    fn fit() {
+        let exact = [1; 128 * 128];
+        assert!(MapData::fit(&exact, None).1);
+        assert!(MapData::fit(&exact, Some(128)).1);
+
+        let (short, is_exact) = MapData::fit(&[1; 100], None);
+        assert!(!is_exact);
+        assert_eq!(short.0[99], 1);
+        assert_eq!(short.0[100], 0);
+
+        let (wide, _) = MapData::fit(&vec![1; 256 * 256], Some(256));
+        assert_eq!(wide.0, [1; 128 * 128]);
+
+        let (narrow, _) = MapData::fit(&[1; 64 * 64], Some(64));
+        assert_eq!(narrow.0[63], 1);
+        assert_eq!(narrow.0[64], 0);
+        assert_eq!(narrow.0[128 + 63], 1);
+        assert_eq!(narrow.0[64 * 128], 0);
+    }
+
     #[test]
     fn compare() {
         fn map(id: u32, s: u64, x: i32) -> Map {
@@ -259,6 +602,8 @@ mod test {
                 id,
                 modified: SystemTime::UNIX_EPOCH + Duration::from_secs(s),
                 tile: Tile::new(0, x, 0),
+                locked: false,
+                tracking: true,
             }
         }
 
@@ -0,0 +1,522 @@
+use crate::banner::{self, Spool};
+use crate::config::{Colors, ImageFormat, MarkerPalette};
+use crate::encoding;
+use crate::geometry::{self, Origin};
+use crate::i18n::Lang;
+use crate::layout;
+use crate::message::Message;
+use crate::palette::PALETTE;
+use crate::paths::{glob_in, tile_key};
+use crate::pattern::{self, TilePattern};
+use crate::tile::Tile;
+use crate::utilities::{progress_bar, read_webp, write_webp_rgb};
+use crate::{
+    prune_tiles, span, write_banner_list, write_index, Error, IndexTemplate, BANNER_SPOOL,
+};
+use anyhow::{anyhow, Context, Result};
+use indicatif::ParallelProgressIterator;
+use log::debug;
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
+
+type TileKey = (u8, i32, i32);
+
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub path: PathBuf,
+    pub offset: (i32, i32),
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Error> {
+        let (named, offset) = match text.rsplit_once('@') {
+            Some((named, offset)) => {
+                let invalid = || Error::Config(format!("Expected offset X,Z: {offset}"));
+                let (x, z) = offset.split_once(',').ok_or_else(invalid)?;
+                (
+                    named,
+                    (
+                        x.parse().map_err(|_| invalid())?,
+                        z.parse().map_err(|_| invalid())?,
+                    ),
+                )
+            }
+            None => (text, (0, 0)),
+        };
+
+        if offset.0 % 2048 != 0 || offset.1 % 2048 != 0 {
+            return Err(Error::Config(format!(
+                "Offset must be a multiple of 2048 blocks: {text}"
+            )));
+        }
+
+        let (name, path) = if let Some((name, path)) = named.split_once('=') {
+            (name.to_owned(), PathBuf::from(path))
+        } else {
+            let path = PathBuf::from(named);
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| {
+                    Error::Config(format!("Unable to derive a layer name from {named}"))
+                })?
+                .to_owned();
+            (name, path)
+        };
+
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(Error::Config(format!("Invalid layer name: {name:?}")));
+        }
+
+        Ok(Self { name, path, offset })
+    }
+}
+
+impl Source {
+    fn shift(&self, (zoom, x, y): TileKey) -> TileKey {
+        let size = 128 * 2_i32.pow(u32::from(4 - zoom));
+
+        (zoom, x + self.offset.0 / size, y + self.offset.1 / size)
+    }
+}
+
+fn namespaced(name: &str, id: &Value) -> String {
+    match id {
+        Value::String(id) => format!("{name}/{id}"),
+        id => format!("{name}/{id}"),
+    }
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    maps: Vec<Value>,
+}
+
+struct Layer {
+    base_path: PathBuf,
+    maps: Vec<String>,
+    modified: SystemTime,
+}
+
+pub fn merge(
+    output_path: &Path,
+    sources: &[Source],
+    center: (i32, i32),
+    quiet: bool,
+    force: bool,
+) -> Result<(), Error> {
+    Ok(merge_sources(output_path, sources, center, quiet, force)?)
+}
+
+fn merge_sources(
+    output_path: &Path,
+    sources: &[Source],
+    center: (i32, i32),
+    quiet: bool,
+    force: bool,
+) -> Result<()> {
+    let start_time = Instant::now();
+
+    layout::migrate(output_path)?;
+    pattern::switch(output_path, &TilePattern::default())?;
+    pattern::switch_origin(output_path, Origin::default())?;
+
+    let names = sources.iter().map(|s| &s.name).collect::<HashSet<_>>();
+    if names.len() != sources.len() {
+        return Err(Error::Config("Layer names must be distinct".to_owned()).into());
+    }
+    for source in sources {
+        let extension = encoding::recorded(&source.path)?;
+        if extension != "webp" {
+            return Err(Error::Config(format!(
+                "Layer {} has {extension} images, but merging requires WebP",
+                source.name
+            ))
+            .into());
+        }
+        let tile_pattern = pattern::recorded(&source.path)?;
+        if tile_pattern != TilePattern::default() {
+            return Err(Error::Config(format!(
+                "Layer {} has tiles named by {tile_pattern}, but merging requires {}",
+                source.name,
+                TilePattern::default()
+            ))
+            .into());
+        }
+        let origin = pattern::recorded_origin(&source.path)?;
+        if origin != Origin::default() {
+            return Err(Error::Config(format!(
+                "Layer {} has tiles numbered from root tile {}, {}, but merging requires the default origin",
+                source.name, origin.x, origin.y
+            ))
+            .into());
+        }
+    }
+
+    let mut layers_by_tile = BTreeMap::<TileKey, Vec<Layer>>::new();
+    for source in sources {
+        for entry in glob_in(&source.path, "tiles/*/*/*.meta.json")? {
+            let meta_path = entry?;
+            let (zoom, x, y) = tile_key(meta_path.strip_prefix(&source.path)?)?;
+
+            let meta: Meta = serde_json::from_reader(File::open(&meta_path)?)
+                .with_context(|| format!("Failed to deserialize {}", meta_path.display()))?;
+
+            layers_by_tile
+                .entry(source.shift((zoom, x, y)))
+                .or_default()
+                .push(Layer {
+                    base_path: meta_path.with_extension("").with_extension(""),
+                    maps: meta
+                        .maps
+                        .iter()
+                        .map(|id| namespaced(&source.name, id))
+                        .collect(),
+                    modified: fs::metadata(&meta_path)?.modified()?,
+                });
+        }
+    }
+
+    let bar = progress_bar(quiet, "Merge", layers_by_tile.len(), "tiles");
+    let tiles_merged = layers_by_tile
+        .par_iter()
+        .progress_with(bar.clone())
+        .map(|(&(zoom, x, y), layers)| merge_tile(output_path, (zoom, x, y), layers, force))
+        .sum::<Result<usize>>()?;
+    bar.finish_and_clear();
+
+    let maps_stacked = layers_by_tile
+        .iter()
+        .filter(|((zoom, _, _), _)| *zoom == 4)
+        .map(|(_, layers)| layers.iter().map(|l| l.maps.len()).sum())
+        .max()
+        .unwrap_or_default();
+
+    let mut maps = HashSet::new();
+    let mut maps_merged = 0;
+    for source in sources {
+        for entry in glob_in(&source.path, "maps/*.webp")? {
+            let path = entry?;
+            let file_name = path.file_name().unwrap();
+            let dir_path = output_path.join("maps").join(&source.name);
+            let target_path = dir_path.join(file_name);
+            let modified = fs::metadata(&path)?.modified()?;
+
+            maps.insert(target_path.clone());
+            if force
+                || fs::metadata(&target_path)
+                    .and_then(|m| m.modified())
+                    .map_or(true, |target_modified| target_modified < modified)
+            {
+                fs::create_dir_all(&dir_path)?;
+                fs::copy(&path, &target_path)?;
+                File::options()
+                    .write(true)
+                    .open(&target_path)?
+                    .set_modified(modified)?;
+                maps_merged += 1;
+            }
+        }
+    }
+
+    let (banners_modified, legend) = merge_banners(output_path, sources, force)?;
+
+    let tiles_pruned = prune_tiles(
+        output_path,
+        &layers_by_tile.keys().copied().collect(),
+        &TilePattern::default(),
+        &*ImageFormat::WebP.sink(Colors::default()),
+    )?;
+    let maps_pruned = glob_in(output_path, "maps/*/*.webp")?
+        .map(|entry| -> Result<usize> {
+            let path = entry?;
+
+            Ok(if maps.contains(&path) {
+                0
+            } else {
+                debug!("Prune: {}", path.display());
+                fs::remove_file(path)?;
+                1
+            })
+        })
+        .sum::<Result<usize>>()?;
+
+    let modified = layers_by_tile
+        .values()
+        .flatten()
+        .map(|l| l.modified)
+        .chain(banners_modified)
+        .max()
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let index_template = IndexTemplate {
+        age: None,
+        banner_colors: &banner::palette(MarkerPalette::default(), Lang::default()),
+        banner_shards: false,
+        bounds: span(
+            &layers_by_tile
+                .keys()
+                .map(|&(zoom, x, y)| Tile { zoom, x, y })
+                .collect(),
+        ),
+        cache_version: &format!(
+            "{:x}",
+            modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs()
+        ),
+        center: [center.1, center.0],
+        cluster_banners: false,
+        events: None,
+        frames: false,
+        freshness: None,
+        generator: &format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        history: false,
+        image_extension: "webp",
+        lang: Lang::default(),
+        layers: &[],
+        legend: &legend,
+        map_images: true,
+        maps_stacked,
+        offline: false,
+        origin: [0, 0],
+        outlines: false,
+        recent_banners: false,
+        tile_pattern: &TilePattern::default().to_string(),
+        world: None,
+    };
+    write_index(output_path, &index_template)?;
+
+    if !quiet {
+        Message::Merged {
+            tiles: tiles_merged,
+            maps: maps_merged,
+            sources: sources.len(),
+            tiles_pruned,
+            maps_pruned,
+            seconds: start_time.elapsed().as_secs_f32(),
+        }
+        .emit();
+    }
+
+    Ok(())
+}
+
+fn merge_tile(
+    output_path: &Path,
+    (zoom, x, y): TileKey,
+    layers: &[Layer],
+    force: bool,
+) -> Result<usize> {
+    let dir_path = output_path.join(format!("tiles/{zoom}/{x}"));
+    let base_path = dir_path.join(y.to_string());
+    let meta_path = base_path.with_extension("meta.json");
+    let modified = layers.iter().map(|l| l.modified).max().unwrap();
+
+    if !force
+        && fs::metadata(&meta_path)
+            .and_then(|m| m.modified())
+            .map_or(false, |meta_modified| meta_modified >= modified)
+    {
+        return Ok(0);
+    }
+
+    let background = &PALETTE[0..3];
+    let mut canvas = background.repeat(128 * 128);
+
+    let mut is_dirty = false;
+    for layer in layers {
+        let webp_path = layer.base_path.with_extension("webp");
+        if !webp_path.exists() {
+            continue;
+        }
+
+        let rgb = read_webp(&webp_path)?;
+        for (pixel, layer_pixel) in canvas.chunks_exact_mut(3).zip(rgb.chunks_exact(3)) {
+            if *pixel == *background && layer_pixel != background {
+                is_dirty = true;
+                pixel.copy_from_slice(layer_pixel);
+            }
+        }
+    }
+
+    // Metadata
+    fs::create_dir_all(&dir_path)?;
+    let maps = layers.iter().flat_map(|l| &l.maps).collect::<Vec<_>>();
+    let meta_file = File::create(&meta_path)?;
+    serde_json::to_writer(&meta_file, &json!({ "maps": maps }))?;
+    meta_file.set_modified(modified)?;
+
+    // Image
+    let webp_path = base_path.with_extension("webp");
+    if is_dirty {
+        let mut webp_file = File::create(&webp_path)?;
+        write_webp_rgb(&mut webp_file, &canvas)?;
+        webp_file.set_modified(modified)?;
+    } else if webp_path.exists() {
+        fs::remove_file(webp_path)?;
+    }
+
+    Ok(1)
+}
+
+fn merge_banners(
+    output_path: &Path,
+    sources: &[Source],
+    force: bool,
+) -> Result<(Option<SystemTime>, BTreeMap<String, usize>)> {
+    let mut features = Vec::new();
+    let mut modified = None;
+
+    for source in sources {
+        let path = source.path.join("banners.json");
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        let source_modified = fs::metadata(&path)?.modified()?;
+        modified = modified.max(Some(source_modified));
+
+        let mut collection: Value = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to deserialize {}", path.display()))?;
+        let source_features = collection
+            .get_mut("features")
+            .and_then(Value::as_array_mut)
+            .ok_or_else(|| anyhow!("Missing features: {}", path.display()))?;
+
+        for mut feature in source_features.drain(..) {
+            if let Some(coordinates) = feature.pointer_mut("/geometry/coordinates") {
+                if let (Some(x), Some(z)) = (coordinates[0].as_i64(), coordinates[1].as_i64()) {
+                    *coordinates = json!([
+                        x + i64::from(source.offset.0),
+                        z + i64::from(source.offset.1)
+                    ]);
+                }
+            }
+            if let Some(properties) = feature.get_mut("properties").and_then(Value::as_object_mut) {
+                if let Some(maps) = properties.get_mut("maps").and_then(Value::as_array_mut) {
+                    for id in maps.iter_mut() {
+                        *id = json!(namespaced(&source.name, id));
+                    }
+                }
+                if let Some(id) = properties.get_mut("supplied_by") {
+                    *id = json!(namespaced(&source.name, id));
+                }
+                if let Some(modified) = properties
+                    .get_mut("maps_modified")
+                    .and_then(Value::as_object_mut)
+                {
+                    *modified = modified
+                        .iter()
+                        .map(|(id, m)| (format!("{}/{id}", source.name), m.clone()))
+                        .collect();
+                }
+                properties.insert("source".to_owned(), json!(source.name));
+            }
+            features.push(feature);
+        }
+    }
+
+    let mut legend = BTreeMap::<String, usize>::new();
+    for feature in &features {
+        if let Some(color) = feature.pointer("/properties/color").and_then(Value::as_str) {
+            *legend.entry(color.to_owned()).or_default() += 1;
+        }
+    }
+
+    let Some(modified) = modified else {
+        return Ok((None, legend));
+    };
+
+    let banners_path = output_path.join("banners.json");
+    if force
+        || fs::metadata(&banners_path)
+            .and_then(|m| m.modified())
+            .map_or(true, |json_modified| json_modified < modified)
+    {
+        let mut label_counts = HashMap::<String, usize>::new();
+        for feature in &features {
+            if let Some(label) = feature.pointer("/properties/name").and_then(Value::as_str) {
+                *label_counts.entry(label.to_owned()).or_default() += 1;
+            }
+        }
+        for feature in &mut features {
+            let unique = feature
+                .pointer("/properties/name")
+                .and_then(Value::as_str)
+                .map_or(false, |l| label_counts[l] == 1);
+            if let Some(properties) = feature.get_mut("properties").and_then(Value::as_object_mut) {
+                properties.insert("unique".to_owned(), json!(unique));
+            }
+        }
+
+        // In order of root tile as spooled
+        features.sort_by_cached_key(|feature| {
+            let coordinate = |i| {
+                feature
+                    .pointer(&format!("/geometry/coordinates/{i}"))
+                    .and_then(Value::as_i64)
+                    .and_then(|c| i32::try_from(c).ok())
+                    .unwrap_or_default()
+            };
+            let (x, z) = (coordinate(0), coordinate(1));
+            let root = geometry::tile_of(0, (x, z));
+            (root.x, root.y, x, z)
+        });
+
+        fs::create_dir_all(output_path)?;
+        let spool = Spool::write(
+            &output_path.join(BANNER_SPOOL),
+            features.into_iter().map(Ok),
+        )?;
+        let banners_file = File::create(&banners_path)?;
+        spool.write_collection(BufWriter::new(&banners_file))?;
+        banners_file.set_modified(modified)?;
+        write_banner_list(
+            output_path,
+            Lang::default(),
+            MarkerPalette::default(),
+            &spool,
+            modified,
+        )?;
+
+        let legend_file = File::create(output_path.join("legend.json"))?;
+        serde_json::to_writer(&legend_file, &json!({ "colors": legend }))?;
+        legend_file.set_modified(modified)?;
+    }
+
+    Ok((Some(modified), legend))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_source() {
+        let source: Source = "survival=/srv/a@2048,-4096".parse().unwrap();
+        assert_eq!(source.name, "survival");
+        assert_eq!(source.path, PathBuf::from("/srv/a"));
+        assert_eq!(source.offset, (2048, -4096));
+
+        let source: Source = "/srv/creative".parse().unwrap();
+        assert_eq!(source.name, "creative");
+        assert_eq!(source.offset, (0, 0));
+
+        assert!("a=/srv/a@100,0".parse::<Source>().is_err());
+        assert!("=/srv/a".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn shift() {
+        let source: Source = "a=/srv/a@2048,-4096".parse().unwrap();
+        assert_eq!(source.shift((0, 0, 0)), (0, 1, -2));
+        assert_eq!(source.shift((4, 3, 5)), (4, 19, -27));
+    }
+}
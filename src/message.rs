@@ -0,0 +1,165 @@
+use crate::Error;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+static FORMAT: OnceCell<Format> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    #[default]
+    Text,
+    // One JSON object per line, for wrappers such as server panels
+    Json,
+}
+
+impl Format {
+    /// Effective for the rest of the process and only if not yet set
+    pub fn set(self) {
+        FORMAT.get_or_init(|| self);
+    }
+
+    #[must_use]
+    pub fn get() -> Self {
+        FORMAT.get().copied().unwrap_or_default()
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::Config(format!("Expected text or json: {s}"))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Message<'a> {
+    Error {
+        message: String,
+    },
+    Log {
+        level: &'a str,
+        target: &'a str,
+        message: String,
+    },
+    Merged {
+        tiles: usize,
+        maps: usize,
+        sources: usize,
+        tiles_pruned: usize,
+        maps_pruned: usize,
+        seconds: f32,
+    },
+    Rendered {
+        tiles: usize,
+        maps: usize,
+        tiles_pruned: usize,
+        maps_pruned: usize,
+        seconds: f32,
+    },
+    Searched {
+        maps: usize,
+        block_regions: usize,
+        entity_regions: usize,
+        players: usize,
+        seconds: f32,
+    },
+    UpToDate,
+}
+
+impl Message<'_> {
+    pub fn emit(&self) {
+        match Format::get() {
+            Format::Text => println!("{self}"),
+            Format::Json => println!("{}", serde_json::to_string(self).unwrap()),
+        }
+    }
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error { message } => write!(f, "Error: {message}"),
+            Self::Log {
+                level,
+                target,
+                message,
+            } => write!(f, "[{level} {target}] {message}"),
+            Self::Merged {
+                tiles,
+                maps,
+                sources,
+                tiles_pruned,
+                maps_pruned,
+                seconds,
+            } => write!(
+                f,
+                "Merged {tiles} tiles and {maps} maps from {sources} sources and pruned {tiles_pruned} tiles and {maps_pruned} maps in {seconds:.2}s"
+            ),
+            Self::Rendered {
+                tiles,
+                maps,
+                tiles_pruned,
+                maps_pruned,
+                seconds,
+            } => write!(
+                f,
+                "Rendered {tiles} tiles and {maps} maps and pruned {tiles_pruned} tiles and {maps_pruned} maps in {seconds:.2}s"
+            ),
+            Self::Searched {
+                maps,
+                block_regions,
+                entity_regions,
+                players,
+                seconds,
+            } => write!(
+                f,
+                "Found {maps} map items across {block_regions} block regions, {entity_regions} entity regions, and {players} players in {seconds:.2}s"
+            ),
+            Self::UpToDate => write!(f, "Already up-to-date"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let message = Message::Rendered {
+            tiles: 2,
+            maps: 1,
+            tiles_pruned: 0,
+            maps_pruned: 0,
+            seconds: 0.5,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "event": "rendered",
+                "tiles": 2,
+                "maps": 1,
+                "tiles_pruned": 0,
+                "maps_pruned": 0,
+                "seconds": 0.5,
+            })
+        );
+        assert_eq!(
+            message.to_string(),
+            "Rendered 2 tiles and 1 maps and pruned 0 tiles and 0 maps in 0.50s"
+        );
+        assert_eq!(
+            serde_json::to_string(&Message::UpToDate).unwrap(),
+            r#"{"event":"up_to_date"}"#
+        );
+    }
+}
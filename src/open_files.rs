@@ -0,0 +1,186 @@
+//! Reads of the files of a world, bounded in how many are open at once and retried on transient
+//! errors, e.g. of worlds of very many regions on NFS
+
+use crate::Error;
+use flate2::read::GzDecoder;
+use log::debug;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Doubled after each failure, so that the last retry is some seconds after the first read
+const BACKOFF: Duration = Duration::from_millis(100);
+const RETRIES: u32 = 5;
+
+/// Limit on the files open at once, shared by the workers of a run
+pub struct OpenFiles {
+    max: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl OpenFiles {
+    /// By default a quarter of the limit of the process, leaving the rest to outputs and to the
+    /// threads of the pool
+    #[must_use]
+    pub fn new(max: Option<usize>) -> Self {
+        let max = max.unwrap_or_else(|| (descriptor_limit() / 4).clamp(16, 1024));
+        debug!("Open at most {max} files of the world at once");
+
+        Self {
+            max: max.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.read_with(path, |mut file, data| file.read_to_end(data))
+    }
+
+    pub fn read_gz(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.read_with(path, |file, data| GzDecoder::new(file).read_to_end(data))
+    }
+
+    fn read_with(
+        &self,
+        path: &Path,
+        read: impl Fn(File, &mut Vec<u8>) -> io::Result<usize>,
+    ) -> Result<Vec<u8>, Error> {
+        retry(path, || {
+            let _permit = self.permit();
+            let mut data = Vec::new();
+            read(File::open(path)?, &mut data)?;
+            Ok(data)
+        })
+        .map_err(|e| Error::io(path, e))
+    }
+
+    fn permit(&self) -> Permit<'_> {
+        *self
+            .closed
+            .wait_while(self.open.lock().unwrap(), |open| *open >= self.max)
+            .unwrap() += 1;
+
+        Permit(self)
+    }
+}
+
+struct Permit<'a>(&'a OpenFiles);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.closed.notify_one();
+    }
+}
+
+fn retry<T>(path: &Path, read: impl Fn() -> io::Result<T>) -> io::Result<T> {
+    let mut backoff = BACKOFF;
+    for _ in 0..RETRIES {
+        match read() {
+            Err(e) if is_transient(&e) => {
+                debug!("Retry {} in {backoff:?}: {e}", path.display());
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    read()
+}
+
+// Errors that may pass by themselves, such as timeouts and stale handles of network filesystems,
+// which report some of these as EIO, or descriptors exhausted by other processes
+fn is_transient(error: &io::Error) -> bool {
+    use io::ErrorKind::{Interrupted, TimedOut, WouldBlock};
+
+    if matches!(error.kind(), Interrupted | TimedOut | WouldBlock) {
+        return true;
+    }
+
+    #[cfg(unix)]
+    if let Some(code) = error.raw_os_error() {
+        return [
+            libc::EAGAIN,
+            libc::EBUSY,
+            libc::EIO,
+            libc::EMFILE,
+            libc::ENFILE,
+            libc::ESTALE,
+        ]
+        .contains(&code);
+    }
+
+    false
+}
+
+#[cfg(unix)]
+fn descriptor_limit() -> usize {
+    let mut limit = std::mem::MaybeUninit::<libc::rlimit>::uninit();
+
+    // SAFETY: getrlimit fully initializes the struct on success.
+    let limit = unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, limit.as_mut_ptr()) != 0 {
+            return 1024;
+        }
+        limit.assume_init()
+    };
+
+    usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX)
+}
+
+#[cfg(not(unix))]
+const fn descriptor_limit() -> usize {
+    2048
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn bounded() {
+        let files = OpenFiles::new(Some(2));
+        let (open, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permit = files.permit();
+                    most.fetch_max(open.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        assert_eq!(most.into_inner(), 2);
+        assert_eq!(*files.open.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn retried() {
+        let attempts = AtomicUsize::new(0);
+        let read = || match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => Err(io::Error::from(io::ErrorKind::TimedOut)),
+            _ => Ok("read"),
+        };
+        assert_eq!(retry(Path::new("r.0.0.mca"), read).unwrap(), "read");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Errors that won't pass by themselves fail at once
+        let attempts = AtomicUsize::new(0);
+        let read = || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+        };
+        assert!(retry(Path::new("r.0.0.mca"), read).is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,4 +1,8 @@
+use crate::config::{Colors, Shading};
+use itertools::Itertools;
 use once_cell::sync::Lazy;
+use std::array;
+use std::collections::HashMap;
 
 const BACKGROUND: [u8; 3] = [211, 188, 148];
 pub const BASE: [[u8; 3]; 62] = [
@@ -69,23 +73,87 @@ const FACTORS: [u8; 4] = [180, 220, 255, 135];
 
 pub const PALETTE_LEN: usize = BASE.len() * FACTORS.len();
 
-pub static PALETTE: Lazy<[u8; PALETTE_LEN * 3]> = Lazy::new(|| {
-    let mut palette: [u8; PALETTE_LEN * 3] = BASE
+pub type Palette = [u8; PALETTE_LEN * 3];
+
+/// Palette of the game
+pub static PALETTE: Lazy<Palette> = Lazy::new(|| derive(Colors::default()));
+
+static PALETTES: Lazy<HashMap<Colors, Palette>> = Lazy::new(|| {
+    [Shading::Relief, Shading::Flat]
+        .into_iter()
+        .cartesian_product([false, true])
+        .map(|(shading, linear)| {
+            let colors = Colors { linear, shading };
+            (colors, derive(colors))
+        })
+        .collect()
+});
+
+/// Palette derived with some choice of colors
+#[must_use]
+pub fn get(colors: Colors) -> &'static Palette {
+    &PALETTES[&colors]
+}
+
+fn derive(colors: Colors) -> Palette {
+    let factors = match colors.shading {
+        Shading::Relief => FACTORS,
+        Shading::Flat => [FACTORS[1]; 4],
+    };
+    let shade = |v: u8, f: u8| {
+        if colors.linear {
+            from_linear(to_linear(v) * f64::from(f) / 255.0)
+        } else {
+            #[allow(clippy::cast_possible_truncation)] // 255×255 < 2^16
+            let shaded = (u16::from(v) * u16::from(f) / 255) as u8;
+            shaded
+        }
+    };
+
+    let mut palette: Palette = BASE
         .iter()
         .flat_map(|rgb| {
-            FACTORS.iter().flat_map(move |&f| {
-                rgb.iter().map(
-                    #[allow(clippy::cast_possible_truncation)] // 255×255 < 2^16
-                    move |&v| (u16::from(v) * u16::from(f) / 255) as u8,
-                )
-            })
+            factors
+                .iter()
+                .flat_map(move |&f| rgb.iter().map(move |&v| shade(v, f)))
         })
         .collect::<Vec<_>>()
         .try_into()
         .unwrap();
     palette[0..3].copy_from_slice(&BACKGROUND);
     palette
-});
+}
+
+fn to_linear(v: u8) -> f64 {
+    let c = f64::from(v) / 255.0;
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // 0.0..=255.0
+fn from_linear(l: f64) -> u8 {
+    let c = if l <= 0.003_130_8 {
+        l * 12.92
+    } else {
+        1.055f64.mul_add(l.powf(1.0 / 2.4), -0.055)
+    };
+    (c * 255.0).round() as u8
+}
+
+/// RGB of each pixel of an indexed map image
+#[must_use]
+pub fn rgb(indexed: &[u8; 128 * 128]) -> [u8; 128 * 128 * 3] {
+    rgb_in(&PALETTE, indexed)
+}
+
+/// RGB of each pixel of an indexed map image in some palette
+#[must_use]
+pub fn rgb_in(palette: &Palette, indexed: &[u8; 128 * 128]) -> [u8; 128 * 128 * 3] {
+    array::from_fn(|i| palette[indexed[i / 3] as usize * 3 + i % 3])
+}
 
 #[cfg(test)]
 mod test {
@@ -104,4 +172,39 @@ mod test {
         assert_eq!(PALETTE[102..105], [255, 255, 255]);
         assert_eq!(PALETTE[105..108], [135, 135, 135]);
     }
+
+    #[test]
+    fn variants() {
+        let flat = get(Colors {
+            linear: false,
+            shading: Shading::Flat,
+        });
+        let linear = get(Colors {
+            linear: true,
+            shading: Shading::Relief,
+        });
+
+        assert_eq!(*get(Colors::default()), *PALETTE);
+        assert_eq!(flat[0..3], BACKGROUND);
+        assert_eq!(flat[12..24], [109, 153, 48].repeat(4)[..]);
+        assert_eq!(linear[0..3], BACKGROUND);
+        assert_eq!(linear[18..21], [127, 178, 56]);
+        assert!(linear[12..15] > PALETTE[12..15]);
+        assert!(linear[21..24] > PALETTE[21..24]);
+        for v in 0..=255 {
+            assert_eq!(from_linear(to_linear(v)), v);
+        }
+    }
+
+    #[test]
+    fn conversion() {
+        let mut indexed = [0; 128 * 128];
+        indexed[1] = 6;
+        indexed[128 * 128 - 1] = 34;
+
+        let rgb = rgb(&indexed);
+        assert_eq!(rgb[0..3], BACKGROUND);
+        assert_eq!(rgb[3..6], [127, 178, 56]);
+        assert_eq!(rgb[128 * 128 * 3 - 3..], [255, 255, 255]);
+    }
 }
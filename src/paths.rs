@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+use glob::{glob, Paths, Pattern};
+use itertools::Itertools;
+use std::env;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// Paths are handled by component rather than as text so that they hold on Windows, where the
+// separator is a backslash and a world or output may be on a UNC share such as \\host\share.
+
+/// Files matching a pattern of `/`-separated components beneath a base path, whose own
+/// characters are matched literally, e.g. a dir named `[backup]`
+pub fn glob_in(base: &Path, pattern: &str) -> Result<Paths> {
+    let text = |path: &Path| -> Result<String> {
+        path.to_str()
+            .map(ToOwned::to_owned)
+            .ok_or_else(|| anyhow!("Path is not valid Unicode: {}", path.display()))
+    };
+
+    let mut escaped = PathBuf::new();
+    for component in base.components() {
+        match component {
+            Component::Normal(name) => escaped.push(Pattern::escape(&text(Path::new(name))?)),
+            other => escaped.push(other),
+        }
+    }
+    escaped.push(pattern);
+
+    Ok(glob(&text(&escaped)?)?)
+}
+
+/// Relative path with `/` as the separator on any platform, e.g. as listed in a manifest
+pub fn slashed(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .join("/")
+}
+
+/// Zoom and position of a tile by its path relative to the output, e.g. `tiles/4/-3/7.webp`
+pub fn tile_key(relative: &Path) -> Result<(u8, i32, i32)> {
+    let parse = || -> Option<(u8, i32, i32)> {
+        let parts = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?;
+        let [_, zoom, x, file_name] = parts[..] else {
+            return None;
+        };
+        let y = file_name.split('.').next()?;
+
+        Some((zoom.parse().ok()?, x.parse().ok()?, y.parse().ok()?))
+    };
+
+    parse().ok_or_else(|| anyhow!("Unexpected tile path: {}", relative.display()))
+}
+
+/// Whether a path is or would be within a dir once symlinks are resolved, e.g. an output yet to be
+/// created beneath a link into a world
+pub fn is_within(path: &Path, dir: &Path) -> io::Result<bool> {
+    let resolve = |path: &Path| -> io::Result<PathBuf> {
+        let absolute = env::current_dir()?.join(path);
+        let existing = absolute
+            .ancestors()
+            .find(|a| a.exists())
+            .unwrap_or(&absolute);
+
+        Ok(existing
+            .canonicalize()?
+            .join(absolute.strip_prefix(existing).unwrap()))
+    };
+
+    Ok(resolve(path)?.starts_with(resolve(dir)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            tile_key(Path::new("tiles/4/-3/7.meta.json")).unwrap(),
+            (4, -3, 7)
+        );
+        assert!(tile_key(Path::new("tiles/4/-3")).is_err());
+        assert!(tile_key(Path::new("tiles/4/x/7.webp")).is_err());
+        assert_eq!(slashed(&Path::new("tiles/4").join("-3")), "tiles/4/-3");
+    }
+
+    #[test]
+    fn literal_base() {
+        let dir = tempfile::Builder::new()
+            .prefix("[")
+            .suffix("]")
+            .tempdir_in(env!("TEST_OUTPUT_PATH"))
+            .unwrap();
+        let base = dir.path();
+        fs::create_dir_all(base.join("maps")).unwrap();
+        fs::write(base.join("maps/1.webp"), "").unwrap();
+
+        let found = glob_in(base, "maps/*.webp")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(found, [base.join("maps/1.webp")]);
+    }
+
+    #[test]
+    fn within() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let base = dir.path();
+        fs::create_dir_all(base.join("world")).unwrap();
+        fs::create_dir_all(base.join("output")).unwrap();
+
+        assert!(is_within(&base.join("world/output"), &base.join("world")).unwrap());
+        assert!(is_within(&base.join("world"), &base.join("world")).unwrap());
+        assert!(!is_within(&base.join("output"), &base.join("world")).unwrap());
+        assert!(!is_within(&base.join("world-output"), &base.join("world")).unwrap());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("world"), base.join("link")).unwrap();
+            assert!(is_within(&base.join("link/output/tiles"), &base.join("world")).unwrap());
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows() {
+        assert_eq!(
+            tile_key(Path::new(r"tiles\4\-3\7.webp")).unwrap(),
+            (4, -3, 7)
+        );
+        assert_eq!(
+            slashed(Path::new(r"tiles\4\-3\7.webp")),
+            "tiles/4/-3/7.webp"
+        );
+
+        // Nonexistent shares match nothing rather than failing to parse as patterns
+        let share = Path::new(r"\\little-a-map.invalid\share\[output]");
+        assert_eq!(glob_in(share, "tiles/*/*/*.webp").unwrap().count(), 0);
+    }
+}
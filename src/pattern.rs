@@ -0,0 +1,406 @@
+//! Paths of the tiles within `tiles/` and `freshness/`, by a pattern such as `{z}/{x}/{y}` to suit
+//! the web server or CDN hosting the output
+
+use crate::geometry::Origin;
+use crate::layout;
+use crate::paths::glob_in;
+use crate::Error;
+use log::{debug, info};
+use serde::de::{self, Deserialize, Deserializer};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const DIRS: [&str; 2] = ["tiles", "freshness"];
+
+/// Zoom and position of a tile as numbered in the output
+type TileKey = (u8, i32, i32);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Placeholder {
+    Zoom,
+    X,
+    Y,
+    Hash,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Path of a tile relative to `tiles/` without its extension
+///
+/// `{z}`, `{x}`, and `{y}` are replaced by the zoom and position of the tile as numbered in the
+/// output and `{hash}` by two hex digits of a hash of those, e.g. `{hash}/{z}_{x}_{y}` to spread
+/// tiles across 256 dirs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TilePattern {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl TilePattern {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Path of a tile by its zoom and position as numbered in the output
+    #[must_use]
+    pub fn format(&self, key: TileKey) -> String {
+        let (zoom, x, y) = key;
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Placeholder(Placeholder::Zoom) => zoom.to_string(),
+                Part::Placeholder(Placeholder::X) => x.to_string(),
+                Part::Placeholder(Placeholder::Y) => y.to_string(),
+                Part::Placeholder(Placeholder::Hash) => format!("{:02x}", hash(key)),
+            })
+            .collect()
+    }
+
+    /// Zoom and position of a tile by its path, if it's one this pattern would format
+    #[must_use]
+    pub fn parse(&self, path: &str) -> Option<TileKey> {
+        let (mut zoom, mut x, mut y) = (None, None, None);
+        let mut rest = path;
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+                Part::Placeholder(Placeholder::Hash) => rest = rest.get(2..)?,
+                Part::Placeholder(placeholder) => {
+                    let end = rest
+                        .char_indices()
+                        .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+                        .map_or(rest.len(), |(i, _)| i);
+                    let digits = &rest[..end];
+                    rest = &rest[end..];
+                    match placeholder {
+                        Placeholder::Zoom => zoom = Some(digits.parse().ok()?),
+                        Placeholder::X => x = Some(digits.parse().ok()?),
+                        _ => y = Some(digits.parse().ok()?),
+                    }
+                }
+            }
+        }
+
+        // Such as by other leading zeros or another hash
+        let key = (zoom?, x?, y?);
+        (rest.is_empty() && self.format(key) == path).then_some(key)
+    }
+
+    /// Glob of the paths of tiles, e.g. `*/*/*`
+    #[must_use]
+    pub fn glob(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal,
+                Part::Placeholder(_) => "*",
+            })
+            .collect()
+    }
+}
+
+impl Default for TilePattern {
+    fn default() -> Self {
+        "{z}/{x}/{y}".parse().unwrap()
+    }
+}
+
+impl fmt::Display for TilePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+// Restricted so that a path parses back unambiguously and is safe in URLs and globs
+impl FromStr for TilePattern {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| Err(Error::Config(format!("{reason}: {source:?}")));
+
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('{') {
+                let Some((name, after)) = after.split_once('}') else {
+                    return invalid("Unclosed placeholder in tile pattern");
+                };
+                let placeholder = match name {
+                    "z" => Placeholder::Zoom,
+                    "x" => Placeholder::X,
+                    "y" => Placeholder::Y,
+                    "hash" => Placeholder::Hash,
+                    _ => return invalid("Expected {z}, {x}, {y}, or {hash} in tile pattern"),
+                };
+                if parts.contains(&Part::Placeholder(placeholder)) {
+                    return invalid("Repeated placeholder in tile pattern");
+                }
+                if matches!(parts.last(), Some(Part::Placeholder(_))) {
+                    return invalid("Adjacent placeholders in tile pattern");
+                }
+                parts.push(Part::Placeholder(placeholder));
+                rest = after;
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                let literal = &rest[..end];
+                if !literal
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+                {
+                    return invalid("Expected only letters, digits, and _-./ in tile pattern");
+                }
+                if literal.starts_with(|c: char| c.is_ascii_digit())
+                    && matches!(
+                        parts.last(),
+                        Some(Part::Placeholder(
+                            Placeholder::Zoom | Placeholder::X | Placeholder::Y
+                        ))
+                    )
+                {
+                    return invalid("Digits following a number in tile pattern");
+                }
+                parts.push(Part::Literal(literal.to_owned()));
+                rest = &rest[end..];
+            }
+        }
+
+        for placeholder in [Placeholder::Zoom, Placeholder::X, Placeholder::Y] {
+            if !parts.contains(&Part::Placeholder(placeholder)) {
+                return invalid("Expected each of {z}, {x}, and {y} in tile pattern");
+            }
+        }
+        if source
+            .split('/')
+            .any(|component| ["", ".", ".."].contains(&component))
+        {
+            return invalid("Empty or relative component in tile pattern");
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            parts,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TilePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+// FNV-1a of the key as in `tiles/{z}/{x}/{y}`, which the viewer computes alike
+fn hash((zoom, x, y): TileKey) -> u8 {
+    format!("{zoom}/{x}/{y}")
+        .bytes()
+        .fold(0x811c_9dc5_u32, |h, b| {
+            (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+        })
+        .to_le_bytes()[0]
+}
+
+/// Pattern by which the tiles of an output are laid out
+pub fn recorded(output_path: &Path) -> Result<TilePattern, Error> {
+    match layout::recorded(output_path, layout::TILE_PATTERN)? {
+        Some(text) => Ok(text.parse()?),
+        None => Ok(TilePattern::default()),
+    }
+}
+
+/// Whether the tiles of an output are laid out by another pattern than before, in which case they're
+/// removed for the output to be rendered again in full
+pub fn switch(output_path: &Path, pattern: &TilePattern) -> Result<bool, Error> {
+    Ok(layout::switch(
+        output_path,
+        layout::TILE_PATTERN,
+        &pattern.to_string(),
+        &TilePattern::default().to_string(),
+        |previous| {
+            info!("Switch tile pattern from {previous} to {pattern}");
+            remove_tiles(output_path)
+        },
+    )?)
+}
+
+/// Origin from which the tiles of an output are numbered
+pub fn recorded_origin(output_path: &Path) -> Result<Origin, Error> {
+    match layout::recorded(output_path, layout::TILE_ORIGIN)? {
+        Some(text) => parse_origin(&text)
+            .ok_or_else(|| Error::Config(format!("Invalid tile origin: {text:?}"))),
+        None => Ok(Origin::default()),
+    }
+}
+
+/// Whether the tiles of an output are numbered from another origin than before, in which case
+/// they're removed since a tile of the same number would otherwise be taken as up to date
+pub fn switch_origin(output_path: &Path, origin: Origin) -> Result<bool, Error> {
+    Ok(layout::switch(
+        output_path,
+        layout::TILE_ORIGIN,
+        &format_origin(origin),
+        &format_origin(Origin::default()),
+        |previous| {
+            info!(
+                "Switch tile origin from {previous} to {}",
+                format_origin(origin)
+            );
+            remove_tiles(output_path)
+        },
+    )?)
+}
+
+fn format_origin(Origin { x, y }: Origin) -> String {
+    format!("{x},{y}")
+}
+
+fn parse_origin(text: &str) -> Option<Origin> {
+    let (x, y) = text.split_once(',')?;
+
+    Some(Origin {
+        x: x.parse().ok()?,
+        y: y.parse().ok()?,
+    })
+}
+
+fn remove_tiles(output_path: &Path) -> anyhow::Result<()> {
+    for dir in DIRS {
+        let path = output_path.join(dir);
+        if path.is_dir() {
+            debug!("Prune: {}", path.display());
+            fs::remove_dir_all(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Tiles of an output laid out by a pattern, by zoom and position as numbered in the output, with
+/// their paths relative to a dir such as `tiles`
+pub fn tiles_in(
+    dir_path: &Path,
+    pattern: &TilePattern,
+    extension: &str,
+) -> Result<Vec<(TileKey, String)>, Error> {
+    let suffix = format!(".{extension}");
+    glob_in(dir_path, &format!("{}{suffix}", pattern.glob()))?
+        .filter_map(|entry| {
+            let path = match entry {
+                Ok(path) => path,
+                Err(e) => return Some(Err(e.into_error().into())),
+            };
+            let relative = crate::paths::slashed(path.strip_prefix(dir_path).ok()?);
+            let stem = relative.strip_suffix(&suffix)?;
+
+            Some(Ok((pattern.parse(stem)?, stem.to_owned())))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let patterns = [
+            "{z}/{x}/{y}",
+            "{z}/{x}_{y}",
+            "{hash}/{z}-{x}-{y}",
+            "z{z}/x{x}.y{y}",
+        ];
+        for source in patterns {
+            let pattern = source.parse::<TilePattern>().unwrap();
+            for key in [(4, -3, 7), (0, 0, -1), (2, -10, -100)] {
+                let path = pattern.format(key);
+                assert_eq!(pattern.parse(&path), Some(key), "{source}: {path}");
+            }
+        }
+
+        let default = TilePattern::default();
+        assert_eq!(default.format((4, -3, 7)), "4/-3/7");
+        assert_eq!(default.glob(), "*/*/*");
+        assert_eq!(default.parse("4/-3/7.5"), None);
+        assert_eq!(default.parse("4/-03/7"), None);
+        assert_eq!(default.parse("4/x/7"), None);
+
+        let hashed = "{hash}/{z}_{x}_{y}".parse::<TilePattern>().unwrap();
+        assert_eq!(hashed.glob(), "*/*_*_*");
+        assert_eq!(hashed.format((4, 0, 0)), "5b/4_0_0");
+        assert_eq!(hashed.parse("00/4_0_0"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        for source in [
+            "",
+            "{z}/{x}",
+            "{z}/{x}/{y}/{x}",
+            "{z}/{x}{y}",
+            "{z}/{x}/{y",
+            "{z}/{x}/{w}",
+            "{z}/{x}0{y}",
+            "/{z}/{x}/{y}",
+            "{z}//{x}/{y}",
+            "../{z}/{x}/{y}",
+            "{z}/{x}/{y}?",
+            "{z}/{x}/*{y}",
+        ] {
+            assert!(source.parse::<TilePattern>().is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn switch_pattern() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = dir.path();
+        fs::create_dir_all(output.join("tiles/4/0")).unwrap();
+        fs::write(output.join("tiles/4/0/0.webp"), "").unwrap();
+        let flat = "{z}_{x}_{y}".parse::<TilePattern>().unwrap();
+
+        assert!(!switch(output, &TilePattern::default()).unwrap());
+        assert_eq!(
+            tiles_in(&output.join("tiles"), &TilePattern::default(), "webp").unwrap(),
+            [((4, 0, 0), "4/0/0".to_owned())]
+        );
+        assert!(tiles_in(&output.join("tiles"), &flat, "webp")
+            .unwrap()
+            .is_empty());
+
+        assert!(switch(output, &flat).unwrap());
+        assert!(!switch(output, &flat).unwrap());
+        assert!(!output.join("tiles").exists());
+
+        assert!(switch(output, &TilePattern::default()).unwrap());
+        assert_eq!(recorded(output).unwrap(), TilePattern::default());
+    }
+
+    #[test]
+    fn switch_tile_origin() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = dir.path();
+        fs::create_dir_all(output.join("tiles/4/0")).unwrap();
+        fs::write(output.join("tiles/4/0/0.webp"), "").unwrap();
+        let far = Origin::at((20000, -8000));
+
+        assert!(!switch_origin(output, Origin::default()).unwrap());
+        assert!(output.join("tiles").exists());
+
+        assert!(switch_origin(output, far).unwrap());
+        assert!(!switch_origin(output, far).unwrap());
+        assert!(!output.join("tiles").exists());
+        assert_eq!(recorded_origin(output).unwrap(), far);
+
+        assert!(switch_origin(output, Origin::default()).unwrap());
+        assert_eq!(recorded_origin(output).unwrap(), Origin::default());
+    }
+}
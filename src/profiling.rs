@@ -0,0 +1,54 @@
+//! Chrome traces of the phases of runs, by which e.g. a regression of decompression can be
+//! localized in Perfetto or `chrome://tracing` without other tooling. Only with the `profiling`
+//! feature.
+
+use crate::Error;
+use log::debug;
+use once_cell::sync::OnceCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::layer::SubscriberExt;
+
+// Spans are recorded by one subscriber for the whole process, since those of the workers of the
+// pool are entered on threads of their own
+static GUARD: OnceCell<Mutex<FlushGuard>> = OnceCell::new();
+
+/// Record the spans of `run` to a trace of its own in `dir`. Runs traced at once are traced one
+/// after another.
+pub fn trace<T>(dir: &Path, run: impl FnOnce() -> T) -> Result<T, Error> {
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!("run-{millis}.json"));
+    fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    let file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+
+    let guard = GUARD.get_or_init(|| {
+        let (layer, guard) = ChromeLayerBuilder::new()
+            .writer(io::sink())
+            .include_args(true)
+            .build();
+        if let Err(e) =
+            tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        {
+            debug!("Not tracing, since another subscriber is set: {e}");
+        }
+
+        Mutex::new(guard)
+    });
+    let guard = guard.lock().unwrap();
+
+    guard.start_new(Some(Box::new(BufWriter::new(file))));
+    let result = run();
+    // The trace is completed by starting another that goes nowhere
+    guard.start_new(Some(Box::new(io::sink())));
+    drop(guard);
+    debug!("Traced the run to {}", path.display());
+
+    Ok(result)
+}
@@ -0,0 +1,161 @@
+//! Generations of an output, each written beside the last and published by switching a symlink so
+//! that a web server never serves a partial run and a bad run can be rolled back
+
+use crate::paths::glob_in;
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::fs::{self, File};
+use std::io::ErrorKind::NotFound;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Symlink within the output to the release being served
+pub const CURRENT: &str = "current";
+
+const DIR: &str = "releases";
+
+// Images are replaced rather than overwritten, so they're shared with the previous release by
+// hard links. Everything else is copied since it may be overwritten in place.
+const LINKED: [&str; 3] = ["avif", "png", "webp"];
+
+/// Release currently published, if any
+pub fn current(output_path: &Path) -> Result<Option<PathBuf>> {
+    match fs::read_link(output_path.join(CURRENT)) {
+        Ok(target) => Ok(Some(output_path.join(target))),
+        Err(e) if e.kind() == NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// New release named by the time, seeded from the current release so that rendering it is as
+/// incremental as rendering in place
+pub fn prepare(output_path: &Path, now: SystemTime) -> Result<PathBuf> {
+    let name = humantime::format_rfc3339_millis(now)
+        .to_string()
+        .replace(['-', ':'], "");
+    let release_path = output_path.join(DIR).join(name);
+    if release_path.exists() {
+        return Err(anyhow!("Release exists: {}", release_path.display()));
+    }
+    fs::create_dir_all(&release_path)?;
+
+    if let Some(current_path) = current(output_path)? {
+        let mut seeded = 0;
+        for entry in glob_in(&current_path, "**/*")? {
+            let path = entry?;
+            if !path.is_file() {
+                continue;
+            }
+            let seed_path = release_path.join(path.strip_prefix(&current_path)?);
+            fs::create_dir_all(seed_path.parent().unwrap())?;
+
+            let is_linked = path
+                .extension()
+                .is_some_and(|e| LINKED.iter().any(|l| e == *l))
+                || path.starts_with(current_path.join(".blobs"));
+            if is_linked {
+                fs::hard_link(&path, &seed_path)?;
+            } else {
+                fs::copy(&path, &seed_path)?;
+                File::options()
+                    .write(true)
+                    .open(&seed_path)?
+                    .set_modified(fs::metadata(&path)?.modified()?)?;
+            }
+            seeded += 1;
+        }
+        debug!("Seeded {seeded} files from {}", current_path.display());
+    }
+
+    Ok(release_path)
+}
+
+/// Point the current release at another atomically, then prune all but the latest releases
+#[cfg(unix)]
+pub fn publish(output_path: &Path, release_path: &Path, keep: usize) -> Result<usize> {
+    let target = release_path.strip_prefix(output_path)?;
+    let pending_path = output_path.join(format!(".{CURRENT}"));
+    match fs::remove_file(&pending_path) {
+        Err(e) if e.kind() != NotFound => return Err(e.into()),
+        _ => {}
+    }
+    std::os::unix::fs::symlink(target, &pending_path)?;
+    fs::rename(&pending_path, output_path.join(CURRENT))?;
+
+    prune(output_path, release_path, keep)
+}
+
+#[cfg(not(unix))]
+pub fn publish(_output_path: &Path, _release_path: &Path, _keep: usize) -> Result<usize> {
+    Err(crate::Error::Config("Releases require symlinks as on Unix".to_owned()).into())
+}
+
+fn prune(output_path: &Path, current_path: &Path, keep: usize) -> Result<usize> {
+    let mut releases = fs::read_dir(output_path.join(DIR))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    releases.sort();
+
+    let mut pruned = 0;
+    let expired = releases.len().saturating_sub(keep.max(1));
+    for path in releases.drain(..expired) {
+        if path != current_path {
+            debug!("Prune: {}", path.display());
+            fs::remove_dir_all(path)?;
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use std::time::Duration;
+
+    #[test]
+    fn releases() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = dir.path();
+        let at = |s| SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+        assert_eq!(current(output).unwrap(), None);
+
+        let first = prepare(output, at(1)).unwrap();
+        assert!(first.ends_with("releases/19700101T000001.000Z"));
+        fs::create_dir_all(first.join("tiles/4/0")).unwrap();
+        fs::write(first.join("tiles/4/0/0.webp"), "tile").unwrap();
+        fs::write(first.join("index.html"), "index").unwrap();
+        assert_eq!(publish(output, &first, 2).unwrap(), 0);
+        assert_eq!(current(output).unwrap(), Some(first.clone()));
+        assert_eq!(
+            fs::read_to_string(output.join("current/index.html")).unwrap(),
+            "index"
+        );
+
+        // Seeded by linking images and copying the rest
+        let second = prepare(output, at(2)).unwrap();
+        assert_eq!(
+            fs::metadata(first.join("tiles/4/0/0.webp"))
+                .unwrap()
+                .nlink(),
+            2
+        );
+        assert_eq!(fs::metadata(first.join("index.html")).unwrap().nlink(), 1);
+        fs::write(second.join("index.html"), "changed").unwrap();
+        assert_eq!(
+            fs::read_to_string(first.join("index.html")).unwrap(),
+            "index"
+        );
+        assert_eq!(publish(output, &second, 2).unwrap(), 0);
+
+        let third = prepare(output, at(3)).unwrap();
+        assert_eq!(publish(output, &third, 2).unwrap(), 1);
+        assert!(!first.exists());
+        assert_eq!(
+            fs::read_to_string(third.join("index.html")).unwrap(),
+            "changed"
+        );
+    }
+}
@@ -1,98 +1,162 @@
 #![allow(clippy::module_name_repetitions)]
 
-use crate::cache::{Cache, IdsBy};
-use crate::utilities::{progress_bar, read_gz};
+use crate::anvil;
+use crate::banner::{Labels, COLORS};
+use crate::cache::{
+    Cache, CacheMiss, IdsBy, MapIds, QuarantinedFile, Region, RegionSummary, SummaryBy,
+};
+use crate::config::PlayerSelection;
+use crate::dimension::Dimension;
+use crate::open_files::OpenFiles;
+use crate::paths::glob_in;
+use crate::stats::Copies;
+use crate::utilities::{progress_bar, read_modified, retry_once};
+use crate::warning::{WarningCollector, WarningKind};
+use crate::Error;
 use anyhow::{Context, Result};
-use fastnbt::from_bytes;
-use glob::glob;
-use indicatif::ParallelProgressIterator;
+use fastnbt::{from_bytes, from_value, Value};
+use indicatif::{ParallelProgressIterator, ProgressBar};
 use itertools::Itertools;
 use log::{debug, log_enabled, Level::Debug};
 use rayon::prelude::*;
-use serde::{de::DeserializeOwned, de::IgnoredAny, Deserialize, Deserializer};
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, File};
-use std::iter;
-use std::path::Path;
-use std::string::ToString;
+use serde::de::{self, DeserializeOwned, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 pub type Bounds = ((i32, i32), (i32, i32));
 
-trait ContainsMapIds {
-    fn map_ids(self) -> HashSet<u32>;
+// Items and entities nest within each other, e.g. a map in a shulker box in a bundle in a chest, as
+// deeply as NBT allows. Survival play nests them only a few levels deep while commands may nest them
+// far deeper, so any nested beyond a limit are skipped rather than exhausting the stack of a worker.
+const MAX_NESTING: usize = 64;
+
+thread_local! {
+    static NESTING: Cell<usize> = const { Cell::new(0) };
 }
 
-struct MapIdsOfBundle(HashSet<u32>);
-impl<'de> Deserialize<'de> for MapIdsOfBundle {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        #[derive(Deserialize)]
-        struct Internal {
-            #[serde(rename = "minecraft:bundle_contents")]
-            contents: Option<Vec<MapIdsOfItem>>,
-        }
+struct Nesting;
 
-        let internal = Internal::deserialize(deserializer)?;
-        Ok(Self(
-            internal
-                .contents
-                .into_iter()
-                .flatten()
-                .flat_map(|i| i.0)
-                .collect(),
-        ))
+impl Nesting {
+    fn enter() -> Option<Self> {
+        NESTING.with(|n| {
+            (n.get() < MAX_NESTING).then(|| {
+                n.set(n.get() + 1);
+                Self
+            })
+        })
     }
 }
 
-struct MapIdsOfContainer(HashSet<u32>);
-impl<'de> Deserialize<'de> for MapIdsOfContainer {
+impl Drop for Nesting {
+    fn drop(&mut self) {
+        NESTING.with(|n| n.set(n.get() - 1));
+    }
+}
+
+trait ContainsMapIds {
+    fn map_ids(self) -> MapIds;
+}
+
+struct MapIdsOfEntity(MapIds);
+impl<'de> Deserialize<'de> for MapIdsOfEntity {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        #[derive(Deserialize)]
-        struct Internal {
-            #[serde(rename = "minecraft:container")]
-            container: Option<Vec<Slot>>,
+        // Holders vary in naming, e.g. `Item` in item frames, `item` in decorated pots and display
+        // entities, and `Book` in lecterns, so any such field is scanned regardless of case.
+        enum Field {
+            Equipment,
+            Item,
+            Items,
+            Other,
+            Passengers,
         }
+        impl<'de> Deserialize<'de> for Field {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct FieldVisitor;
 
-        #[derive(Deserialize)]
-        struct Slot {
-            item: MapIdsOfItem,
+                impl Visitor<'_> for FieldVisitor {
+                    type Value = Field;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        formatter.write_str("field name")
+                    }
+
+                    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                        let is = |name: &str| value.eq_ignore_ascii_case(name);
+
+                        Ok(if is("item") || is("book") {
+                            Field::Item
+                        } else if is("items") || is("handitems") || is("armoritems") {
+                            Field::Items
+                        } else if is("equipment") {
+                            Field::Equipment
+                        } else if is("passengers") {
+                            Field::Passengers
+                        } else {
+                            Field::Other
+                        })
+                    }
+                }
+
+                deserializer.deserialize_identifier(FieldVisitor)
+            }
         }
 
-        let internal = Internal::deserialize(deserializer)?;
-        Ok(Self(
-            internal
-                .container
-                .into_iter()
-                .flatten()
-                .flat_map(|i| i.item.0)
-                .collect(),
-        ))
-    }
-}
+        struct EntityVisitor;
 
-struct MapIdsOfEntity(HashSet<u32>);
-impl<'de> Deserialize<'de> for MapIdsOfEntity {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        #[derive(Deserialize)]
-        #[serde(rename_all = "PascalCase")]
-        struct Internal {
-            item: Option<MapIdsOfItem>,
-            items: Option<Vec<MapIdsOfItem>>,
+        impl<'de> Visitor<'de> for EntityVisitor {
+            type Value = MapIdsOfEntity;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("entity")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut ids = MapIds::default();
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        // 1.21.5+
+                        Field::Equipment => ids.extend(
+                            map.next_value::<HashMap<String, MapIdsOfItem>>()?
+                                .into_values()
+                                .flat_map(|i| i.0),
+                        ),
+                        Field::Item => ids.extend(map.next_value::<MapIdsOfItem>()?.0),
+                        Field::Items => ids.extend(
+                            map.next_value::<Vec<MapIdsOfItem>>()?
+                                .into_iter()
+                                .flat_map(|i| i.0),
+                        ),
+                        Field::Other => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                        // e.g. a minecart with chest riding a boat
+                        Field::Passengers => ids.extend(
+                            map.next_value::<Vec<MapIdsOfEntity>>()?
+                                .into_iter()
+                                .flat_map(|e| e.0),
+                        ),
+                    }
+                }
+
+                Ok(MapIdsOfEntity(ids))
+            }
         }
 
-        let internal = Internal::deserialize(deserializer)?;
-        Ok(Self(
-            internal
-                .items
-                .into_iter()
-                .flatten()
-                .chain(internal.item)
-                .flat_map(|i| i.0)
-                .collect(),
-        ))
+        let Some(_nesting) = Nesting::enter() else {
+            IgnoredAny::deserialize(deserializer)?;
+            return Ok(Self(MapIds::default()));
+        };
+
+        deserializer.deserialize_map(EntityVisitor)
     }
 }
 
-struct MapIdsOfEntitiesChunk(HashSet<u32>);
+pub struct MapIdsOfEntitiesChunk(pub MapIds);
 impl<'de> Deserialize<'de> for MapIdsOfEntitiesChunk {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         #[derive(Deserialize)]
@@ -111,168 +175,205 @@ impl<'de> Deserialize<'de> for MapIdsOfEntitiesChunk {
     }
 }
 impl ContainsMapIds for MapIdsOfEntitiesChunk {
-    fn map_ids(self) -> HashSet<u32> {
+    fn map_ids(self) -> MapIds {
         self.0
     }
 }
 
-struct MapIdsOfItem(HashSet<u32>);
+struct MapIdsOfItem(MapIds);
 impl<'de> Deserialize<'de> for MapIdsOfItem {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Fields are visited in storage order, which doesn't reliably lead with the item ID, so
+        // candidate map IDs are collected alongside the ID and filtered once the item is complete.
         #[derive(Deserialize)]
-        #[serde(tag = "id")]
-        enum Internal {
-            #[serde(rename = "minecraft:bundle")]
-            #[serde(alias = "minecraft:black_bundle")]
-            #[serde(alias = "minecraft:blue_bundle")]
-            #[serde(alias = "minecraft:brown_bundle")]
-            #[serde(alias = "minecraft:cyan_bundle")]
-            #[serde(alias = "minecraft:gray_bundle")]
-            #[serde(alias = "minecraft:green_bundle")]
-            #[serde(alias = "minecraft:light_blue_bundle")]
-            #[serde(alias = "minecraft:light_gray_bundle")]
-            #[serde(alias = "minecraft:lime_bundle")]
-            #[serde(alias = "minecraft:magenta_bundle")]
-            #[serde(alias = "minecraft:orange_bundle")]
-            #[serde(alias = "minecraft:pink_bundle")]
-            #[serde(alias = "minecraft:purple_bundle")]
-            #[serde(alias = "minecraft:red_bundle")]
-            #[serde(alias = "minecraft:white_bundle")]
-            #[serde(alias = "minecraft:yellow_bundle")]
-            Bundle(Bundle),
-
-            #[serde(rename = "minecraft:shulker_box")]
-            #[serde(alias = "minecraft:black_shulker_box")]
-            #[serde(alias = "minecraft:blue_shulker_box")]
-            #[serde(alias = "minecraft:brown_shulker_box")]
-            #[serde(alias = "minecraft:cyan_shulker_box")]
-            #[serde(alias = "minecraft:gray_shulker_box")]
-            #[serde(alias = "minecraft:green_shulker_box")]
-            #[serde(alias = "minecraft:light_blue_shulker_box")]
-            #[serde(alias = "minecraft:light_gray_shulker_box")]
-            #[serde(alias = "minecraft:lime_shulker_box")]
-            #[serde(alias = "minecraft:magenta_shulker_box")]
-            #[serde(alias = "minecraft:orange_shulker_box")]
-            #[serde(alias = "minecraft:pink_shulker_box")]
-            #[serde(alias = "minecraft:purple_shulker_box")]
-            #[serde(alias = "minecraft:red_shulker_box")]
-            #[serde(alias = "minecraft:white_shulker_box")]
-            #[serde(alias = "minecraft:yellow_shulker_box")]
-            Container(Container),
-
-            #[serde(rename = "minecraft:filled_map")]
-            FilledMap(FilledMap),
-
+        #[serde(field_identifier)]
+        enum Field {
+            #[serde(rename = "id")]
+            Id,
+            #[serde(rename = "components")]
+            Components,
+            // 1.20.5+, or before as a byte
+            #[serde(rename = "count", alias = "Count")]
+            Count,
+            #[serde(rename = "tag")]
+            Tag,
             #[serde(other)]
             Other,
         }
 
-        #[derive(Deserialize)]
-        struct Bundle {
-            components: Option<MapIdsOfBundle>,
+        enum Kind {
+            Bundle,
+            FilledMap,
+            ShulkerBox,
+            Other,
         }
+        impl<'de> Deserialize<'de> for Kind {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct KindVisitor;
 
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum Container {
-            V1204(ContainerV1204),
-            V1205(ContainerV1205),
-        }
+                impl Visitor<'_> for KindVisitor {
+                    type Value = Kind;
 
-        #[derive(serde_query::Deserialize)]
-        struct ContainerV1204 {
-            #[query(".tag.BlockEntityTag")]
-            map_ids: MapIdsOfEntity,
-        }
+                    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        formatter.write_str("item ID")
+                    }
 
-        #[derive(Deserialize)]
-        struct ContainerV1205 {
-            components: Option<MapIdsOfContainer>,
+                    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                        let is_dyed = |suffix| {
+                            value
+                                .strip_prefix("minecraft:")
+                                .and_then(|v| v.strip_suffix(suffix))
+                                .map_or(false, |color| COLORS.contains(&color))
+                        };
+
+                        Ok(match value {
+                            "minecraft:filled_map" => Kind::FilledMap,
+                            "minecraft:bundle" => Kind::Bundle,
+                            "minecraft:shulker_box" => Kind::ShulkerBox,
+                            _ if is_dyed("_bundle") => Kind::Bundle,
+                            _ if is_dyed("_shulker_box") => Kind::ShulkerBox,
+                            _ => Kind::Other,
+                        })
+                    }
+                }
+
+                deserializer.deserialize_str(KindVisitor)
+            }
         }
 
         #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum FilledMap {
-            V1204(FilledMapV1204),
-            V1205(FilledMapV1205),
+        struct Components {
+            #[serde(rename = "minecraft:bundle_contents")]
+            bundle_contents: Option<Vec<MapIdsOfItem>>,
+            #[serde(rename = "minecraft:container")]
+            container: Option<Vec<Slot>>,
+            #[serde(rename = "minecraft:item_name")]
+            item_name: Option<IgnoredAny>,
+            #[serde(rename = "minecraft:map_id")]
+            map_id: Option<u32>,
         }
 
         #[derive(Deserialize)]
-        struct FilledMapV1204 {
-            tag: FilledMapV1204Tag,
+        struct Slot {
+            item: MapIdsOfItem,
         }
 
         #[derive(Deserialize)]
-        struct FilledMapV1204Tag {
+        struct Tag {
+            #[serde(rename = "BlockEntityTag")]
+            block_entity: Option<MapIdsOfEntity>,
             display: Option<IgnoredAny>,
-            map: u32,
+            #[serde(rename = "Items")]
+            items: Option<Vec<MapIdsOfItem>>,
+            map: Option<u32>,
         }
 
-        #[derive(Deserialize)]
-        struct FilledMapV1205 {
-            components: FilledMapV1205Components,
-        }
+        struct ItemVisitor;
 
-        #[derive(Deserialize)]
-        struct FilledMapV1205Components {
-            #[serde(rename = "minecraft:item_name")]
-            item_name: Option<IgnoredAny>,
-            #[serde(rename = "minecraft:map_id")]
-            map_id: u32,
-        }
+        impl<'de> Visitor<'de> for ItemVisitor {
+            type Value = MapIdsOfItem;
 
-        Ok(Self(match Internal::deserialize(deserializer)? {
-            Internal::Bundle(t) => t.components.into_iter().flat_map(|c| c.0).collect(),
-            Internal::Container(Container::V1204(c)) => c.map_ids.0.into_iter().collect(),
-            Internal::Container(Container::V1205(t)) => {
-                t.components.into_iter().flat_map(|c| c.0).collect()
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("item")
             }
-            Internal::FilledMap(FilledMap::V1204(t)) if t.tag.display.is_none() => {
-                iter::once(t.tag.map).collect()
-            }
-            Internal::FilledMap(FilledMap::V1205(t)) if t.components.item_name.is_none() => {
-                iter::once(t.components.map_id).collect()
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut kind = Kind::Other;
+                let mut count = 1;
+                let mut components = None;
+                let mut tag = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Id => kind = map.next_value()?,
+                        Field::Count => count = map.next_value::<i32>()?,
+                        Field::Components => components = Some(map.next_value::<Components>()?),
+                        Field::Tag => tag = Some(map.next_value::<Tag>()?),
+                        Field::Other => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                // Contents of a stack are counted once for each item of it
+                let count = u32::try_from(count).unwrap_or_default().max(1);
+                Ok(MapIdsOfItem(
+                    match (kind, components, tag) {
+                        (Kind::Bundle, Some(c), _) => c
+                            .bundle_contents
+                            .into_iter()
+                            .flatten()
+                            .flat_map(|i| i.0)
+                            .collect(),
+                        (Kind::Bundle, None, Some(t)) => {
+                            t.items.into_iter().flatten().flat_map(|i| i.0).collect()
+                        }
+                        (Kind::FilledMap, Some(c), _) if c.item_name.is_none() => {
+                            c.map_id.into_iter().map(|id| (id, 1)).collect()
+                        }
+                        (Kind::FilledMap, None, Some(t)) if t.display.is_none() => {
+                            t.map.into_iter().map(|id| (id, 1)).collect()
+                        }
+                        (Kind::ShulkerBox, Some(c), _) => c
+                            .container
+                            .into_iter()
+                            .flatten()
+                            .flat_map(|s| s.item.0)
+                            .collect(),
+                        (Kind::ShulkerBox, None, Some(t)) => {
+                            t.block_entity.into_iter().flat_map(|e| e.0).collect()
+                        }
+                        _ => MapIds::default(),
+                    }
+                    .times(count),
+                ))
             }
-            _ => HashSet::default(),
-        }))
+        }
+
+        let Some(_nesting) = Nesting::enter() else {
+            IgnoredAny::deserialize(deserializer)?;
+            return Ok(Self(MapIds::default()));
+        };
+
+        deserializer.deserialize_map(ItemVisitor)
     }
 }
 
-struct MapIdsOfLevelChunk(HashSet<u32>);
+pub struct MapIdsOfLevelChunk(pub MapIds);
 impl<'de> Deserialize<'de> for MapIdsOfLevelChunk {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum Chunk {
-            V117(V117),
-            V118(V118),
-        }
-
-        #[derive(serde_query::Deserialize)]
-        struct V117 {
-            #[query(".Level.TileEntities")]
-            block_entities: Vec<MapIdsOfEntity>,
+        struct Internal {
+            #[serde(rename = "Level")]
+            level: Option<LevelV117>,
+            block_entities: Option<Vec<MapIdsOfEntity>>,
         }
 
         #[derive(Deserialize)]
-        struct V118 {
-            block_entities: Vec<MapIdsOfEntity>,
+        struct LevelV117 {
+            #[serde(rename = "TileEntities")]
+            block_entities: Option<Vec<MapIdsOfEntity>>,
         }
 
-        let entities = match Chunk::deserialize(deserializer)? {
-            Chunk::V117(c) => c.block_entities,
-            Chunk::V118(c) => c.block_entities,
-        };
-        Ok(Self(entities.into_iter().flat_map(|e| e.0).collect()))
+        let internal = Internal::deserialize(deserializer)?;
+        Ok(Self(
+            internal
+                .block_entities
+                .into_iter()
+                .chain(internal.level.and_then(|l| l.block_entities))
+                .flatten()
+                .flat_map(|e| e.0)
+                .collect(),
+        ))
     }
 }
 impl ContainsMapIds for MapIdsOfLevelChunk {
-    fn map_ids(self) -> HashSet<u32> {
+    fn map_ids(self) -> MapIds {
         self.0
     }
 }
 
-struct MapIdsOfPlayer(HashSet<u32>);
+pub struct MapIdsOfPlayer(pub MapIds);
 impl<'de> Deserialize<'de> for MapIdsOfPlayer {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         #[derive(Deserialize)]
@@ -294,137 +395,682 @@ impl<'de> Deserialize<'de> for MapIdsOfPlayer {
     }
 }
 
+// Datapacks keep arbitrary NBT in command storage, so any compound bearing an ID is tried as an item
+// wherever it's nested.
+pub struct MapIdsOfStorage(pub MapIds);
+impl<'de> Deserialize<'de> for MapIdsOfStorage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        fn visit(value: &Value, ids: &mut MapIds) {
+            match value {
+                Value::Compound(compound) => {
+                    if compound.contains_key("id") {
+                        if let Ok(item) = from_value::<MapIdsOfItem>(value) {
+                            ids.extend(item.0);
+                        }
+                    }
+                    for v in compound.values() {
+                        visit(v, ids);
+                    }
+                }
+                Value::List(list) => {
+                    for v in list {
+                        visit(v, ids);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut ids = MapIds::default();
+        visit(&Value::deserialize(deserializer)?, &mut ids);
+        Ok(Self(ids))
+    }
+}
+
+fn search_region<T: ContainsMapIds + DeserializeOwned>(
+    path: &Path,
+    (dimension, rx, rz): Region,
+    files: &OpenFiles,
+    bar: &ProgressBar,
+) -> Result<(MapIds, RegionSummary)> {
+    span!("search_region", path = %path.display());
+    let mut in_region = MapIds::default();
+    let mut summary = RegionSummary {
+        modified: read_modified(path)?,
+        ..RegionSummary::default()
+    };
+
+    let chunks = {
+        span!("decompress");
+        anvil::chunks(path, (rx, rz), files)
+            .with_context(|| format!("Failed to deserialize {}", path.display()))?
+    };
+    span!("parse", chunks = chunks.len());
+    for anvil::Chunk { data, x, z } in chunks {
+        let in_chunk = from_bytes::<T>(&data)
+            .with_context(|| format!("Failed to deserialize {} chunk ({x}, {z})", path.display()))?
+            .map_ids();
+
+        if log_enabled!(Debug) && !in_chunk.is_empty() {
+            let list = in_chunk.ids().sorted().join(", ");
+            bar.suspend(|| {
+                debug!(
+                    "{} region ({rx}, {rz}) chunk ({x}, {z}) maps: {list}",
+                    dimension.name()
+                );
+            });
+        }
+
+        summary.chunks += 1;
+        if !in_chunk.is_empty() {
+            summary.chunks_with_maps += 1;
+        }
+
+        in_region.extend(in_chunk);
+    }
+
+    Ok((in_region, summary))
+}
+
+type Quarantined = Vec<(PathBuf, Option<QuarantinedFile>)>;
+
+struct RegionsFound {
+    length: usize,
+    map_ids_by_region: IdsBy<Region>,
+    summary_by_region: SummaryBy<Region>,
+    quarantined: Quarantined,
+}
+
 fn search_regions<T: ContainsMapIds + DeserializeOwned>(
     world_path: &Path,
-    quiet: bool,
     bounds: Option<&Bounds>,
     cache: &Cache,
+    files: &OpenFiles,
+    bar: &ProgressBar,
     pattern: &str,
-) -> Result<(usize, IdsBy<(i32, i32)>)> {
-    let regions = glob(world_path.join(pattern).to_str().unwrap())?
-        .map(|entry| {
-            let path = entry?;
-            let base = path.file_stem().unwrap().to_str().unwrap();
+) -> Result<RegionsFound> {
+    span!("search_regions", pattern);
+    let mut paths = Vec::new();
+    for dimension in Dimension::ALL {
+        if let Some(dimension_path) = dimension.path(world_path) {
+            for entry in glob_in(&dimension_path, pattern)? {
+                paths.push((dimension, entry?));
+            }
+        }
+    }
+
+    let regions = paths
+        .into_iter()
+        .map(|(dimension, path)| {
+            let base = path.file_stem().unwrap().to_string_lossy();
             let mut parts = base.split('.').skip(1);
             let x = parts.next().unwrap().parse()?;
             let z = parts.next().unwrap().parse()?;
 
             Ok(match bounds {
                 Some(&((x0, z0), (x1, z1))) if x < x0 || x > x1 || z < z0 || z > z1 => None,
-                _ => cache.is_expired_for(&path)?.then_some(((x, z), path)),
+                _ => cache
+                    .is_expired_for(&path)?
+                    .then_some(((dimension, x, z), path)),
             })
         })
         .filter_map(Result::transpose)
         .collect::<Result<Vec<_>>>()?;
 
     let length = regions.len();
-    let bar = progress_bar(quiet, "Search for map items", length, "regions");
+    bar.inc_length(length as u64);
 
-    let map_ids_by_region = regions
+    let results = regions
         .into_par_iter()
         .progress_with(bar.clone())
-        .map(|((rx, rz), path)| {
-            let mut in_region = HashSet::new();
-
-            match fastanvil::Region::from_stream(File::open(&path)?) {
-                Ok(mut region) => {
-                    for chunk in region.iter() {
-                        let fastanvil::ChunkData { data, x, z } = chunk?;
-
-                        let in_chunk = from_bytes::<T>(&data)
-                            .with_context(|| {
-                                format!("Failed to deserialize {} chunk ({x}, {z})", path.display())
-                            })
-                            .unwrap()
-                            .map_ids();
-
-                        if log_enabled!(Debug) && !in_chunk.is_empty() {
-                            let list = in_chunk.iter().sorted().map(ToString::to_string).join(", ");
-                            bar.suspend(|| {
-                                debug!("Region ({rx}, {rz}) chunk ({x}, {z}) maps: {list}");
-                            });
-                        }
+        .map(|(region, path)| {
+            let result = search_region::<T>(&path, region, files, bar);
+            (region, path, result)
+        })
+        .collect::<Vec<_>>();
 
-                        in_region.extend(in_chunk);
-                    }
-                }
-                Err(fastanvil::Error::IO(e))
-                    if e.kind() == std::io::ErrorKind::UnexpectedEof
-                        && fs::metadata(&path)?.len() == 0 => {}
-                Err(e) => {
-                    return Err(e)
-                        .with_context(|| format!("Failed to deserialize {}", path.display()))
-                }
+    let mut found = RegionsFound {
+        length,
+        map_ids_by_region: HashMap::new(),
+        summary_by_region: HashMap::new(),
+        quarantined: Vec::new(),
+    };
+    for (region, path, result) in results {
+        match result {
+            Ok((in_region, summary)) => {
+                found.quarantined.push((path, None));
+                found.map_ids_by_region.insert(region, in_region);
+                found.summary_by_region.insert(region, summary);
             }
+            Err(e) => found.quarantined.push(quarantine(path, &e, bar)?),
+        }
+    }
 
-            Ok(((rx, rz), in_region))
-        })
-        .collect::<Result<HashMap<_, _>>>()?;
+    Ok(found)
+}
 
-    bar.finish_and_clear();
-    Ok((length, map_ids_by_region))
+// Skipped until modified, and meanwhile listed in quarantine.json
+fn quarantine(
+    path: PathBuf,
+    error: &anyhow::Error,
+    bar: &ProgressBar,
+) -> Result<(PathBuf, Option<QuarantinedFile>)> {
+    bar.suspend(|| debug!("Quarantine {}: {error:#}", path.display()));
+    let modified = read_modified(&path)?
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let error = format!("{error:#}");
+
+    Ok((path, Some(QuarantinedFile { modified, error })))
 }
 
-pub fn search_players(world_path: &Path, quiet: bool, cache: &mut Cache) -> Result<usize> {
-    let pattern = world_path.join("playerdata/????????-????-????-????-????????????.dat");
-    let mut paths = glob(pattern.to_str().unwrap())?.collect::<Result<Vec<_>, _>>()?;
-    paths.sort();
+struct PlayersFound {
+    length: usize,
+    map_ids_by_player: IdsBy<String>,
+    quarantined: Quarantined,
+}
 
-    let players = paths
-        .into_iter()
-        .enumerate()
-        .map(|(index, path)| Ok(cache.is_expired_for(&path)?.then_some((index, path))))
+fn search_players(
+    world_path: &Path,
+    players: &PlayerSelection,
+    cache: &Cache,
+    files: &OpenFiles,
+    bar: &ProgressBar,
+) -> Result<PlayersFound> {
+    span!("search_players");
+    let pattern = "playerdata/????????-????-????-????-????????????.dat";
+    let players = glob_in(world_path, pattern)?
+        .map(|entry| {
+            let path = entry?;
+            let uuid = path.file_stem().unwrap().to_string_lossy().into_owned();
+            if !players.permits(&uuid) {
+                return Ok(None);
+            }
+
+            // Players newly permitted are searched regardless of the age of the cache
+            let known =
+                cache.map_ids_by_player.contains_key(&uuid) || cache.quarantine.contains_key(&path);
+            let expired = !known || cache.is_expired_for(&path)?;
+            Ok(expired.then_some((uuid, path)))
+        })
         .filter_map(Result::transpose)
         .collect::<Result<Vec<_>>>()?;
 
     let length = players.len();
-    let bar = progress_bar(quiet, "Search for map items", length, "players");
-    let ids = players
+    bar.inc_length(length as u64);
+
+    let results = players
         .into_par_iter()
         .progress_with(bar.clone())
-        .map(|(index, path)| {
-            let ids = from_bytes::<MapIdsOfPlayer>(&read_gz(&path)?)
-                .with_context(|| format!("Failed to deserialize {}", path.display()))?
-                .0;
-
-            if log_enabled!(Debug) && !ids.is_empty() {
-                let list = ids.iter().sorted().map(ToString::to_string).join(", ");
-                bar.suspend(|| debug!("Player {index} maps: {list}"));
+        .map(|(uuid, path)| {
+            let result = retry_once(&path, || {
+                Ok::<_, Error>(
+                    from_bytes::<MapIdsOfPlayer>(&files.read_gz(&path)?)
+                        .map_err(|e| Error::nbt(&path, e))?
+                        .0,
+                )
+            })
+            .map_err(anyhow::Error::from);
+            (uuid, path, result)
+        })
+        .collect::<Vec<_>>();
+
+    let mut found = PlayersFound {
+        length,
+        map_ids_by_player: HashMap::new(),
+        quarantined: Vec::new(),
+    };
+    for (uuid, path, result) in results {
+        match result {
+            Ok(ids) => {
+                if log_enabled!(Debug) && !ids.is_empty() {
+                    let list = ids.ids().sorted().join(", ");
+                    bar.suspend(|| debug!("Player {uuid} maps: {list}"));
+                }
+
+                found.quarantined.push((path, None));
+                found.map_ids_by_player.insert(uuid, ids);
             }
+            Err(e) => found.quarantined.push(quarantine(path, &e, bar)?),
+        }
+    }
 
-            Ok((index, ids))
-        })
-        .collect::<Result<HashMap<_, _>>>()?;
-    bar.finish_and_clear();
+    Ok(found)
+}
 
-    cache.map_ids_by_player.extend(ids);
-    Ok(length)
+struct StorageFound {
+    length: usize,
+    map_ids_by_storage: IdsBy<String>,
+    quarantined: Quarantined,
 }
 
-pub fn search_entities(
+fn search_storage(
     world_path: &Path,
-    quiet: bool,
-    bounds: Option<&Bounds>,
-    cache: &mut Cache,
-) -> Result<usize> {
-    let pattern = "entities/r.*.mca";
-    let (length, ids) =
-        search_regions::<MapIdsOfEntitiesChunk>(world_path, quiet, bounds, cache, pattern)?;
+    cache: &Cache,
+    files: &OpenFiles,
+    bar: &ProgressBar,
+) -> Result<StorageFound> {
+    span!("search_storage");
+    let storage = glob_in(world_path, "data/command_storage_*.dat")?
+        .map(|entry| {
+            let path = entry?;
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            // Storage is searched in full once enabled, regardless of the age of the cache
+            let expired =
+                !cache.map_ids_by_storage.contains_key(&name) || cache.is_expired_for(&path)?;
+            Ok(expired.then_some((name, path)))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
 
-    cache.map_ids_by_entities_region.extend(ids);
-    Ok(length)
+    let length = storage.len();
+    bar.inc_length(length as u64);
+
+    let results = storage
+        .into_par_iter()
+        .progress_with(bar.clone())
+        .map(|(name, path)| {
+            let result = retry_once(&path, || {
+                Ok::<_, Error>(
+                    from_bytes::<MapIdsOfStorage>(&files.read_gz(&path)?)
+                        .map_err(|e| Error::nbt(&path, e))?
+                        .0,
+                )
+            })
+            .map_err(anyhow::Error::from);
+            (name, path, result)
+        })
+        .collect::<Vec<_>>();
+
+    let mut found = StorageFound {
+        length,
+        map_ids_by_storage: HashMap::new(),
+        quarantined: Vec::new(),
+    };
+    for (name, path, result) in results {
+        match result {
+            Ok(ids) => {
+                if log_enabled!(Debug) && !ids.is_empty() {
+                    let list = ids.ids().sorted().join(", ");
+                    bar.suspend(|| debug!("Storage {name} maps: {list}"));
+                }
+
+                found.quarantined.push((path, None));
+                found.map_ids_by_storage.insert(name, ids);
+            }
+            Err(e) => found.quarantined.push(quarantine(path, &e, bar)?),
+        }
+    }
+
+    Ok(found)
+}
+
+pub struct Searched {
+    pub players: usize,
+    pub storage: usize,
+    pub entity_regions: usize,
+    pub block_regions: usize,
+    pub complete: bool,
+    pub tile_size: Option<u64>,
+    // Of everything in the cache as of the end of the search rather than only of this pass
+    pub copies: Copies,
+    pub labels: Labels,
+    // Dimensions of the regions in which items of each map were found
+    pub dimensions_by_map_id: HashMap<u32, BTreeSet<Dimension>>,
+    // Files searched by why they weren't taken from the cache, over every pass
+    pub cache_misses: BTreeMap<CacheMiss, usize>,
 }
 
-pub fn search_level(
+impl Searched {
+    pub const fn total(&self) -> usize {
+        self.players + self.storage + self.entity_regions + self.block_regions
+    }
+}
+
+// Players, entity regions, and block regions are searched concurrently in one pool, which mostly
+// benefits worlds on storage with high latency. Command storage is searched only on request.
+#[allow(clippy::too_many_arguments)]
+pub fn search_world(
     world_path: &Path,
     quiet: bool,
     bounds: Option<&Bounds>,
+    command_storage: bool,
+    players: &PlayerSelection,
     cache: &mut Cache,
-) -> Result<usize> {
-    let pattern = "region/r.*.mca";
-    let (length, ids) =
-        search_regions::<MapIdsOfLevelChunk>(world_path, quiet, bounds, cache, pattern)?;
+    files: &OpenFiles,
+    warnings: &WarningCollector,
+) -> Result<Searched> {
+    // Players left out are forgotten along with whatever was found of them
+    let players_path = world_path.join("playerdata");
+    cache
+        .map_ids_by_player
+        .retain(|uuid, _| players.permits(uuid));
+    cache.quarantine.retain(|p, _| {
+        !p.starts_with(&players_path)
+            || players.permits(&p.file_stem().unwrap_or_default().to_string_lossy())
+    });
+    cache.players = players.clone();
+
+    let bar = progress_bar(quiet, "Search for map items", 0, "files");
+
+    let ((players, storage), (entities, blocks)) = {
+        let cache = &*cache;
+        rayon::join(
+            || {
+                rayon::join(
+                    || search_players(world_path, players, cache, files, &bar),
+                    || {
+                        command_storage
+                            .then(|| search_storage(world_path, cache, files, &bar))
+                            .transpose()
+                    },
+                )
+            },
+            || {
+                rayon::join(
+                    || {
+                        let pattern = "entities/r.*.mca";
+                        search_regions::<MapIdsOfEntitiesChunk>(
+                            world_path, bounds, cache, files, &bar, pattern,
+                        )
+                    },
+                    || {
+                        let pattern = "region/r.*.mca";
+                        search_regions::<MapIdsOfLevelChunk>(
+                            world_path, bounds, cache, files, &bar, pattern,
+                        )
+                    },
+                )
+            },
+        )
+    };
+    bar.finish_and_clear();
+
+    let (players, storage, entities, blocks) = (players?, storage?, entities?, blocks?);
+
+    cache.map_ids_by_player.extend(players.map_ids_by_player);
+    let (storage_length, storage_quarantined) = if let Some(s) = storage {
+        cache.map_ids_by_storage.extend(s.map_ids_by_storage);
+        (s.length, s.quarantined)
+    } else {
+        cache.map_ids_by_storage.clear();
+        let storage_path = world_path.join("data");
+        cache
+            .quarantine
+            .retain(|p, _| !p.starts_with(&storage_path));
+        (0, Vec::new())
+    };
+    cache
+        .map_ids_by_entities_region
+        .extend(entities.map_ids_by_region);
+    cache
+        .summary_by_entities_region
+        .extend(entities.summary_by_region);
+    cache
+        .map_ids_by_block_region
+        .extend(blocks.map_ids_by_region);
+    cache
+        .summary_by_block_region
+        .extend(blocks.summary_by_region);
+    for (path, quarantined) in players
+        .quarantined
+        .into_iter()
+        .chain(storage_quarantined)
+        .chain(entities.quarantined)
+        .chain(blocks.quarantined)
+    {
+        match quarantined {
+            Some(q) => {
+                warnings.add(WarningKind::Quarantined, path.display().to_string());
+                cache.quarantine.insert(path, q)
+            }
+            None => cache.quarantine.remove(&path),
+        };
+    }
 
-    cache.map_ids_by_block_region.extend(ids);
-    Ok(length)
+    Ok(Searched {
+        players: players.length,
+        storage: storage_length,
+        entity_regions: entities.length,
+        block_regions: blocks.length,
+        complete: bounds.is_none() && cache.quarantine.is_empty(),
+        tile_size: cache.tile_size,
+        copies: Copies::default(),
+        labels: Labels::default(),
+        dimensions_by_map_id: HashMap::default(),
+        cache_misses: BTreeMap::default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fastnbt::{nbt, to_bytes};
+    use itertools::assert_equal;
+
+    fn map_ids(item: &fastnbt::Value) -> Vec<u32> {
+        from_bytes::<MapIdsOfItem>(&to_bytes(item).unwrap())
+            .unwrap()
+            .0
+            .ids()
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn holders() {
+        let map = |id: i32| nbt!({ "id": "minecraft:filled_map", "components": { "minecraft:map_id": id } });
+        let map_ids = |entity: &fastnbt::Value| -> Vec<u32> {
+            from_bytes::<MapIdsOfEntity>(&to_bytes(entity).unwrap())
+                .unwrap()
+                .0
+                .ids()
+                .sorted()
+                .collect()
+        };
+
+        let item_frame = nbt!({ "id": "minecraft:item_frame", "Item": map(1) });
+        assert_equal(map_ids(&item_frame), [1]);
+
+        let chest = nbt!({ "id": "minecraft:chest", "Items": [map(2), map(3)] });
+        assert_equal(map_ids(&chest), [2, 3]);
+
+        let lectern = nbt!({ "id": "minecraft:lectern", "Book": map(4), "Page": 0 });
+        assert_equal(map_ids(&lectern), [4]);
+
+        let bookshelf = nbt!({ "id": "minecraft:chiseled_bookshelf", "Items": [map(5)] });
+        assert_equal(map_ids(&bookshelf), [5]);
+
+        let campfire =
+            nbt!({ "id": "minecraft:campfire", "Items": [map(6)], "CookingTimes": [I; 0] });
+        assert_equal(map_ids(&campfire), [6]);
+
+        let decorated_pot = nbt!({ "id": "minecraft:decorated_pot", "item": map(7) });
+        assert_equal(map_ids(&decorated_pot), [7]);
+
+        let item_display = nbt!({ "id": "minecraft:item_display", "item": map(8) });
+        assert_equal(map_ids(&item_display), [8]);
+
+        let armor_stand = nbt!({
+            "id": "minecraft:armor_stand",
+            "ArmorItems": [{}, {}, {}, map(9)],
+            "HandItems": [map(10), {}],
+        });
+        assert_equal(map_ids(&armor_stand), [9, 10]);
+
+        let armor_stand = nbt!({
+            "id": "minecraft:armor_stand",
+            "equipment": { "head": map(11), "offhand": map(12) },
+        });
+        assert_equal(map_ids(&armor_stand), [11, 12]);
+
+        let boat = nbt!({
+            "id": "minecraft:oak_boat",
+            "Passengers": [
+                { "id": "minecraft:chest_minecart", "Items": [map(13)] },
+                { "id": "minecraft:llama", "Items": [map(14)], "Passengers": [{ "id": "minecraft:item_frame", "Item": map(15) }] },
+            ],
+        });
+        assert_equal(map_ids(&boat), [13, 14, 15]);
+
+        let sign = nbt!({ "id": "minecraft:sign", "is_waxed": 0_i8 });
+        assert!(map_ids(&sign).is_empty());
+    }
+
+    #[test]
+    fn item() {
+        // 1.20.5+
+        let map = |id: i32| nbt!({ "id": "minecraft:filled_map", "components": { "minecraft:map_id": id } });
+        assert_equal(map_ids(&map(1)), [1]);
+        assert_equal(
+            map_ids(&nbt!({
+                "components": { "minecraft:bundle_contents": [map(2), map(3)] },
+                "id": "minecraft:light_blue_bundle",
+            })),
+            [2, 3],
+        );
+        assert_equal(
+            map_ids(&nbt!({
+                "id": "minecraft:shulker_box",
+                "components": { "minecraft:container": [{ "slot": 0, "item": map(4) }] },
+            })),
+            [4],
+        );
+        assert!(map_ids(&nbt!({
+            "id": "minecraft:filled_map",
+            "components": { "minecraft:item_name": "\"Art\"", "minecraft:map_id": 5 },
+        }))
+        .is_empty());
+
+        // 1.20.4
+        assert_equal(
+            map_ids(&nbt!({ "id": "minecraft:filled_map", "tag": { "map": 6 } })),
+            [6],
+        );
+        assert_equal(
+            map_ids(&nbt!({
+                "id": "minecraft:red_shulker_box",
+                "tag": { "BlockEntityTag": { "Items": [{ "id": "minecraft:filled_map", "tag": { "map": 7 } }] } },
+            })),
+            [7],
+        );
+        assert_equal(
+            map_ids(&nbt!({
+                "id": "minecraft:bundle",
+                "tag": { "Items": [{ "id": "minecraft:filled_map", "Count": 1_i8, "tag": { "map": 8 } }] },
+            })),
+            [8],
+        );
+        assert!(map_ids(&nbt!({ "id": "minecraft:filled_map" })).is_empty());
+
+        // Stacks
+        let copies = |item: &fastnbt::Value| {
+            from_bytes::<MapIdsOfItem>(&to_bytes(item).unwrap())
+                .unwrap()
+                .0
+                .copies()
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            copies(
+                &nbt!({ "id": "minecraft:filled_map", "count": 3, "components": { "minecraft:map_id": 10 } })
+            ),
+            [(10, 3)]
+        );
+        assert_eq!(
+            copies(&nbt!({ "id": "minecraft:filled_map", "Count": 2_i8, "tag": { "map": 11 } })),
+            [(11, 2)]
+        );
+        assert_eq!(
+            copies(&nbt!({
+                "id": "minecraft:bundle",
+                "components": { "minecraft:bundle_contents": [
+                    { "id": "minecraft:filled_map", "count": 2, "components": { "minecraft:map_id": 12 } },
+                    { "id": "minecraft:filled_map", "count": 1, "components": { "minecraft:map_id": 12 } },
+                ] },
+            })),
+            [(12, 3)]
+        );
+        assert!(
+            map_ids(&nbt!({ "id": "minecraft:red_bundle_of_joy", "tag": { "map": 9 } })).is_empty()
+        );
+    }
+
+    #[test]
+    fn nested() {
+        // 1.20.5+
+        let map = |id: i32| nbt!({ "id": "minecraft:filled_map", "components": { "minecraft:map_id": id } });
+        let bundle = |items: Vec<fastnbt::Value>| nbt!({ "id": "minecraft:bundle", "components": { "minecraft:bundle_contents": items } });
+        let shulker_box = |items: Vec<fastnbt::Value>| {
+            let slots = items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| nbt!({ "slot": i32::try_from(i).unwrap(), "item": item }))
+                .collect::<Vec<_>>();
+            nbt!({ "id": "minecraft:blue_shulker_box", "components": { "minecraft:container": slots } })
+        };
+        let chest = nbt!({
+            "id": "minecraft:chest",
+            "Items": [bundle(vec![map(1), shulker_box(vec![bundle(vec![shulker_box(vec![map(2)]), map(3)])])])],
+        });
+        assert_equal(
+            from_bytes::<MapIdsOfEntity>(&to_bytes(&chest).unwrap())
+                .unwrap()
+                .0
+                .ids()
+                .sorted(),
+            [1, 2, 3],
+        );
+        assert_equal(
+            map_ids(&shulker_box(vec![shulker_box(vec![bundle(vec![bundle(
+                vec![map(4)],
+            )])])])),
+            [4],
+        );
+
+        // 1.20.4
+        let map =
+            |id: i32| nbt!({ "id": "minecraft:filled_map", "Count": 1_i8, "tag": { "map": id } });
+        let bundle = |items: Vec<fastnbt::Value>| nbt!({ "id": "minecraft:bundle", "tag": { "Items": items } });
+        let shulker_box = |items: Vec<fastnbt::Value>| nbt!({ "id": "minecraft:shulker_box", "tag": { "BlockEntityTag": { "Items": items } } });
+        assert_equal(
+            map_ids(&bundle(vec![shulker_box(vec![bundle(vec![
+                map(5),
+                shulker_box(vec![map(6)]),
+            ])])])),
+            [5, 6],
+        );
+
+        // Beyond the limit
+        let deep = |depth| (0..depth).fold(map(7), |item, _| bundle(vec![item]));
+        assert_equal(map_ids(&deep(MAX_NESTING - 1)), [7]);
+        assert!(map_ids(&deep(MAX_NESTING)).is_empty());
+        assert_equal(map_ids(&bundle(vec![deep(MAX_NESTING), map(8)])), [8]);
+        assert_eq!(NESTING.with(Cell::get), 0);
+    }
+
+    #[test]
+    fn storage() {
+        let map = |id: i32| nbt!({ "id": "minecraft:filled_map", "components": { "minecraft:map_id": id } });
+        let storage = nbt!({
+            "DataVersion": 4189,
+            "data": {
+                "contents": {
+                    "quest": { "reward": map(1), "stages": [{ "items": [map(2)] }] },
+                    "gallery": { "id": "minecraft:filled_map", "components": { "minecraft:item_name": "\"Art\"", "minecraft:map_id": 3 } },
+                    "marker": { "id": "minecraft:marker", "data": { "Item": map(4) } },
+                },
+            },
+        });
+
+        assert_equal(
+            from_bytes::<MapIdsOfStorage>(&to_bytes(&storage).unwrap())
+                .unwrap()
+                .0
+                .ids()
+                .sorted(),
+            [1, 2, 4],
+        );
+    }
 }
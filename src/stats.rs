@@ -0,0 +1,195 @@
+use crate::cache::Cache;
+use crate::map::MapScan;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::iter;
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Upper bounds in days of the ages by which maps are counted, as in the freshness overlay
+const AGES: [u64; 3] = [7, 30, 182];
+
+/// Aggregates of the maps and banners of a world, e.g. for the curiosity of its community
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Statistics {
+    pub banners_by_color: BTreeMap<String, usize>,
+    /// Including maps of dimensions that aren't rendered
+    pub maps_by_dimension: BTreeMap<&'static str, usize>,
+    pub maps_by_scale: BTreeMap<u8, usize>,
+    /// Counts of maps by days since last modified as of the run
+    pub maps_by_age: Vec<AgeCount>,
+    /// Of the maps found by the search, if the run included one
+    pub copies: Option<Copies>,
+}
+
+/// Copies of the maps found by the search, e.g. to tell widely distributed maps from originals
+///
+/// Totals are by where the copies are held: `players` in inventories and ender chests, `blocks`
+/// in chests and other containers, `entities` in item frames and other entities, and `storage` in
+/// command storage.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Copies {
+    pub by_holder: BTreeMap<&'static str, u64>,
+    pub by_map: BTreeMap<u32, u64>,
+}
+
+impl Copies {
+    pub(crate) fn from_cache(cache: &Cache) -> Self {
+        let holders = (cache.map_ids_by_player.values().map(|ids| ("players", ids)))
+            .chain(
+                cache
+                    .map_ids_by_block_region
+                    .values()
+                    .map(|ids| ("blocks", ids)),
+            )
+            .chain(
+                cache
+                    .map_ids_by_entities_region
+                    .values()
+                    .map(|ids| ("entities", ids)),
+            )
+            .chain(
+                cache
+                    .map_ids_by_storage
+                    .values()
+                    .map(|ids| ("storage", ids)),
+            );
+
+        let mut copies = Self::default();
+        for (holder, ids) in holders {
+            for (id, count) in ids.copies() {
+                *copies.by_holder.entry(holder).or_default() += u64::from(count);
+                *copies.by_map.entry(id).or_default() += u64::from(count);
+            }
+        }
+
+        copies
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct AgeCount {
+    pub from_days: u64,
+    pub within_days: Option<u64>,
+    pub maps: usize,
+}
+
+impl Statistics {
+    #[must_use]
+    pub fn from_scan(scan: &MapScan, now: SystemTime) -> Self {
+        let mut banners_by_color = BTreeMap::new();
+        for banner in scan.banners.keys() {
+            *banners_by_color.entry(banner.color.clone()).or_default() += 1;
+        }
+
+        let mut maps_by_scale = BTreeMap::new();
+        let mut maps_by_age = iter::once(0)
+            .chain(AGES)
+            .zip(AGES.map(Some).into_iter().chain([None]))
+            .map(|(from_days, within_days)| AgeCount {
+                from_days,
+                within_days,
+                maps: 0,
+            })
+            .collect::<Vec<_>>();
+        for map in scan.maps_by_tile.values().flatten() {
+            *maps_by_scale.entry(4 - map.tile.zoom).or_default() += 1;
+
+            let days = now
+                .duration_since(map.modified)
+                .unwrap_or_default()
+                .as_secs()
+                / DAY.as_secs();
+            let age = maps_by_age
+                .iter_mut()
+                .find(|a| a.within_days.map_or(true, |within| days < within))
+                .unwrap();
+            age.maps += 1;
+        }
+
+        Self {
+            banners_by_color,
+            maps_by_dimension: scan
+                .maps_by_dimension
+                .iter()
+                .map(|(dimension, &count)| (dimension.name(), count))
+                .collect(),
+            maps_by_scale,
+            maps_by_age,
+            copies: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dimension::Dimension;
+
+    #[test]
+    fn from_scan() {
+        let nbt = |dimension: &str, scale: i8| {
+            fastnbt::to_bytes(&fastnbt::nbt!({
+                "data": {
+                    "banners": [{ "color": "red", "pos": [I; 100, 64, -100] }],
+                    "dimension": dimension,
+                    "scale": scale,
+                    "xCenter": 64,
+                    "zCenter": -64,
+                }
+            }))
+            .unwrap()
+        };
+        let now = SystemTime::UNIX_EPOCH + 365 * DAY;
+
+        let scan = [
+            (1, "minecraft:overworld", 0, 1),
+            (2, "minecraft:overworld", 0, 10),
+            (3, "minecraft:overworld", 2, 300),
+            (4, "minecraft:the_nether", 0, 1),
+        ]
+        .into_iter()
+        .map(|(id, dimension, scale, days)| {
+            MapScan::from_nbt(id, &nbt(dimension, scale), now - days * DAY).unwrap()
+        })
+        .fold(MapScan::default(), MapScan::merge);
+        let statistics = Statistics::from_scan(&scan, now);
+
+        assert_eq!(statistics.banners_by_color, [("red".to_owned(), 1)].into());
+        assert_eq!(
+            statistics.maps_by_dimension,
+            [("overworld", 3), ("the_nether", 1)].into()
+        );
+        assert_eq!(statistics.maps_by_scale, [(0, 2), (2, 1)].into());
+        assert_eq!(
+            statistics
+                .maps_by_age
+                .iter()
+                .map(|a| a.maps)
+                .collect::<Vec<_>>(),
+            [1, 1, 0, 1]
+        );
+    }
+
+    #[test]
+    fn copies() {
+        let mut cache = Cache::default();
+        cache
+            .map_ids_by_player
+            .insert("player".to_owned(), [(1, 1), (2, 3)].into_iter().collect());
+        cache
+            .map_ids_by_block_region
+            .insert((Dimension::Overworld, 0, 0), [1, 3].into());
+        cache
+            .map_ids_by_entities_region
+            .insert((Dimension::Overworld, 0, 0), [1].into());
+
+        let copies = Copies::from_cache(&cache);
+        assert_eq!(
+            copies.by_holder,
+            [("blocks", 2), ("entities", 1), ("players", 4)].into()
+        );
+        assert_eq!(copies.by_map, [(1, 3), (2, 3), (3, 1)].into());
+    }
+}
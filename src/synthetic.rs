@@ -0,0 +1,362 @@
+use crate::dimension::Dimension;
+use crate::level::{CHUNK_ROOT_DATA_VERSION, ITEM_COMPONENTS_DATA_VERSION};
+use crate::Error;
+use anyhow::Result;
+use fastnbt::{nbt, ByteArray, Value};
+use flate2::{write::GzEncoder, Compression};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+pub struct SyntheticWorld {
+    pub version: String,
+    pub data_version: i32,
+    pub spawn: (i32, i32),
+    pub maps: Vec<SyntheticMap>,
+    pub chests: Vec<SyntheticChest>,
+    pub players: Vec<SyntheticPlayer>,
+    pub split_dimensions: bool,
+    // Contents of command storage by namespace
+    pub storage: BTreeMap<String, Value>,
+}
+
+impl Default for SyntheticWorld {
+    fn default() -> Self {
+        Self {
+            version: "1.21.4".to_owned(),
+            data_version: 4189,
+            spawn: (0, 0),
+            maps: Vec::default(),
+            chests: Vec::default(),
+            players: Vec::default(),
+            split_dimensions: false,
+            storage: BTreeMap::default(),
+        }
+    }
+}
+
+pub struct SyntheticMap {
+    pub id: u32,
+    pub scale: u8,
+    pub center: (i32, i32),
+    pub dimension: String,
+    pub banners: Vec<SyntheticBanner>,
+    pub colors: Vec<u8>,
+    pub locked: bool,
+    pub tracking: bool,
+}
+
+impl SyntheticMap {
+    #[must_use]
+    pub fn new(id: u32, scale: u8, center: (i32, i32)) -> Self {
+        Self {
+            id,
+            scale,
+            center,
+            dimension: "minecraft:overworld".to_owned(),
+            banners: Vec::default(),
+            colors: vec![u8::try_from(id % 61 + 1).unwrap() * 4 + 2; 128 * 128],
+            locked: false,
+            tracking: true,
+        }
+    }
+}
+
+pub struct SyntheticBanner {
+    pub position: (i32, i32, i32),
+    pub color: String,
+    pub label: Option<String>,
+}
+
+pub struct SyntheticChest {
+    pub dimension: Dimension,
+    pub position: (i32, i32, i32),
+    pub items: Vec<Value>,
+}
+
+pub struct SyntheticPlayer {
+    pub uuid: String,
+    pub inventory: Vec<Value>,
+    pub ender_items: Vec<Value>,
+}
+
+#[must_use]
+pub fn filled_map(id: u32) -> Value {
+    nbt!({
+        "id": "minecraft:filled_map",
+        "count": 1,
+        "components": { "minecraft:map_id": i32::try_from(id).unwrap() },
+    })
+}
+
+#[must_use]
+pub fn container(id: &str, items: &[Value]) -> Value {
+    let slots = (0..)
+        .zip(items)
+        .map(|(slot, item)| nbt!({ "slot": slot, "item": item }))
+        .collect::<Vec<_>>();
+
+    nbt!({
+        "id": id,
+        "count": 1,
+        "components": { "minecraft:container": slots },
+    })
+}
+
+#[must_use]
+pub fn bundle(items: &[Value]) -> Value {
+    nbt!({
+        "id": "minecraft:bundle",
+        "count": 1,
+        "components": { "minecraft:bundle_contents": items },
+    })
+}
+
+impl SyntheticWorld {
+    pub fn write_to(&self, world_path: &Path) -> Result<(), Error> {
+        Ok(self.write_world(world_path)?)
+    }
+
+    fn write_world(&self, world_path: &Path) -> Result<()> {
+        let level = nbt!({
+            "Data": {
+                "BorderCenterX": 0.0,
+                "BorderCenterZ": 0.0,
+                "BorderSize": 59_999_968.0,
+                "DataVersion": self.data_version,
+                "DayTime": 29_622_000_i64,
+                "GameRules": { "spawnChunkRadius": "2", "spawnRadius": "10" },
+                "LastPlayed": 1_700_000_000_000_i64,
+                "SpawnX": self.spawn.0,
+                "SpawnZ": self.spawn.1,
+                "Time": 30_000_000_i64,
+                "Version": { "Id": self.data_version, "Name": self.version.as_str() },
+            },
+        });
+        write_gz(&world_path.join("level.dat"), &level)?;
+
+        for map in &self.maps {
+            let banners = map
+                .banners
+                .iter()
+                .map(|b| {
+                    let (x, y, z) = b.position;
+                    let mut banner = nbt!({
+                        "color": b.color.as_str(),
+                        "pos": [I; x, y, z],
+                    });
+                    if let (Value::Compound(c), Some(label)) = (&mut banner, &b.label) {
+                        c.insert(
+                            "name".to_owned(),
+                            Value::String(serde_json::to_string(label)?),
+                        );
+                    }
+                    Ok(banner)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            #[allow(clippy::cast_possible_wrap)] // Colors are stored as signed bytes
+            let colors = map.colors.iter().map(|&c| c as i8).collect();
+            let data = nbt!({
+                "DataVersion": self.data_version,
+                "data": {
+                    "banners": banners,
+                    "colors": Value::ByteArray(ByteArray::new(colors)),
+                    "dimension": map.dimension.as_str(),
+                    "locked": i8::from(map.locked),
+                    "scale": i8::try_from(map.scale)?,
+                    "trackingPosition": i8::from(map.tracking),
+                    "unlimitedTracking": 0_i8,
+                    "xCenter": map.center.0,
+                    "zCenter": map.center.1,
+                },
+            });
+            write_gz(&world_path.join(format!("data/map_{}.dat", map.id)), &data)?;
+        }
+
+        let mut chests_by_chunk = BTreeMap::<(Dimension, i32, i32), Vec<&SyntheticChest>>::new();
+        for chest in &self.chests {
+            let (x, _, z) = chest.position;
+            chests_by_chunk
+                .entry((chest.dimension, x.div_euclid(16), z.div_euclid(16)))
+                .or_default()
+                .push(chest);
+        }
+        let mut chunks_by_region =
+            BTreeMap::<(Dimension, i32, i32), Vec<((i32, i32), Value)>>::new();
+        for ((dimension, cx, cz), chests) in chests_by_chunk {
+            let block_entities = chests
+                .into_iter()
+                .map(|chest| {
+                    let (x, y, z) = chest.position;
+                    let items = (0_i8..)
+                        .zip(&chest.items)
+                        .map(|(slot, item)| {
+                            let mut item = self.item(item);
+                            if let Value::Compound(c) = &mut item {
+                                c.insert("Slot".to_owned(), Value::Byte(slot));
+                            }
+                            item
+                        })
+                        .collect::<Vec<_>>();
+                    nbt!({ "id": "minecraft:chest", "x": x, "y": y, "z": z, "Items": items })
+                })
+                .collect::<Vec<_>>();
+            let chunk = if self.data_version < CHUNK_ROOT_DATA_VERSION {
+                nbt!({
+                    "DataVersion": self.data_version,
+                    "Level": {
+                        "Status": "full",
+                        "TileEntities": block_entities,
+                        "xPos": cx,
+                        "zPos": cz,
+                    },
+                })
+            } else {
+                nbt!({
+                    "DataVersion": self.data_version,
+                    "Status": "minecraft:full",
+                    "block_entities": block_entities,
+                    "xPos": cx,
+                    "zPos": cz,
+                })
+            };
+            chunks_by_region
+                .entry((dimension, cx.div_euclid(32), cz.div_euclid(32)))
+                .or_default()
+                .push(((cx, cz), chunk));
+        }
+        for ((dimension, rx, rz), chunks) in chunks_by_region {
+            let dimension_path = if self.split_dimensions {
+                dimension.split_path(world_path)
+            } else {
+                dimension.vanilla_path(world_path)
+            };
+            let dir_path = dimension_path.join("region");
+            fs::create_dir_all(&dir_path)?;
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(dir_path.join(format!("r.{rx}.{rz}.mca")))?;
+            let mut region = fastanvil::Region::new(file)?;
+            for ((cx, cz), chunk) in chunks {
+                #[allow(clippy::cast_sign_loss)] // Euclidean remainder
+                let (x, z) = (cx.rem_euclid(32) as usize, cz.rem_euclid(32) as usize);
+                region.write_chunk(x, z, &fastnbt::to_bytes(&chunk)?)?;
+            }
+        }
+
+        for player in &self.players {
+            let data = nbt!({
+                "DataVersion": self.data_version,
+                "EnderItems": player.ender_items.iter().map(|i| self.item(i)).collect::<Vec<_>>(),
+                "Inventory": player.inventory.iter().map(|i| self.item(i)).collect::<Vec<_>>(),
+            });
+            write_gz(
+                &world_path.join(format!("playerdata/{}.dat", player.uuid)),
+                &data,
+            )?;
+        }
+
+        for (namespace, contents) in &self.storage {
+            let data = nbt!({
+                "DataVersion": self.data_version,
+                "data": { "contents": contents.clone() },
+            });
+            write_gz(
+                &world_path.join(format!("data/command_storage_{namespace}.dat")),
+                &data,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Items are given as of the latest format and written as of the world's
+    fn item(&self, item: &Value) -> Value {
+        if self.data_version < ITEM_COMPONENTS_DATA_VERSION {
+            legacy_item(item)
+        } else {
+            item.clone()
+        }
+    }
+}
+
+fn legacy_item(item: &Value) -> Value {
+    let Value::Compound(fields) = item else {
+        return item.clone();
+    };
+
+    let mut legacy = HashMap::new();
+    let mut tag = HashMap::new();
+    for (name, value) in fields {
+        match (name.as_str(), value) {
+            ("count", Value::Int(count)) => {
+                legacy.insert(
+                    "Count".to_owned(),
+                    Value::Byte(i8::try_from(*count).unwrap()),
+                );
+            }
+            ("components", Value::Compound(components)) => {
+                for (component, value) in components {
+                    match (component.as_str(), value) {
+                        ("minecraft:bundle_contents", Value::List(items)) => {
+                            let items = items.iter().map(legacy_item).collect();
+                            tag.insert("Items".to_owned(), Value::List(items));
+                        }
+                        ("minecraft:container", Value::List(slots)) => {
+                            let items = slots
+                                .iter()
+                                .filter_map(|slot| {
+                                    let Value::Compound(slot) = slot else {
+                                        return None;
+                                    };
+                                    let mut item = legacy_item(slot.get("item")?);
+                                    if let (Value::Compound(i), Some(Value::Int(s))) =
+                                        (&mut item, slot.get("slot"))
+                                    {
+                                        i.insert(
+                                            "Slot".to_owned(),
+                                            Value::Byte(i8::try_from(*s).ok()?),
+                                        );
+                                    }
+                                    Some(item)
+                                })
+                                .collect();
+                            tag.insert(
+                                "BlockEntityTag".to_owned(),
+                                nbt!({ "Items": Value::List(items) }),
+                            );
+                        }
+                        ("minecraft:item_name", name) => {
+                            tag.insert("display".to_owned(), nbt!({ "Name": name.clone() }));
+                        }
+                        ("minecraft:map_id", id) => {
+                            tag.insert("map".to_owned(), id.clone());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {
+                legacy.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    if !tag.is_empty() {
+        legacy.insert("tag".to_owned(), Value::Compound(tag));
+    }
+
+    Value::Compound(legacy)
+}
+
+fn write_gz(path: &Path, value: &Value) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+    encoder.write_all(&fastnbt::to_bytes(value)?)?;
+    encoder.finish()?;
+
+    Ok(())
+}
@@ -1,12 +1,37 @@
+use crate::batch::Batch;
+use crate::blob;
+use crate::encoding::ImageSink;
+use crate::geometry::Origin;
 use crate::map::{Map, MapData};
-use crate::utilities::write_webp;
-use anyhow::Result;
-use serde_json::json;
+use crate::pattern::TilePattern;
+use crate::remove_if_exists;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
 use std::fs::{self, File};
 use std::ops::Add;
 use std::path::Path;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Palette colors by age of the map, from green within a week through yellow and orange to red
+// beyond half a year
+const FRESHNESS: [(u64, u8); 3] = [(7, 7 * 4 + 2), (30, 18 * 4 + 2), (182, 15 * 4 + 2)];
+const STALE: u8 = 28 * 4 + 2;
+
+/// Outcome of rendering a tile
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rendered {
+    /// Rendered since its maps were last modified
+    UpToDate,
+    /// Metadata rewritten, but the image kept since the composited pixels are the same
+    Unchanged,
+    /// Metadata and image rewritten
+    Changed,
+}
 
+/// Square of the output at a zoom level, where a tile of zoom 4 spans one map of scale 0
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Tile {
     pub zoom: u8,
@@ -15,6 +40,7 @@ pub struct Tile {
 }
 
 impl Tile {
+    #[must_use]
     pub fn from_position(scale: u8, x: i32, z: i32) -> Self {
         let size = 128 * 2_i32.pow(u32::from(scale));
 
@@ -26,16 +52,19 @@ impl Tile {
     }
 
     #[cfg(test)]
+    #[must_use]
     pub const fn new(zoom: u8, x: i32, y: i32) -> Self {
         Self { zoom, x, y }
     }
 
+    #[must_use]
     pub fn position(&self) -> (i32, i32) {
         let size = 128 * 2_i32.pow(u32::from(4 - self.zoom));
 
         (size * self.x, size * self.y)
     }
 
+    #[must_use]
     pub const fn quadrants(&self) -> [Self; 4] {
         let zoom = self.zoom + 1;
         let x = self.x * 2;
@@ -53,53 +82,207 @@ impl Tile {
         ]
     }
 
+    // Squared, in tiles of this zoom from the one containing the block position
+    #[must_use]
+    pub fn distance_from(&self, (x, z): (i32, i32)) -> i64 {
+        let origin = Self::from_position(4 - self.zoom, x, z);
+
+        (i64::from(self.x) - i64::from(origin.x)).pow(2)
+            + (i64::from(self.y) - i64::from(origin.y)).pow(2)
+    }
+
+    // Self followed by each enclosing tile up to the root
+    pub fn ancestry(&self) -> impl Iterator<Item = Self> {
+        std::iter::successors(Some(self.clone()), |t| {
+            (t.zoom > 0).then(|| Self {
+                zoom: t.zoom - 1,
+                x: t.x.div_euclid(2),
+                y: t.y.div_euclid(2),
+            })
+        })
+    }
+
+    // Tiles of the most detailed zoom within this one
+    pub fn leaves(&self) -> impl Iterator<Item = Self> + '_ {
+        let size = 2_i32.pow(u32::from(4 - self.zoom));
+
+        (0..size).flat_map(move |dx| {
+            (0..size).map(move |dy| Self {
+                zoom: 4,
+                x: self.x * size + dx,
+                y: self.y * size + dy,
+            })
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn render<'a>(
         &self,
         output_path: &Path,
+        origin: Origin,
+        pattern: &TilePattern,
         maps: impl IntoIterator<Item = &'a (&'a Map, MapData)>,
         maps_modified: SystemTime,
+        sink: &dyn ImageSink,
         force: bool,
-    ) -> Result<bool> {
-        let dir_path = output_path.join(format!("tiles/{}/{}", self.zoom, self.x));
-
-        let base_path = dir_path.join(self.y.to_string());
-        let meta_path = base_path.with_extension("meta.json");
+        dedupe: bool,
+        batch: Option<&Batch>,
+    ) -> Result<Rendered> {
+        let base_path = format!("tiles/{}", pattern.format(origin.number(self)));
+        let meta_path = output_path.join(format!("{base_path}.meta.json"));
 
         if !force
             && fs::metadata(&meta_path)
                 .and_then(|m| m.modified())
                 .map_or(false, |meta_modified| meta_modified >= maps_modified)
         {
-            return Ok(false);
+            return Ok(Rendered::UpToDate);
         }
 
-        let mut canvas = Canvas::default();
+        let maps = maps.into_iter().collect::<Vec<_>>();
+        let composite = {
+            span!("composite", zoom = self.zoom, x = self.x, y = self.y);
+            batch.map_or_else(
+                || Arc::new(Composite::draw(self, maps.iter().copied())),
+                |b| b.composite(self, &maps),
+            )
+        };
+        let canvas = &composite.canvas;
+
+        // Maps are listed from the top of the stack down, each with the fraction of the tile that
+        // it shows through those above
+        let mut ids = Vec::new();
+        let mut layers = Vec::new();
+        for ((map, _), &covered) in maps.iter().zip(&composite.coverage) {
+            ids.push(map.id);
+            layers.push(json!({
+                "id": map.id,
+                "modified": map.modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+                "coverage": f64::from(covered) / f64::from(CANVAS_PIXELS),
+                "locked": map.locked,
+                "tracking": map.tracking,
+            }));
+        }
 
-        let ids = maps
-            .into_iter()
-            .map(|(map, data)| {
-                canvas.draw(self, map, data);
+        // Encoding is skipped where a change of the maps, e.g. beyond this tile, leaves the pixels as
+        // they were
+        let image_path = output_path.join(format!("{base_path}.{}", sink.extension()));
+        let pixels = format!("{:08x}", crc32fast::hash(&canvas.pixels));
+        let unchanged = !force
+            && previous_pixels(&meta_path).as_ref() == Some(&pixels)
+            && (!canvas.is_dirty || image_path.exists());
+
+        // Metadata is removed if the tile fails to be written so that the next run doesn't take it
+        // as up to date
+        fs::create_dir_all(meta_path.parent().unwrap())?;
+        let written = (|| {
+            let meta_file = File::create(&meta_path)?;
+            serde_json::to_writer(
+                &meta_file,
+                &json!({ "maps": ids, "layers": layers, "pixels": pixels }),
+            )?;
+            #[cfg(feature = "faults")]
+            crate::fault::check(crate::fault::Fault::FailedSetModified, &meta_path)?;
+            meta_file.set_modified(maps_modified)?;
+            if unchanged {
+                return Ok(Rendered::Unchanged);
+            }
+
+            // Image
+            // Replaced rather than overwritten since it may be linked
+            if canvas.is_dirty {
+                let mut image = Vec::new();
+                {
+                    span!("encode", zoom = self.zoom, x = self.x, y = self.y);
+                    sink.write(&mut image, &canvas.pixels)?;
+                }
+                remove_if_exists(&image_path)?;
+                #[cfg(feature = "faults")]
+                crate::fault::check(crate::fault::Fault::DeniedTileWrite, &image_path)?;
+                if dedupe {
+                    blob::write(output_path, &image, &image_path)?;
+                } else {
+                    fs::write(&image_path, image)?;
+                }
+                File::options()
+                    .write(true)
+                    .open(&image_path)?
+                    .set_modified(maps_modified)?;
+            }
+
+            Ok(Rendered::Changed)
+        })();
 
-                map.id
+        written
+            .or_else(|e: anyhow::Error| {
+                remove_if_exists(&meta_path)?;
+                Err(e)
             })
-            .collect::<Vec<_>>();
+            .with_context(|| format!("Failed to write tile {base_path}"))
+    }
+
+    /// Overlay colored by the age of the map drawn at each pixel as of `now`. Since the colors
+    /// shift with time, it's rendered again at least daily.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_freshness<'a>(
+        &self,
+        output_path: &Path,
+        origin: Origin,
+        pattern: &TilePattern,
+        maps: impl IntoIterator<Item = &'a (&'a Map, MapData)>,
+        maps_modified: SystemTime,
+        now: SystemTime,
+        sink: &dyn ImageSink,
+        force: bool,
+    ) -> Result<bool> {
+        let image_path = output_path.join(format!(
+            "freshness/{}.{}",
+            pattern.format(origin.number(self)),
+            sink.extension()
+        ));
+
+        if !force
+            && fs::metadata(&image_path)
+                .and_then(|m| m.modified())
+                .map_or(false, |rendered| {
+                    rendered >= maps_modified && rendered + DAY > now
+                })
+        {
+            return Ok(false);
+        }
 
-        // Metadata
-        fs::create_dir_all(&dir_path)?;
-        let meta_file = File::create(&meta_path)?;
-        serde_json::to_writer(&meta_file, &json!({ "maps": ids }))?;
-        meta_file.set_modified(maps_modified)?;
+        let mut canvas = Canvas::default();
+        for (map, data) in maps {
+            let days = now
+                .duration_since(map.modified)
+                .unwrap_or_default()
+                .as_secs()
+                / DAY.as_secs();
+            let color = FRESHNESS
+                .iter()
+                .find(|&&(within, _)| days < within)
+                .map_or(STALE, |&(_, color)| color);
+
+            canvas.draw(
+                self,
+                map,
+                &MapData(data.0.map(|p| if p < 4 { p } else { color })),
+            );
+        }
 
-        // Image
+        // Replaced rather than overwritten since it may be linked
+        remove_if_exists(&image_path)?;
         if canvas.is_dirty {
-            let mut webp_file = File::create(base_path.with_extension("webp"))?;
-            write_webp(&mut webp_file, &canvas.pixels)?;
-            webp_file.set_modified(maps_modified)?;
+            fs::create_dir_all(image_path.parent().unwrap())?;
+            let mut image_file = File::create(&image_path)?;
+            sink.write(&mut image_file, &canvas.pixels)?;
+            image_file.set_modified(now)?;
         }
 
         Ok(true)
     }
 
+    #[must_use]
     pub fn root(&self) -> Self {
         let (x, y) = self.position();
 
@@ -123,19 +306,41 @@ impl Add<(i32, i32)> for &Tile {
     }
 }
 
+const CANVAS_PIXELS: u16 = 128 * 128;
+
+/// Maps drawn onto a tile, each where none drawn before it is, with how many pixels each covers
+pub struct Composite {
+    canvas: Canvas,
+    coverage: Vec<u16>,
+}
+
+impl Composite {
+    pub fn draw<'a>(tile: &Tile, maps: impl IntoIterator<Item = &'a (&'a Map, MapData)>) -> Self {
+        let mut canvas = Canvas::default();
+        let coverage = maps
+            .into_iter()
+            .map(|(map, data)| canvas.draw(tile, map, data))
+            .collect();
+
+        Self { canvas, coverage }
+    }
+}
+
 struct Canvas {
     is_dirty: bool,
     pixels: [u8; 128 * 128],
 }
 
 impl Canvas {
-    fn draw(&mut self, tile: &Tile, map: &Map, data: &MapData) {
+    // Pixels are drawn only where none of a map drawn before is, returning how many
+    fn draw(&mut self, tile: &Tile, map: &Map, data: &MapData) -> u16 {
         let ((tx, ty), (mx, my)) = (tile.position(), map.tile.position());
         let factor = 2_usize.pow(u32::from(tile.zoom - map.tile.zoom));
         #[allow(clippy::cast_sign_loss)] // tile ⊆ map
         let a = (tx - mx) as usize / factor + (ty - my) as usize / factor * 128;
         let b = 128 - 128 / factor;
 
+        let mut covered = 0;
         for (i, pixel) in self.pixels.iter_mut().enumerate().filter(|(_, p)| **p < 4) {
             let (j, k) = (i / factor, i / 128);
             let map_pixel = data.0[a + j + b * k - (k - j / 128) * 128];
@@ -143,11 +348,21 @@ impl Canvas {
             if map_pixel >= 4 {
                 self.is_dirty = true;
                 *pixel = map_pixel;
+                covered += 1;
             }
         }
+
+        covered
     }
 }
 
+// Checksum of the composited pixels as of the last render, absent from metadata of old versions
+fn previous_pixels(meta_path: &Path) -> Option<String> {
+    let meta: Value = serde_json::from_reader(File::open(meta_path).ok()?).ok()?;
+
+    meta.get("pixels")?.as_str().map(ToOwned::to_owned)
+}
+
 // Pending https://github.com/rust-lang/rust/issues/61415
 impl Default for Canvas {
     fn default() -> Self {
@@ -161,6 +376,7 @@ impl Default for Canvas {
 #[cfg(test)]
 mod test {
     use super::*;
+    use itertools::{assert_equal, Itertools};
 
     #[test]
     fn from_position() {
@@ -218,5 +434,24 @@ mod test {
         assert_eq!(steps[1].quadrants()[3], steps[2]);
         assert_eq!(steps[2].quadrants()[2], steps[3]);
         assert_eq!(steps[3].quadrants()[3], steps[4]);
+
+        assert_equal(steps[4].ancestry(), steps.iter().rev().cloned());
+        assert!(steps[0].leaves().contains(&steps[4]));
+        assert_eq!(steps[0].leaves().count(), 256);
+        assert_equal(steps[4].leaves(), [steps[4].clone()]);
+    }
+
+    #[test]
+    fn distance_from() {
+        assert_eq!(Tile::new(4, 0, 0).distance_from((127, 127)), 0);
+        assert_eq!(Tile::new(4, 3, -4).distance_from((0, 0)), 25);
+        assert_eq!(Tile::new(0, -1, 0).distance_from((0, 0)), 1);
+        assert_eq!(Tile::new(0, -1, 0).distance_from((-1, 0)), 0);
+
+        let quadrants = Tile::new(0, 0, 0).quadrants();
+        let nearest = quadrants
+            .iter()
+            .min_by_key(|q| q.distance_from((1500, 100)));
+        assert_eq!(nearest, Some(&Tile::new(1, 1, 0)));
     }
 }
@@ -1,12 +1,16 @@
-use crate::palette::PALETTE;
+use crate::Error;
 use anyhow::{anyhow, Result};
-use flate2::read::GzDecoder;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use indicatif::{ProgressBar, ProgressStyle};
+use log::debug;
 use std::array;
 use std::borrow::Cow;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 pub fn progress_bar(
     quiet: bool,
@@ -32,18 +36,156 @@ pub fn progress_bar(
     }
 }
 
-pub fn read_gz(path: &Path) -> Result<Vec<u8>> {
-    let mut decoder = GzDecoder::new(File::open(path)?);
+// The game rewrites files such as level.dat in place while saving, so a file that fails to parse
+// may be read again once it has likely been completed
+pub fn retry_once<T, E: Display>(path: &Path, read: impl Fn() -> Result<T, E>) -> Result<T, E> {
+    read().or_else(|e| {
+        debug!("Retry {}: {e}", path.display());
+        thread::sleep(Duration::from_millis(250));
+        read()
+    })
+}
+
+pub fn gz_path(path: &Path) -> PathBuf {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    gz_path.into()
+}
+
+// A gzip stream ends with the CRC32 and size of what it holds, by which a compressed copy is judged
+// stale even where the original was rewritten under the same modification time, as outputs dated to
+// the maps are.
+fn is_current(gz_path: &Path, original: &[u8]) -> io::Result<bool> {
+    let mut trailer = [0; 8];
+    let mut f = File::open(gz_path)?;
+    f.seek(SeekFrom::End(-8))?;
+    f.read_exact(&mut trailer)?;
+
+    #[allow(clippy::cast_possible_truncation)] // Modulo 2³², as gzip records it
+    let size = original.len() as u32;
+    Ok(trailer[..4] == crc32fast::hash(original).to_le_bytes()
+        && trailer[4..] == size.to_le_bytes())
+}
+
+// The compressed copy shares the modification time of the original
+pub fn precompress(path: &Path, force: bool) -> Result<bool> {
+    let gz_path = gz_path(path);
+    let original = fs::read(path)?;
+
+    if !force && is_current(&gz_path, &original).unwrap_or(false) {
+        return Ok(false);
+    }
+
+    let modified = fs::metadata(path)?.modified()?;
+    let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::best());
+    encoder.write_all(&original)?;
+    encoder.finish()?.set_modified(modified)?;
+
+    Ok(true)
+}
+
+// Files of the world are dated by whichever of these first succeeds, since some FUSE and network
+// mounts fail one but not another, e.g. stat of the path yet not fstat of the open file
+pub fn read_modified(path: &Path) -> Result<SystemTime, Error> {
+    let strategies: [fn(&Path) -> io::Result<SystemTime>; 3] = [
+        |p| fs::metadata(p)?.modified(),
+        |p| fs::symlink_metadata(p)?.modified(),
+        |p| File::open(p)?.metadata()?.modified(),
+    ];
+
+    let mut first_error = None;
+    for strategy in strategies {
+        match strategy(path) {
+            Ok(modified) => return Ok(modified),
+            // Removed meanwhile, which no other strategy will change
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::io(path, e)),
+            Err(e) => {
+                debug!(
+                    "Failed to read modification time of {}: {e}",
+                    path.display()
+                );
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(Error::io(path, first_error.unwrap()))
+}
+
+pub fn read_gz(path: &Path) -> Result<Vec<u8>, Error> {
     let mut data = Vec::new();
 
-    decoder.read_to_end(&mut data)?;
+    File::open(path)
+        .and_then(|f| GzDecoder::new(f).read_to_end(&mut data))
+        .map_err(|e| Error::io(path, e))?;
 
     Ok(data)
 }
 
-pub fn write_webp(w: &mut impl Write, indexed: &[u8; 128 * 128]) -> Result<()> {
-    let rgb: [u8; 128 * 128 * 3] = array::from_fn(|i| PALETTE[indexed[i / 3] as usize * 3 + i % 3]);
-    let encoder = webp::Encoder::from_rgb(&rgb, 128, 128);
+// Bytes available to this user on the filesystem of the path, or of its nearest existing ancestor
+// since the output may be yet to be created
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(path);
+    let c_path = CString::new(existing.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: The path is NUL-terminated and statvfs fully initializes the struct on success.
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+
+    #[allow(clippy::useless_conversion)] // Widths vary by platform
+    Ok(Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize)))
+}
+
+#[cfg(not(unix))]
+pub const fn available_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(feature = "native")]
+pub fn read_webp(path: &Path) -> Result<[u8; 128 * 128 * 3]> {
+    let data = fs::read(path)?;
+    let image = webp::Decoder::new(&data)
+        .decode()
+        .ok_or_else(|| anyhow!("WebP decoding error: {}", path.display()))?;
+    let channels = if image.is_alpha() { 4 } else { 3 };
+
+    if (image.width(), image.height()) != (128, 128) {
+        return Err(anyhow!("Unexpected dimensions: {}", path.display()));
+    }
+
+    Ok(array::from_fn(|i| image[i / 3 * channels + i % 3]))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn read_webp(path: &Path) -> Result<[u8; 128 * 128 * 3]> {
+    let mut decoder = image_webp::WebPDecoder::new(io::Cursor::new(fs::read(path)?))
+        .map_err(|e| anyhow!("WebP decoding error: {}: {e}", path.display()))?;
+    let channels = if decoder.has_alpha() { 4 } else { 3 };
+
+    if decoder.dimensions() != (128, 128) {
+        return Err(anyhow!("Unexpected dimensions: {}", path.display()));
+    }
+
+    let mut image = vec![0; 128 * 128 * channels];
+    decoder
+        .read_image(&mut image)
+        .map_err(|e| anyhow!("WebP decoding error: {}: {e}", path.display()))?;
+
+    Ok(array::from_fn(|i| image[i / 3 * channels + i % 3]))
+}
+
+#[cfg(feature = "native")]
+pub fn write_webp_rgb(w: &mut impl Write, rgb: &[u8]) -> Result<()> {
+    let encoder = webp::Encoder::from_rgb(rgb, 128, 128);
     let encoded = encoder
         .encode_simple(true, 100.0)
         .map_err(|e| anyhow!("WebP encoding error: {:?}", e))?;
@@ -51,3 +193,43 @@ pub fn write_webp(w: &mut impl Write, indexed: &[u8; 128 * 128]) -> Result<()> {
 
     Ok(())
 }
+
+// Lossless as by libwebp, though larger
+#[cfg(not(feature = "native"))]
+pub fn write_webp_rgb(w: &mut impl Write, rgb: &[u8]) -> Result<()> {
+    image_webp::WebPEncoder::new(w)
+        .encode(rgb, 128, 128, image_webp::ColorType::Rgb8)
+        .map_err(|e| anyhow!("WebP encoding error: {e}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn precompress_rewritten() {
+        let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let path = dir.path().join("banners.json");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let write = |contents: &str| {
+            fs::write(&path, contents).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+
+        write("[]");
+        assert!(precompress(&path, false).unwrap());
+        assert!(!precompress(&path, false).unwrap());
+
+        // Under the same modification time
+        write("[1]");
+        assert!(precompress(&path, false).unwrap());
+        assert_eq!(read_gz(&gz_path(&path)).unwrap(), b"[1]");
+    }
+}
@@ -0,0 +1,116 @@
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Maps whose items were found only in other dimensions than the one their data depicts
+    CrossDimension,
+    /// Search caches that failed to be read, which are replaced by a search of the whole world
+    DiscardedCache,
+    /// Files that failed to be read, which are skipped until modified
+    Quarantined,
+    /// Maps of other than 128 × 128 colors, which are cropped or padded
+    Resized,
+    /// Worlds that the game was saving as the search began, which may read inconsistently
+    Saving,
+}
+
+impl WarningKind {
+    const fn description(self) -> &'static str {
+        match self {
+            Self::CrossDimension => "maps found only in other dimensions than they depict",
+            Self::DiscardedCache => "search caches discarded",
+            Self::Quarantined => "files quarantined",
+            Self::Resized => "maps cropped or padded to 128 × 128",
+            Self::Saving => "worlds being saved as the search began",
+        }
+    }
+}
+
+/// Warnings of one kind, each described by its subject, e.g. a path or a map ID
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct WarningGroup {
+    pub kind: WarningKind,
+    pub subjects: Vec<String>,
+}
+
+impl fmt::Display for WarningGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.subjects.len(),
+            self.kind.description(),
+            self.subjects.join(", ")
+        )
+    }
+}
+
+// Warnings raised within workers would interleave with each other and with progress bars, so
+// they're collected and summarized by kind once the run is over.
+#[derive(Default)]
+pub struct WarningCollector(Mutex<BTreeMap<WarningKind, Vec<String>>>);
+
+impl WarningCollector {
+    pub fn add(&self, kind: WarningKind, subject: impl Into<String>) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(kind)
+            .or_default()
+            .push(subject.into());
+    }
+
+    pub fn summarize(self) -> Vec<WarningGroup> {
+        self.0
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|(kind, mut subjects)| {
+                subjects.sort_unstable();
+                subjects.dedup();
+                WarningGroup { kind, subjects }
+            })
+            .collect()
+    }
+
+    pub fn log(self) -> Vec<WarningGroup> {
+        let groups = self.summarize();
+        for group in &groups {
+            warn!("{group}");
+        }
+
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn grouped() {
+        let warnings = WarningCollector::default();
+        (0..4).into_par_iter().for_each(|id| {
+            warnings.add(WarningKind::Resized, id.to_string());
+        });
+        warnings.add(WarningKind::Quarantined, "region/r.0.0.mca");
+        warnings.add(WarningKind::Quarantined, "region/r.0.0.mca");
+
+        let groups = warnings.summarize();
+        assert_eq!(groups[0].kind, WarningKind::Quarantined);
+        assert_eq!(
+            groups[0].to_string(),
+            "1 files quarantined: region/r.0.0.mca"
+        );
+        assert_eq!(
+            groups[1].to_string(),
+            "4 maps cropped or padded to 128 × 128: 0, 1, 2, 3"
+        );
+    }
+}
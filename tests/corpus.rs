@@ -0,0 +1,28 @@
+use little_a_map::corpus;
+use std::collections::BTreeSet;
+use std::env;
+use std::path::Path;
+
+// Snippets of each game version are read as by the search and compared with their snapshots, which
+// are replaced by what's read when run with BLESS=1, e.g. after deliberately changing what's read.
+#[test]
+fn snapshots() {
+    let corpus_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/corpus");
+    let outcomes = corpus::run(&corpus_path, env::var_os("BLESS").is_some()).unwrap();
+
+    let versions = outcomes
+        .iter()
+        .map(|o| o.version.as_str())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(versions, ["1.20.2", "1.20.6", "1.21.4"].into());
+
+    let differences = outcomes
+        .iter()
+        .filter_map(|o| Some(format!("{}:\n{}", o.snippet, o.diff.as_ref()?)))
+        .collect::<Vec<_>>();
+    assert!(
+        differences.is_empty(),
+        "Snapshots differ:\n{}",
+        differences.join("\n")
+    );
+}
@@ -0,0 +1,93 @@
+use glob::glob;
+use little_a_map::dimension::Dimension;
+use little_a_map::fault::{inject, Fault};
+use little_a_map::synthetic::{filled_map, SyntheticChest, SyntheticMap, SyntheticWorld};
+use little_a_map::warning::WarningKind;
+use little_a_map::{Error, Generator, RunReport};
+use std::io;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn world() -> TempDir {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    input
+}
+
+fn run(input: &Path, output: &Path) -> Result<RunReport, Error> {
+    Generator::builder()
+        .world(input)
+        .output(output)
+        .quiet(true)
+        .build()
+        .unwrap()
+        .run()
+}
+
+fn count(output: &Path, pattern: &str) -> usize {
+    glob(output.join(pattern).to_str().unwrap())
+        .unwrap()
+        .count()
+}
+
+#[test]
+fn truncated_region() {
+    let input = world();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let _injected = inject(Fault::TruncatedRegion, input.path());
+    let report = run(input.path(), output.path()).unwrap();
+    assert_eq!(report.maps_found, 0);
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!(report.warnings[0].kind, WarningKind::Quarantined);
+    assert!(report.warnings[0].subjects[0].ends_with("r.0.0.mca"));
+}
+
+#[test]
+fn denied_tile_write() {
+    let input = world();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let injected = inject(Fault::DeniedTileWrite, output.path());
+    let error = run(input.path(), output.path()).unwrap_err();
+    assert!(
+        matches!(error, Error::Io { ref source, .. } if source.kind() == io::ErrorKind::PermissionDenied)
+    );
+    assert_eq!(count(output.path(), "tiles/**/*.meta.json"), 0);
+
+    // Rendered in full once the output is writable again
+    drop(injected);
+    let report = run(input.path(), output.path()).unwrap();
+    assert!(report.tiles_rendered > 0);
+    assert_eq!(
+        count(output.path(), "tiles/**/*.webp"),
+        count(output.path(), "tiles/**/*.meta.json")
+    );
+}
+
+#[test]
+fn failed_set_modified() {
+    let input = world();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let injected = inject(Fault::FailedSetModified, output.path());
+    let error = run(input.path(), output.path()).unwrap_err();
+    assert!(
+        matches!(error, Error::Io { ref source, .. } if source.kind() == io::ErrorKind::Unsupported)
+    );
+    assert_eq!(count(output.path(), "tiles/**/*.meta.json"), 0);
+
+    drop(injected);
+    assert!(run(input.path(), output.path()).unwrap().tiles_rendered > 0);
+}
@@ -0,0 +1,1342 @@
+use itertools::{assert_equal, Itertools};
+use little_a_map::dimension::Dimension;
+use little_a_map::i18n::Lang;
+use little_a_map::synthetic::{
+    bundle, container, filled_map, SyntheticBanner, SyntheticChest, SyntheticMap, SyntheticPlayer,
+    SyntheticWorld,
+};
+use little_a_map::warning::WarningKind;
+use little_a_map::{
+    cache_size,
+    config::{Config, MapImages, PlayerSelection},
+    level::Level,
+    palette, prune_cache, render, render_map, search, CacheMiss, Error, Generator,
+};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn synthetic() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let mut banner_map = SyntheticMap::new(4, 1, (128, 128));
+    banner_map.banners.push(SyntheticBanner {
+        position: (100, 64, 100),
+        color: "red".to_owned(),
+        label: Some("Synthetic Banner".to_owned()),
+    });
+
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(0, 0, (64, 64)),
+            SyntheticMap::new(1, 0, (-64, 64)),
+            SyntheticMap::new(2, 2, (256, -256)),
+            SyntheticMap::new(3, 4, (1024, 1024)),
+            banner_map,
+        ],
+        chests: vec![
+            SyntheticChest {
+                dimension: Dimension::Overworld,
+                position: (1, 64, 1),
+                items: vec![
+                    filled_map(0),
+                    container("minecraft:red_shulker_box", &[filled_map(1)]),
+                ],
+            },
+            SyntheticChest {
+                dimension: Dimension::Overworld,
+                position: (-600, 70, 900),
+                items: vec![bundle(&[filled_map(2)])],
+            },
+        ],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+            inventory: vec![filled_map(3)],
+            ender_items: vec![filled_map(4)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    assert_equal(ids.iter().sorted(), &[0, 1, 2, 3, 4]);
+
+    render(
+        input.path(),
+        output.path(),
+        true,
+        true,
+        &level,
+        &ids,
+        &Config::default(),
+    )
+    .unwrap();
+    for id in 0..5 {
+        assert!(output.path().join(format!("maps/{id}.webp")).exists());
+    }
+
+    let banners: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("banners.json")).unwrap()).unwrap();
+    assert_eq!(
+        banners["features"][0]["properties"]["name"],
+        "Synthetic Banner"
+    );
+    assert_eq!(banners["features"][0]["geometry"]["coordinates"][0], 100);
+}
+
+#[test]
+fn unicode() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let labels = ["地図の家", "Café 🗺️", "\"<b>\""];
+
+    let mut map = SyntheticMap::new(0, 0, (64, 64));
+    for (i, label) in (0..).zip(labels) {
+        map.banners.push(SyntheticBanner {
+            position: (10 * i, 64, 0),
+            color: "blue".to_owned(),
+            label: Some(label.to_owned()),
+        });
+    }
+    SyntheticWorld {
+        maps: vec![map],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+            inventory: vec![filled_map(0)],
+            ender_items: Vec::new(),
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    let config = Config {
+        lang: Lang::Ja,
+        ..Config::default()
+    };
+    render(
+        input.path(),
+        output.path(),
+        true,
+        true,
+        &level,
+        &ids,
+        &config,
+    )
+    .unwrap();
+
+    let banners: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("banners.json")).unwrap()).unwrap();
+    assert_equal(
+        banners["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["properties"]["name"].as_str().unwrap())
+            .sorted(),
+        labels.into_iter().sorted(),
+    );
+
+    let index = fs::read_to_string(output.path().join("index.html")).unwrap();
+    assert!(index.contains(r#"<html lang="ja">"#));
+    assert!(index.contains("未踏"));
+}
+
+#[test]
+fn banner_icons() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let mut map = SyntheticMap::new(0, 0, (64, 64));
+    for (x, label) in [(0, "[shop] Bakery"), (10, "⚒ Smithy"), (20, "Spawn")] {
+        map.banners.push(SyntheticBanner {
+            position: (x, 64, 0),
+            color: "white".to_owned(),
+            label: Some(label.to_owned()),
+        });
+    }
+    SyntheticWorld {
+        maps: vec![map],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+            inventory: vec![filled_map(0)],
+            ender_items: Vec::new(),
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    let config: Config =
+        toml::from_str("[icons]\n\"[shop]\" = \"shop\"\n\"⚒\" = \"anvil\"").unwrap();
+    render(
+        input.path(),
+        output.path(),
+        true,
+        true,
+        &level,
+        &ids,
+        &config,
+    )
+    .unwrap();
+
+    let banners: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("banners.json")).unwrap()).unwrap();
+    assert_equal(
+        banners["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["properties"]["icon"].as_str())
+            .sorted(),
+        [None, Some("anvil"), Some("shop")],
+    );
+    assert!(fs::read_to_string(output.path().join("icons.svg"))
+        .unwrap()
+        .contains(r#"id="shop""#));
+
+    render(
+        input.path(),
+        output.path(),
+        true,
+        false,
+        &level,
+        &ids,
+        &Config::default(),
+    )
+    .unwrap();
+    assert!(!output.path().join("icons.svg").exists());
+    let banners: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("banners.json")).unwrap()).unwrap();
+    assert!(banners["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|f| f["properties"]["icon"].is_null()));
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn profile() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let traces = output.path().join("traces");
+    let generator = Generator::builder()
+        .world(input.path())
+        .output(output.path().join("output"))
+        .quiet(true)
+        .profile(&traces)
+        .build()
+        .unwrap();
+    generator.run().unwrap();
+    generator.run().unwrap();
+
+    // One trace per run
+    let paths = glob::glob(traces.join("run-*.json").to_str().unwrap())
+        .unwrap()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_eq!(paths.len(), 2);
+    let trace: serde_json::Value = serde_json::from_reader(File::open(&paths[0]).unwrap()).unwrap();
+    let names = trace
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|event| event["name"].as_str())
+        .collect::<Vec<_>>();
+    for phase in [
+        "search",
+        "decompress",
+        "parse",
+        "render",
+        "composite",
+        "encode",
+    ] {
+        assert!(names.contains(&phase), "{phase} missing from {names:?}");
+    }
+}
+
+#[cfg(feature = "gis")]
+#[test]
+fn geopackage() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let mut map = SyntheticMap::new(0, 0, (64, 64));
+    map.banners.push(SyntheticBanner {
+        position: (10, 64, 20),
+        color: "red".to_owned(),
+        label: Some("Spawn".to_owned()),
+    });
+    SyntheticWorld {
+        maps: vec![map, SyntheticMap::new(1, 1, (64, 64))],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+            inventory: vec![filled_map(0), filled_map(1)],
+            ender_items: Vec::new(),
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let run = |geopackage| {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .config(Config {
+                geopackage,
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+
+    run(true);
+    let gpkg_path = output.path().join("world.gpkg");
+    let db = rusqlite::Connection::open(&gpkg_path).unwrap();
+    let query = |sql: &str| -> i64 { db.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(query("PRAGMA application_id"), 0x4750_4b47);
+    assert_eq!(
+        query("SELECT COUNT(*) FROM banners WHERE name = 'Spawn'"),
+        1
+    );
+    assert_eq!(query("SELECT COUNT(*) FROM maps"), 2);
+    assert_eq!(query("SELECT COUNT(*) FROM maps WHERE scale = 1"), 1);
+    assert_eq!(
+        query("SELECT COUNT(*) FROM tiles WHERE path LIKE 'tiles/%.webp'"),
+        query("SELECT COUNT(*) FROM tiles")
+    );
+    assert!(query("SELECT COUNT(*) FROM tiles") > 0);
+
+    let geom: Vec<u8> = db
+        .query_row("SELECT geom FROM banners", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(&geom[..2], b"GP");
+    assert_eq!(geom[21..29], (-20.0_f64).to_le_bytes());
+    drop(db);
+
+    run(false);
+    assert!(!gpkg_path.exists());
+}
+
+#[test]
+fn split_dimensions() {
+    let root = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let input = root.path().join("world");
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(0, 0, (64, 64)),
+            SyntheticMap::new(1, 0, (-64, 64)),
+        ],
+        chests: vec![
+            SyntheticChest {
+                dimension: Dimension::Nether,
+                position: (8, 64, 8),
+                items: vec![filled_map(0)],
+            },
+            SyntheticChest {
+                dimension: Dimension::End,
+                position: (100, 64, 0),
+                items: vec![filled_map(1)],
+            },
+        ],
+        split_dimensions: true,
+        ..SyntheticWorld::default()
+    }
+    .write_to(&input)
+    .unwrap();
+
+    assert!(root.path().join("world_nether/DIM-1/region").is_dir());
+    assert!(root.path().join("world_the_end/DIM1/region").is_dir());
+
+    let ids = search(&input, output.path(), true, true, None, 0).unwrap();
+    assert_equal(ids.iter().sorted(), &[0, 1]);
+}
+
+#[test]
+fn quarantine() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    let corrupt = input.path().join("region/r.3.3.mca");
+    fs::write(&corrupt, b"truncated").unwrap();
+    fs::write(input.path().join("region/r.4.4.mca"), b"").unwrap();
+    let corrupt_player = input
+        .path()
+        .join("playerdata/65836968-df64-4ca6-af3c-12f547c7f765.dat");
+    fs::create_dir_all(corrupt_player.parent().unwrap()).unwrap();
+    fs::write(&corrupt_player, b"truncated").unwrap();
+
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    assert_equal(ids.iter().sorted(), &[0]);
+
+    let quarantine_path = output.path().join(".cache/quarantine.json");
+    let quarantined = || {
+        let quarantine: serde_json::Value =
+            serde_json::from_reader(File::open(&quarantine_path).unwrap()).unwrap();
+        quarantine
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        quarantined(),
+        [corrupt_player.to_str().unwrap(), corrupt.to_str().unwrap()]
+    );
+
+    // Quarantine persists while the files are unchanged
+    search(input.path(), output.path(), true, false, None, 0).unwrap();
+    assert_eq!(quarantined().len(), 2);
+
+    fs::remove_file(&corrupt_player).unwrap();
+    fs::remove_file(&corrupt).unwrap();
+    search(input.path(), output.path(), true, false, None, 0).unwrap();
+    assert!(!quarantine_path.exists());
+}
+
+#[test]
+fn error_categories() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let run = || {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .build()
+            .unwrap()
+            .run()
+    };
+
+    SyntheticWorld {
+        version: "1.19.4".to_owned(),
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    assert!(matches!(
+        Level::from_world_path(input.path()),
+        Err(Error::Incompatible { ref version, .. }) if version == "1.19.4"
+    ));
+
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+            inventory: vec![filled_map(0)],
+            ender_items: Vec::new(),
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    run().unwrap();
+
+    let map_path = input.path().join("data/map_0.dat");
+    let mut encoder = flate2::write::GzEncoder::new(
+        File::create(&map_path).unwrap(),
+        flate2::Compression::default(),
+    );
+    std::io::Write::write_all(&mut encoder, b"truncated").unwrap();
+    encoder.finish().unwrap();
+    assert!(matches!(
+        run(),
+        Err(Error::Nbt { path: Some(ref p), map_id: Some(0), .. }) if *p == map_path
+    ));
+
+    fs::remove_file(&map_path).unwrap();
+    assert!(matches!(
+        run(),
+        Err(Error::Io { path: Some(ref p), .. }) if *p == map_path
+    ));
+}
+
+#[test]
+fn level_backup() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    SyntheticWorld {
+        spawn: (100, -200),
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    let level_path = input.path().join("level.dat");
+
+    fs::copy(&level_path, input.path().join("level.dat_old")).unwrap();
+    fs::write(&level_path, b"truncated").unwrap();
+    let level = Level::from_world_path(input.path()).unwrap();
+    assert_eq!((level.spawn_x, level.spawn_z), (100, -200));
+
+    fs::remove_file(input.path().join("level.dat_old")).unwrap();
+    assert!(matches!(
+        Level::from_world_path(input.path()),
+        Err(Error::Io { path: Some(ref p), .. }) if *p == level_path
+    ));
+}
+
+#[test]
+fn world_age() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    assert_eq!((level.day(), level.time), (1234, 30_000_000));
+    assert_eq!(
+        level.last_played(),
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    );
+
+    let config = Config {
+        statistics: true,
+        ..Config::default()
+    };
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    render(
+        input.path(),
+        output.path(),
+        true,
+        true,
+        &level,
+        &ids,
+        &config,
+    )
+    .unwrap();
+
+    let run: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("run.json")).unwrap()).unwrap();
+    assert_eq!(run["world"]["day"], 1234);
+    assert_eq!(run["world"]["ticks"], 30_000_000);
+    assert_eq!(run["world"]["last_played"], "2023-11-14T22:13:20.000Z");
+
+    let index = fs::read_to_string(output.path().join("index.html")).unwrap();
+    assert!(index.contains(r#""day": 1234"#));
+}
+
+#[test]
+fn version_families() {
+    for (version, data_version) in [
+        ("1.17.1", 2730), // Chunks within `Level`
+        ("1.18.2", 2975), // Items with `tag`
+        ("1.20.4", 3700),
+        ("1.21.4", 4189), // Items with components
+    ] {
+        let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+        SyntheticWorld {
+            version: version.to_owned(),
+            data_version,
+            maps: (0..4)
+                .map(|id| SyntheticMap::new(id, 0, (64, 64)))
+                .collect(),
+            chests: vec![SyntheticChest {
+                dimension: Dimension::Overworld,
+                position: (1, 64, 1),
+                items: vec![
+                    filled_map(0),
+                    container("minecraft:shulker_box", &[filled_map(1)]),
+                    bundle(&[filled_map(2)]),
+                ],
+            }],
+            players: vec![SyntheticPlayer {
+                uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+                inventory: vec![filled_map(3)],
+                ender_items: Vec::new(),
+            }],
+            ..SyntheticWorld::default()
+        }
+        .write_to(input.path())
+        .unwrap();
+
+        let level = Level::from_world_path(input.path()).unwrap();
+        assert_eq!(level.data_version, data_version);
+        let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+        assert_equal(ids.iter().sorted(), &[0, 1, 2, 3]);
+        render(
+            input.path(),
+            output.path(),
+            true,
+            true,
+            &level,
+            &ids,
+            &Config::default(),
+        )
+        .unwrap();
+        assert!(output.path().join("tiles/4/0/0.webp").exists(), "{version}");
+    }
+
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    SyntheticWorld {
+        version: "1.16.5".to_owned(),
+        data_version: 2586,
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    assert!(matches!(
+        Level::from_world_path(input.path()),
+        Err(Error::Incompatible { .. })
+    ));
+}
+
+#[test]
+fn stacking() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let mut partial = SyntheticMap::new(1, 0, (64, 64));
+    partial.colors[..128 * 64].fill(0);
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64)), partial],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0), filled_map(1)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    for (id, age) in [(0, 60), (1, 0)] {
+        File::options()
+            .write(true)
+            .open(input.path().join(format!("data/map_{id}.dat")))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+    }
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    render(
+        input.path(),
+        output.path(),
+        true,
+        true,
+        &level,
+        &ids,
+        &Config::default(),
+    )
+    .unwrap();
+
+    let meta: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("tiles/4/0/0.meta.json")).unwrap())
+            .unwrap();
+    assert_eq!(meta["maps"], serde_json::json!([1, 0]));
+    let layers = meta["layers"].as_array().unwrap();
+    assert_eq!(layers[0]["id"], 1);
+    assert_eq!(layers[0]["coverage"], 0.5);
+    assert_eq!(layers[1]["id"], 0);
+    assert_eq!(layers[1]["coverage"], 0.5);
+    assert!(layers[0]["modified"].as_u64() > layers[1]["modified"].as_u64());
+}
+
+#[test]
+fn single_map() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let mut map = SyntheticMap::new(5, 0, (64, 64));
+    map.colors[128] = 0;
+    SyntheticWorld {
+        maps: vec![map],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let rgb = render_map(input.path(), 5).unwrap();
+    assert_eq!(rgb[0..3], palette::BASE[6]);
+    assert_eq!(rgb[128 * 3..129 * 3], palette::PALETTE[0..3]);
+    assert!(matches!(render_map(input.path(), 6), Err(Error::Io { .. })));
+}
+
+#[test]
+fn command_storage() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(0, 0, (64, 64)),
+            SyntheticMap::new(1, 0, (-64, 64)),
+        ],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-df64-4ca6-af3c-12f547c7f765".to_owned(),
+            inventory: vec![filled_map(0)],
+            ender_items: Vec::new(),
+        }],
+        storage: [(
+            "adventure".to_owned(),
+            fastnbt::nbt!({ "rewards": [bundle(&[filled_map(1)])] }),
+        )]
+        .into(),
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let run = |command_storage| {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .config(Config {
+                command_storage,
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+
+    // Storage is searched once enabled even though the cache is newer, and forgotten once disabled
+    run(false);
+    assert!(!output.path().join("maps/1.webp").exists());
+    run(true);
+    assert!(output.path().join("maps/1.webp").exists());
+    run(false);
+    assert!(!output.path().join("maps/1.webp").exists());
+}
+
+#[test]
+fn read_only() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    // As on a read-only mount, short of mounting one
+    let set_readonly = |readonly| {
+        let within = glob::glob(input.path().join("**/*").to_str().unwrap()).unwrap();
+        for path in within.map(Result::unwrap).chain([input.path().to_owned()]) {
+            let mut permissions = fs::metadata(&path).unwrap().permissions();
+            permissions.set_readonly(readonly);
+            fs::set_permissions(&path, permissions).unwrap();
+        }
+    };
+    set_readonly(true);
+
+    let generator = |output: &Path| {
+        Generator::builder()
+            .world(input.path())
+            .output(output)
+            .quiet(true)
+            .config(Config {
+                read_only: true,
+                ..Config::default()
+            })
+            .build()
+    };
+    let report = generator(output.path()).unwrap().run();
+    let refused = generator(&input.path().join("output"));
+    set_readonly(false);
+
+    assert_eq!(report.unwrap().maps_found, 1);
+    assert!(output.path().join("maps/0.webp").exists());
+    assert!(
+        matches!(refused, Err(Error::Config(message)) if message.contains("within the read-only world"))
+    );
+    assert!(!input.path().join("output").exists());
+}
+
+#[test]
+fn player_filter() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let (a, b) = (
+        "65836968-df64-4ca6-af3c-12f547c7f765",
+        "0d3e4f2a-7c1b-4e8e-9b7a-2f6c1d5e8a90",
+    );
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(0, 0, (64, 64)),
+            SyntheticMap::new(1, 0, (-64, 64)),
+        ],
+        players: vec![
+            SyntheticPlayer {
+                uuid: a.to_owned(),
+                inventory: vec![filled_map(0)],
+                ender_items: Vec::new(),
+            },
+            SyntheticPlayer {
+                uuid: b.to_owned(),
+                inventory: Vec::new(),
+                ender_items: vec![filled_map(1)],
+            },
+        ],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let run = |players| {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .config(Config {
+                players,
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+    let exists = |id: u32| output.path().join(format!("maps/{id}.webp")).exists();
+
+    // Maps of players left out are withdrawn, and those of players let back in are found again
+    // even though the cache is newer
+    run(PlayerSelection::default());
+    assert!(exists(0) && exists(1));
+    run(PlayerSelection {
+        include: Vec::new(),
+        exclude: vec![b.to_owned()],
+    });
+    assert!(exists(0) && !exists(1));
+    let report = run(PlayerSelection {
+        include: vec![b.to_owned()],
+        exclude: Vec::new(),
+    });
+    assert_eq!(report.players_searched, 1);
+    assert!(!exists(0) && exists(1));
+}
+
+#[test]
+fn warnings() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let mut small_map = SyntheticMap::new(1, 0, (64, 64));
+    small_map.colors.truncate(64 * 64);
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64)), small_map],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0), filled_map(1)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    for name in ["r.3.3.mca", "r.-3.3.mca"] {
+        fs::write(input.path().join("region").join(name), b"truncated").unwrap();
+    }
+
+    let report = Generator::builder()
+        .world(input.path())
+        .output(output.path())
+        .quiet(true)
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    let groups = report
+        .warnings
+        .iter()
+        .map(|g| (g.kind, g.subjects.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        groups,
+        [(WarningKind::Quarantined, 2), (WarningKind::Resized, 1)]
+    );
+    assert_eq!(report.warnings[1].subjects, ["1"]);
+}
+
+#[test]
+fn cross_dimension() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let mut nether_map = SyntheticMap::new(1, 0, (64, 64));
+    nether_map.dimension = "minecraft:the_nether".to_owned();
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(0, 0, (64, 64)),
+            nether_map,
+            SyntheticMap::new(2, 0, (192, 64)),
+        ],
+        chests: vec![
+            SyntheticChest {
+                dimension: Dimension::Nether,
+                position: (8, 64, 8),
+                items: vec![filled_map(0), filled_map(1), filled_map(2)],
+            },
+            SyntheticChest {
+                dimension: Dimension::Overworld,
+                position: (1, 64, 1),
+                items: vec![filled_map(2)],
+            },
+        ],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let report = Generator::builder()
+        .world(input.path())
+        .output(output.path())
+        .quiet(true)
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    // Rendered by the dimension of its data, though found only in the Nether
+    assert_eq!(report.maps_found, 3);
+    assert!(output.path().join("tiles/4/0/0.meta.json").exists());
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!(report.warnings[0].kind, WarningKind::CrossDimension);
+    assert_eq!(
+        report.warnings[0].subjects,
+        ["0: overworld found in the_nether"]
+    );
+}
+
+#[test]
+fn discarded_cache() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let cache_path = output.path().join(".cache/little-a-map.dat");
+
+    SyntheticWorld {
+        maps: vec![SyntheticMap::new(0, 0, (64, 64))],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+    let run = |strict_cache| {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .config(Config {
+                strict_cache,
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .run()
+    };
+
+    let report = run(false).unwrap();
+    assert_equal(report.cache_misses.keys(), &[CacheMiss::Absent]);
+    assert!(report.warnings.is_empty());
+    assert!(run(false).unwrap().cache_misses.is_empty());
+
+    let truncate = || {
+        let bytes = fs::read(&cache_path).unwrap();
+        fs::write(&cache_path, &bytes[..bytes.len() / 2]).unwrap();
+    };
+    truncate();
+    let report = run(false).unwrap();
+    assert_equal(report.cache_misses.keys(), &[CacheMiss::Corrupt]);
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!(report.warnings[0].kind, WarningKind::DiscardedCache);
+
+    truncate();
+    assert!(matches!(run(true), Err(Error::Other(m)) if m.starts_with("Refusing to discard")));
+}
+
+#[test]
+fn copies() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+
+    let mut stack = filled_map(1);
+    if let fastnbt::Value::Compound(item) = &mut stack {
+        item.insert("count".to_owned(), fastnbt::Value::Int(3));
+    }
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(1, 0, (64, 64)),
+            SyntheticMap::new(2, 0, (192, 64)),
+        ],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![stack, bundle(&[filled_map(2)])],
+        }],
+        players: vec![SyntheticPlayer {
+            uuid: "65836968-3bd4-4a3b-a8b8-5b5a2e9e8a1e".to_owned(),
+            inventory: vec![filled_map(1)],
+            ender_items: Vec::new(),
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let report = Generator::builder()
+        .world(input.path())
+        .output(output.path())
+        .quiet(true)
+        .config(Config {
+            statistics: true,
+            ..Config::default()
+        })
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    let copies = report.statistics.unwrap().copies.unwrap();
+    assert_eq!(copies.by_holder, [("blocks", 4), ("players", 1)].into());
+    assert_eq!(copies.by_map, [(1, 4), (2, 1)].into());
+
+    let run: serde_json::Value =
+        serde_json::from_reader(File::open(output.path().join("run.json")).unwrap()).unwrap();
+    assert_eq!(run["statistics"]["copies"]["by_map"]["1"], 4);
+}
+
+#[test]
+fn gallery() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let map = |id, center, locked, varied: bool| {
+        let mut map = SyntheticMap::new(id, 0, center);
+        map.locked = locked;
+        if varied {
+            for (i, color) in map.colors.iter_mut().enumerate() {
+                *color = u8::try_from(i % 61 + 1).unwrap() * 4 + 2;
+            }
+        }
+        map
+    };
+    let maps = vec![
+        map(1, (64, 64), true, true),    // Art
+        map(2, (192, 64), true, false),  // Plain
+        map(3, (64, 192), true, true),   // Overlapped by the terrain of #4
+        map(4, (64, 192), false, false), // Terrain
+        map(5, (320, 64), false, true),  // Unlocked
+        map(6, (-64, -64), true, true),  // Art
+    ];
+    SyntheticWorld {
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: maps.iter().map(|m| filled_map(m.id)).collect(),
+        }],
+        maps,
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    let render_with = |gallery| {
+        let config = Config {
+            gallery,
+            map_images: MapImages::Off,
+            ..Config::default()
+        };
+        render(
+            input.path(),
+            output.path(),
+            true,
+            false,
+            &level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+
+    render_with(true);
+    let html = fs::read_to_string(output.path().join("gallery.html")).unwrap();
+    let listed = (1..=6)
+        .filter(|id| html.contains(&format!("./maps/{id}.webp")))
+        .collect_vec();
+    assert_eq!(listed, [1, 6]);
+    assert!(html.contains("x = -64, z = -64"));
+    assert!(output.path().join("maps/1.webp").exists());
+    assert!(!output.path().join("maps/2.webp").exists());
+
+    render_with(false);
+    assert!(!output.path().join("gallery.html").exists());
+    assert!(!output.path().join("maps/1.webp").exists());
+}
+
+#[test]
+fn map_flags() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let map = |id, center, locked, tracking| {
+        let mut map = SyntheticMap::new(id, 0, center);
+        map.locked = locked;
+        map.tracking = tracking;
+        map
+    };
+    let maps = vec![
+        map(1, (64, 64), true, true),
+        map(2, (192, 64), false, false),
+        map(3, (64, 192), false, true),
+    ];
+    SyntheticWorld {
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: maps.iter().map(|m| filled_map(m.id)).collect(),
+        }],
+        maps,
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let level = Level::from_world_path(input.path()).unwrap();
+    let ids = search(input.path(), output.path(), true, true, None, 0).unwrap();
+    render(
+        input.path(),
+        output.path(),
+        true,
+        false,
+        &level,
+        &ids,
+        &Config::default(),
+    )
+    .unwrap();
+
+    // Of the topmost map of each tile
+    let flags = |key: &str| {
+        let meta: serde_json::Value = serde_json::from_reader(
+            File::open(output.path().join(format!("tiles/{key}.meta.json"))).unwrap(),
+        )
+        .unwrap();
+        let top = &meta["layers"][0];
+        (
+            top["locked"].as_bool().unwrap(),
+            top["tracking"].as_bool().unwrap(),
+        )
+    };
+    assert_eq!(flags("4/0/0"), (true, true));
+    assert_eq!(flags("4/1/0"), (false, false));
+    assert_eq!(flags("4/0/1"), (false, true));
+}
+
+#[test]
+fn deleted_regions() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let chest = |x, id| SyntheticChest {
+        dimension: Dimension::Overworld,
+        position: (x, 64, 1),
+        items: vec![filled_map(id)],
+    };
+    SyntheticWorld {
+        maps: vec![
+            SyntheticMap::new(1, 0, (0, 0)),
+            SyntheticMap::new(2, 0, (0, 0)),
+        ],
+        chests: vec![chest(1, 1), chest(5 * 512 + 1, 2)],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    let ids = search(input.path(), output.path(), true, false, None, 0).unwrap();
+    assert_equal(ids.into_iter().sorted(), [1, 2]);
+    let size = cache_size(output.path(), None).unwrap();
+    assert!(size.bytes > 0);
+    assert_eq!(size.entries.block_regions, 2);
+
+    // Dropped by pruning alone
+    fs::remove_file(input.path().join("region/r.5.0.mca")).unwrap();
+    let dropped = prune_cache(input.path(), output.path(), None).unwrap();
+    assert_eq!(dropped.block_regions, 1);
+    assert_eq!(dropped.total(), 1);
+    assert_eq!(
+        cache_size(output.path(), None)
+            .unwrap()
+            .entries
+            .block_regions,
+        1
+    );
+
+    // Dropped by searching
+    fs::remove_file(input.path().join("region/r.0.0.mca")).unwrap();
+    let ids = search(input.path(), output.path(), true, false, None, 0).unwrap();
+    assert!(ids.is_empty());
+    assert_eq!(cache_size(output.path(), None).unwrap().entries.total(), 0);
+}
+
+#[test]
+fn unique_labels() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let map = |id, center, x| {
+        let mut map = SyntheticMap::new(id, 0, center);
+        map.banners.push(SyntheticBanner {
+            position: (x, 64, 10),
+            color: "red".to_owned(),
+            label: Some("Home".to_owned()),
+        });
+        map
+    };
+    SyntheticWorld {
+        maps: vec![map(0, (64, 64), 10), map(1, (-64, 64), -10)],
+        chests: vec![SyntheticChest {
+            dimension: Dimension::Overworld,
+            position: (1, 64, 1),
+            items: vec![filled_map(0), filled_map(1)],
+        }],
+        ..SyntheticWorld::default()
+    }
+    .write_to(input.path())
+    .unwrap();
+
+    // The other banner of the label is on a map excluded from the output
+    let run = || {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .config(toml::from_str("[maps]\nexclude = [1]").unwrap())
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let banners: serde_json::Value =
+            serde_json::from_reader(File::open(output.path().join("banners.json")).unwrap())
+                .unwrap();
+        assert_eq!(banners["features"].as_array().unwrap().len(), 1);
+        banners["features"][0]["properties"]["unique"].clone()
+    };
+    assert_eq!(run(), false);
+
+    fs::remove_file(input.path().join("data/map_1.dat")).unwrap();
+    assert_eq!(run(), true);
+}
+
+#[test]
+fn banner_changes() {
+    let input = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let output = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let changes_path = output.path().join("banners-changes.json");
+    let write_world = |banners: &[(i32, &str, Option<&str>)]| {
+        let mut map = SyntheticMap::new(0, 0, (64, 64));
+        for &(x, color, label) in banners {
+            map.banners.push(SyntheticBanner {
+                position: (x, 64, 10),
+                color: color.to_owned(),
+                label: label.map(str::to_owned),
+            });
+        }
+        SyntheticWorld {
+            maps: vec![map],
+            chests: vec![SyntheticChest {
+                dimension: Dimension::Overworld,
+                position: (1, 64, 1),
+                items: vec![filled_map(0)],
+            }],
+            ..SyntheticWorld::default()
+        }
+        .write_to(input.path())
+        .unwrap();
+    };
+    let run = || {
+        Generator::builder()
+            .world(input.path())
+            .output(output.path())
+            .quiet(true)
+            .config(toml::from_str("recent_banners = true").unwrap())
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+    };
+    let changes = || -> serde_json::Value {
+        serde_json::from_reader(File::open(&changes_path).unwrap()).unwrap()
+    };
+
+    // Nothing to compare the first banners to
+    write_world(&[(10, "red", Some("Home")), (20, "white", None)]);
+    run();
+    assert!(!changes_path.exists());
+    assert!(fs::read_to_string(output.path().join("index.html"))
+        .unwrap()
+        .contains("banners-changes.json"));
+
+    write_world(&[(10, "red", Some("Home")), (30, "blue", Some("Mine"))]);
+    run();
+    assert_eq!(
+        changes()["added"],
+        serde_json::json!([{ "x": 30, "z": 10, "color": "blue", "name": "Mine" }])
+    );
+    assert_eq!(
+        changes()["removed"],
+        serde_json::json!([{ "x": 20, "z": 10, "color": "white", "name": null }])
+    );
+
+    // Kept until the banners change again
+    let before = changes();
+    run();
+    assert_eq!(changes(), before);
+}
@@ -1,13 +1,27 @@
+use flate2::read::GzDecoder;
 use forgiving_semver::VersionReq;
 use glob::glob;
 use image::{GenericImageView, Pixel};
 use itertools::{assert_equal, Itertools};
-use little_a_map::{level::Level, palette, render, search};
+use little_a_map::{
+    cache_status,
+    config::{
+        Colors, Config, GeoJson, History, ImageFormat, MapImages, MarkerPalette, Profile, Prune,
+        Shading,
+    },
+    diff::diff,
+    layer::LayerProvider,
+    level::Level,
+    palette,
+    pattern::TilePattern,
+    render, search, verify_cache, CacheStatus, Error, Generator, GeneratorBuilder,
+};
 use rstest::*;
 use rstest_reuse::{self, *};
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::thread;
@@ -66,12 +80,21 @@ struct World {
 impl World {
     fn render(&self, ids: &HashSet<u32>) -> &Path {
         let output = self.output.path();
-        render(&self.input, output, true, true, &self.level, ids).unwrap();
+        render(
+            &self.input,
+            output,
+            true,
+            true,
+            &self.level,
+            ids,
+            &Config::default(),
+        )
+        .unwrap();
         output
     }
 
     fn search(&self) -> HashSet<u32> {
-        search(&self.input, self.output.path(), true, true, None).unwrap()
+        search(&self.input, self.output.path(), true, true, None, 0).unwrap()
     }
 }
 
@@ -140,6 +163,16 @@ fn spawn(world: World) {
     assert_eq!((world.level.spawn_x, world.level.spawn_z), (0, 0));
 }
 
+#[apply(worlds)]
+fn world_info(world: World) {
+    let output = world.render(&world.search());
+    let json = File::open(output.join("world.json")).unwrap();
+    let info: serde_json::Value = serde_json::from_reader(json).unwrap();
+
+    assert_eq!(info["spawn"]["position"], serde_json::json!([0, 0]));
+    assert!(info["border"]["size"].as_f64().unwrap() > 0.0);
+}
+
 #[apply(worlds)]
 fn map_ids(world: World) {
     assert_equal(
@@ -179,6 +212,479 @@ fn banners(world: World) {
     assert_equal(actual, expected);
 }
 
+#[apply(worlds)]
+fn banner_list(world: World) {
+    let output = world.render(&world.search());
+    let list = fs::read_to_string(output.join("banners.html")).unwrap();
+    assert_eq!(list.matches("<li>").count(), BANNERS.len());
+    for (name, _) in BANNERS {
+        if let Some(name) = name {
+            assert!(list.contains(&format!("{name}: x = ")));
+        }
+    }
+
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(index.contains(r#"<noscript><p><a href="./banners.html">Banners</a></p></noscript>"#));
+}
+
+#[apply(worlds)]
+fn outlines(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |outlines| {
+        let config = Config {
+            outlines,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+
+    render_with(true);
+    let collection: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("outlines.json")).unwrap()).unwrap();
+    let scale_of = |id: u32| {
+        collection["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["properties"]["id"] == id)
+            .map(|f| {
+                assert_eq!(f["properties"]["explorer"], false);
+                f["properties"]["scale"].as_u64().unwrap()
+            })
+    };
+    assert_eq!(scale_of(0), Some(0));
+    assert!(scale_of(11).unwrap() > 0); // Enlarged
+
+    render_with(false);
+    assert!(!output.join("outlines.json").exists());
+}
+
+#[apply(worlds)]
+fn image_format(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |image_format, history| {
+        let config = Config {
+            history,
+            image_format,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+    };
+    let count = |pattern: &str| {
+        glob(output.join(pattern).to_str().unwrap())
+            .unwrap()
+            .count()
+    };
+
+    render_with(ImageFormat::WebP, None).unwrap();
+    let tiles = count("tiles/*/*/*.webp");
+    assert!(tiles > 0);
+
+    render_with(ImageFormat::Png, None).unwrap();
+    assert_eq!(count("tiles/*/*/*.png"), tiles);
+    assert_eq!(count("tiles/*/*/*.webp"), 0);
+    assert_eq!(count("maps/*.webp"), 0);
+    let map = image::open(output.join("maps/0.png")).unwrap();
+    assert_eq!(map.dimensions(), (128, 128));
+    assert!(fs::read_to_string(output.join("index.html"))
+        .unwrap()
+        .contains("./tiles/{z}/{x}/{y}.png"));
+
+    render_with(ImageFormat::Indexed, None).unwrap();
+    assert_eq!(
+        fs::read(output.join("maps/0.indexed")).unwrap().len(),
+        128 * 128
+    );
+
+    assert!(matches!(
+        render_with(ImageFormat::Png, Some(History::default())),
+        Err(Error::Config(_))
+    ));
+}
+
+#[apply(worlds)]
+fn colors(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |colors| {
+        let config = Config {
+            colors,
+            image_format: ImageFormat::Png,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    // Pixels of the swatch in the shade that the game doesn't darken
+    let swatch = || {
+        let view = image::open(output.join("maps/1.png")).unwrap();
+        (1..62).map(move |i| view.get_pixel(i, 0).to_rgb().0)
+    };
+
+    let flat = Colors {
+        shading: Shading::Flat,
+        ..Colors::default()
+    };
+    render_with(flat);
+    let palette = palette::get(flat);
+    assert_equal(
+        swatch(),
+        (1..62).map(|i| <[u8; 3]>::try_from(&palette[i * 12 + 3..i * 12 + 6]).unwrap()),
+    );
+    assert!(swatch()
+        .zip(palette::BASE.into_iter().skip(1))
+        .any(|(a, b)| a != b));
+
+    // Changing colors renders again without forcing
+    render_with(Colors::default());
+    assert_equal(swatch(), palette::BASE.into_iter().skip(1));
+
+    // Linear light changes only the darkened shades
+    render_with(Colors {
+        linear: true,
+        ..Colors::default()
+    });
+    assert_equal(swatch(), palette::BASE.into_iter().skip(1));
+}
+
+#[apply(worlds)]
+fn banner_shards(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |banner_shards| {
+        let config = Config {
+            banner_shards,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    let read = |path: &Path| -> serde_json::Value {
+        serde_json::from_reader(File::open(path).unwrap()).unwrap()
+    };
+
+    render_with(true);
+    let banners = read(&output.join("banners.json"));
+    let index = read(&output.join("banners/index.json"));
+    let banners_modified = fs::metadata(output.join("banners.json"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    let mut sharded = 0;
+    for shard in index["shards"].as_array().unwrap() {
+        let path = output.join(format!("banners/{}_{}.json", shard[0], shard[1]));
+        let features = read(&path)["features"].as_array().unwrap().len();
+        assert!(features > 0);
+        assert!(fs::metadata(&path).unwrap().modified().unwrap() <= banners_modified);
+        sharded += features;
+    }
+    assert_eq!(sharded, banners["features"].as_array().unwrap().len());
+    assert!(index["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|f| f["properties"]["unique"] == true));
+
+    render_with(false);
+    assert!(!output.join("banners").exists());
+}
+
+#[apply(worlds)]
+fn statistics(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |statistics| {
+        let config = Config {
+            statistics,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+
+    render_with(true);
+    let run: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("run.json")).unwrap()).unwrap();
+    assert_eq!(run["maps"], ids.len());
+    let by_dimension = run["statistics"]["maps_by_dimension"].as_object().unwrap();
+    assert!(
+        by_dimension
+            .values()
+            .map(|c| c.as_u64().unwrap())
+            .sum::<u64>()
+            > 0
+    );
+    // Counted only of a search within the run
+    assert!(run["statistics"]["copies"].is_null());
+    assert!(output.join("stats.html").exists());
+
+    render_with(false);
+    assert!(!output.join("run.json").exists());
+    assert!(!output.join("stats.html").exists());
+}
+
+#[apply(worlds)]
+fn banner_export(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |geojson| {
+        let config = Config {
+            geojson,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    let read = |name: &str| -> serde_json::Value {
+        serde_json::from_reader(File::open(output.join(name)).unwrap()).unwrap()
+    };
+
+    render_with(Some(GeoJson {
+        flip: true,
+        ..GeoJson::default()
+    }));
+    let (banners, exported) = (read("banners.json"), read("banners.geojson"));
+    assert!(banners.get("crs").is_none());
+    assert_eq!(exported["crs"]["type"], "name");
+    assert_eq!(exported["bbox"].as_array().unwrap().len(), 4);
+    for (banner, feature) in banners["features"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .zip(exported["features"].as_array().unwrap())
+    {
+        let (x, z) = (
+            &banner["geometry"]["coordinates"][0],
+            &banner["geometry"]["coordinates"][1],
+        );
+        let coordinates = &feature["geometry"]["coordinates"];
+        assert_eq!(coordinates[0].as_f64(), x.as_f64());
+        assert_eq!(coordinates[1].as_f64(), z.as_f64().map(|z| -z));
+        assert_eq!(feature["properties"], banner["properties"]);
+    }
+
+    // Exported again once the options change
+    render_with(Some(GeoJson::default()));
+    let (banners, exported) = (read("banners.json"), read("banners.geojson"));
+    assert_eq!(
+        exported["features"][0]["geometry"]["coordinates"][1].as_f64(),
+        banners["features"][0]["geometry"]["coordinates"][1].as_f64()
+    );
+
+    render_with(None);
+    assert!(!output.join("banners.geojson").exists());
+}
+
+#[apply(worlds)]
+fn banner_spool(world: World) {
+    let ids = world.search();
+    let output = world.render(&ids);
+    let spool = fs::read_to_string(output.join(".banners.jsonl")).unwrap();
+    let banners: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("banners.json")).unwrap()).unwrap();
+
+    let spooled = spool
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(spooled.len(), BANNERS.len());
+    assert_eq!(&spooled, banners["features"].as_array().unwrap());
+
+    world.render(&ids);
+    assert_eq!(
+        fs::read_to_string(output.join(".banners.jsonl")).unwrap(),
+        spool
+    );
+}
+
+#[apply(worlds)]
+fn marker_palette(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |marker_palette| {
+        let config = Config {
+            marker_palette,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+        (
+            fs::read_to_string(output.join("index.html")).unwrap(),
+            fs::read_to_string(output.join("banners.html")).unwrap(),
+        )
+    };
+
+    let (index, list) = render_with(MarkerPalette::Game);
+    assert!(index.contains(".banner-light_blue { color: #3998cf; }"));
+    assert!(list.contains(".banner-light_blue { color: #3998cf; }"));
+    assert!(index.contains(r#""label": "Light Blue""#));
+
+    let (index, list) = render_with(MarkerPalette::Colorblind);
+    assert!(index.contains(".banner-light_blue { color: #56b4e9; }"));
+    assert!(list.contains(".banner-light_blue { color: #56b4e9; }"));
+    assert!(!index.contains(".banner-light_blue { color: #3998cf; }"));
+}
+
+#[apply(worlds)]
+fn site_config(world: World) {
+    let output = world.render(&world.search());
+    let site_config: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("site-config.json")).unwrap()).unwrap();
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+
+    assert_eq!(site_config["center"], serde_json::json!([0, 0]));
+    assert_eq!(site_config["lang"], "en");
+    assert_eq!(site_config["tile_pattern"], "{z}/{x}/{y}");
+    assert!(index.contains(&format!(
+        "banners.json?v={}",
+        site_config["cache_version"].as_str().unwrap()
+    )));
+    let [[x0, z0], [x1, z1]] =
+        serde_json::from_value::<[[i32; 2]; 2]>(site_config["bounds"].clone()).unwrap();
+    assert!(x0 < x1 && z0 < z1);
+    assert!(site_config["maps_stacked"].as_u64().unwrap() >= 1);
+    assert_eq!(site_config["banner_colors"]["red"]["label"], "Red");
+
+    // Rewritten only when changed
+    let modified = fs::metadata(output.join("site-config.json"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    world.render(&world.search());
+    assert_eq!(
+        fs::metadata(output.join("site-config.json"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        modified
+    );
+}
+
+#[apply(worlds)]
+fn banner_maps(world: World) {
+    let output = world.render(&world.search());
+    let json = File::open(output.join("banners.json")).unwrap();
+    let geo: serde_json::Value = serde_json::from_reader(json).unwrap();
+
+    for feature in geo["features"].as_array().unwrap() {
+        let properties = &feature["properties"];
+        let maps = properties["maps"].as_array().unwrap();
+        let ids = maps
+            .iter()
+            .map(|id| id.as_u64().unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(
+            ids.windows(2).all(|w| w[0] < w[1]),
+            "{ids:?} should be sorted"
+        );
+        assert!(maps.contains(&properties["supplied_by"]));
+        for id in ids {
+            assert!(properties["maps_modified"][id.to_string()].is_u64());
+        }
+    }
+}
+
+#[apply(worlds)]
+fn banner_clusters(world: World) {
+    let output = world.render(&world.search());
+    let json = File::open(output.join("banners-clustered.json")).unwrap();
+    let clustered: serde_json::Value = serde_json::from_reader(json).unwrap();
+
+    for clusters in clustered["zooms"].as_object().unwrap().values() {
+        let features = clusters
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|c| c["features"].as_array().unwrap())
+            .map(|i| i.as_u64().unwrap())
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_equal(features, 0..BANNERS.len() as u64);
+    }
+}
+
+#[apply(worlds)]
+fn legend(world: World) {
+    let output = world.render(&world.search());
+    let json = File::open(output.join("legend.json")).unwrap();
+    let legend: serde_json::Value = serde_json::from_reader(json).unwrap();
+
+    let actual = legend["colors"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(color, count)| (color.as_str(), count.as_u64().unwrap()))
+        .collect::<Vec<_>>();
+    let expected = BANNERS
+        .iter()
+        .map(|&(_, c)| c)
+        .sorted()
+        .dedup_with_count()
+        .map(|(n, c)| (c, n as u64))
+        .collect::<Vec<_>>();
+    assert_eq!(actual, expected);
+}
+
 #[apply(worlds)]
 fn swatch(world: World, #[values("maps/1.webp", "tiles/4/0/0.webp")] relative_path: &str) {
     let output = world.render(&world.search());
@@ -203,6 +709,519 @@ fn swatch(world: World, #[values("maps/1.webp", "tiles/4/0/0.webp")] relative_pa
     );
 }
 
+#[apply(worlds)]
+fn layout(world: World) {
+    let legacy = world.output.path().join("tiles/4/0/0.png");
+    fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+    File::create(&legacy).unwrap();
+
+    let output = world.render(&world.search());
+
+    assert!(!legacy.exists());
+    assert!(output.join(".layout_version").exists());
+}
+
+#[apply(worlds)]
+fn precompress(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let paths = ["banners.json", "index.html", "tiles/4/0/0.meta.json"];
+
+    let config = Config {
+        precompress: true,
+        ..Config::default()
+    };
+    render(
+        &world.input,
+        output,
+        true,
+        true,
+        &world.level,
+        &ids,
+        &config,
+    )
+    .unwrap();
+    for path in paths {
+        let original = output.join(path);
+        let compressed = output.join(format!("{path}.gz"));
+        let mut decompressed = Vec::new();
+        GzDecoder::new(File::open(&compressed).unwrap())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        assert_eq!(decompressed, fs::read(&original).unwrap());
+        assert_eq!(
+            fs::metadata(&compressed).unwrap().modified().unwrap(),
+            fs::metadata(&original).unwrap().modified().unwrap()
+        );
+    }
+
+    world.render(&ids);
+    for path in paths {
+        assert!(!output.join(format!("{path}.gz")).exists());
+    }
+}
+
+#[apply(worlds)]
+fn map_selection(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let config: Config = toml::from_str("[maps]\nexclude = [1]\ninclude = [2]").unwrap();
+
+    render(
+        &world.input,
+        output,
+        true,
+        true,
+        &world.level,
+        &ids,
+        &config,
+    )
+    .unwrap();
+
+    assert!(!output.join("maps/1.webp").exists());
+    assert!(output.join("maps/2.webp").exists()); // Not held in any container
+}
+
+#[apply(worlds)]
+fn history(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let config: Config = toml::from_str("[history]\nperiod = \"day\"").unwrap();
+
+    for _ in 0..2 {
+        render(
+            &world.input,
+            output,
+            true,
+            true,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    }
+
+    let index: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("history/index.json")).unwrap()).unwrap();
+    let snapshots = index["snapshots"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 1);
+
+    let snapshot = snapshots[0].as_str().unwrap();
+    let manifest: HashMap<String, u64> = serde_json::from_reader(
+        File::open(output.join(format!("history/{snapshot}.json"))).unwrap(),
+    )
+    .unwrap();
+    let version = manifest["4/0/0"];
+    assert_eq!(
+        fs::read(output.join(format!("history/tiles/4/0/0/{version}.webp"))).unwrap(),
+        fs::read(output.join("tiles/4/0/0.webp")).unwrap()
+    );
+}
+
+#[apply(worlds)]
+fn output_diff(world: World) {
+    let ids = world.search();
+    let old = world.render(&ids);
+    let new = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let config: Config = toml::from_str("[maps]\nexclude = [1]").unwrap();
+    render(
+        &world.input,
+        new.path(),
+        true,
+        true,
+        &world.level,
+        &ids,
+        &config,
+    )
+    .unwrap();
+
+    assert!(diff(old, old).unwrap().is_empty());
+
+    let changes = diff(old, new.path()).unwrap();
+    assert_eq!(changes.maps.removed, ["maps/1"]);
+    assert!(changes.maps.added.is_empty());
+    assert!(!changes.tiles.changed.is_empty() || !changes.tiles.removed.is_empty());
+}
+
+#[apply(worlds)]
+fn generator(world: World) {
+    let generator = Generator::builder()
+        .world(&world.input)
+        .output(world.output.path())
+        .quiet(true)
+        .force(true)
+        .build()
+        .unwrap();
+
+    let report = generator.run().unwrap();
+    assert_eq!(report.maps_found, world.search().len());
+    assert!(report.tiles_rendered > 0);
+
+    let report = generator.run().unwrap();
+    assert_eq!(report.tiles_pruned, 0);
+}
+
+#[apply(worlds)]
+fn generator_scoped_force(world: World) {
+    let output = world.output.path();
+    let generator = |builder: GeneratorBuilder| {
+        builder
+            .world(&world.input)
+            .output(output)
+            .quiet(true)
+            .build()
+            .unwrap()
+    };
+
+    let all = generator(Generator::builder()).run().unwrap().tiles_changed;
+    let &(zoom, x, y) = all.iter().next().unwrap();
+    let parent = (zoom - 1, x.div_euclid(2), y.div_euclid(2));
+
+    let forced = generator(Generator::builder().force_tiles([parent]))
+        .run()
+        .unwrap()
+        .tiles_changed;
+    assert!(forced.contains(&(zoom, x, y)));
+    assert!(forced
+        .iter()
+        .all(|&(_, fx, fy)| (fx.div_euclid(2), fy.div_euclid(2)) == (parent.1, parent.2)));
+
+    let report = generator(Generator::builder().force_render(true))
+        .run()
+        .unwrap();
+    assert_eq!(report.tiles_changed, all);
+    assert_eq!(report.maps_found, world.search().len());
+
+    let report = generator(Generator::builder()).run().unwrap();
+    assert!(report.tiles_changed.is_empty());
+}
+
+#[apply(worlds)]
+fn layers(world: World) {
+    struct Rails;
+    impl LayerProvider for Rails {
+        fn name(&self) -> &str {
+            "rails"
+        }
+
+        fn label(&self) -> &str {
+            "Rail lines"
+        }
+
+        fn features(&self) -> Result<Vec<serde_json::Value>, Error> {
+            Ok(vec![serde_json::json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": [[0, 0], [100, 0]] },
+                "properties": { "name": "Main line" },
+            })])
+        }
+    }
+
+    let output = world.output.path();
+    let generator = |builder: GeneratorBuilder| {
+        builder
+            .world(&world.input)
+            .output(output)
+            .quiet(true)
+            .build()
+            .unwrap()
+    };
+
+    generator(Generator::builder().layer(Rails)).run().unwrap();
+    let collection: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("layers/rails.json")).unwrap()).unwrap();
+    assert_eq!(collection["features"][0]["properties"]["name"], "Main line");
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(index.contains(r#""label": "Rail lines""#));
+
+    // Pruned once no longer provided
+    generator(Generator::builder()).run().unwrap();
+    assert!(!output.join("layers").exists());
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(!index.contains("Rail lines"));
+}
+
+#[apply(worlds)]
+fn unchanged_pixels(world: World) {
+    let output = world.output.path();
+    let generator = Generator::builder()
+        .world(&world.input)
+        .output(output)
+        .quiet(true)
+        .build()
+        .unwrap();
+    let images = || {
+        glob(&format!("{}/tiles/*/*/*.webp", output.display()))
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap();
+                let modified = fs::metadata(&path).unwrap().modified().unwrap();
+                (path, modified)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+
+    let all = generator.run().unwrap().tiles_changed;
+    let before = images();
+    assert!(!before.is_empty());
+
+    // Metadata older than the maps is rendered again, but the pixels are as they were
+    for entry in glob(&format!("{}/tiles/*/*/*.meta.json", output.display())).unwrap() {
+        File::options()
+            .write(true)
+            .open(entry.unwrap())
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+    }
+    let report = generator.run().unwrap();
+    assert_eq!(report.tiles_rendered, all.len());
+    assert!(report.tiles_changed.is_empty());
+    assert_eq!(images(), before);
+}
+
+#[cfg(feature = "async")]
+#[apply(worlds)]
+fn generator_async(world: World) {
+    let generator = std::sync::Arc::new(
+        Generator::builder()
+            .world(&world.input)
+            .output(world.output.path())
+            .quiet(true)
+            .build()
+            .unwrap(),
+    );
+
+    let pending = generator.run_async();
+    let report = futures_executor::block_on(pending).unwrap();
+    assert_eq!(report.maps_found, world.search().len());
+    assert!(report.tiles_rendered > 0);
+}
+
+#[apply(worlds)]
+fn events(world: World) {
+    let output = world.output.path();
+    let generator = Generator::builder()
+        .world(&world.input)
+        .output(output)
+        .quiet(true)
+        .config(Config {
+            events_url: Some("https://example.com/events".to_owned()),
+            ..Config::default()
+        })
+        .build()
+        .unwrap();
+
+    let report = generator.run().unwrap();
+    assert_eq!(report.tiles_changed.len(), report.tiles_rendered);
+    for (zoom, x, y) in &report.tiles_changed {
+        assert!(output.join(format!("tiles/{zoom}/{x}/{y}.webp")).exists());
+    }
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(index.contains(r#"new EventSource("https://example.com/events")"#));
+
+    let report = generator.run().unwrap();
+    assert!(report.tiles_changed.is_empty());
+}
+
+#[apply(worlds)]
+fn map_images(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let images = || {
+        glob(output.join("maps/*.webp").to_str().unwrap())
+            .unwrap()
+            .count()
+    };
+    let render_with = |map_images| {
+        let config = Config {
+            map_images,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            true,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+
+    render_with(MapImages::All);
+    let all = images();
+    assert!(all > 0);
+
+    render_with(MapImages::Popup);
+    assert!(images() < all);
+
+    render_with(MapImages::Off);
+    assert_eq!(images(), 0);
+    assert!(!fs::read_to_string(output.join("index.html"))
+        .unwrap()
+        .contains("./maps/"));
+}
+
+#[apply(worlds)]
+fn offline_assets(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let assets = TempDir::new().unwrap();
+    fs::write(assets.path().join("leaflet.js"), "").unwrap();
+    let config = Config {
+        assets: Some(assets.path().to_owned()),
+        ..Config::default()
+    };
+    let render_with = || {
+        render(
+            &world.input,
+            output,
+            true,
+            true,
+            &world.level,
+            &ids,
+            &config,
+        )
+    };
+
+    assert!(render_with().is_err());
+
+    fs::write(assets.path().join("leaflet.css"), "").unwrap();
+    fs::create_dir(assets.path().join("images")).unwrap();
+    fs::write(assets.path().join("images/layers.png"), "").unwrap();
+    render_with().unwrap();
+
+    for path in ["leaflet.css", "leaflet.js", "images/layers.png"] {
+        assert!(output.join("assets").join(path).is_file());
+    }
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(index.contains("./assets/leaflet.js"));
+    assert!(!index.contains("unpkg.com"));
+}
+
+#[cfg(unix)]
+#[apply(worlds)]
+fn tile_deduplication(world: World) {
+    use std::os::unix::fs::MetadataExt;
+
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |dedupe_tiles| {
+        let config = Config {
+            dedupe_tiles,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            true,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    let blobs = || fs::read_dir(output.join(".blobs")).unwrap().count();
+    let tiles = || {
+        glob(output.join("tiles/*/*/*.webp").to_str().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    render_with(true);
+    assert!(blobs() > 0);
+    assert!(blobs() <= tiles().len());
+    for tile in tiles() {
+        assert!(fs::metadata(tile).unwrap().nlink() >= 2);
+    }
+
+    render_with(false);
+    assert_eq!(blobs(), 0);
+    for tile in tiles() {
+        assert_eq!(fs::metadata(tile).unwrap().nlink(), 1);
+    }
+}
+
+#[apply(worlds)]
+fn pruning_policy(world: World) {
+    let run = |prune| {
+        Generator::builder()
+            .world(&world.input)
+            .output(world.output.path())
+            .quiet(true)
+            .force(true)
+            .bounds(((1000, 1000), (1000, 1000)))
+            .config(Config {
+                prune,
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+    world.render(&world.search());
+
+    let report = run(Prune::Safe);
+    assert_eq!((report.maps_pruned, report.tiles_pruned), (0, 0));
+
+    let report = run(Prune::Aggressive);
+    assert!(report.maps_pruned > 0);
+    assert!(report.tiles_pruned > 0);
+}
+
+#[apply(worlds)]
+fn profiles(world: World) {
+    let public = TempDir::new().unwrap();
+    let config: Config = toml::from_str(&format!(
+        "[[profiles]]\noutput = {:?}\nhide_banners = true",
+        public.path()
+    ))
+    .unwrap();
+    Generator::builder()
+        .world(&world.input)
+        .output(world.output.path())
+        .quiet(true)
+        .force(true)
+        .config(config)
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    let features = |output: &Path| {
+        let banners: serde_json::Value =
+            serde_json::from_reader(File::open(output.join("banners.json")).unwrap()).unwrap();
+        banners["features"].as_array().unwrap().len()
+    };
+    assert!(public.path().join("tiles/4/0/0.webp").is_file());
+    assert!(!public.path().join(".cache").exists());
+
+    // Tiles drawn once for both outputs are alike
+    for tile in ["tiles/4/0/0.webp", "tiles/4/0/0.meta.json"] {
+        assert_eq!(
+            fs::read(public.path().join(tile)).unwrap(),
+            fs::read(world.output.path().join(tile)).unwrap(),
+            "{tile}"
+        );
+    }
+    assert!(features(world.output.path()) > 0);
+    assert_eq!(features(public.path()), 0);
+}
+
+#[apply(worlds)]
+fn cache_verification(world: World) {
+    world.search();
+    let discrepancies = verify_cache(&world.input, world.output.path(), None, true, None).unwrap();
+
+    assert!(discrepancies.is_empty());
+}
+
 #[apply(worlds)]
 fn rerun(world: World) {
     let ids_1 = world.search();
@@ -220,3 +1239,457 @@ fn rerun(world: World) {
         &modifications_2,
     );
 }
+
+#[apply(worlds)]
+fn manifest(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |manifest| {
+        let config = Config {
+            dedupe_tiles: true,
+            manifest,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    let read = || fs::read_to_string(output.join("MANIFEST")).unwrap();
+
+    render_with(true);
+    let manifest = read();
+    let paths = manifest
+        .lines()
+        .map(|line| line.splitn(4, ' ').nth(3).unwrap())
+        .collect::<Vec<_>>();
+    assert!(paths.windows(2).all(|w| w[0] < w[1]));
+    assert!(paths.contains(&"index.html"));
+    assert!(paths.iter().any(|p| p.starts_with("tiles/")));
+    assert!(!paths.iter().any(|p| p.starts_with('.') || *p == "MANIFEST"));
+    for line in manifest.lines() {
+        let mut parts = line.splitn(4, ' ');
+        let (checksum, size, path) = (
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+            parts.nth(1).unwrap(),
+        );
+        let data = fs::read(output.join(path)).unwrap();
+        assert_eq!(checksum, format!("{:08x}", crc32fast::hash(&data)));
+        assert_eq!(size, data.len().to_string());
+    }
+
+    render_with(true);
+    assert_eq!(read(), manifest);
+
+    render_with(false);
+    assert!(!output.join("MANIFEST").exists());
+}
+
+#[apply(worlds)]
+fn max_maps(world: World) {
+    let ids = world.search();
+    let render_with = |max_maps| {
+        let config = Config {
+            max_maps: Some(max_maps),
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            world.output.path(),
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+    };
+
+    let error = render_with(ids.len() - 1).unwrap_err();
+    assert!(matches!(error, Error::Config(ref m) if m.contains("max_maps")));
+    assert!(!world.output.path().join("index.html").exists());
+
+    render_with(ids.len()).unwrap();
+    assert!(world.output.path().join("index.html").exists());
+
+    // Surveys are capped alike
+    let survey_with = |max_maps| {
+        Generator::builder()
+            .world(&world.input)
+            .output(world.output.path())
+            .quiet(true)
+            .config(Config {
+                max_maps: Some(max_maps),
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .survey()
+    };
+    assert!(matches!(survey_with(ids.len() - 1), Err(Error::Config(_))));
+    survey_with(ids.len()).unwrap();
+}
+
+#[apply(worlds)]
+fn info(world: World) {
+    let output = world.output.path();
+    let status = || cache_status(output, None).unwrap();
+
+    assert!(world.level.is_compatible());
+    assert!(world.level.data_version >= 3578); // 1.20.2
+    assert_eq!(status(), CacheStatus::Absent);
+
+    world.search();
+    assert_eq!(status(), CacheStatus::Current);
+
+    fs::write(output.join(".cache/little-a-map.dat"), b"stale").unwrap();
+    assert_eq!(status(), CacheStatus::Corrupt);
+}
+
+#[apply(worlds)]
+fn max_output_size(world: World) {
+    let ids = world.search();
+    let render_with = |max_output_size| {
+        let config = Config {
+            max_output_size: Some(max_output_size),
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            world.output.path(),
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+    };
+
+    let error = render_with(1).unwrap_err();
+    assert!(matches!(error, Error::Config(ref m) if m.contains("max_output_size")));
+    assert!(!world.output.path().join("tiles").exists());
+
+    render_with(u64::MAX).unwrap();
+    assert!(world.output.path().join("tiles").exists());
+}
+
+#[apply(worlds)]
+fn freshness(world: World) {
+    let ids = world.search();
+    let output = world.output.path();
+    let render_with = |freshness| {
+        let config = Config {
+            freshness,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    let overlays = || {
+        glob(output.join("freshness/4/*/*.webp").to_str().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    render_with(true);
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(index.contains("./freshness/"));
+    assert!(!overlays().is_empty());
+    for overlay in overlays() {
+        let relative = overlay.strip_prefix(output.join("freshness")).unwrap();
+        assert!(output.join("tiles").join(relative).exists());
+
+        // Green, yellow, orange, or red, or else the background
+        let expected = [7, 18, 15, 28].map(|i| palette::BASE[i]);
+        for (_, _, pixel) in image::open(&overlay).unwrap().pixels() {
+            let rgb = pixel.to_rgb().0;
+            assert!(expected.contains(&rgb) || palette::PALETTE[0..3] == rgb);
+        }
+    }
+
+    render_with(false);
+    assert!(!output.join("freshness").exists());
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(!index.contains("./freshness/"));
+}
+
+#[apply(worlds)]
+fn cache_dir(world: World) {
+    let cache = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let run = || {
+        Generator::builder()
+            .world(&world.input)
+            .output(world.output.path())
+            .quiet(true)
+            .config(Config {
+                cache_dir: Some(cache.path().to_owned()),
+                ..Config::default()
+            })
+            .build()
+            .unwrap()
+            .run()
+            .unwrap()
+    };
+
+    let report = run();
+    assert!(report.block_regions_searched > 0);
+    assert!(cache.path().join("little-a-map.dat").exists());
+    assert!(!world.output.path().join(".cache").exists());
+
+    // The cache survives a wiped output
+    fs::remove_dir_all(world.output.path()).unwrap();
+    fs::create_dir(world.output.path()).unwrap();
+    let report = run();
+    assert_eq!(report.block_regions_searched, 0);
+    assert_eq!(report.maps_found, world.search().len());
+    assert!(world.output.path().join("index.html").exists());
+}
+
+#[apply(worlds)]
+fn changed_list(world: World) {
+    let dir = tempfile::tempdir_in(env!("TEST_OUTPUT_PATH")).unwrap();
+    let list_path = dir.path().join("changed.txt");
+    let generator = Generator::builder()
+        .world(&world.input)
+        .output(world.output.path())
+        .quiet(true)
+        .config(Config {
+            changed_list: Some(list_path.clone()),
+            ..Config::default()
+        })
+        .build()
+        .unwrap();
+    let read = || fs::read_to_string(&list_path).unwrap();
+
+    let report = generator.run().unwrap();
+    let listed = read();
+    let paths = listed.lines().collect::<Vec<_>>();
+    assert_eq!(report.files_changed.unwrap().len(), paths.len());
+    assert!(paths.windows(2).all(|w| w[0] < w[1]));
+    assert!(paths.contains(&"index.html"));
+    assert!(paths.iter().any(|p| p.starts_with("tiles/")));
+    assert!(!paths.iter().any(|p| p.starts_with('.')));
+
+    // Only the viewer is written again
+    thread::sleep(Duration::from_millis(100));
+    generator.run().unwrap();
+    assert!(read().lines().all(|p| p == "index.html"));
+    assert!(world.output.path().join("MANIFEST").exists());
+}
+
+#[cfg(unix)]
+#[apply(worlds)]
+fn releases(world: World) {
+    let output = world.output.path();
+    let generator = Generator::builder()
+        .world(&world.input)
+        .output(output)
+        .quiet(true)
+        .config(Config {
+            releases: Some(2),
+            ..Config::default()
+        })
+        .build()
+        .unwrap();
+    let releases = || {
+        fs::read_dir(output.join("releases"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .sorted()
+            .collect_vec()
+    };
+
+    let first = generator.run().unwrap();
+    assert!(!first.tiles_changed.is_empty());
+    assert!(output.join("current/index.html").exists());
+    assert!(!output.join("index.html").exists());
+
+    // Seeded from the current release, so rendered incrementally
+    thread::sleep(Duration::from_millis(10));
+    let second = generator.run().unwrap();
+    assert!(second.tiles_changed.is_empty());
+    let published = releases();
+    assert_eq!(published.len(), 2);
+    assert_eq!(
+        fs::canonicalize(output.join("current")).unwrap(),
+        fs::canonicalize(&published[1]).unwrap()
+    );
+
+    // Only the latest are kept
+    thread::sleep(Duration::from_millis(10));
+    generator.run().unwrap();
+    let kept = releases();
+    assert_eq!(kept.len(), 2);
+    assert!(!kept.contains(&published[0]));
+}
+
+#[apply(worlds)]
+fn tile_origin(world: World) {
+    let ids = world.search();
+    let shifted = TempDir::new().unwrap();
+    let render_to = |output: &Path, tile_origin| {
+        let config = Config {
+            tile_origin,
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            true,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    render_to(world.output.path(), (0, 0));
+    render_to(shifted.path(), (2048, -2048));
+
+    // Numbered from the root tile at 1, -1 and otherwise alike
+    let tiles = glob(&format!("{}/tiles/*/*/*", world.output.path().display()))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert!(!tiles.is_empty());
+    for path in tiles {
+        let relative = path.strip_prefix(world.output.path()).unwrap();
+        let parts = relative.iter().map(|p| p.to_str().unwrap()).collect_vec();
+        let (zoom, x) = (
+            parts[1].parse::<u8>().unwrap(),
+            parts[2].parse::<i32>().unwrap(),
+        );
+        let (y, extension) = parts[3].split_once('.').unwrap();
+        let y = y.parse::<i32>().unwrap();
+        let moved = format!(
+            "tiles/{zoom}/{}/{}.{extension}",
+            x - (1 << zoom),
+            y + (1 << zoom)
+        );
+
+        assert_eq!(
+            fs::read(shifted.path().join(&moved)).unwrap(),
+            fs::read(&path).unwrap(),
+            "{moved}"
+        );
+    }
+    let index = fs::read_to_string(shifted.path().join("index.html")).unwrap();
+    assert!(index.contains("const origin = [\n  1,\n  -1\n];"));
+}
+
+#[apply(worlds)]
+fn tile_pattern(world: World) {
+    let ids = world.search();
+    let hashed = TempDir::new().unwrap();
+    let pattern = TilePattern::from_str("{hash}/{z}_{x}_{y}").unwrap();
+    let render_to = |output: &Path, tile_pattern: &TilePattern| {
+        let config = Config {
+            tile_pattern: tile_pattern.clone(),
+            ..Config::default()
+        };
+        render(
+            &world.input,
+            output,
+            false,
+            false,
+            &world.level,
+            &ids,
+            &config,
+        )
+        .unwrap();
+    };
+    render_to(world.output.path(), &TilePattern::default());
+    render_to(hashed.path(), &pattern);
+
+    // Named otherwise and otherwise alike
+    let tiles = glob(&format!("{}/tiles/*/*/*", world.output.path().display()))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert!(!tiles.is_empty());
+    for path in &tiles {
+        let relative = path
+            .strip_prefix(world.output.path().join("tiles"))
+            .unwrap();
+        let (stem, extension) = relative.to_str().unwrap().split_once('.').unwrap();
+        let key = TilePattern::default().parse(stem).unwrap();
+        let moved = format!("tiles/{}.{extension}", pattern.format(key));
+
+        assert_eq!(
+            fs::read(hashed.path().join(&moved)).unwrap(),
+            fs::read(path).unwrap(),
+            "{moved}"
+        );
+    }
+    let index = fs::read_to_string(hashed.path().join("index.html")).unwrap();
+    assert!(index.contains(r#"const tilePattern = "{hash}/{z}_{x}_{y}";"#));
+
+    // Switching the pattern renders the tiles again under their new names
+    render_to(world.output.path(), &pattern);
+    assert_eq!(
+        glob(&format!("{}/tiles/*/*/*", world.output.path().display()))
+            .unwrap()
+            .count(),
+        0
+    );
+    assert_eq!(
+        glob(&format!("{}/tiles/*/*_*_*", world.output.path().display()))
+            .unwrap()
+            .count(),
+        tiles.len()
+    );
+}
+
+#[apply(worlds)]
+fn survey(world: World) {
+    let generator = Generator::builder()
+        .world(&world.input)
+        .output(world.output.path())
+        .quiet(true)
+        .build()
+        .unwrap();
+    let count = |pattern: &str| {
+        glob(&format!("{}/{pattern}", world.output.path().display()))
+            .unwrap()
+            .count()
+    };
+
+    let survey = generator.survey().unwrap();
+    assert!(survey.tiles > 0);
+    assert_eq!(count("tiles/**/*"), 0);
+    assert_eq!(count("index.html"), 0);
+
+    // As rendered
+    let report = generator.run().unwrap();
+    let ((x0, z0), (x1, z1)) = survey.bounds.unwrap();
+    assert_eq!(survey.tiles, report.tiles_rendered);
+    assert_eq!(survey.tiles, count("tiles/4/*/*.meta.json"));
+    assert_eq!((x1 - x0 + 1) % 128, 0);
+    assert_eq!((z1 - z0 + 1) % 128, 0);
+    assert!(survey.maps <= report.maps_found);
+    assert_eq!(
+        survey.banners,
+        serde_json::from_reader::<_, serde_json::Value>(
+            File::open(world.output.path().join("banners.json")).unwrap()
+        )
+        .unwrap()["features"]
+            .as_array()
+            .unwrap()
+            .len()
+    );
+}